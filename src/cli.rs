@@ -1,32 +1,446 @@
-use clap::{Parser, ValueEnum};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
 use std::path::PathBuf;
 
-#[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
+use crate::config::{ConflictPolicy, OcrLayer, SettingsLayer, SpecialsPolicy, SubtitleLayer};
+use crate::workflows::library::LibraryDuplicatePolicy;
+use crate::workflows::space::LowSpacePolicy;
+
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum MatchMode {
     ProductionCode,
     Subtitles,
+    /// Match the subtitle text against TVDB episode names and overviews, prompting only
+    /// when no episode stands out
+    SubtitlesAuto,
+    /// Read SXXEXX, 1x02 or air date patterns from the file path (including `Season 02/`)
+    Filename,
+    /// Pick the only episode whose TVDB runtime fits the file, for specials and
+    /// double-length episodes
+    Duration,
+    /// Experimental: compare audio fingerprints with episodes fingerprinted by --learn-audio
+    Audio,
+    /// Compare the subtitle text with reference SRTs of every episode, from --reference-subs
+    /// or downloaded from OpenSubtitles
+    Reference,
+    /// Read the air date from the file path, MKV tags, on-screen text or subtitles and
+    /// pick the episode that aired that day, for daily and news shows
+    AirDate,
+    /// Try the filename, the production code and the subtitles in turn, then prompt
+    Auto,
+}
+
+/// Episode numbering that filenames, manually entered numbers and new names use.
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum EpisodeOrder {
+    /// Season and episode as aired, e.g. S05E23
+    Aired,
+    /// TVDB's absolute numbering across all seasons, e.g. E0153, common for anime. New names
+    /// keep the aired season and episode
+    Absolute,
+    /// TVDB's DVD order
+    Dvd,
+    /// TVDB's alternate order
+    Alternate,
+}
+
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
+pub enum OutputFormat {
+    Text,
+    /// Never prompt, and print one JSON object per file
+    Json,
+}
+
+/// How a file gets its new name.
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Move the file to its new name
+    Rename,
+    /// Keep the file and add a hardlink under the new name, copying if that's on another
+    /// filesystem
+    Hardlink,
+    /// Keep the file and copy it to the new name
+    Copy,
+    /// Keep the file and add a symlink to it under the new name
+    Symlink,
+}
+
+/// Where in the video production codes are shown.
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScanPosition {
+    /// Opening credits
+    Start,
+    /// End credits
+    End,
+    Both,
+}
+
+/// Whether to read subtitle tracks for the deaf and hard of hearing.
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SdhPreference {
+    Prefer,
+    Avoid,
+}
+
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OcrBackend {
+    /// Apple Vision if this build has it, otherwise Tesseract
+    Auto,
+    Tesseract,
+    /// Apple Vision framework (macOS builds with the `vision` feature)
+    Vision,
+}
+
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Provider {
+    Tvdb,
+    /// For shows missing from TVDB, has no production codes
+    Tvmaze,
+}
+
+#[derive(Debug, Clone, ValueEnum, PartialEq, Eq)]
+pub enum ImportSource {
+    /// FileBot history.xml
+    Filebot,
+    /// tinyMediaManager/Kodi episode .nfo files next to the videos
+    Nfo,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Seed the cache and rename journal from another tool's match history
+    Import {
+        /// Tool the history comes from
+        #[arg(long, value_enum)]
+        from: ImportSource,
+
+        /// FileBot history files, or library files/directories with episode NFOs
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+    /// Make the renames of a plan written by `--dry-run --plan-file`
+    ApplyPlan {
+        /// Rename plan JSON file, possibly edited since the dry run
+        plan_file: PathBuf,
+    },
+    /// Rename the files of the last run (or the given run) back to their old names
+    Undo {
+        /// Run to undo, as shown by --list
+        #[arg(long)]
+        run: Option<u64>,
+
+        /// List the runs in the rename journal instead of undoing one
+        #[arg(long)]
+        list: bool,
+    },
+    /// Check that the content of already renamed files matches the episode in their name,
+    /// without renaming anything
+    Verify {
+        /// Library directory of the series, searched recursively
+        library_dir: PathBuf,
+    },
+    /// Search the provider for series by name and print their IDs
+    Search {
+        /// Part of the series name
+        query: String,
+
+        /// `text` prints a table, `json` an array of the series
+        #[arg(long, value_enum, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Print the episodes of a series with their production codes
+    Episodes {
+        /// Show ID at the provider
+        series_id: String,
+
+        /// `text` prints a table, `json` an array of the episodes
+        #[arg(long, value_enum, default_value = "text")]
+        output: OutputFormat,
+    },
+    /// Manage the cached series and episode data
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+    /// Write a starter config file or print the effective settings
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Store the TVDB API key in the OS keyring or show where it comes from
+    Auth {
+        #[command(subcommand)]
+        action: AuthAction,
+    },
+    /// Catalog the video files of a library with their show, episodes, size and checksum,
+    /// for --catalog. Scanning again only reads new and changed files
+    Scan {
+        /// Library directory, searched recursively
+        library: PathBuf,
+    },
+    /// Print which aired episodes of each season the library catalog built by `scan` has no
+    /// file of
+    Gaps,
+}
+
+#[derive(Subcommand)]
+pub enum AuthAction {
+    /// Read the TVDB API key from the terminal or stdin and store it in the OS keyring,
+    /// which is checked after TVDB_API_KEY and before the config file (builds with the
+    /// `keyring` feature)
+    SetKey,
+    /// Show where the TVDB API key is read from
+    Status,
+}
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Write a config file listing every setting with its default, commented out
+    Init {
+        /// Replace an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the settings a run would use, from the defaults, the config file, the
+    /// environment and the command line (with --show-id or --show, the series' section too)
+    Show,
+}
+
+#[derive(Subcommand)]
+pub enum CacheAction {
+    /// Fetch the episodes of a series again, e.g. for newly aired ones
+    Refresh {
+        /// Show ID at the provider
+        series_id: String,
+    },
+    /// Write the cached series and episodes to a file, to import them on another machine
+    Export {
+        /// JSON file to write
+        file: PathBuf,
+        /// Only export this series (cache key, e.g. `77398` or `tvmaze:123`), can be repeated
+        #[arg(long = "series")]
+        series: Vec<String>,
+    },
+    /// Add the series and episodes of an exported file to the cache
+    Import {
+        /// JSON file written by `cache export`
+        file: PathBuf,
+        /// Only import this series (cache key, e.g. `77398` or `tvmaze:123`), can be repeated
+        #[arg(long = "series")]
+        series: Vec<String>,
+    },
+    /// Delete the cached series and episodes
+    Clear {
+        /// Also delete the episodes of matched files, the learned audio fingerprints and the
+        /// shows chosen for searched names
+        #[arg(long)]
+        all: bool,
+    },
 }
 
 #[derive(Parser)]
 #[command(name = "episode-matcher")]
 #[command(about = "Extract production codes from video files and rename them using TVDB data")]
+#[command(subcommand_negates_reqs = true)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// Input files or directories to process
-    #[arg(required = true)]
+    #[arg(required_unless_present = "sonarr")]
     pub inputs: Vec<PathBuf>,
 
-    /// Show name to search in TVDB
-    #[arg(long)]
+    /// Show name to search at the provider
+    #[arg(long, global = true)]
     pub show: Option<String>,
 
-    /// Direct TVDB show ID
-    #[arg(long)]
+    /// Direct show ID at the provider
+    #[arg(long, global = true)]
     pub show_id: Option<String>,
 
-    /// Skip confirmation prompts
+    /// Year the searched show started, to tell a reboot from the original without asking.
+    /// A suffix like in `--show "Doctor Who (2005)"` does the same
+    #[arg(long, global = true)]
+    pub year: Option<u16>,
+
+    /// Without --show/--show-id, search the show named in the title tag of the first input
+    /// file, and ask for a name if there's none or nothing is found
+    #[arg(long, conflicts_with_all = ["show", "show_id", "sonarr"])]
+    pub detect_show: bool,
+
+    /// Search the show name again instead of using the show chosen for it before
+    #[arg(long, global = true)]
+    pub forget_show: bool,
+
+    /// Without --show/--show-id, match the files of several shows: files whose path matches
+    /// the case-insensitive regex belong to the show, e.g. `x.?files=77398`. Can be repeated
+    /// and comes before the `[[mappings]]` of the config
+    #[arg(long = "map", value_name = "PATTERN=SHOW_ID", conflicts_with_all = ["show", "show_id", "tui", "detect_show"])]
+    pub map: Vec<String>,
+
+    /// Where series and episode data comes from [default: tvdb]
+    #[arg(long, value_enum, global = true)]
+    pub provider: Option<Provider>,
+
+    /// Run as a Sonarr custom script: check the episode an imported file was identified as,
+    /// exit with 2 on a mismatch and 3 if the episode can't be identified
+    #[arg(long)]
+    pub sonarr: bool,
+
+    /// Write the JSON report of a --sonarr check to this file instead of stdout
+    #[arg(long, requires = "sonarr")]
+    pub report: Option<PathBuf>,
+
+    /// Output format. `json` never prompts and prints one JSON object per file to stdout
+    /// (other output goes to stderr)
+    #[arg(long, value_enum, default_value = "text", conflicts_with = "sonarr")]
+    pub output: OutputFormat,
+
+    /// Write the JSON objects of `--output json` to this file instead of stdout
+    #[arg(long = "output-file")]
+    pub output_file: Option<PathBuf>,
+
+    /// Also write the summary printed at the end of the run to this file
+    #[arg(long = "summary-file")]
+    pub summary_file: Option<PathBuf>,
+
+    /// Review all files in a terminal UI: they're matched in the background, and every
+    /// rename is approved, edited or skipped before anything is renamed
+    #[arg(long, conflicts_with_all = ["output", "dry_run", "preview_dir", "sonarr"])]
+    pub tui: bool,
+
+    /// Rename without asking for confirmation (same as `rename = "auto"` in the [prompts] config)
     #[arg(long)]
     pub no_confirm: bool,
 
+    /// Match every file automatically first and only then ask about those that couldn't be
+    /// identified, low-confidence matches and specials, one after another
+    #[arg(long, conflicts_with = "tui")]
+    pub defer_prompts: bool,
+
+    /// Match files again even if an earlier run already identified them
+    #[arg(long)]
+    pub force: bool,
+
+    /// Stop the run at the first file that fails instead of going on with the others
+    #[arg(long)]
+    pub strict: bool,
+
+    /// Never access the network: the series and its episodes must be in the cache, e.g.
+    /// fetched by an earlier run
+    #[arg(long, conflicts_with = "download_tessdata")]
+    pub offline: bool,
+
+    /// Look files up by their OpenSubtitles hash before matching them with the match mode,
+    /// which only runs for files OpenSubtitles doesn't know
+    #[arg(long, conflicts_with = "offline")]
+    pub opensubtitles: bool,
+
+    /// Match files but don't rename anything, print the rename plan at the end instead
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Also write the rename plan of a dry run to this JSON file
+    #[arg(long = "plan-file", requires = "dry_run")]
+    pub plan_file: Option<PathBuf>,
+
+    /// Don't rename anything, build a `<Show>/Season XX/` tree of symlinks to the files
+    /// under their proposed names in this directory instead
+    #[arg(long = "preview-dir")]
+    pub preview_dir: Option<PathBuf>,
+
+    /// Also move renamed files into a `<Show>/Season XX/` tree under the library root
+    #[arg(long, conflicts_with = "preview_dir")]
+    pub organize: bool,
+
+    /// Library root for --organize (default: `library_root` in the config)
+    #[arg(long = "library-root", requires = "organize")]
+    pub library_root: Option<PathBuf>,
+
+    /// With --organize, look for files of the episode already in its season directory of
+    /// the library and keep the better copy, replace it, skip the new file or ask. Identical
+    /// copies are always skipped. With --catalog, the cataloged files count too
+    #[arg(long = "library-duplicates", value_enum)]
+    pub library_duplicates: Option<LibraryDuplicatePolicy>,
+
+    /// Check the catalog `scan` built for files of the matched episodes, which are skipped
+    /// unless --library-duplicates says otherwise. Implies --gaps
+    #[arg(long)]
+    pub catalog: bool,
+
+    /// After the run, print which aired episodes of each season neither the run nor the
+    /// catalog (with --catalog) has a file of
+    #[arg(long)]
+    pub gaps: bool,
+
+    /// What to do when a new name is taken by another file (default: `overwrite` in the
+    /// config's `[prompts]`, else `suffix`). `apply-plan` only replaces files with `overwrite`
+    #[arg(long = "on-conflict", value_enum)]
+    pub on_conflict: Option<ConflictPolicy>,
+
+    /// Move files to their new name, or keep them in place (e.g. for seeding) and link or
+    /// copy them to it
+    #[arg(long = "link-mode", value_enum, default_value_t = LinkMode::Rename)]
+    pub link_mode: LinkMode,
+
+    /// Write a Kodi `<name>.nfo` with the episode's title, numbers, air date, plot and ID
+    /// next to every renamed file
+    #[arg(long)]
+    pub nfo: bool,
+
+    /// Write the episode into the MKV's title and tags (series and episode ID, production
+    /// code) with mkvpropedit, so the identification travels with the file
+    #[arg(long = "tag-file")]
+    pub tag_file: bool,
+
+    /// How often to retry a rename that fails because the file is busy (e.g. on SMB/NFS)
+    #[arg(long = "rename-retries", default_value_t = 3)]
+    pub rename_retries: u32,
+
+    /// Wait before the first rename retry in milliseconds, doubled for every further retry
+    #[arg(long = "rename-backoff", default_value_t = 500)]
+    pub rename_backoff: u64,
+
+    /// Don't compare the checksums of copies with the original, which --link-mode copy,
+    /// hardlinks falling back to copies and moves to another filesystem do by default
+    /// (reading every copied file twice). Copies that differ are retried like busy files
+    #[arg(long = "no-verify-checksum")]
+    pub no_verify_checksum: bool,
+
+    /// What to do when a destination lacks the free space for the files a run or plan
+    /// copies to it (--link-mode copy, moves and hardlinks to another filesystem): stop
+    /// before touching any file, or only process the files that fit
+    #[arg(long = "low-space", value_enum, default_value_t = LowSpacePolicy::Abort)]
+    pub low_space: LowSpacePolicy,
+
+    /// Extract frames and run OCR for this many files at once, prompts and renames still
+    /// happen one file at a time [default: 1]
+    #[arg(short = 'j', long)]
+    pub jobs: Option<usize>,
+
+    /// OCR engine for frames and PGS subtitles [default: auto]
+    #[arg(long = "ocr-backend", value_enum)]
+    pub ocr_backend: Option<OcrBackend>,
+
+    /// Tesseract languages for frame and PGS subtitle OCR, e.g. `deu` or `eng+deu` [default: eng]
+    #[arg(long = "ocr-lang")]
+    pub ocr_lang: Option<String>,
+
+    /// Directory containing the traineddata, instead of searching TESSDATA_PREFIX and the
+    /// usual install locations
+    #[arg(long)]
+    pub tessdata: Option<PathBuf>,
+
+    /// Download the traineddata to the config directory if it isn't installed
+    #[arg(long = "download-tessdata")]
+    pub download_tessdata: bool,
+
     /// Recursively scan directories for MKV files
     #[arg(short = 'r', long = "recursive")]
     pub recursive: bool,
@@ -35,7 +449,158 @@ pub struct Cli {
     #[arg(long = "prompt-size")]
     pub prompt_size: Option<u64>,
 
-    /// Matching mode
-    #[arg(long, default_value = "production-code")]
-    pub match_mode: MatchMode,
+    /// Matching mode [default: production-code]
+    #[arg(long)]
+    pub match_mode: Option<MatchMode>,
+
+    /// Fingerprint the audio of every matched file for the `audio` match mode
+    #[arg(long = "learn-audio")]
+    pub learn_audio: bool,
+
+    /// Seconds into the files to take audio fingerprints at [default: 60,300]
+    #[arg(long = "audio-offsets", value_delimiter = ',')]
+    pub audio_offsets: Option<Vec<u64>>,
+
+    /// Write OCR candidates to a `<name>.ocr.json` sidecar and reuse it on later runs
+    #[arg(long)]
+    pub ocr_sidecar: bool,
+
+    /// Template for new file names with {show}, {season:02}, {episode:02}, {title}, {year} and
+    /// {code}, or a preset: default, plex, jellyfin or archive (with the production code)
+    #[arg(long = "name-template")]
+    pub name_template: Option<String>,
+
+    /// Episode numbering of filenames, prompt input and new names, `dvd` and `alternate`
+    /// with the TVDB provider [default: aired]
+    #[arg(long, value_enum)]
+    pub order: Option<EpisodeOrder>,
+
+    /// Only match episodes of this season, e.g. when a directory holds one season
+    #[arg(long)]
+    pub season: Option<u64>,
+
+    /// Only match these episode numbers (within --season if given), e.g. `1-12` or `5`
+    #[arg(long)]
+    pub episodes: Option<String>,
+
+    /// Name the one input file as this episode, e.g. `S03E07`, without matching it
+    #[arg(long, conflicts_with = "episode_offset")]
+    pub assume: Option<String>,
+
+    /// CSV of `filename,SXXEXX` rows (or production codes instead of SXXEXX), or a JSON
+    /// object of the same, naming the listed files as their episodes without matching them
+    #[arg(long = "mapping-file")]
+    pub mapping_file: Option<PathBuf>,
+
+    /// Add this to the episode numbers read from file names, e.g. `-1` for releases whose
+    /// numbering is ahead of the provider's. Only with --match-mode filename or auto
+    #[arg(long = "episode-offset", allow_negative_numbers = true)]
+    pub episode_offset: Option<i64>,
+
+    /// Whether files may be matched to specials of season 0 [default: include, or the
+    /// `specials` prompt policy of the config]
+    #[arg(long, value_enum)]
+    pub specials: Option<SpecialsPolicy>,
+
+    /// Template or preset for the new names of specials, e.g. `{show} - Special - {title}`
+    /// [default: the name template]
+    #[arg(long = "specials-template")]
+    pub specials_template: Option<String>,
+
+    /// Regex for the show's production codes, matched against OCR text with whitespace removed
+    #[arg(long = "code-regex")]
+    pub code_regex: Option<String>,
+
+    /// Drop OCR candidates read with a lower word confidence (0-100) [default: 0]
+    #[arg(long = "min-ocr-confidence")]
+    pub min_ocr_confidence: Option<i32>,
+
+    /// Seconds of video to scan for the production code [default: 15]
+    #[arg(long = "scan-window")]
+    pub scan_window: Option<u32>,
+
+    /// Scan the start, the end or both ends of the video [default: end]
+    #[arg(long = "scan-position", value_enum)]
+    pub scan_position: Option<ScanPosition>,
+
+    /// OCR the PNG/JPG screenshots in this directory instead of the video's frames, those
+    /// of several videos in subdirectories named after them
+    #[arg(long = "frames-dir")]
+    pub frames_dir: Option<PathBuf>,
+
+    /// ISO 639-2 language of the subtitle track to read, e.g. `ger` [default: eng]
+    #[arg(long = "subtitle-lang")]
+    pub subtitle_lang: Option<String>,
+
+    /// Prefer or avoid SDH subtitle tracks among those of the language
+    #[arg(long = "subtitle-sdh", value_enum)]
+    pub subtitle_sdh: Option<SdhPreference>,
+
+    /// In the subtitle match modes, only read the subtitles of this many minutes of the video
+    #[arg(long = "subtitle-window")]
+    pub subtitle_window: Option<u32>,
+
+    /// Read the subtitles of the start, the end or both ends of the video [default: start]
+    #[arg(long = "subtitle-position", value_enum, requires = "subtitle_window")]
+    pub subtitle_position: Option<ScanPosition>,
+
+    /// Directory of reference SRTs with `SxxExx` in their names for the `reference` match
+    /// mode [default: download them from OpenSubtitles]
+    #[arg(long = "reference-subs")]
+    pub reference_subs: Option<PathBuf>,
+
+    /// Frames per second of the scan window to OCR [default: 1]
+    #[arg(long = "scan-fps")]
+    pub scan_fps: Option<u32>,
+
+    /// Extract frames at this rate and OCR only the sharpest frame of each second (for scrolling credits)
+    #[arg(long = "burst-fps")]
+    pub burst_fps: Option<u32>,
+
+    /// Detect and correct slightly rotated credits text before OCR, `--deskew=false` turns
+    /// it off for a series that has it on in the config
+    #[arg(long, num_args = 0..=1, require_equals = true, default_missing_value = "true")]
+    pub deskew: Option<bool>,
+
+    /// Only OCR this part of the frames: left,top,width,height in fractions of the frame,
+    /// e.g. `0,0.75,1,0.25` for the bottom quarter
+    #[arg(long, value_delimiter = ',')]
+    pub crop: Option<Vec<f32>>,
+
+    /// Rotate frames clockwise by this many degrees before OCR (overrides --deskew)
+    #[arg(long = "ocr-rotate", allow_negative_numbers = true)]
+    pub ocr_rotate: Option<f32>,
+
+    /// Tesseract page segmentation mode for frames, e.g. 7 for a single line [default: 6]
+    #[arg(long = "ocr-psm", value_parser = clap::value_parser!(u8).range(0..=13))]
+    pub ocr_psm: Option<u8>,
+
+    /// The only characters tesseract may read on frames, empty for all
+    /// [default: 0-9, A-Z and #, all with --code-regex]
+    #[arg(long = "ocr-whitelist")]
+    pub ocr_whitelist: Option<String>,
+}
+
+impl Cli {
+    /// The settings given on the command line, which override the config file and the
+    /// environment.
+    pub fn settings(&self) -> SettingsLayer {
+        SettingsLayer {
+            provider: self.provider.clone(),
+            name_template: self.name_template.clone(),
+            specials_template: self.specials_template.clone(),
+            jobs: self.jobs,
+            cache_backend: None,
+            ocr: OcrLayer {
+                backend: self.ocr_backend.clone(),
+                lang: self.ocr_lang.clone(),
+                min_confidence: self.min_ocr_confidence,
+            },
+            subtitles: SubtitleLayer {
+                language: self.subtitle_lang.clone(),
+                sdh: self.subtitle_sdh.clone(),
+                avoid_forced: None,
+            },
+        }
+    }
 }
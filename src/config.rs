@@ -1,42 +1,565 @@
 use anyhow::bail;
 use anyhow::Result;
-use serde::Deserialize;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path::PathBuf;
+use std::str::FromStr;
 
-#[derive(Debug, Deserialize)]
+use crate::cli::{EpisodeOrder, MatchMode, OcrBackend, Provider, ScanPosition, SdhPreference};
+use crate::infra::secrets;
+use crate::media::tessdata;
+
+const DEFAULT_CACHE_TTL_DAYS: u64 = 7;
+
+// Environment variables of settings are named after their key, e.g. `EPISODE_MATCHER_JOBS`
+// and `EPISODE_MATCHER_OCR_LANG` for `lang` of the `[ocr]` section
+const ENV_PREFIX: &str = "EPISODE_MATCHER_";
+
+// Written by `config init`, every setting commented out with its default
+const STARTER_CONFIG: &str = include_str!("config.example.toml");
+
+#[derive(Debug, Deserialize, Default)]
 struct ConfigFile {
     tvdb_api_key: Option<String>,
+    opensubtitles_api_key: Option<String>, // for --opensubtitles
+    #[serde(flatten)]
+    settings: SettingsLayer, // those the environment and command line can override
+    library_root: Option<PathBuf>,         // where --organize moves files to
+    cache_ttl_days: Option<u64>,           // 0 keeps cached episodes forever
+    tvdb_requests_per_second: Option<f64>,
+    #[serde(default)]
+    series: HashMap<String, SeriesConfig>, // series_id or show name -> overrides
+    #[serde(default)]
+    mappings: Vec<SeriesMapping>, // routes files to series when no show is given
+    #[serde(default)]
+    prompts: PromptPolicy,
+    #[serde(default)]
+    filenames: FilenamePolicy,
+}
+
+/// Settings of the config file, the environment or the command line, each of which
+/// overrides the one before, see [`Settings::resolve`]. Unset ones are `None`.
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct SettingsLayer {
+    pub provider: Option<Provider>,
+    pub name_template: Option<String>, // template or preset for new file names
+    pub specials_template: Option<String>, // name_template of season 0
+    pub jobs: Option<usize>,           // files whose frames are OCRed at once
+    pub cache_backend: Option<CacheBackend>,
+    pub ocr: OcrLayer,
+    pub subtitles: SubtitleLayer,
+}
+
+/// The `[ocr]` section.
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct OcrLayer {
+    pub backend: Option<OcrBackend>,
+    pub lang: Option<String>, // tesseract languages, e.g. `eng+deu`
+    pub min_confidence: Option<i32>,
+}
+
+/// The `[subtitles]` section.
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct SubtitleLayer {
+    pub language: Option<String>, // ISO 639-2
+    pub sdh: Option<SdhPreference>,
+    pub avoid_forced: Option<bool>, // forced tracks only subtitle foreign scenes
+}
+
+/// Where the cache is kept.
+#[derive(Debug, Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheBackend {
+    /// `cache.db`, written as it changes
+    #[default]
+    Sqlite,
+    /// `cache.json` of older versions, written after every file
+    Json,
+}
+
+/// The settings a run uses: the defaults, overridden by the config file, the environment
+/// and the command line. `config show` prints them.
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct Settings {
+    pub provider: Provider,
+    pub name_template: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub specials_template: Option<String>,
+    pub jobs: usize,
+    pub cache_backend: CacheBackend,
+    pub ocr: OcrSettings,
+    pub subtitles: SubtitleSettings,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct OcrSettings {
+    pub backend: OcrBackend,
+    pub lang: String,
+    pub min_confidence: i32,
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+pub struct SubtitleSettings {
+    pub language: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sdh: Option<SdhPreference>,
+    pub avoid_forced: bool,
+}
+
+impl Settings {
+    /// The settings of the layers, a later layer's overriding an earlier one's, with the
+    /// defaults for those no layer sets.
+    pub fn resolve(layers: &[&SettingsLayer]) -> Self {
+        fn last<T>(
+            layers: &[&SettingsLayer],
+            get: impl Fn(&SettingsLayer) -> Option<T>,
+        ) -> Option<T> {
+            layers.iter().rev().find_map(|layer| get(layer))
+        }
+        Self {
+            provider: last(layers, |l| l.provider.clone()).unwrap_or(Provider::Tvdb),
+            name_template: last(layers, |l| l.name_template.clone())
+                .unwrap_or_else(|| "default".to_string()),
+            specials_template: last(layers, |l| l.specials_template.clone()),
+            jobs: last(layers, |l| l.jobs).unwrap_or(1),
+            cache_backend: last(layers, |l| l.cache_backend).unwrap_or_default(),
+            ocr: OcrSettings {
+                backend: last(layers, |l| l.ocr.backend.clone()).unwrap_or(OcrBackend::Auto),
+                lang: last(layers, |l| l.ocr.lang.clone())
+                    .unwrap_or_else(|| tessdata::DEFAULT_LANGUAGE.to_string()),
+                min_confidence: last(layers, |l| l.ocr.min_confidence).unwrap_or(0),
+            },
+            subtitles: SubtitleSettings {
+                language: last(layers, |l| l.subtitles.language.clone())
+                    .unwrap_or_else(|| "eng".to_string()),
+                sdh: last(layers, |l| l.subtitles.sdh.clone()),
+                avoid_forced: last(layers, |l| l.subtitles.avoid_forced).unwrap_or(true),
+            },
+        }
+    }
+}
+
+/// When to ask the user instead of acting on their behalf, from the `[prompts]` section.
+#[derive(Debug, Deserialize, Default, Clone)]
+#[serde(default)]
+pub struct PromptPolicy {
+    pub rename: RenamePolicy,
+    pub overwrite: ConflictPolicy,
+    pub low_confidence: LowConfidencePolicy,
+    pub unmatched: UnmatchedPolicy,
+    pub duplicates: DuplicatePolicy,
+    pub specials: SpecialsPolicy,
+    pub multi_part: MultiPartPolicy,
+}
+
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RenamePolicy {
+    #[default]
+    Ask,
+    Auto,
+}
+
+/// What to do when the new name is already taken by another file, also `--on-conflict`.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum ConflictPolicy {
+    /// Keep both files by adding a `[copy N]` suffix
+    #[default]
+    #[serde(alias = "never")]
+    Suffix,
+    /// Leave the file under its old name
+    Skip,
+    /// Replace the existing file
+    Overwrite,
+    /// Ask whether to replace the existing file, even when renames are automatic
+    #[serde(alias = "always-ask")]
+    Prompt,
+}
+
+/// What to do when two files of a run are matched to the same episode.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Ask which file gets the episode's name
+    #[default]
+    Ask,
+    /// Rename both, the second one with a `[copy N]` suffix
+    KeepBoth,
+    /// Rename the larger file only
+    KeepLarger,
+}
+
+/// Whether files may be matched to specials of season 0, also `--specials`. Misread codes
+/// often map to them.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum SpecialsPolicy {
+    /// Match specials like any other episode
+    #[default]
+    Include,
+    /// Never match specials
+    Exclude,
+    /// Ask before using a match of a special
+    Prompt,
+}
+
+/// What to do with an OCR match whose text confidence is low.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum LowConfidencePolicy {
+    #[default]
+    Accept,
+    Ask,
+    Skip,
+}
+
+/// What to do with a file holding the production codes of two episodes that aren't
+/// consecutive, e.g. a movie edit of a story the provider lists as two parts.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum MultiPartPolicy {
+    /// Ask whether to name it as both episodes, as one of them or leave it
+    #[default]
+    Ask,
+    /// Name it as both episodes, e.g. `S01E03-E07`
+    Combine,
+    /// Leave it unrenamed and flag it in the report for manual handling
+    Flag,
+}
+
+/// What to do when no episode could be matched automatically.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum UnmatchedPolicy {
+    /// Prompt for the episode (for files above `--prompt-size` in production code mode)
+    #[default]
+    Ask,
+    Skip,
+}
+
+/// How new file names are made safe for the filesystem, from the `[filenames]` section.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct FilenamePolicy {
+    pub replacement: String, // for characters Windows doesn't allow in file names
+    pub replace: HashMap<char, String>, // per character, overrides `replacement`
+    pub max_name_length: usize, // bytes, most filesystems allow 255
+    pub max_path_length: Option<usize>, // bytes of the whole path, e.g. 260 for Windows
+    pub collapse_whitespace: bool, // runs of spaces in values become one
+    pub strip_brackets: bool, // titles lose `(...)` and `[...]`, like `{title|nobrackets}`
+    pub ascii: bool,         // titles are transliterated, like `{title|ascii}`
+}
+
+impl Default for FilenamePolicy {
+    fn default() -> Self {
+        Self {
+            replacement: "-".to_string(),
+            replace: HashMap::new(),
+            max_name_length: 255,
+            max_path_length: None,
+            collapse_whitespace: false,
+            strip_brackets: false,
+            ascii: false,
+        }
+    }
+}
+
+/// A `[[mappings]]` entry: files whose path matches `pattern` belong to the series.
+#[derive(Debug, Deserialize, Clone)]
+pub struct SeriesMapping {
+    pub pattern: String, // case-insensitive regex
+    pub series: String,  // show ID at the provider
+}
+
+/// Settings from a `[series."<id or name>"]` section that override the defaults for one show.
+#[derive(Debug, Deserialize, Default, Clone)]
+pub struct SeriesConfig {
+    pub match_mode: Option<MatchMode>,
+    pub order: Option<EpisodeOrder>,
+    pub subtitle_language: Option<String>,
+    pub subtitle_sdh: Option<SdhPreference>,
+    pub avoid_forced_subtitles: Option<bool>, // forced tracks only subtitle foreign scenes
+    pub subtitle_window: Option<u32>,         // minutes
+    pub subtitle_position: Option<ScanPosition>,
+    pub reference_subs: Option<PathBuf>, // SRTs of the episodes for the reference match mode
+    pub ocr_lang: Option<String>,        // tesseract languages, e.g. `eng+deu`
+    pub scan_window: Option<u32>,        // seconds
+    pub scan_position: Option<ScanPosition>,
+    pub scan_fps: Option<u32>,
+    pub burst_fps: Option<u32>,
+    pub crop: Option<Vec<f32>>, // left, top, width and height in fractions of the frame
+    pub deskew: Option<bool>,
+    pub ocr_rotate: Option<f32>,
+    pub ocr_psm: Option<u8>, // tesseract page segmentation mode for frames
+    pub ocr_whitelist: Option<String>, // characters tesseract may read on frames
+    pub audio_offsets: Option<Vec<u64>>, // seconds into the files to fingerprint the audio at
+    pub code_regex: Option<String>, // production code pattern of the show
+    pub production_codes: Option<PathBuf>, // TOML or CSV table of code -> SxxExx
+    pub name_template: Option<String>, // overrides the top-level name_template
+    pub specials_template: Option<String>, // overrides the top-level specials_template
+    #[serde(default)]
+    pub ignore_candidates: Vec<String>, // regexes for recurring on-screen text that isn't a code
+}
+
+impl SeriesConfig {
+    /// The settings the section overrides, which rank between the top-level ones of the
+    /// config file and the environment.
+    pub fn settings(&self) -> SettingsLayer {
+        SettingsLayer {
+            name_template: self.name_template.clone(),
+            specials_template: self.specials_template.clone(),
+            ocr: OcrLayer {
+                lang: self.ocr_lang.clone(),
+                ..OcrLayer::default()
+            },
+            subtitles: SubtitleLayer {
+                language: self.subtitle_language.clone(),
+                sdh: self.subtitle_sdh.clone(),
+                avoid_forced: self.avoid_forced_subtitles,
+            },
+            ..SettingsLayer::default()
+        }
+    }
+}
+
+/// Where an API key was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeySource {
+    Environment,
+    Keyring,
+    ConfigFile,
 }
 
 pub fn get_tvdb_api_key() -> Result<String> {
-    // First, check environment variable
-    if let Ok(key) = env::var("TVDB_API_KEY") {
+    match find_tvdb_api_key()? {
+        Some((key, _)) => Ok(key),
+        None => bail!("TVDB API key not found. Set TVDB_API_KEY environment variable, store it with `episode-matcher auth set-key` or create config file at $HOME/.episode-matcher/config.toml with tvdb_api_key = \"your-key\""),
+    }
+}
+
+/// The TVDB API key from the TVDB_API_KEY environment variable, the OS keyring or the
+/// config file, in that order. A keyring that can't be read is skipped with a warning.
+pub fn find_tvdb_api_key() -> Result<Option<(String, KeySource)>> {
+    pick_api_key(
+        env::var("TVDB_API_KEY").ok(),
+        || secrets::get(secrets::TVDB_API_KEY),
+        || Ok(load_config_file()?.tvdb_api_key),
+    )
+}
+
+/// The first key of the environment, the keyring and the config file, the later ones
+/// only looked up if needed.
+fn pick_api_key(
+    env: Option<String>,
+    keyring: impl FnOnce() -> Result<Option<String>>,
+    config_file: impl FnOnce() -> Result<Option<String>>,
+) -> Result<Option<(String, KeySource)>> {
+    if let Some(key) = env {
+        return Ok(Some((key, KeySource::Environment)));
+    }
+
+    match keyring() {
+        Ok(Some(key)) => return Ok(Some((key, KeySource::Keyring))),
+        Ok(None) => {}
+        Err(e) => eprintln!("Warning: Failed to read the TVDB API key from the keyring: {e}"),
+    }
+
+    Ok(config_file()?.map(|key| (key, KeySource::ConfigFile)))
+}
+
+pub fn get_opensubtitles_api_key() -> Result<String> {
+    if let Ok(key) = env::var("OPENSUBTITLES_API_KEY") {
+        return Ok(key);
+    }
+
+    if let Some(key) = load_config_file()?.opensubtitles_api_key {
         return Ok(key);
     }
 
-    // Then, check config file
+    bail!("OpenSubtitles API key not found. Set OPENSUBTITLES_API_KEY environment variable or add opensubtitles_api_key = \"your-key\" to $HOME/.episode-matcher/config.toml")
+}
+
+pub fn get_prompt_policy() -> Result<PromptPolicy> {
+    Ok(load_config_file()?.prompts)
+}
+
+pub fn get_filename_policy() -> Result<FilenamePolicy> {
+    Ok(load_config_file()?.filenames)
+}
+
+/// The effective settings: the defaults, then those of the config file (and of the series'
+/// section, if any), the `EPISODE_MATCHER_*` environment variables and `cli`.
+pub fn get_settings(series: Option<&SeriesConfig>, cli: &SettingsLayer) -> Result<Settings> {
+    let file = load_config_file()?.settings;
+    let series = series.map(SeriesConfig::settings).unwrap_or_default();
+    let env = env_settings(|name| env::var(name).ok())?;
+    Ok(Settings::resolve(&[&file, &series, &env, cli]))
+}
+
+/// The settings of the environment variables `var` looks up.
+fn env_settings(var: impl Fn(&str) -> Option<String>) -> Result<SettingsLayer> {
+    Ok(SettingsLayer {
+        provider: env_value(&var, "PROVIDER", value_enum)?,
+        name_template: env_value(&var, "NAME_TEMPLATE", text)?,
+        specials_template: env_value(&var, "SPECIALS_TEMPLATE", text)?,
+        jobs: env_value(&var, "JOBS", parsed)?,
+        cache_backend: env_value(&var, "CACHE_BACKEND", value_enum)?,
+        ocr: OcrLayer {
+            backend: env_value(&var, "OCR_BACKEND", value_enum)?,
+            lang: env_value(&var, "OCR_LANG", text)?,
+            min_confidence: env_value(&var, "OCR_MIN_CONFIDENCE", parsed)?,
+        },
+        subtitles: SubtitleLayer {
+            language: env_value(&var, "SUBTITLES_LANGUAGE", text)?,
+            sdh: env_value(&var, "SUBTITLES_SDH", value_enum)?,
+            avoid_forced: env_value(&var, "SUBTITLES_AVOID_FORCED", parsed)?,
+        },
+    })
+}
+
+/// The value of `EPISODE_MATCHER_<key>`, `None` if it's unset or empty.
+fn env_value<T>(
+    var: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    parse: impl Fn(&str) -> Option<T>,
+) -> Result<Option<T>> {
+    let name = format!("{ENV_PREFIX}{key}");
+    let Some(value) = var(&name).filter(|value| !value.is_empty()) else {
+        return Ok(None);
+    };
+    match parse(&value) {
+        Some(parsed) => Ok(Some(parsed)),
+        None => bail!("Invalid value '{value}' of {name}"),
+    }
+}
+
+fn text(value: &str) -> Option<String> {
+    Some(value.to_string())
+}
+
+fn parsed<T: FromStr>(value: &str) -> Option<T> {
+    value.parse().ok()
+}
+
+fn value_enum<T: ValueEnum>(value: &str) -> Option<T> {
+    T::from_str(value, true).ok()
+}
+
+/// Writes the commented starter config of `config init` and returns its path. An existing
+/// config is only replaced with `force`.
+pub fn write_starter_config(force: bool) -> Result<PathBuf> {
+    let path = get_config_path();
+    if path.exists() && !force {
+        bail!("{path:?} already exists, pass --force to replace it");
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, STARTER_CONFIG)?;
+    Ok(path)
+}
+
+pub fn get_library_root() -> Result<Option<PathBuf>> {
+    Ok(load_config_file()?.library_root)
+}
+
+pub fn get_series_mappings() -> Result<Vec<SeriesMapping>> {
+    Ok(load_config_file()?.mappings)
+}
+
+/// Days after which the cached episodes of a series are fetched again.
+pub fn get_cache_ttl_days() -> Result<u64> {
+    Ok(load_config_file()?
+        .cache_ttl_days
+        .unwrap_or(DEFAULT_CACHE_TTL_DAYS))
+}
+
+/// The most requests per second sent to TheTVDB, if they're limited.
+pub fn get_tvdb_requests_per_second() -> Result<Option<f64>> {
+    tvdb_requests_per_second(&load_config_file()?)
+}
+
+fn tvdb_requests_per_second(config: &ConfigFile) -> Result<Option<f64>> {
+    match config.tvdb_requests_per_second {
+        Some(rate) if rate <= 0.0 => bail!("tvdb_requests_per_second must be greater than 0"),
+        rate => Ok(rate),
+    }
+}
+
+pub fn get_series_config(series_id: &str, show_name: &str) -> Result<SeriesConfig> {
+    Ok(find_series_config(
+        &load_config_file()?,
+        series_id,
+        show_name,
+    ))
+}
+
+fn find_series_config(config: &ConfigFile, series_id: &str, show_name: &str) -> SeriesConfig {
+    // An ID section wins over a name section, names are matched case-insensitively
+    config
+        .series
+        .get(series_id)
+        .or_else(|| {
+            config
+                .series
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(show_name))
+                .map(|(_, series)| series)
+        })
+        .cloned()
+        .unwrap_or_default()
+}
+
+fn load_config_file() -> Result<ConfigFile> {
     let config_path = get_config_path();
-    if config_path.exists() {
-        let config_content = fs::read_to_string(&config_path)?;
-        let config: ConfigFile = toml::from_str(&config_content)?;
-        if let Some(key) = config.tvdb_api_key {
-            return Ok(key);
-        }
+    if !config_path.exists() {
+        return Ok(ConfigFile::default());
     }
 
-    bail!("TVDB API key not found. Set TVDB_API_KEY environment variable or create config file at $HOME/.episode-matcher/config.toml with tvdb_api_key = \"your-key\"")
+    let config_content = fs::read_to_string(&config_path)?;
+    Ok(toml::from_str(&config_content)?)
 }
 
+/// The cache of versions before the SQLite one, see `get_cache_db_path`.
 pub fn get_cache_path() -> PathBuf {
-    println!(
-        "Using cache path: {}",
-        get_config_dir_path().join("cache.json").display()
-    );
     get_config_dir_path().join("cache.json")
 }
 
+pub fn get_cache_db_path() -> PathBuf {
+    get_config_dir_path().join("cache.db")
+}
+
+/// The catalog of media libraries that `scan` builds.
+pub fn get_catalog_db_path() -> PathBuf {
+    get_config_dir_path().join("catalog.db")
+}
+
+/// Where the TVDB bearer token is kept between runs.
+pub fn get_tvdb_token_path() -> PathBuf {
+    get_config_dir_path().join("tvdb_token.json")
+}
+
+pub fn get_journal_path() -> PathBuf {
+    get_config_dir_path().join("journal.jsonl")
+}
+
+/// Where tessdata downloaded by `--download-tessdata` is kept.
+pub fn get_tessdata_dir_path() -> PathBuf {
+    get_config_dir_path().join("tessdata")
+}
+
+/// Where reference subtitles downloaded for the reference match mode are kept, per series.
+pub fn get_reference_subs_dir_path() -> PathBuf {
+    get_config_dir_path().join("reference_subs")
+}
+
 fn get_config_dir_path() -> PathBuf {
     xdir::config()
         .map(|path| path.join("episode-matcher"))
@@ -45,6 +568,248 @@ fn get_config_dir_path() -> PathBuf {
         .unwrap_or_default()
 }
 
-fn get_config_path() -> PathBuf {
+pub fn get_config_path() -> PathBuf {
     get_config_dir_path().join("config.toml")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pick_api_key() {
+        let some = |key: &str| Ok(Some(key.to_string()));
+        let unused = || -> Result<Option<String>> { panic!("looked up after a key was found") };
+
+        // The environment comes first, then the keyring, then the config file
+        assert_eq!(
+            pick_api_key(Some("env".to_string()), unused, unused).unwrap(),
+            Some(("env".to_string(), KeySource::Environment))
+        );
+        assert_eq!(
+            pick_api_key(None, || some("keyring"), unused).unwrap(),
+            Some(("keyring".to_string(), KeySource::Keyring))
+        );
+        assert_eq!(
+            pick_api_key(None, || Ok(None), || some("file")).unwrap(),
+            Some(("file".to_string(), KeySource::ConfigFile))
+        );
+        // A keyring that can't be reached is skipped
+        assert_eq!(
+            pick_api_key(
+                None,
+                || Err(anyhow::anyhow!("no session bus")),
+                || some("file")
+            )
+            .unwrap(),
+            Some(("file".to_string(), KeySource::ConfigFile))
+        );
+        assert_eq!(pick_api_key(None, || Ok(None), || Ok(None)).unwrap(), None);
+        assert!(pick_api_key(None, || Ok(None), || Err(anyhow::anyhow!("bad toml"))).is_err());
+    }
+
+    #[test]
+    fn test_find_series_config() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+tvdb_api_key = "key"
+name_template = "plex"
+specials_template = "{show} - Special - {title}"
+library_root = "/tv"
+cache_ttl_days = 30
+
+[prompts]
+specials = "exclude"
+
+[series."77398"]
+match_mode = "subtitles"
+order = "absolute"
+subtitle_language = "ger"
+
+[series."millennium"]
+burst_fps = 5
+deskew = true
+ocr_rotate = -2.5
+ocr_psm = 7
+ocr_whitelist = "0123456789-"
+code_regex = "\\d{5}-\\d{3}"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.settings.name_template.as_deref(), Some("plex"));
+        assert_eq!(
+            config.settings.specials_template.as_deref(),
+            Some("{show} - Special - {title}")
+        );
+        assert_eq!(config.prompts.specials, SpecialsPolicy::Exclude);
+        assert_eq!(config.library_root, Some(PathBuf::from("/tv")));
+        assert_eq!(config.cache_ttl_days, Some(30));
+
+        let by_id = find_series_config(&config, "77398", "The X-Files");
+        assert_eq!(by_id.match_mode, Some(MatchMode::Subtitles));
+        assert_eq!(by_id.subtitle_language.as_deref(), Some("ger"));
+        assert_eq!(by_id.order, Some(EpisodeOrder::Absolute));
+
+        let by_name = find_series_config(&config, "71021", "Millennium");
+        assert_eq!(by_name.burst_fps, Some(5));
+        assert_eq!(by_name.deskew, Some(true));
+        assert_eq!(by_name.ocr_rotate, Some(-2.5));
+        assert_eq!(by_name.ocr_psm, Some(7));
+        assert_eq!(by_name.ocr_whitelist.as_deref(), Some("0123456789-"));
+        assert_eq!(by_name.code_regex.as_deref(), Some(r"\d{5}-\d{3}"));
+        assert_eq!(by_name.match_mode, None);
+
+        let missing = find_series_config(&config, "1", "Other");
+        assert!(missing.match_mode.is_none() && missing.burst_fps.is_none());
+    }
+
+    #[test]
+    fn test_tvdb_requests_per_second() {
+        let rate = |toml: &str| tvdb_requests_per_second(&toml::from_str(toml).unwrap());
+        assert_eq!(rate("").unwrap(), None);
+        assert_eq!(rate("tvdb_requests_per_second = 2.5").unwrap(), Some(2.5));
+        assert!(rate("tvdb_requests_per_second = 0.0").is_err());
+        assert!(rate("tvdb_requests_per_second = -1.0").is_err());
+    }
+
+    #[test]
+    fn test_prompt_policy() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+[prompts]
+rename = "auto"
+overwrite = "overwrite"
+low_confidence = "ask"
+duplicates = "keep-larger"
+multi_part = "combine"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.prompts.rename, RenamePolicy::Auto);
+        assert_eq!(config.prompts.overwrite, ConflictPolicy::Overwrite);
+        assert_eq!(config.prompts.low_confidence, LowConfidencePolicy::Ask);
+        assert_eq!(config.prompts.unmatched, UnmatchedPolicy::Ask);
+        assert_eq!(config.prompts.duplicates, DuplicatePolicy::KeepLarger);
+        assert_eq!(config.prompts.multi_part, MultiPartPolicy::Combine);
+
+        let defaults: ConfigFile = toml::from_str("").unwrap();
+        assert_eq!(defaults.prompts.rename, RenamePolicy::Ask);
+        assert_eq!(defaults.prompts.overwrite, ConflictPolicy::Suffix);
+
+        // The names of older versions
+        let older: ConfigFile = toml::from_str("[prompts]\noverwrite = \"always-ask\"").unwrap();
+        assert_eq!(older.prompts.overwrite, ConflictPolicy::Prompt);
+        assert_eq!(defaults.prompts.low_confidence, LowConfidencePolicy::Accept);
+        assert_eq!(defaults.prompts.duplicates, DuplicatePolicy::Ask);
+        assert_eq!(defaults.prompts.multi_part, MultiPartPolicy::Ask);
+    }
+
+    #[test]
+    fn test_filename_policy() {
+        let config: ConfigFile = toml::from_str(
+            r#"
+[filenames]
+replacement = ""
+max_path_length = 260
+ascii = true
+
+[filenames.replace]
+":" = " -"
+"#,
+        )
+        .unwrap();
+        assert_eq!(config.filenames.replacement, "");
+        assert_eq!(config.filenames.replace[&':'], " -");
+        assert_eq!(config.filenames.max_name_length, 255);
+        assert_eq!(config.filenames.max_path_length, Some(260));
+        assert!(config.filenames.ascii);
+        assert!(!config.filenames.collapse_whitespace && !config.filenames.strip_brackets);
+    }
+
+    #[test]
+    fn test_settings_layers() {
+        let file: ConfigFile = toml::from_str(
+            r#"
+provider = "tvmaze"
+name_template = "plex"
+jobs = 4
+
+[ocr]
+lang = "eng+deu"
+min_confidence = 60
+
+[subtitles]
+avoid_forced = false
+"#,
+        )
+        .unwrap();
+        assert_eq!(file.settings.jobs, Some(4));
+
+        let env = env_settings(|name| match name {
+            "EPISODE_MATCHER_JOBS" => Some("8".to_string()),
+            "EPISODE_MATCHER_OCR_BACKEND" => Some("Tesseract".to_string()),
+            "EPISODE_MATCHER_CACHE_BACKEND" => Some("json".to_string()),
+            "EPISODE_MATCHER_SUBTITLES_SDH" => Some(String::new()),
+            _ => None,
+        })
+        .unwrap();
+        assert!(env.subtitles.sdh.is_none());
+        let series = SeriesConfig {
+            ocr_lang: Some("jpn".to_string()),
+            ..SeriesConfig::default()
+        }
+        .settings();
+        let cli = SettingsLayer {
+            name_template: Some("jellyfin".to_string()),
+            ..SettingsLayer::default()
+        };
+
+        let settings = Settings::resolve(&[&file.settings, &series, &env, &cli]);
+        assert_eq!(settings.provider, Provider::Tvmaze);
+        assert_eq!(settings.name_template, "jellyfin");
+        assert_eq!(settings.jobs, 8);
+        assert_eq!(settings.cache_backend, CacheBackend::Json);
+        assert_eq!(settings.ocr.backend, OcrBackend::Tesseract);
+        assert_eq!(settings.ocr.lang, "jpn");
+        assert_eq!(settings.ocr.min_confidence, 60);
+        assert!(!settings.subtitles.avoid_forced);
+
+        let defaults = Settings::resolve(&[]);
+        assert_eq!(defaults.provider, Provider::Tvdb);
+        assert_eq!(defaults.name_template, "default");
+        assert_eq!((defaults.jobs, defaults.ocr.lang.as_str()), (1, "eng"));
+        assert_eq!(defaults.subtitles.language, "eng");
+        assert!(defaults.subtitles.avoid_forced);
+
+        assert!(env_settings(|_| Some("many".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_starter_config() {
+        let config: ConfigFile = toml::from_str(STARTER_CONFIG).unwrap();
+        assert!(config.settings.jobs.is_none());
+
+        // Every setting it lists is valid once uncommented
+        let uncommented: Vec<&str> = STARTER_CONFIG
+            .lines()
+            .map(|line| match line.strip_prefix("# ") {
+                Some(setting) if setting.starts_with('[') || setting.contains(" = ") => setting,
+                _ => line,
+            })
+            .collect();
+        let config: ConfigFile = toml::from_str(&uncommented.join("\n")).unwrap();
+        let settings = Settings::resolve(&[&config.settings]);
+        let defaults = Settings::resolve(&[]);
+        assert_eq!(settings.provider, defaults.provider);
+        assert_eq!(settings.jobs, defaults.jobs);
+        assert_eq!(settings.ocr, defaults.ocr);
+        assert_eq!(settings.subtitles.language, defaults.subtitles.language);
+        assert_eq!(config.cache_ttl_days, Some(DEFAULT_CACHE_TTL_DAYS));
+        assert_eq!(config.prompts.overwrite, ConflictPolicy::Suffix);
+        assert_eq!(config.filenames.replace[&':'], " -");
+        assert_eq!(config.mappings[0].series, "77398");
+        let series = find_series_config(&config, "77398", "The X-Files");
+        assert_eq!(series.match_mode, Some(MatchMode::Subtitles));
+    }
+}
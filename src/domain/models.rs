@@ -1,4 +1,7 @@
+use anyhow::Result;
 use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct EpisodeEntry {
@@ -6,4 +9,204 @@ pub struct EpisodeEntry {
     pub season_number: u64,
     pub episode_number: u64,
     pub name: String,
+    #[serde(default)]
+    pub overview: Option<String>, // plot summary, used to match subtitle text
+    #[serde(default)]
+    pub aired: Option<String>, // first air date as YYYY-MM-DD
+    #[serde(default)]
+    pub runtime: Option<u64>, // minutes
+    #[serde(default)]
+    pub id: Option<u64>, // the provider's episode ID
+    #[serde(default)]
+    pub absolute_number: Option<u64>, // position in the whole series, used by anime releases
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct OcrCandidate {
+    pub code: String,
+    pub frame: String,
+    pub confidence: i32, // lowest tesseract confidence (0-100) of the words the code spans
+}
+
+/// Audio fingerprint of an identified episode, matched against unidentified files of the
+/// same series.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AudioFingerprint {
+    pub season_number: u64,
+    pub episode_number: u64,
+    pub offset: u64, // seconds into the file
+    pub fingerprint: Vec<u32>,
+}
+
+/// A file whose episode is already known, identified by size and modification time
+/// so that a changed file is not mistaken for the one that was matched.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProcessedFile {
+    pub series_id: String,
+    pub size: u64,
+    pub modified: u64, // seconds since the Unix epoch
+    pub episode: EpisodeEntry,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub more_episodes: Vec<EpisodeEntry>, // the following ones of a multi-episode file
+}
+
+impl ProcessedFile {
+    pub fn from_path(path: &Path, series_id: &str, episode: EpisodeEntry) -> Result<Self> {
+        let metadata = path.metadata()?;
+        Ok(Self {
+            series_id: series_id.to_string(),
+            size: metadata.len(),
+            modified: metadata.modified()?.duration_since(UNIX_EPOCH)?.as_secs(),
+            episode,
+            more_episodes: Vec::new(),
+        })
+    }
+
+    /// All episodes of the file, in order.
+    pub fn episodes(&self) -> Vec<EpisodeEntry> {
+        let mut episodes = vec![self.episode.clone()];
+        episodes.extend(self.more_episodes.iter().cloned());
+        episodes
+    }
+
+    /// Whether the file at `path` still looks like the one that was matched.
+    pub fn is_current(&self, path: &Path) -> bool {
+        size_and_modified(path) == Some((self.size, self.modified))
+    }
+}
+
+/// The video and audio of a file as ffprobe reports them, for `--output json` and the
+/// `{resolution}`, `{vcodec}`, `{channels}` and `{hdr}` placeholders of name templates.
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct MediaInfo {
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub video_codec: Option<String>, // ffprobe's name, e.g. `hevc`
+    pub audio_channels: Option<u64>, // of the first audio track
+    pub hdr: bool,
+}
+
+impl MediaInfo {
+    /// `1080p` and the like, the higher of what the width and the height say, so that a
+    /// 1920x800 widescreen video and a 1440x1080 anamorphic one are both 1080p.
+    pub fn resolution(&self) -> Option<String> {
+        let (width, height) = (self.width?, self.height?);
+        let by_width = match width {
+            3800.. => 2160,
+            1900.. => 1080,
+            1260.. => 720,
+            _ => 0,
+        };
+        let by_height = match height {
+            2000.. => 2160,
+            1000.. => 1080,
+            700.. => 720,
+            _ => height,
+        };
+        Some(format!("{}p", by_width.max(by_height)))
+    }
+
+    /// The codec as release names have it, `x265` for HEVC and `x264` for H.264.
+    pub fn codec_label(&self) -> Option<String> {
+        let codec = self.video_codec.as_deref()?;
+        Some(match codec {
+            "hevc" => "x265".to_string(),
+            "h264" => "x264".to_string(),
+            "mpeg2video" => "MPEG2".to_string(),
+            _ => codec.to_uppercase(),
+        })
+    }
+
+    /// `2.0`, `5.1` or `7.1` for the usual layouts, otherwise the number of channels.
+    pub fn channel_layout(&self) -> Option<String> {
+        Some(match self.audio_channels? {
+            1 => "1.0".to_string(),
+            2 => "2.0".to_string(),
+            6 => "5.1".to_string(),
+            8 => "7.1".to_string(),
+            channels => channels.to_string(),
+        })
+    }
+}
+
+/// The media info of a file with the size and modification time it was probed at, so that
+/// a replaced file is probed again.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProbedMedia {
+    pub size: u64,
+    pub modified: u64, // seconds since the Unix epoch
+    pub info: MediaInfo,
+}
+
+impl ProbedMedia {
+    pub fn from_path(path: &Path, info: MediaInfo) -> Option<Self> {
+        let (size, modified) = size_and_modified(path)?;
+        Some(Self {
+            size,
+            modified,
+            info,
+        })
+    }
+
+    pub fn is_current(&self, path: &Path) -> bool {
+        size_and_modified(path) == Some((self.size, self.modified))
+    }
+}
+
+/// A media file of a library as `scan` cataloged it, with the episodes its path names.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CatalogEntry {
+    pub path: PathBuf,
+    pub show: Option<String>, // from `<Show>/Season XX/` or the name before `S01E03`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub series_id: Option<String>, // if a run matched the file
+    pub season: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub episodes: Vec<u64>, // several for a multi-episode file
+    pub size: u64,
+    pub modified: u64,    // seconds since the Unix epoch
+    pub checksum: String, // XXH3 of the content in hex
+}
+
+impl CatalogEntry {
+    /// Whether the file at the entry's path is still the one that was cataloged.
+    pub fn is_current(&self) -> bool {
+        size_and_modified(&self.path) == Some((self.size, self.modified))
+    }
+}
+
+/// The size of the file and when it was last modified, in seconds since the Unix epoch.
+pub fn size_and_modified(path: &Path) -> Option<(u64, u64)> {
+    let metadata = path.metadata().ok()?;
+    let modified = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), modified.as_secs()))
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct JournalEntry {
+    pub timestamp: u64, // seconds since the Unix epoch
+    #[serde(default)]
+    pub run_id: u64, // see `journal::new_run_id`, groups the renames of a run for undo
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub series_id: String,
+    pub season_number: u64,
+    pub episode_number: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_episode_number: Option<u64>, // of a multi-episode file
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub kept_original: bool, // linked or copied, undoing deletes the new file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub checksum: Option<String>, // XXH3 in hex of a copy that was verified
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub imported: bool, // from another tool's history by `import`, not renamed by this one
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub created: bool, // written by the run next to the file, like an NFO, undoing deletes it
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
 }
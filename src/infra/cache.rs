@@ -1,23 +1,123 @@
-use crate::config::get_cache_path;
-use crate::domain::models::EpisodeEntry;
+use super::cache_db::CacheDb;
+use crate::config::{get_cache_db_path, get_cache_path, CacheBackend};
+use crate::domain::models::{
+    AudioFingerprint, EpisodeEntry, MediaInfo, ProbedMedia, ProcessedFile,
+};
 use anyhow::Result;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
+use std::path::Path;
 
 #[derive(Debug, Serialize, Deserialize, Default)]
 pub struct Cache {
     pub series: HashMap<String, String>, // series_id -> series_name
     pub episodes_by_production_code: HashMap<String, HashMap<String, EpisodeEntry>>, // series_id -> production_code -> episode_info
     pub episodes_by_sxxexx: HashMap<String, HashMap<u64, HashMap<u64, EpisodeEntry>>>, // series_id -> season_number -> episode_number -> episode_info
+    #[serde(skip)]
+    episodes_by_aired: HashMap<String, HashMap<String, Vec<(u64, u64)>>>, // series_id -> YYYY-MM-DD -> season and episode numbers
+    #[serde(default)]
+    pub processed_files: HashMap<String, ProcessedFile>, // file_path -> match result
+    #[serde(default)]
+    pub media_info: HashMap<String, ProbedMedia>, // file_path -> what ffprobe reported
+    #[serde(default)]
+    pub fingerprints: HashMap<String, Vec<AudioFingerprint>>, // series_id -> identified episodes
+    #[serde(default)]
+    pub fetched: HashMap<String, u64>, // series_id -> when its episodes were fetched (Unix time)
+    #[serde(default)]
+    pub show_choices: HashMap<String, String>, // searched show name -> series_id chosen
+    #[serde(skip)]
+    db: Option<CacheDb>, // every change is written through to it
+}
+
+/// The series and episodes of a cache, as written by `cache export`.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct CacheExport {
+    pub series: BTreeMap<String, ExportedSeries>, // series_id -> its metadata
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExportedSeries {
+    pub name: Option<String>,
+    pub episodes: Vec<EpisodeEntry>,
+    pub fetched: Option<u64>, // Unix time
 }
 
 impl Cache {
-    pub fn load() -> Self {
-        let cache_path = get_cache_path();
+    /// Opens the SQLite cache, moving the contents of an old `cache.json` into it on first use,
+    /// or reads `cache.json` for the JSON backend, moving the SQLite cache into it on the
+    /// first use after switching back.
+    pub fn load(backend: CacheBackend) -> Self {
+        if backend == CacheBackend::Json {
+            let (json_path, db_path) = (get_cache_path(), get_cache_db_path());
+            println!("Using cache path: {}", json_path.display());
+            if !json_path.exists() && db_path.exists() {
+                match Self::move_to_json(&db_path, &json_path) {
+                    Ok(cache) => return cache,
+                    Err(e) => eprintln!(
+                        "Warning: Failed to move the cache from {db_path:?} to {json_path:?}: {e}"
+                    ),
+                }
+            }
+            return Self::load_json();
+        }
+        let db_path = get_cache_db_path();
+        println!("Using cache path: {}", db_path.display());
+        match Self::open(&db_path) {
+            Ok(cache) => cache,
+            Err(e) => {
+                eprintln!("Warning: Failed to open the cache {db_path:?}, using cache.json: {e}");
+                // The database was made from the migrated cache.json, which is all there is
+                // to go on without it
+                let json_path = get_cache_path();
+                let migrated = json_path.with_extension("json.migrated");
+                if json_path.exists() || !migrated.exists() {
+                    Self::read_json(&json_path)
+                } else {
+                    Self::read_json(&migrated)
+                }
+            }
+        }
+    }
+
+    fn open(db_path: &Path) -> Result<Self> {
+        if let Some(parent) = db_path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let db = CacheDb::open(db_path)?;
+
+        let json_path = get_cache_path();
+        if db.is_empty()? && json_path.exists() {
+            println!("Moving the cache from {json_path:?} to {db_path:?}");
+            db.import(&Self::load_json())?;
+            fs::rename(&json_path, json_path.with_extension("json.migrated"))?;
+        }
+
+        let mut cache = db.load()?;
+        cache.db = Some(db);
+        Ok(cache)
+    }
+
+    /// Writes the SQLite cache to `json_path` and keeps the database as `cache.db.migrated`,
+    /// the way [`Cache::open`] keeps `cache.json`, so that neither backend is left behind
+    /// with stale data when the other one is used.
+    fn move_to_json(db_path: &Path, json_path: &Path) -> Result<Self> {
+        println!("Moving the cache from {db_path:?} to {json_path:?}");
+        let cache = CacheDb::open(db_path)?.load()?;
+        cache.write_json(json_path)?;
+        fs::rename(db_path, db_path.with_extension("db.migrated"))?;
+        Ok(cache)
+    }
+
+    fn load_json() -> Self {
+        Self::read_json(&get_cache_path())
+    }
+
+    fn read_json(cache_path: &Path) -> Self {
         if cache_path.exists() {
-            if let Ok(content) = fs::read_to_string(&cache_path) {
-                if let Ok(cache) = serde_json::from_str(&content) {
+            if let Ok(content) = fs::read_to_string(cache_path) {
+                if let Ok(mut cache) = serde_json::from_str::<Cache>(&content) {
+                    cache.index_aired();
                     return cache;
                 }
             }
@@ -25,16 +125,26 @@ impl Cache {
         Cache::default()
     }
 
+    /// Writes the cache to `cache.json`, unless the database backs it and already has
+    /// every change.
     pub fn save(&self) -> Result<()> {
-        let cache_path = get_cache_path();
+        if self.db.is_some() {
+            return Ok(());
+        }
+        self.write_json(&get_cache_path())
+    }
 
+    fn write_json(&self, cache_path: &Path) -> Result<()> {
         // Create parent directory if it doesn't exist
         if let Some(parent) = cache_path.parent() {
             fs::create_dir_all(parent)?;
         }
 
+        // Written to a temporary file first, an interrupted save must not lose the cache
         let content = serde_json::to_string_pretty(self)?;
-        fs::write(&cache_path, content)?;
+        let temp_path = cache_path.with_extension("json.tmp");
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, cache_path)?;
         Ok(())
     }
 
@@ -43,6 +153,7 @@ impl Cache {
     }
 
     pub fn set_series_name(&mut self, series_id: String, name: String) {
+        self.persist(|db| db.set_series_name(&series_id, &name));
         self.series.insert(series_id, name);
     }
 
@@ -67,7 +178,68 @@ impl Cache {
         })
     }
 
+    pub fn get_episode_by_absolute(
+        &self,
+        series_id: &str,
+        absolute_number: u64,
+    ) -> Option<&EpisodeEntry> {
+        self.get_series_episodes(series_id)
+            .into_iter()
+            .find(|episode| episode.absolute_number == Some(absolute_number))
+    }
+
+    /// The episodes of the series that aired on the day (YYYY-MM-DD), several for a double
+    /// bill.
+    pub fn get_episodes_by_aired(&self, series_id: &str, aired: &str) -> Vec<&EpisodeEntry> {
+        let Some(numbers) = self
+            .episodes_by_aired
+            .get(series_id)
+            .and_then(|dates| dates.get(aired))
+        else {
+            return Vec::new();
+        };
+        // Entries of episodes whose date changed since are left in the index
+        let mut episodes: Vec<&EpisodeEntry> = numbers
+            .iter()
+            .filter_map(|&(season, episode)| self.get_episode_by_sxxexx(series_id, season, episode))
+            .filter(|episode| episode.aired.as_deref() == Some(aired))
+            .collect();
+        episodes.sort_by_key(|episode| (episode.season_number, episode.episode_number));
+        episodes.dedup_by_key(|episode| (episode.season_number, episode.episode_number));
+        episodes
+    }
+
+    /// Indexes the episodes by air date, which `cache.json` doesn't store.
+    fn index_aired(&mut self) {
+        for (series_id, seasons) in &self.episodes_by_sxxexx {
+            for episode in seasons.values().flat_map(|episodes| episodes.values()) {
+                if let Some(aired) = &episode.aired {
+                    self.episodes_by_aired
+                        .entry(series_id.clone())
+                        .or_default()
+                        .entry(aired.clone())
+                        .or_default()
+                        .push((episode.season_number, episode.episode_number));
+                }
+            }
+        }
+    }
+
+    /// All cached episodes of the series, in no particular order.
+    pub fn get_series_episodes(&self, series_id: &str) -> Vec<&EpisodeEntry> {
+        self.episodes_by_sxxexx
+            .get(series_id)
+            .map(|seasons| {
+                seasons
+                    .values()
+                    .flat_map(|episodes| episodes.values())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
     pub fn set_episode(&mut self, series_id: &str, episode: &EpisodeEntry) {
+        self.persist(|db| db.set_episode(series_id, episode));
         // Store in lowercase for case-insensitive lookup
         if let Some(key) = episode
             .clone()
@@ -79,6 +251,14 @@ impl Cache {
                 .or_default()
                 .insert(key.clone(), episode.clone());
         }
+        if let Some(aired) = &episode.aired {
+            self.episodes_by_aired
+                .entry(series_id.to_string())
+                .or_default()
+                .entry(aired.clone())
+                .or_default()
+                .push((episode.season_number, episode.episode_number));
+        }
         self.episodes_by_sxxexx
             .entry(series_id.to_string())
             .or_default()
@@ -87,6 +267,184 @@ impl Cache {
             .insert(episode.episode_number, episode.clone());
     }
 
+    /// Replaces the cached episodes of the series with freshly fetched ones, so episodes the
+    /// provider removed or renumbered don't linger.
+    pub fn replace_series_episodes(
+        &mut self,
+        series_id: &str,
+        episodes: &[EpisodeEntry],
+        fetched: u64,
+    ) {
+        self.episodes_by_production_code.remove(series_id);
+        self.episodes_by_sxxexx.remove(series_id);
+        self.episodes_by_aired.remove(series_id);
+        self.persist(|db| db.remove_series_episodes(series_id));
+        for episode in episodes {
+            self.set_episode(series_id, episode);
+        }
+        self.fetched.insert(series_id.to_string(), fetched);
+        self.persist(|db| db.set_fetched(series_id, fetched));
+    }
+
+    /// Whether the episodes of the series were fetched more than `ttl_days` ago, or by a
+    /// version that didn't record when. A TTL of 0 never expires them.
+    pub fn is_expired(&self, series_id: &str, ttl_days: u64, now: u64) -> bool {
+        if ttl_days == 0 {
+            return false;
+        }
+        match self.fetched.get(series_id) {
+            Some(fetched) => now.saturating_sub(*fetched) > ttl_days * 24 * 60 * 60,
+            None => true,
+        }
+    }
+
+    /// The series and episodes of `series_ids`, or of every series if it's empty.
+    pub fn export_metadata(&self, series_ids: &[String]) -> CacheExport {
+        let mut export = CacheExport::default();
+        let cached = self.series.keys().chain(self.episodes_by_sxxexx.keys());
+        for series_id in cached {
+            if !series_ids.is_empty() && !series_ids.contains(series_id) {
+                continue;
+            }
+            let mut episodes: Vec<EpisodeEntry> = self
+                .get_series_episodes(series_id)
+                .into_iter()
+                .cloned()
+                .collect();
+            episodes.sort_by_key(|episode| (episode.season_number, episode.episode_number));
+            export.series.insert(
+                series_id.clone(),
+                ExportedSeries {
+                    name: self.series.get(series_id).cloned(),
+                    episodes,
+                    fetched: self.fetched.get(series_id).copied(),
+                },
+            );
+        }
+        export
+    }
+
+    /// Adds the exported series of `series_ids`, or all of them if it's empty. Episodes
+    /// fetched more recently than the exported ones are kept. Returns the number of series
+    /// imported.
+    pub fn import_metadata(&mut self, export: CacheExport, series_ids: &[String]) -> usize {
+        let mut imported = 0;
+        for (series_id, series) in export.series {
+            if !series_ids.is_empty() && !series_ids.contains(&series_id) {
+                continue;
+            }
+            if let Some(name) = series.name {
+                self.set_series_name(series_id.clone(), name);
+            }
+            // Without a fetch time the episodes count as expired, see `is_expired`
+            let fetched = series.fetched.unwrap_or(0);
+            let newer_here = self.has_series_episodes(&series_id)
+                && self
+                    .fetched
+                    .get(&series_id)
+                    .is_some_and(|&here| here > fetched);
+            if !series.episodes.is_empty() && !newer_here {
+                self.replace_series_episodes(&series_id, &series.episodes, fetched);
+            }
+            imported += 1;
+        }
+        imported
+    }
+
+    /// Deletes the series and episodes, which are fetched again when next needed.
+    pub fn clear_metadata(&mut self) {
+        self.series.clear();
+        self.episodes_by_production_code.clear();
+        self.episodes_by_sxxexx.clear();
+        self.episodes_by_aired.clear();
+        self.fetched.clear();
+        self.persist(CacheDb::clear_metadata);
+    }
+
+    /// Deletes what earlier runs learned: the episodes and media info of files, the audio
+    /// fingerprints and the shows chosen for searches.
+    pub fn clear_history(&mut self) {
+        self.processed_files.clear();
+        self.media_info.clear();
+        self.fingerprints.clear();
+        self.show_choices.clear();
+        self.persist(CacheDb::clear_history);
+    }
+
+    /// The series chosen when the show name was last searched. Names are kept as given,
+    /// callers normalize them and tell apart those of different providers.
+    pub fn get_show_choice(&self, query: &str) -> Option<&String> {
+        self.show_choices.get(query)
+    }
+
+    pub fn set_show_choice(&mut self, query: String, series_id: String) {
+        self.persist(|db| db.set_show_choice(&query, &series_id));
+        self.show_choices.insert(query, series_id);
+    }
+
+    /// Forgets the series chosen for the show name, returns whether there was one.
+    pub fn forget_show_choice(&mut self, query: &str) -> bool {
+        self.persist(|db| db.remove_show_choice(query));
+        self.show_choices.remove(query).is_some()
+    }
+
+    pub fn get_processed_file(&self, path: &Path) -> Option<&ProcessedFile> {
+        self.processed_files.get(path.to_string_lossy().as_ref())
+    }
+
+    pub fn set_processed_file(&mut self, path: &Path, processed: ProcessedFile) {
+        self.persist(|db| db.set_processed_file(path, &processed));
+        self.processed_files
+            .insert(path.to_string_lossy().into_owned(), processed);
+    }
+
+    /// The media info of the file, unless it changed since it was probed.
+    pub fn get_media_info(&self, path: &Path) -> Option<&MediaInfo> {
+        self.media_info
+            .get(path.to_string_lossy().as_ref())
+            .filter(|probed| probed.is_current(path))
+            .map(|probed| &probed.info)
+    }
+
+    pub fn set_media_info(&mut self, path: &Path, probed: ProbedMedia) {
+        self.persist(|db| db.set_media_info(path, &probed));
+        self.media_info
+            .insert(path.to_string_lossy().into_owned(), probed);
+    }
+
+    pub fn get_fingerprints(&self, series_id: &str) -> &[AudioFingerprint] {
+        self.fingerprints
+            .get(series_id)
+            .map(Vec::as_slice)
+            .unwrap_or_default()
+    }
+
+    /// Stores the fingerprint, replacing an older one of the same episode and offset.
+    pub fn add_fingerprint(&mut self, series_id: &str, fingerprint: AudioFingerprint) {
+        self.persist(|db| db.set_fingerprint(series_id, &fingerprint));
+        let fingerprints = self.fingerprints.entry(series_id.to_string()).or_default();
+        fingerprints.retain(|existing| {
+            (
+                existing.season_number,
+                existing.episode_number,
+                existing.offset,
+            ) != (
+                fingerprint.season_number,
+                fingerprint.episode_number,
+                fingerprint.offset,
+            )
+        });
+        fingerprints.push(fingerprint);
+    }
+
+    fn persist(&self, write: impl FnOnce(&CacheDb) -> Result<()>) {
+        if let Some(db) = &self.db {
+            if let Err(e) = write(db) {
+                eprintln!("Warning: Failed to write to the cache database: {e}");
+            }
+        }
+    }
+
     pub fn has_series_episodes(&self, series_id: &str) -> bool {
         // Check if we have any episodes cached for this series
         self.episodes_by_production_code.contains_key(series_id)
@@ -98,6 +456,92 @@ impl Cache {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_move_to_json() {
+        let dir = tempfile::tempdir().unwrap();
+        let (db_path, json_path) = (dir.path().join("cache.db"), dir.path().join("cache.json"));
+        let db = CacheDb::open(&db_path).unwrap();
+        db.set_series_name("77398", "The X-Files").unwrap();
+        drop(db);
+
+        let cache = Cache::move_to_json(&db_path, &json_path).unwrap();
+        assert_eq!(cache.get_series_name("77398").unwrap(), "The X-Files");
+        // The database is kept aside, switching back to it moves cache.json in again
+        assert!(!db_path.exists() && dir.path().join("cache.db.migrated").exists());
+        let json: Cache = serde_json::from_str(&fs::read_to_string(&json_path).unwrap()).unwrap();
+        assert_eq!(json.get_series_name("77398").unwrap(), "The X-Files");
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let episode = |episode_number, production_code: Option<&str>| EpisodeEntry {
+            production_code: production_code.map(str::to_string),
+            season_number: 1,
+            episode_number,
+            name: format!("Episode {episode_number}"),
+            overview: Some("Mulder and Scully".to_string()),
+            aired: Some("1993-09-10".to_string()),
+            runtime: Some(45),
+            id: Some(episode_number),
+            absolute_number: Some(episode_number),
+        };
+        let mut cache = Cache::default();
+        cache.set_series_name("77398".to_string(), "The X-Files".to_string());
+        cache.set_episode("77398", &episode(1, Some("1X79")));
+        cache.set_episode("77398", &episode(2, None));
+        cache.set_processed_file(
+            Path::new("/tv/a.mkv"),
+            ProcessedFile {
+                series_id: "77398".to_string(),
+                size: 1024,
+                modified: 1_700_000_000,
+                episode: episode(1, Some("1X79")),
+                more_episodes: vec![episode(2, None)],
+            },
+        );
+        cache.set_media_info(
+            Path::new("/tv/a.mkv"),
+            ProbedMedia {
+                size: 1024,
+                modified: 1_700_000_000,
+                info: MediaInfo {
+                    width: Some(1920),
+                    height: Some(1080),
+                    video_codec: Some("hevc".to_string()),
+                    audio_channels: Some(6),
+                    hdr: true,
+                },
+            },
+        );
+        cache.add_fingerprint(
+            "77398",
+            AudioFingerprint {
+                season_number: 1,
+                episode_number: 1,
+                offset: 60,
+                fingerprint: vec![1, 2, 3],
+            },
+        );
+        cache.fetched.insert("77398".to_string(), 1_700_000_000);
+        cache.set_show_choice("the x-files".to_string(), "77398".to_string());
+
+        let dir = tempfile::tempdir().unwrap();
+        let json_path = dir.path().join("cache.json");
+        cache.write_json(&json_path).unwrap();
+        let from_json = Cache::read_json(&json_path);
+        let db = CacheDb::open_in_memory().unwrap();
+        db.import(&cache).unwrap();
+        let from_db = db.load().unwrap();
+
+        let value = |cache: &Cache| serde_json::to_value(cache).unwrap();
+        assert_eq!(value(&from_json), value(&cache));
+        assert_eq!(value(&from_json), value(&from_db));
+        assert_eq!(
+            from_json.get_episodes_by_aired("77398", "1993-09-10").len(),
+            2
+        );
+    }
+
     #[test]
     fn test_cache_operations() {
         let mut cache = Cache::default();
@@ -116,6 +560,11 @@ mod tests {
             season_number: 1,
             episode_number: 1,
             name: "Pilot".to_string(),
+            overview: None,
+            aired: Some("1993-09-10".to_string()),
+            runtime: None,
+            id: None,
+            absolute_number: Some(1),
         };
 
         cache.set_episode(series_id, &episode);
@@ -130,6 +579,32 @@ mod tests {
         assert!(retrieved.is_some());
         assert_eq!(retrieved.unwrap().name, "Pilot");
 
+        // Check retrieval by absolute number
+        assert_eq!(
+            cache.get_episode_by_absolute(series_id, 1).unwrap().name,
+            "Pilot"
+        );
+        assert!(cache.get_episode_by_absolute(series_id, 2).is_none());
+
+        // Check retrieval by air date, which follows changes of the date
+        assert_eq!(
+            cache.get_episodes_by_aired(series_id, "1993-09-10")[0].name,
+            "Pilot"
+        );
+        let moved = EpisodeEntry {
+            aired: Some("1993-09-17".to_string()),
+            ..episode.clone()
+        };
+        cache.set_episode(series_id, &moved);
+        assert!(cache
+            .get_episodes_by_aired(series_id, "1993-09-10")
+            .is_empty());
+        assert_eq!(
+            cache.get_episodes_by_aired(series_id, "1993-09-17").len(),
+            1
+        );
+        cache.set_episode(series_id, &episode);
+
         // Check retrieval by SxxExx
         let retrieved = cache.get_episode_by_sxxexx(series_id, 1, 1);
         assert!(retrieved.is_some());
@@ -138,5 +613,122 @@ mod tests {
         // Check has_series_episodes
         assert!(cache.has_series_episodes(series_id));
         assert!(!cache.has_series_episodes("other_id"));
+
+        // Check processed files
+        let path = Path::new("/library/Test Show - S01E01 - Pilot.mkv");
+        assert!(cache.get_processed_file(path).is_none());
+        cache.set_processed_file(
+            path,
+            ProcessedFile {
+                series_id: series_id.to_string(),
+                size: 1024,
+                modified: 1_700_000_000,
+                episode: episode.clone(),
+                more_episodes: Vec::new(),
+            },
+        );
+        let processed = cache.get_processed_file(path).unwrap();
+        assert_eq!(processed.episode.name, "Pilot");
+        assert_eq!(processed.episodes().len(), 1);
+        assert_eq!(processed.size, 1024);
+
+        // Check fingerprints, a newer one replaces the old one of the same episode and offset
+        let fingerprint = |offset, items: Vec<u32>| AudioFingerprint {
+            season_number: 1,
+            episode_number: 1,
+            offset,
+            fingerprint: items,
+        };
+        cache.add_fingerprint(series_id, fingerprint(60, vec![1, 2]));
+        cache.add_fingerprint(series_id, fingerprint(300, vec![3]));
+        cache.add_fingerprint(series_id, fingerprint(60, vec![4]));
+        let fingerprints = cache.get_fingerprints(series_id);
+        assert_eq!(fingerprints.len(), 2);
+        assert_eq!(fingerprints[1].fingerprint, vec![4]);
+        assert!(cache.get_fingerprints("other_id").is_empty());
+    }
+
+    #[test]
+    fn test_refresh_series_episodes() {
+        let day = 24 * 60 * 60;
+        let episode = |episode_number, name: &str| EpisodeEntry {
+            production_code: Some(format!("1X0{episode_number}")),
+            season_number: 1,
+            episode_number,
+            name: name.to_string(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        };
+        let mut cache = Cache::default();
+        cache.set_episode("77398", &episode(1, "Pilot"));
+        cache.set_episode("77398", &episode(9, "Tooms (Unaired)"));
+
+        // Caches of older versions don't know when they were fetched
+        assert!(cache.is_expired("77398", 7, 100 * day));
+        assert!(!cache.is_expired("77398", 0, 100 * day));
+
+        let fetched = [episode(1, "Pilot"), episode(2, "Deep Throat")];
+        cache.replace_series_episodes("77398", &fetched, 100 * day);
+        assert_eq!(cache.get_series_episodes("77398").len(), 2);
+        assert!(cache.get_episode("77398", "1x09").is_none());
+        assert!(!cache.is_expired("77398", 7, 107 * day));
+        assert!(cache.is_expired("77398", 7, 108 * day));
+
+        cache.clear_metadata();
+        assert!(!cache.has_series_episodes("77398"));
+    }
+
+    #[test]
+    fn test_export_and_import_metadata() {
+        let episode = |episode_number, name: &str| EpisodeEntry {
+            production_code: Some(format!("1X0{episode_number}")),
+            season_number: 1,
+            episode_number,
+            name: name.to_string(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        };
+        let mut desktop = Cache::default();
+        desktop.set_series_name("77398".to_string(), "The X-Files".to_string());
+        desktop.replace_series_episodes(
+            "77398",
+            &[episode(2, "Deep Throat"), episode(1, "Pilot")],
+            200,
+        );
+        desktop.set_series_name("tvmaze:83".to_string(), "The Simpsons".to_string());
+        desktop.replace_series_episodes("tvmaze:83", &[episode(1, "Simpsons Roasting")], 200);
+
+        let export = desktop.export_metadata(&["77398".to_string()]);
+        assert_eq!(export.series.len(), 1);
+        assert_eq!(export.series["77398"].episodes[0].name, "Pilot");
+        let export = desktop.export_metadata(&[]);
+        assert_eq!(export.series.len(), 2);
+
+        // Round trip through the file format
+        let export: CacheExport =
+            serde_json::from_str(&serde_json::to_string(&export).unwrap()).unwrap();
+        let mut nas = Cache::default();
+        nas.replace_series_episodes("tvmaze:83", &[episode(1, "Newer Name")], 300);
+        assert_eq!(nas.import_metadata(export, &[]), 2);
+        assert_eq!(
+            nas.get_series_name("77398"),
+            Some(&"The X-Files".to_string())
+        );
+        assert_eq!(
+            nas.get_episode("77398", "1x02").unwrap().name,
+            "Deep Throat"
+        );
+        assert!(!nas.is_expired("77398", 7, 200));
+        // Episodes fetched here after the export are kept
+        assert_eq!(
+            nas.get_episode("tvmaze:83", "1x01").unwrap().name,
+            "Newer Name"
+        );
     }
 }
@@ -0,0 +1,369 @@
+use anyhow::Result;
+use rusqlite::{params, Connection};
+use std::path::Path;
+
+use super::cache::Cache;
+use crate::domain::models::{AudioFingerprint, EpisodeEntry, ProbedMedia, ProcessedFile};
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS series (
+        id TEXT PRIMARY KEY,
+        name TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS episodes (
+        series_id TEXT NOT NULL,
+        season_number INTEGER NOT NULL,
+        episode_number INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (series_id, season_number, episode_number)
+    );
+    CREATE TABLE IF NOT EXISTS processed_files (
+        path TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS media_info (
+        path TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS fingerprints (
+        series_id TEXT NOT NULL,
+        season_number INTEGER NOT NULL,
+        episode_number INTEGER NOT NULL,
+        offset_secs INTEGER NOT NULL,
+        data TEXT NOT NULL,
+        PRIMARY KEY (series_id, season_number, episode_number, offset_secs)
+    );
+    CREATE TABLE IF NOT EXISTS fetched (
+        series_id TEXT PRIMARY KEY,
+        fetched_at INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS show_choices (
+        query TEXT PRIMARY KEY,
+        series_id TEXT NOT NULL
+    );
+";
+
+/// SQLite storage of the cache. Every change is written as it's made, instead of the whole
+/// cache at the end of a run. Rows hold their records as JSON, so new fields need no
+/// schema changes.
+#[derive(Debug)]
+pub struct CacheDb {
+    conn: Connection,
+}
+
+impl CacheDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        // The write-ahead log makes the many small writes cheap
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.pragma_update(None, "synchronous", "NORMAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        let series: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM series", [], |row| row.get(0))?;
+        let episodes: i64 = self
+            .conn
+            .query_row("SELECT COUNT(*) FROM episodes", [], |row| row.get(0))?;
+        Ok(series == 0 && episodes == 0)
+    }
+
+    /// Reads everything into an in-memory cache, which isn't attached to the database yet.
+    pub fn load(&self) -> Result<Cache> {
+        let mut cache = Cache::default();
+
+        let mut statement = self.conn.prepare("SELECT id, name FROM series")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (id, name) = row?;
+            cache.set_series_name(id, name);
+        }
+
+        let mut statement = self.conn.prepare("SELECT series_id, data FROM episodes")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (series_id, data) = row?;
+            let episode: EpisodeEntry = serde_json::from_str(&data)?;
+            cache.set_episode(&series_id, &episode);
+        }
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT path, data FROM processed_files")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (path, data) = row?;
+            let processed: ProcessedFile = serde_json::from_str(&data)?;
+            cache.set_processed_file(Path::new(&path), processed);
+        }
+
+        let mut statement = self.conn.prepare("SELECT path, data FROM media_info")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (path, data) = row?;
+            let probed: ProbedMedia = serde_json::from_str(&data)?;
+            cache.set_media_info(Path::new(&path), probed);
+        }
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT series_id, data FROM fingerprints")?;
+        let rows = statement.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+        })?;
+        for row in rows {
+            let (series_id, data) = row?;
+            let fingerprint: AudioFingerprint = serde_json::from_str(&data)?;
+            cache.add_fingerprint(&series_id, fingerprint);
+        }
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT series_id, fetched_at FROM fetched")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (series_id, fetched): (String, u64) = row?;
+            cache.fetched.insert(series_id, fetched);
+        }
+
+        let mut statement = self
+            .conn
+            .prepare("SELECT query, series_id FROM show_choices")?;
+        let rows = statement.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            let (query, series_id) = row?;
+            cache.set_show_choice(query, series_id);
+        }
+
+        Ok(cache)
+    }
+
+    /// Writes all of a cache loaded from elsewhere, e.g. the old `cache.json`.
+    pub fn import(&self, cache: &Cache) -> Result<()> {
+        // One transaction instead of one per row, rolled back if anything fails
+        let transaction = self.conn.unchecked_transaction()?;
+        for (id, name) in &cache.series {
+            self.set_series_name(id, name)?;
+        }
+        // Every episode is stored by SXXEXX, the production codes only index them
+        for (series_id, seasons) in &cache.episodes_by_sxxexx {
+            for episode in seasons.values().flat_map(|episodes| episodes.values()) {
+                self.set_episode(series_id, episode)?;
+            }
+        }
+        for (path, processed) in &cache.processed_files {
+            self.set_processed_file(Path::new(path), processed)?;
+        }
+        for (path, probed) in &cache.media_info {
+            self.set_media_info(Path::new(path), probed)?;
+        }
+        for (series_id, fingerprints) in &cache.fingerprints {
+            for fingerprint in fingerprints {
+                self.set_fingerprint(series_id, fingerprint)?;
+            }
+        }
+        for (series_id, fetched) in &cache.fetched {
+            self.set_fetched(series_id, *fetched)?;
+        }
+        for (query, series_id) in &cache.show_choices {
+            self.set_show_choice(query, series_id)?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    pub fn set_series_name(&self, series_id: &str, name: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO series (id, name) VALUES (?1, ?2)",
+            params![series_id, name],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_episode(&self, series_id: &str, episode: &EpisodeEntry) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO episodes (series_id, season_number, episode_number, data)
+             VALUES (?1, ?2, ?3, ?4)",
+            params![
+                series_id,
+                episode.season_number,
+                episode.episode_number,
+                serde_json::to_string(episode)?
+            ],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_series_episodes(&self, series_id: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM episodes WHERE series_id = ?1", [series_id])?;
+        Ok(())
+    }
+
+    pub fn set_fetched(&self, series_id: &str, fetched: u64) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO fetched (series_id, fetched_at) VALUES (?1, ?2)",
+            params![series_id, fetched],
+        )?;
+        Ok(())
+    }
+
+    pub fn clear_metadata(&self) -> Result<()> {
+        self.conn
+            .execute_batch("DELETE FROM series; DELETE FROM episodes; DELETE FROM fetched;")?;
+        Ok(())
+    }
+
+    pub fn clear_history(&self) -> Result<()> {
+        self.conn.execute_batch(
+            "DELETE FROM processed_files; DELETE FROM media_info; DELETE FROM fingerprints;
+             DELETE FROM show_choices;",
+        )?;
+        Ok(())
+    }
+
+    pub fn set_show_choice(&self, query: &str, series_id: &str) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO show_choices (query, series_id) VALUES (?1, ?2)",
+            params![query, series_id],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove_show_choice(&self, query: &str) -> Result<()> {
+        self.conn
+            .execute("DELETE FROM show_choices WHERE query = ?1", [query])?;
+        Ok(())
+    }
+
+    pub fn set_processed_file(&self, path: &Path, processed: &ProcessedFile) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO processed_files (path, data) VALUES (?1, ?2)",
+            params![path.to_string_lossy(), serde_json::to_string(processed)?],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_media_info(&self, path: &Path, probed: &ProbedMedia) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO media_info (path, data) VALUES (?1, ?2)",
+            params![path.to_string_lossy(), serde_json::to_string(probed)?],
+        )?;
+        Ok(())
+    }
+
+    pub fn set_fingerprint(&self, series_id: &str, fingerprint: &AudioFingerprint) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO fingerprints
+             (series_id, season_number, episode_number, offset_secs, data)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                series_id,
+                fingerprint.season_number,
+                fingerprint.episode_number,
+                fingerprint.offset,
+                serde_json::to_string(fingerprint)?
+            ],
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::MediaInfo;
+
+    #[test]
+    fn test_import_and_load() {
+        let episode = EpisodeEntry {
+            production_code: Some("1X03".to_string()),
+            season_number: 1,
+            episode_number: 3,
+            name: "Squeeze".to_string(),
+            overview: None,
+            aired: None,
+            runtime: Some(45),
+            id: Some(184601),
+            absolute_number: Some(3),
+        };
+        let mut cache = Cache::default();
+        cache.set_series_name("77398".to_string(), "The X-Files".to_string());
+        cache.set_episode("77398", &episode);
+        cache.add_fingerprint(
+            "77398",
+            AudioFingerprint {
+                season_number: 1,
+                episode_number: 3,
+                offset: 60,
+                fingerprint: vec![1, 2, 3],
+            },
+        );
+
+        cache.media_info.insert(
+            "/tv/squeeze.mkv".to_string(),
+            ProbedMedia {
+                size: 1,
+                modified: 2,
+                info: MediaInfo {
+                    width: Some(1920),
+                    ..MediaInfo::default()
+                },
+            },
+        );
+
+        let db = CacheDb::open_in_memory().unwrap();
+        assert!(db.is_empty().unwrap());
+        db.import(&cache).unwrap();
+        assert!(!db.is_empty().unwrap());
+
+        // Writes replace the row of the same episode
+        let renamed = EpisodeEntry {
+            name: "Squeeze (Remastered)".to_string(),
+            ..episode
+        };
+        db.set_episode("77398", &renamed).unwrap();
+        db.set_show_choice("the x-files", "77398").unwrap();
+
+        let loaded = db.load().unwrap();
+        assert_eq!(
+            loaded.get_series_name("77398"),
+            Some(&"The X-Files".to_string())
+        );
+        assert_eq!(
+            loaded.get_episode("77398", "1x03").unwrap().name,
+            "Squeeze (Remastered)"
+        );
+        assert_eq!(loaded.get_series_episodes("77398").len(), 1);
+        assert_eq!(
+            loaded.get_fingerprints("77398")[0].fingerprint,
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            loaded.get_show_choice("the x-files"),
+            Some(&"77398".to_string())
+        );
+
+        assert_eq!(loaded.media_info["/tv/squeeze.mkv"].info.width, Some(1920));
+
+        db.remove_show_choice("the x-files").unwrap();
+        assert!(db.load().unwrap().get_show_choice("the x-files").is_none());
+    }
+}
@@ -0,0 +1,83 @@
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension, Transaction};
+use std::path::Path;
+
+use crate::domain::models::CatalogEntry;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS files (
+        path TEXT PRIMARY KEY,
+        data TEXT NOT NULL
+    );
+";
+
+/// SQLite storage of the library catalog `scan` builds, apart from the cache so that
+/// clearing one keeps the other. Rows hold their entries as JSON, like the cache's.
+#[derive(Debug)]
+pub struct CatalogDb {
+    conn: Connection,
+}
+
+impl CatalogDb {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        Self::init(Connection::open(path)?)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        conn.pragma_update(None, "journal_mode", "WAL")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Writes until the transaction is committed are made at once, or not at all if it's
+    /// dropped.
+    pub fn transaction(&self) -> Result<Transaction<'_>> {
+        Ok(self.conn.unchecked_transaction()?)
+    }
+
+    pub fn get(&self, path: &Path) -> Result<Option<CatalogEntry>> {
+        let data: Option<String> = self
+            .conn
+            .query_row(
+                "SELECT data FROM files WHERE path = ?1",
+                params![path.to_string_lossy()],
+                |row| row.get(0),
+            )
+            .optional()?;
+        Ok(data.map(|data| serde_json::from_str(&data)).transpose()?)
+    }
+
+    pub fn set(&self, entry: &CatalogEntry) -> Result<()> {
+        self.conn.execute(
+            "INSERT OR REPLACE INTO files (path, data) VALUES (?1, ?2)",
+            params![entry.path.to_string_lossy(), serde_json::to_string(entry)?],
+        )?;
+        Ok(())
+    }
+
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        self.conn.execute(
+            "DELETE FROM files WHERE path = ?1",
+            params![path.to_string_lossy()],
+        )?;
+        Ok(())
+    }
+
+    pub fn entries(&self) -> Result<Vec<CatalogEntry>> {
+        let mut statement = self.conn.prepare("SELECT data FROM files ORDER BY path")?;
+        let rows = statement.query_map([], |row| row.get::<_, String>(0))?;
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(serde_json::from_str(&row?)?);
+        }
+        Ok(entries)
+    }
+}
@@ -0,0 +1,166 @@
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use crate::infra::cache::Cache;
+
+/// A user-maintained production code for a series that has none in TVDB.
+#[derive(Debug, PartialEq)]
+struct CodeRow {
+    code: String,
+    season_number: u64,
+    episode_number: u64,
+}
+
+/// Loads a table of production codes and adds them to the cached episodes of the series.
+///
+/// The table is either a TOML file of `"<code>" = "SxxExx"` pairs or a CSV file with
+/// `<code>,SxxExx` rows. Returns the number of episodes that received a code.
+pub fn merge(path: &Path, series_id: &str, cache: &mut Cache) -> Result<usize> {
+    let content = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read production code table {path:?}"))?;
+    let rows = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("toml") => parse_toml(&content)?,
+        Some("csv") => parse_csv(&content)?,
+        _ => bail!("Production code table {path:?} must be a .toml or .csv file"),
+    };
+
+    let mut merged = 0;
+    for row in rows {
+        let Some(episode) = cache
+            .get_episode_by_sxxexx(series_id, row.season_number, row.episode_number)
+            .cloned()
+        else {
+            eprintln!(
+                "Warning: S{}E{} from {path:?} is not in the cache for series {series_id}",
+                row.season_number, row.episode_number
+            );
+            continue;
+        };
+
+        let mut episode = episode;
+        episode.production_code = Some(row.code);
+        cache.set_episode(series_id, &episode);
+        merged += 1;
+    }
+
+    Ok(merged)
+}
+
+fn parse_toml(content: &str) -> Result<Vec<CodeRow>> {
+    let table: HashMap<String, String> = toml::from_str(content)?;
+    table
+        .into_iter()
+        .map(|(code, sxxexx)| code_row(&code, &sxxexx))
+        .collect()
+}
+
+fn parse_csv(content: &str) -> Result<Vec<CodeRow>> {
+    let mut rows = Vec::new();
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((code, sxxexx)) = line.split_once(',') else {
+            bail!("Line {}: expected `<code>,SxxExx`", i + 1);
+        };
+        let (code, sxxexx) = (
+            code.trim().trim_matches('"'),
+            sxxexx.trim().trim_matches('"'),
+        );
+        // Optional header row
+        if i == 0 && parse_sxxexx(sxxexx).is_none() {
+            continue;
+        }
+        rows.push(code_row(code, sxxexx).with_context(|| format!("Line {}", i + 1))?);
+    }
+    Ok(rows)
+}
+
+fn code_row(code: &str, sxxexx: &str) -> Result<CodeRow> {
+    let Some((season_number, episode_number)) = parse_sxxexx(sxxexx) else {
+        bail!("Invalid episode '{sxxexx}' for production code '{code}', expected SxxExx");
+    };
+    Ok(CodeRow {
+        code: code.to_string(),
+        season_number,
+        episode_number,
+    })
+}
+
+fn parse_sxxexx(input: &str) -> Option<(u64, u64)> {
+    let re = Regex::new(r"(?i)^s(\d{1,2})e(\d{1,3})$").unwrap();
+    let caps = re.captures(input.trim())?;
+    Some((caps[1].parse().ok()?, caps[2].parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::EpisodeEntry;
+
+    #[test]
+    fn test_parse_csv() {
+        let content = "code,episode\n# pilot\n101,S01E01\n\"102\", s1e2\n";
+        assert_eq!(
+            parse_csv(content).unwrap(),
+            vec![
+                CodeRow {
+                    code: "101".to_string(),
+                    season_number: 1,
+                    episode_number: 1,
+                },
+                CodeRow {
+                    code: "102".to_string(),
+                    season_number: 1,
+                    episode_number: 2,
+                },
+            ]
+        );
+        assert!(parse_csv("101,S01E01\n102;S01E02\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_toml() {
+        let rows = parse_toml(r#""4ABC01" = "S02E01""#).unwrap();
+        assert_eq!(
+            rows,
+            vec![CodeRow {
+                code: "4ABC01".to_string(),
+                season_number: 2,
+                episode_number: 1,
+            }]
+        );
+        assert!(parse_toml(r#""4ABC01" = "two""#).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("codes.csv");
+        fs::write(&path, "101,S01E01\n999,S09E09\n").unwrap();
+
+        let mut cache = Cache::default();
+        cache.set_episode(
+            "1",
+            &EpisodeEntry {
+                production_code: None,
+                season_number: 1,
+                episode_number: 1,
+                name: "Pilot".to_string(),
+                overview: None,
+                aired: None,
+                runtime: None,
+                id: None,
+                absolute_number: None,
+            },
+        );
+
+        assert_eq!(merge(&path, "1", &mut cache).unwrap(), 1);
+        assert_eq!(cache.get_episode("1", "101").unwrap().name, "Pilot");
+    }
+}
@@ -0,0 +1,98 @@
+use anyhow::Result;
+use reqwest::Client;
+use std::time::Duration;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const USER_AGENT: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+
+/// The HTTP client of a metadata provider. It's kept for all of the provider's requests,
+/// so they reuse pooled connections instead of a new TLS handshake each.
+pub fn client() -> Result<Client> {
+    Ok(Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()?)
+}
+
+/// Like [`client`], for the lookups matchers make while processing a file.
+pub fn blocking_client() -> Result<reqwest::blocking::Client> {
+    Ok(reqwest::blocking::Client::builder()
+        .user_agent(USER_AGENT)
+        .connect_timeout(CONNECT_TIMEOUT)
+        .timeout(REQUEST_TIMEOUT)
+        .build()?)
+}
+
+/// Serves `responses` in turn on a local port, one per request. The thread returns the
+/// requests it read, each with the number of the connection it came on.
+#[cfg(test)]
+pub fn serve(responses: Vec<String>) -> (String, std::thread::JoinHandle<Vec<(usize, String)>>) {
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let base = format!("http://{}", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        let mut requests = Vec::new();
+        let mut connections = 0;
+        let mut reader: Option<BufReader<std::net::TcpStream>> = None;
+        for response in responses {
+            let request = loop {
+                let connection = match &mut reader {
+                    Some(connection) => connection,
+                    None => {
+                        connections += 1;
+                        reader.insert(BufReader::new(listener.accept().unwrap().0))
+                    }
+                };
+                let mut head = String::new();
+                while connection.read_line(&mut head).unwrap_or(0) > 0
+                    && !head.ends_with("\r\n\r\n")
+                {}
+                if head.ends_with("\r\n\r\n") {
+                    break head;
+                }
+                // The client closed the connection, the request comes on a new one
+                reader = None;
+            };
+            requests.push((connections, request));
+            let stream = reader.as_mut().unwrap().get_mut();
+            stream.write_all(response.as_bytes()).unwrap();
+        }
+        requests
+    });
+    (base, server)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_reuses_connections() {
+        let ok = "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_string();
+        let (base, server) = serve(vec![ok.clone(), ok]);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        runtime.block_on(async {
+            let client = client().unwrap();
+            for path in ["/first", "/second"] {
+                let response = client.get(format!("{base}{path}")).send().await.unwrap();
+                assert_eq!(response.text().await.unwrap(), "ok");
+            }
+        });
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].1.starts_with("GET /first "));
+        assert!(requests[1].1.starts_with("GET /second "));
+        // The second request is sent on the pooled connection of the first
+        assert_eq!((requests[0].0, requests[1].0), (1, 1));
+        let user_agent = format!("user-agent: {USER_AGENT}\r\n");
+        assert!(requests[0]
+            .1
+            .to_lowercase()
+            .contains(&user_agent.to_lowercase()));
+    }
+}
@@ -0,0 +1,78 @@
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::get_journal_path;
+use crate::domain::models::JournalEntry;
+
+/// Appends entries to the rename journal, one JSON object per line.
+pub fn append(entries: &[JournalEntry]) -> Result<()> {
+    let journal_path = get_journal_path();
+    if let Some(parent) = journal_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&journal_path)
+        .with_context(|| format!("Failed to open rename journal {journal_path:?}"))?;
+    for entry in entries {
+        writeln!(file, "{}", serde_json::to_string(entry)?)?;
+    }
+    Ok(())
+}
+
+/// Identifies a new run in the journal: milliseconds since the Unix epoch, so that a run
+/// started right after another (e.g. an undo) still gets an ID of its own.
+pub fn new_run_id() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Loads every entry of the rename journal in the order they were written.
+pub fn load() -> Result<Vec<JournalEntry>> {
+    let journal_path = get_journal_path();
+    match fs::read_to_string(&journal_path) {
+        Ok(content) => Ok(parse(&content)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read rename journal {journal_path:?}")),
+    }
+}
+
+fn parse(content: &str) -> Vec<JournalEntry> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .filter_map(|line| match serde_json::from_str(line) {
+            Ok(entry) => Some(entry),
+            Err(e) => {
+                // A line cut short by a crash shouldn't make the rest of the journal unusable
+                eprintln!("Warning: Skipping invalid journal line: {e}");
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let content = r#"{"timestamp":10,"run_id":9,"old_path":"/tv/a.mkv","new_path":"/tv/b.mkv","series_id":"1","season_number":1,"episode_number":2}
+{"timestamp":11,"old_path":"/tv/c.mkv","new_path":"/tv/d.mkv","series_id":"1","season_number":1,"episode_number":3}
+{"timestamp":12,"old_pa
+
+"#;
+        let entries = parse(content);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].run_id, 9);
+        assert_eq!(entries[1].run_id, 0);
+        assert_eq!(entries[1].new_path, std::path::PathBuf::from("/tv/d.mkv"));
+    }
+}
@@ -1,3 +1,12 @@
-pub mod tvdb;
 pub mod cache;
-
+pub mod cache_db;
+pub mod catalog_db;
+pub mod code_table;
+pub mod http;
+pub mod journal;
+pub mod opensubtitles;
+pub mod provider;
+pub mod secrets;
+pub mod sidecar;
+pub mod tvdb;
+pub mod tvmaze;
@@ -0,0 +1,293 @@
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::infra::http;
+
+const OPENSUBTITLES_API_BASE: &str = "https://api.opensubtitles.com/api/v1";
+
+// The hash covers the file size and the first and last 64 KiB
+const HASH_CHUNK_SIZE: u64 = 64 * 1024;
+
+/// Client for the OpenSubtitles REST API, which knows the season and episode of many
+/// releases by a hash of their video file.
+pub struct OpenSubtitlesClient {
+    client: reqwest::blocking::Client,
+    api_key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResponse {
+    #[serde(default)]
+    data: Vec<Subtitle>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Subtitle {
+    attributes: Attributes,
+}
+
+#[derive(Debug, Deserialize)]
+struct Attributes {
+    #[serde(default)]
+    moviehash_match: bool, // false for subtitles found by other means than the hash
+    feature_details: Option<FeatureDetails>,
+    #[serde(default)]
+    files: Vec<SubtitleFile>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SubtitleFile {
+    file_id: u64,
+}
+
+#[derive(Debug, Deserialize)]
+struct DownloadResponse {
+    link: String,           // temporary URL of the SRT file
+    remaining: Option<i64>, // downloads left today
+}
+
+/// A downloaded SRT file.
+pub struct SubtitleDownload {
+    pub srt: String,
+    pub remaining: Option<i64>, // downloads the API key has left today
+}
+
+#[derive(Debug, Deserialize)]
+struct FeatureDetails {
+    feature_type: Option<String>, // `Episode`, `Movie` or `Tvshow`
+    season_number: Option<u64>,
+    episode_number: Option<u64>,
+    parent_title: Option<String>, // the show
+}
+
+impl OpenSubtitlesClient {
+    pub fn new(api_key: String) -> Result<Self> {
+        Ok(Self {
+            client: http::blocking_client()?,
+            api_key,
+        })
+    }
+
+    /// The season and episode of the release with the hash, if it's an episode of the show.
+    pub fn find_episode(&self, hash: &str, show_name: &str) -> Result<Option<(u64, u64)>> {
+        let response = self
+            .client
+            .get(format!("{OPENSUBTITLES_API_BASE}/subtitles"))
+            .header("Api-Key", &self.api_key)
+            .query(&[("moviehash", hash)])
+            .send()?;
+
+        if !response.status().is_success() {
+            bail!("OpenSubtitles search failed: HTTP {}", response.status());
+        }
+        let response: SearchResponse = serde_json::from_str(&response.text()?)?;
+        Ok(best_episode(&response, show_name))
+    }
+
+    /// The most downloaded SRT of an episode in an ISO 639-2 language, `None` if there's
+    /// none. Every download counts against the daily quota of the API key.
+    pub fn download_episode_subtitle(
+        &self,
+        show_name: &str,
+        season: u64,
+        episode: u64,
+        language: &str,
+    ) -> Result<Option<SubtitleDownload>> {
+        let response = self
+            .client
+            .get(format!("{OPENSUBTITLES_API_BASE}/subtitles"))
+            .header("Api-Key", &self.api_key)
+            .query(&[
+                ("query", show_name.to_string()),
+                ("type", "episode".to_string()),
+                ("season_number", season.to_string()),
+                ("episode_number", episode.to_string()),
+                ("languages", opensubtitles_language(language).to_string()),
+                ("order_by", "download_count".to_string()),
+            ])
+            .send()?;
+        if !response.status().is_success() {
+            bail!("OpenSubtitles search failed: HTTP {}", response.status());
+        }
+        let response: SearchResponse = serde_json::from_str(&response.text()?)?;
+        let Some(file_id) = episode_file(&response, show_name, season, episode) else {
+            return Ok(None);
+        };
+
+        let response = self
+            .client
+            .post(format!("{OPENSUBTITLES_API_BASE}/download"))
+            .header("Api-Key", &self.api_key)
+            .header("Content-Type", "application/json")
+            .body(format!(r#"{{"file_id": {file_id}}}"#))
+            .send()?;
+        if !response.status().is_success() {
+            bail!("OpenSubtitles download failed: HTTP {}", response.status());
+        }
+        let download: DownloadResponse = serde_json::from_str(&response.text()?)?;
+        Ok(Some(SubtitleDownload {
+            srt: self
+                .client
+                .get(&download.link)
+                .send()?
+                .error_for_status()?
+                .text()?,
+            remaining: download.remaining,
+        }))
+    }
+}
+
+/// The first subtitle file of the episode among the search results, which also hold
+/// episodes of shows with similar names.
+fn episode_file(
+    response: &SearchResponse,
+    show_name: &str,
+    season: u64,
+    episode: u64,
+) -> Option<u64> {
+    response.data.iter().find_map(|subtitle| {
+        let details = subtitle.attributes.feature_details.as_ref()?;
+        let same_episode = details.season_number == Some(season)
+            && details.episode_number == Some(episode)
+            && details
+                .parent_title
+                .as_deref()
+                .is_some_and(|title| normalize(title) == normalize(show_name));
+        if !same_episode {
+            return None;
+        }
+        subtitle.attributes.files.first().map(|file| file.file_id)
+    })
+}
+
+/// OpenSubtitles names languages by their ISO 639-1 code.
+fn opensubtitles_language(language: &str) -> &str {
+    match language {
+        "eng" => "en",
+        "ger" | "deu" => "de",
+        "fre" | "fra" => "fr",
+        "spa" => "es",
+        "ita" => "it",
+        "por" => "pt-PT",
+        "dut" | "nld" => "nl",
+        "swe" => "sv",
+        "dan" => "da",
+        "nor" => "no",
+        "fin" => "fi",
+        "pol" => "pl",
+        "rus" => "ru",
+        "jpn" => "ja",
+        "kor" => "ko",
+        "chi" | "zho" => "zh-CN",
+        other => other,
+    }
+}
+
+/// The episode most subtitles matched by hash belong to. Hashes aren't unique, so those of
+/// other shows are ignored.
+fn best_episode(response: &SearchResponse, show_name: &str) -> Option<(u64, u64)> {
+    let mut votes: HashMap<(u64, u64), usize> = HashMap::new();
+    for subtitle in &response.data {
+        let attributes = &subtitle.attributes;
+        let Some(details) = attributes.feature_details.as_ref() else {
+            continue;
+        };
+        let same_show = details
+            .parent_title
+            .as_deref()
+            .is_some_and(|title| normalize(title) == normalize(show_name));
+        if !attributes.moviehash_match
+            || details.feature_type.as_deref() != Some("Episode")
+            || !same_show
+        {
+            continue;
+        }
+        if let (Some(season), Some(episode)) = (details.season_number, details.episode_number) {
+            *votes.entry((season, episode)).or_default() += 1;
+        }
+    }
+    votes
+        .into_iter()
+        .max_by_key(|&(episode, count)| (count, std::cmp::Reverse(episode)))
+        .map(|(episode, _)| episode)
+}
+
+/// Lowercase letters and digits only, so `The X-Files` and `The X Files` compare equal.
+fn normalize(title: &str) -> String {
+    title
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The OpenSubtitles hash of a video file: its size plus the sums of the 64-bit
+/// little-endian words of its first and last 64 KiB, as 16 hex digits.
+pub fn moviehash(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    if size < HASH_CHUNK_SIZE {
+        bail!("{path:?} is too small to hash");
+    }
+
+    let mut hash = size;
+    let mut chunk = vec![0u8; HASH_CHUNK_SIZE as usize];
+    for offset in [0, size - HASH_CHUNK_SIZE] {
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut chunk)?;
+        for word in chunk.chunks_exact(8) {
+            hash = hash.wrapping_add(u64::from_le_bytes(word.try_into().unwrap()));
+        }
+    }
+    Ok(format!("{hash:016x}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_moviehash() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let size = 2 * HASH_CHUNK_SIZE as usize;
+        let mut data = vec![0u8; size];
+        data[0] = 1; // first chunk
+        data[size - 8] = 2; // last chunk
+        file.write_all(&data).unwrap();
+
+        assert_eq!(moviehash(file.path()).unwrap(), "0000000000020003");
+    }
+
+    #[test]
+    fn test_best_episode() {
+        let response: SearchResponse = serde_json::from_str(
+            r#"{"data": [
+                {"attributes": {"moviehash_match": true, "feature_details": {"feature_type": "Episode", "season_number": 1, "episode_number": 3, "parent_title": "The X-Files"}}},
+                {"attributes": {"moviehash_match": true, "feature_details": {"feature_type": "Episode", "season_number": 1, "episode_number": 3, "parent_title": "The X Files"}}},
+                {"attributes": {"moviehash_match": true, "feature_details": {"feature_type": "Episode", "season_number": 2, "episode_number": 1, "parent_title": "Millennium"}}},
+                {"attributes": {"moviehash_match": false, "feature_details": {"feature_type": "Episode", "season_number": 1, "episode_number": 4, "parent_title": "The X-Files"}}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(best_episode(&response, "The X-Files"), Some((1, 3)));
+        assert_eq!(best_episode(&response, "Lost"), None);
+    }
+
+    #[test]
+    fn test_episode_file() {
+        let response: SearchResponse = serde_json::from_str(
+            r#"{"data": [
+                {"attributes": {"feature_details": {"season_number": 1, "episode_number": 3, "parent_title": "The X-Files Reopened"}, "files": [{"file_id": 7}]}},
+                {"attributes": {"feature_details": {"season_number": 1, "episode_number": 3, "parent_title": "The X-Files"}, "files": [{"file_id": 42}]}}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(episode_file(&response, "The X-Files", 1, 3), Some(42));
+        assert_eq!(episode_file(&response, "The X-Files", 1, 4), None);
+    }
+}
@@ -0,0 +1,256 @@
+use anyhow::Result;
+use futures_util::future::BoxFuture;
+use serde::Serialize;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Handle;
+use tokio::task::JoinHandle;
+
+use crate::domain::models::{unix_now, EpisodeEntry};
+use crate::infra::cache::Cache;
+
+/// A show returned by a provider search, with the details that tell shows of the same
+/// name apart where the provider has them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SeriesMatch {
+    pub id: String,
+    pub name: String,
+    pub year: Option<String>, // of the first episode
+    pub network: Option<String>,
+    pub country: Option<String>,
+    pub status: Option<String>, // e.g. `Continuing` or `Ended`
+    pub overview: Option<String>,
+}
+
+/// A source of series and episode metadata, such as TVDB or TVmaze. Requests are async,
+/// so episodes can be fetched in the background (see `EpisodeFetch`).
+pub trait MetadataProvider: Send + Sync {
+    /// Key the series is stored under in the cache, journal and per-series config, so
+    /// that IDs of different providers can't collide.
+    fn series_key(&self, series_id: &str) -> String {
+        series_id.to_string()
+    }
+
+    fn search_series<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<Vec<SeriesMatch>>>;
+
+    fn get_series_name<'a>(&'a self, series_id: &'a str) -> BoxFuture<'a, Result<String>>;
+
+    /// Fetches all episodes of the series. `cached` are its episodes as an earlier run
+    /// fetched them at `since`, which a provider that knows what changed since may reuse.
+    /// A provider fetching them one by one counts them in `progress`.
+    fn fetch_episodes<'a>(
+        &'a self,
+        series_id: &'a str,
+        cached: Vec<EpisodeEntry>,
+        since: Option<u64>,
+        progress: &'a FetchProgress,
+    ) -> BoxFuture<'a, Result<Vec<EpisodeEntry>>>;
+}
+
+// How often the progress bar is redrawn while waiting for a fetch
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How many of the requests of an episode fetch are done.
+#[derive(Debug, Default)]
+pub struct FetchProgress {
+    done: AtomicUsize,
+    total: AtomicUsize,
+}
+
+impl FetchProgress {
+    pub fn start(&self, total: usize) {
+        self.total.store(total, Ordering::Relaxed);
+    }
+
+    pub fn advance(&self) {
+        self.done.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> (usize, usize) {
+        (
+            self.done.load(Ordering::Relaxed),
+            self.total.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// The episodes of a series being fetched on the async runtime while the run goes on.
+pub struct EpisodeFetch {
+    series_key: String,
+    fetched: u64, // taken before fetching, so updates made meanwhile are seen next time
+    runtime: Handle,
+    task: JoinHandle<Result<Vec<EpisodeEntry>>>,
+    progress: Arc<FetchProgress>,
+}
+
+impl EpisodeFetch {
+    pub fn start(
+        runtime: &Handle,
+        provider: &Arc<dyn MetadataProvider>,
+        series_id: &str,
+        cache: &Cache,
+    ) -> Self {
+        let series_key = provider.series_key(series_id);
+        println!("Preloading episode cache for series {series_key}...");
+        let cached = cache
+            .get_series_episodes(&series_key)
+            .into_iter()
+            .cloned()
+            .collect();
+        let since = cache.fetched.get(&series_key).copied();
+        let fetched = unix_now();
+
+        let provider = Arc::clone(provider);
+        let series_id = series_id.to_string();
+        let progress = Arc::new(FetchProgress::default());
+        let task = {
+            let progress = Arc::clone(&progress);
+            runtime.spawn(async move {
+                provider
+                    .fetch_episodes(&series_id, cached, since, &progress)
+                    .await
+            })
+        };
+        Self {
+            series_key,
+            fetched,
+            runtime: runtime.clone(),
+            task,
+            progress,
+        }
+    }
+
+    /// Waits for the episodes and caches them in place of the cached ones (see
+    /// `Cache::replace_series_episodes`). The progress bar is only drawn while waiting,
+    /// in the background it would run into the output of the files matched meanwhile.
+    pub fn finish(self, cache: &mut Cache) -> Result<()> {
+        let mut drawn = None;
+        loop {
+            let finished = self.task.is_finished();
+            let (done, total) = self.progress.get();
+            if total > 0 && drawn != Some(done) {
+                print!("\r  {}", progress_bar(done, total));
+                let _ = io::stdout().flush();
+                drawn = Some(done);
+            }
+            if finished {
+                break;
+            }
+            thread::sleep(PROGRESS_INTERVAL);
+        }
+        if drawn.is_some() {
+            println!();
+        }
+
+        let episodes = self.runtime.block_on(self.task)??;
+        cache.replace_series_episodes(&self.series_key, &episodes, self.fetched);
+        println!("Cache preloaded successfully.");
+        Ok(())
+    }
+}
+
+/// `[#####     ] 5/10`
+fn progress_bar(done: usize, total: usize) -> String {
+    const WIDTH: usize = 30;
+    let filled = WIDTH * done.min(total) / total.max(1);
+    format!(
+        "[{}{}] {done}/{total}",
+        "#".repeat(filled),
+        " ".repeat(WIDTH - filled)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn episode(episode_number: u64, name: &str) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number,
+            name: name.to_string(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: Some(episode_number),
+            absolute_number: None,
+        }
+    }
+
+    /// Renames the cached episodes and adds one, recording what it was asked for.
+    #[derive(Default)]
+    struct FakeProvider {
+        asked: Mutex<Option<(String, usize, Option<u64>)>>,
+    }
+
+    impl MetadataProvider for FakeProvider {
+        fn series_key(&self, series_id: &str) -> String {
+            format!("fake:{series_id}")
+        }
+
+        fn search_series<'a>(&'a self, _query: &'a str) -> BoxFuture<'a, Result<Vec<SeriesMatch>>> {
+            Box::pin(async { Ok(Vec::new()) })
+        }
+
+        fn get_series_name<'a>(&'a self, _series_id: &'a str) -> BoxFuture<'a, Result<String>> {
+            Box::pin(async { Ok("Show".to_string()) })
+        }
+
+        fn fetch_episodes<'a>(
+            &'a self,
+            series_id: &'a str,
+            cached: Vec<EpisodeEntry>,
+            since: Option<u64>,
+            progress: &'a FetchProgress,
+        ) -> BoxFuture<'a, Result<Vec<EpisodeEntry>>> {
+            Box::pin(async move {
+                *self.asked.lock().unwrap() = Some((series_id.to_string(), cached.len(), since));
+                progress.start(2);
+                progress.advance();
+                progress.advance();
+                Ok(vec![episode(1, "Pilot (Updated)"), episode(2, "Second")])
+            })
+        }
+    }
+
+    #[test]
+    fn test_episode_fetch() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let fake = Arc::new(FakeProvider::default());
+        let provider: Arc<dyn MetadataProvider> = fake.clone();
+        let mut cache = Cache::default();
+        cache.replace_series_episodes("fake:7", &[episode(1, "Pilot")], 1000);
+
+        let fetch = EpisodeFetch::start(runtime.handle(), &provider, "7", &cache);
+        fetch.finish(&mut cache).unwrap();
+
+        // The provider is given the cached episodes and when they were fetched
+        assert_eq!(
+            *fake.asked.lock().unwrap(),
+            Some(("7".to_string(), 1, Some(1000)))
+        );
+        let mut names: Vec<&str> = cache
+            .get_series_episodes("fake:7")
+            .iter()
+            .map(|episode| episode.name.as_str())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["Pilot (Updated)", "Second"]);
+        assert!(cache.fetched["fake:7"] > 1000);
+    }
+
+    #[test]
+    fn test_progress_bar() {
+        assert_eq!(progress_bar(0, 4), format!("[{}] 0/4", " ".repeat(30)));
+        assert_eq!(
+            progress_bar(2, 4),
+            format!("[{}{}] 2/4", "#".repeat(15), " ".repeat(15))
+        );
+        assert_eq!(progress_bar(4, 4), format!("[{}] 4/4", "#".repeat(30)));
+    }
+}
@@ -0,0 +1,55 @@
+use anyhow::Result;
+
+// Entries are stored under this service name, one per key
+#[cfg(feature = "keyring")]
+const SERVICE: &str = "episode-matcher";
+
+pub const TVDB_API_KEY: &str = "tvdb_api_key";
+
+/// Whether this build can keep API keys in the OS keyring.
+pub const KEYRING_AVAILABLE: bool = cfg!(feature = "keyring");
+
+/// The key stored in the OS keyring under `name`, `None` if there's none.
+#[cfg(feature = "keyring")]
+pub fn get(name: &str) -> Result<Option<String>> {
+    match keyring::Entry::new(SERVICE, name)?.get_password() {
+        Ok(key) => Ok(Some(key)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn get(_name: &str) -> Result<Option<String>> {
+    Ok(None)
+}
+
+/// Stores the key in the OS keyring under `name`, replacing an earlier one.
+#[cfg(feature = "keyring")]
+pub fn set(name: &str, key: &str) -> Result<()> {
+    Ok(keyring::Entry::new(SERVICE, name)?.set_password(key)?)
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn set(_name: &str, _key: &str) -> Result<()> {
+    anyhow::bail!("This build has no keyring support, build it with `--features keyring`")
+}
+
+/// A key typed at the terminal, not echoed so it doesn't end up in the scrollback, or the
+/// first line piped to stdin.
+#[cfg(feature = "keyring")]
+pub fn read_key(prompt: &str) -> Result<String> {
+    use std::io::{self, IsTerminal};
+
+    if io::stdin().is_terminal() {
+        return Ok(rpassword::prompt_password(prompt)?);
+    }
+    let mut key = String::new();
+    io::stdin().read_line(&mut key)?;
+    Ok(key)
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn read_key(_prompt: &str) -> Result<String> {
+    anyhow::bail!("This build has no keyring support, build it with `--features keyring`")
+}
@@ -0,0 +1,99 @@
+use anyhow::Result;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::domain::models::{EpisodeEntry, OcrCandidate};
+
+/// OCR evidence for a single video file, stored next to it as `<stem>.ocr.json`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OcrSidecar {
+    pub file: String,
+    pub candidates: Vec<OcrCandidate>,
+    #[serde(default, deserialize_with = "one_or_many")]
+    pub matched: Vec<EpisodeEntry>, // several for a multi-episode file
+}
+
+/// Sidecars written before multi-episode files were supported hold one episode or null.
+fn one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Vec<EpisodeEntry>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Option<EpisodeEntry>),
+        Many(Vec<EpisodeEntry>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(episode) => episode.into_iter().collect(),
+        OneOrMany::Many(episodes) => episodes,
+    })
+}
+
+pub fn sidecar_path(file_path: &Path) -> PathBuf {
+    file_path.with_extension("ocr.json")
+}
+
+pub fn load(file_path: &Path) -> Option<OcrSidecar> {
+    let content = fs::read_to_string(sidecar_path(file_path)).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
+pub fn save(file_path: &Path, sidecar: &OcrSidecar) -> Result<()> {
+    let content = serde_json::to_string_pretty(sidecar)?;
+    fs::write(sidecar_path(file_path), content)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_sidecar_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("title_t00.mkv");
+        assert_eq!(
+            sidecar_path(&file_path),
+            temp_dir.path().join("title_t00.ocr.json")
+        );
+        assert!(load(&file_path).is_none());
+
+        let sidecar = OcrSidecar {
+            file: "title_t00.mkv".to_string(),
+            candidates: vec![OcrCandidate {
+                code: "3X22".to_string(),
+                frame: "frame_0004.png".to_string(),
+                confidence: 87,
+            }],
+            matched: Vec::new(),
+        };
+        save(&file_path, &sidecar).unwrap();
+
+        let loaded = load(&file_path).unwrap();
+        assert_eq!(loaded.file, "title_t00.mkv");
+        assert_eq!(loaded.candidates.len(), 1);
+        assert_eq!(loaded.candidates[0].code, "3X22");
+        assert_eq!(loaded.candidates[0].confidence, 87);
+    }
+
+    #[test]
+    fn test_load_single_episode_sidecar() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("title_t00.mkv");
+        fs::write(
+            sidecar_path(&file_path),
+            r#"{"file": "title_t00.mkv", "candidates": [], "matched": {"production_code": "3X22", "season_number": 3, "episode_number": 22, "name": "Anasazi"}}"#,
+        )
+        .unwrap();
+        assert_eq!(load(&file_path).unwrap().matched[0].name, "Anasazi");
+
+        fs::write(
+            sidecar_path(&file_path),
+            r#"{"file": "title_t00.mkv", "candidates": [], "matched": null}"#,
+        )
+        .unwrap();
+        assert!(load(&file_path).unwrap().matched.is_empty());
+    }
+}
@@ -1,16 +1,52 @@
-use serde::Deserialize;
-use std::collections::HashMap;
-
-use anyhow::{bail, Result};
-
-use crate::domain::models::EpisodeEntry;
+use futures_util::future::BoxFuture;
+use futures_util::stream::{self, StreamExt};
+use reqwest::header::RETRY_AFTER;
+use reqwest::StatusCode;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::{self, Instant};
+
+use anyhow::{bail, Context, Result};
+
+use crate::config::get_tvdb_token_path;
+use crate::domain::models::{unix_now, EpisodeEntry};
+use crate::infra::http;
+use crate::infra::provider::{FetchProgress, MetadataProvider, SeriesMatch};
 
 const TVDB_API_BASE: &str = "https://api4.thetvdb.com/v4";
+const FETCH_CONCURRENCY: usize = 8; // extended episode records requested at once
+const MAX_RETRIES: u32 = 5;
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+// TVDB tokens are valid for a month, they're renewed a little early
+const TOKEN_LIFETIME_SECS: u64 = 28 * 24 * 60 * 60;
+pub const DEFAULT_SEASON_TYPE: &str = "default"; // aired order
+
+/// The series ID of a key of `series_key`, without the ordering.
+pub fn series_id(series_key: &str) -> &str {
+    series_key.split('#').next().unwrap_or(series_key)
+}
 
-#[derive(Debug, Clone)]
+#[derive(Debug)]
 pub struct TvdbClient {
+    client: reqwest::Client,
     api_key: String,
-    token: Option<String>,
+    token: Mutex<Option<String>>, // shared by the concurrent episode fetches
+    season_type: String,          // episode numbering, see `with_season_type`
+    request_interval: Option<Duration>, // see `with_rate_limit`
+    next_request: Mutex<Instant>,
+}
+
+/// The bearer token kept in the config dir between runs.
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredToken {
+    token: String,
+    expires: u64,      // Unix time
+    api_key_hash: u64, // a token is only valid for the API key it was issued for
 }
 
 #[derive(Debug, Deserialize)]
@@ -29,10 +65,16 @@ struct SearchResponse {
 }
 
 #[derive(Debug, Deserialize)]
-pub struct SearchResult {
+struct SearchResult {
     pub tvdb_id: String,
     #[serde(rename = "translations")]
     pub name: Option<HashMap<String, String>>,
+    pub year: Option<String>,
+    pub network: Option<String>,
+    pub country: Option<String>, // ISO 3166-1 alpha-3, e.g. `usa`
+    pub status: Option<String>,
+    pub overview: Option<String>, // in the original language
+    pub overviews: Option<HashMap<String, String>>, // by language
 }
 
 #[derive(Debug, Deserialize)]
@@ -48,6 +90,13 @@ struct EpisodesData {
 #[derive(Debug, Deserialize, Clone)]
 pub struct Episode {
     pub id: u32,
+    // Numbers in the requested season type, the extended record only has the default ones
+    #[serde(rename = "seasonNumber")]
+    pub season_number: Option<u64>,
+    pub number: Option<u64>,
+    /// `YYYY-MM-DD HH:MM:SS` (UTC)
+    #[serde(rename = "lastUpdated")]
+    pub last_updated: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -64,6 +113,11 @@ struct ExtendedEpisodeData {
     #[serde(rename = "number")]
     pub episode_number: u32,
     pub name: String,
+    pub overview: Option<String>,
+    pub aired: Option<String>,
+    pub runtime: Option<u64>,
+    #[serde(rename = "absoluteNumber")]
+    pub absolute_number: Option<u64>, // 0 when TVDB has none
 }
 
 #[derive(Debug, Deserialize)]
@@ -77,107 +131,165 @@ struct SeriesData {
 }
 
 impl TvdbClient {
-    pub fn new(api_key: String) -> Self {
-        Self {
+    /// Creates a client that reuses the token of an earlier run if it hasn't expired yet.
+    pub fn new(api_key: String) -> Result<Self> {
+        let token = load_token(&api_key);
+        Ok(Self {
+            client: http::client()?,
             api_key,
-            token: None,
-        }
+            token: Mutex::new(token),
+            season_type: DEFAULT_SEASON_TYPE.to_string(),
+            request_interval: None,
+            next_request: Mutex::new(Instant::now()),
+        })
+    }
+
+    /// Numbers episodes by another TVDB season type, e.g. `dvd` or `absolute`. Their episodes
+    /// are cached under a key of their own, see `series_key`.
+    pub fn with_season_type(mut self, season_type: &str) -> Self {
+        self.season_type = season_type.to_string();
+        self
     }
 
-    pub fn login(&mut self) -> Result<()> {
-        let client = reqwest::blocking::Client::new();
+    /// Sends at most `requests_per_second` requests, shared by all concurrent fetches.
+    pub fn with_rate_limit(mut self, requests_per_second: f64) -> Self {
+        self.request_interval = Some(Duration::from_secs_f64(1.0 / requests_per_second));
+        self
+    }
+
+    /// Requests a new token and stores it for later runs.
+    async fn login(&self) -> Result<String> {
         let body = serde_json::json!({
             "apikey": self.api_key
         });
-        let response = client
+        let response = self
+            .client
             .post(format!("{TVDB_API_BASE}/login"))
             .header("Content-Type", "application/json")
             .body(body.to_string())
-            .send()?;
+            .send()
+            .await?;
 
         if !response.status().is_success() {
             bail!("TVDB login failed: HTTP {}", response.status());
         }
 
-        let login_resp: LoginResponse = serde_json::from_str(&response.text()?)?;
-        self.token = Some(login_resp.data.token);
-        Ok(())
+        let login_resp: LoginResponse = serde_json::from_str(&response.text().await?)?;
+        let token = login_resp.data.token;
+        if let Err(e) = save_token(&self.api_key, &token) {
+            eprintln!("Warning: Failed to store the TVDB token: {e}");
+        }
+        Ok(token)
     }
 
-    fn ensure_authenticated(&mut self) -> Result<()> {
-        if self.token.is_none() {
-            self.login()?;
+    /// The current token, logging in first if there is none.
+    async fn token(&self) -> Result<String> {
+        let mut token = self.token.lock().await;
+        if token.is_none() {
+            *token = Some(self.login().await?);
         }
-        Ok(())
+        Ok(token.clone().unwrap())
     }
 
-    pub fn search_series(&mut self, query: &str) -> Result<Vec<SearchResult>> {
-        self.ensure_authenticated()?;
-
-        let client = reqwest::blocking::Client::new();
-        let response = client
-            .get(format!("{TVDB_API_BASE}/search"))
-            .header(
-                "Authorization",
-                format!("Bearer {}", self.token.as_ref().unwrap()),
-            )
-            .query(&[("query", query), ("type", "series")])
-            .send()?;
-
-        if !response.status().is_success() {
-            bail!("TVDB search failed: HTTP {}", response.status());
+    /// Logs in again after TVDB rejected `expired`, unless another fetch already did.
+    async fn renew_token(&self, expired: &str) -> Result<()> {
+        let mut token = self.token.lock().await;
+        if token.as_deref() == Some(expired) {
+            *token = Some(self.login().await?);
         }
-
-        let search_resp: SearchResponse = serde_json::from_str(&response.text()?)?;
-        Ok(search_resp.data)
+        Ok(())
     }
 
-    pub fn get_series_name(&mut self, series_id: &str) -> Result<String> {
-        self.ensure_authenticated()?;
-
-        let client = reqwest::blocking::Client::new();
-        let response = client
-            .get(format!("{TVDB_API_BASE}/series/{series_id}"))
-            .header(
-                "Authorization",
-                format!("Bearer {}", self.token.as_ref().unwrap()),
-            )
-            .send()?;
+    /// Sends an authenticated GET request, retrying with a growing delay while TVDB is rate
+    /// limiting (or waiting as long as its `Retry-After` says), has a server error or can't
+    /// be reached. A rejected token is renewed once.
+    async fn get(&self, url: &str, query: &[(&str, String)]) -> Result<reqwest::Response> {
+        let mut backoff = INITIAL_BACKOFF;
+        let mut retries = 0;
+        let mut renewed = false;
+        loop {
+            let token = self.token().await?;
+            self.wait_for_slot().await;
+            let response = match self
+                .client
+                .get(url)
+                .header("Authorization", format!("Bearer {token}"))
+                .query(query)
+                .send()
+                .await
+            {
+                Ok(response) => response,
+                Err(e) if (e.is_timeout() || e.is_connect()) && retries < MAX_RETRIES => {
+                    time::sleep(backoff).await;
+                    backoff *= 2;
+                    retries += 1;
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
 
-        if !response.status().is_success() {
-            bail!("TVDB series lookup failed: HTTP {}", response.status());
+            let status = response.status();
+            if status == StatusCode::UNAUTHORIZED && !renewed {
+                self.renew_token(&token).await?;
+                renewed = true;
+                continue;
+            }
+            let transient = status == StatusCode::TOO_MANY_REQUESTS || status.is_server_error();
+            if !transient || retries == MAX_RETRIES {
+                return Ok(response);
+            }
+            let retry_after = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok()?.parse().ok())
+                .map(Duration::from_secs);
+            time::sleep(retry_after.unwrap_or(backoff)).await;
+            backoff *= 2;
+            retries += 1;
         }
+    }
 
-        let series_resp: SeriesResponse = serde_json::from_str(&response.text()?)?;
-
-        Ok(series_resp.data.name)
+    /// Waits until the rate limit allows the next request.
+    async fn wait_for_slot(&self) {
+        let Some(interval) = self.request_interval else {
+            return;
+        };
+        // Sleeping with the lock held queues the other fetches behind this request
+        let mut next_request = self.next_request.lock().await;
+        time::sleep_until(*next_request).await;
+        *next_request = Instant::now() + interval;
     }
 
-    pub fn preload_episodes(
-        &mut self,
-        series_id: &str,
-        cache: &mut crate::infra::cache::Cache,
-    ) -> Result<()> {
-        self.ensure_authenticated()?;
+    /// All episodes of the series in the client's season type. Other orders than the aired
+    /// one often leave out the specials, which are added from it.
+    async fn list_episodes(&self, series_id: &str) -> Result<Vec<Episode>> {
+        let mut episodes = self.list_season_type(series_id, &self.season_type).await?;
+        if self.season_type == DEFAULT_SEASON_TYPE {
+            return Ok(episodes);
+        }
+        match self.list_season_type(series_id, DEFAULT_SEASON_TYPE).await {
+            Ok(aired) => {
+                let listed: HashSet<u32> = episodes.iter().map(|episode| episode.id).collect();
+                episodes.extend(aired.into_iter().filter(|episode| {
+                    episode.season_number == Some(0) && !listed.contains(&episode.id)
+                }));
+            }
+            Err(e) => eprintln!("Warning: Failed to list the specials of series {series_id}: {e}"),
+        }
+        Ok(episodes)
+    }
 
-        // Get all episodes for the series
-        let client = reqwest::blocking::Client::new();
+    /// All episodes of the series in a season type, page by page.
+    async fn list_season_type(&self, series_id: &str, season_type: &str) -> Result<Vec<Episode>> {
+        let url = format!("{TVDB_API_BASE}/series/{series_id}/episodes/{season_type}");
         let mut page = 0;
         let mut all_episodes = Vec::new();
 
         loop {
-            let url = format!("{TVDB_API_BASE}/series/{series_id}/episodes/default");
-            let response = client
-                .get(&url)
-                .header(
-                    "Authorization",
-                    format!("Bearer {}", self.token.as_ref().unwrap()),
-                )
-                .query(&[("page", page.to_string())])
-                .send()?;
+            let response = self.get(&url, &[("page", page.to_string())]).await?;
 
             let status = response.status();
-            let response_text = response.text()?;
+            let response_text = response.text().await?;
 
             if !status.is_success() {
                 if status == 404 {
@@ -197,37 +309,434 @@ impl TvdbClient {
             page += 1;
         }
 
-        // Fetch extended details for each episode and cache them
-        println!("Caching {} episodes...", all_episodes.len());
-        for (idx, episode) in all_episodes.iter().enumerate() {
-            if (idx + 1) % 50 == 0 {
-                println!("  Cached {}/{} episodes...", idx + 1, all_episodes.len());
-            }
+        Ok(all_episodes)
+    }
 
-            let extended_url = format!("{}/episodes/{}/extended", TVDB_API_BASE, episode.id);
-            let extended_response = client
-                .get(&extended_url)
-                .header(
-                    "Authorization",
-                    format!("Bearer {}", self.token.as_ref().unwrap()),
+    /// The episode's extended record. Errors, also a rate limit outlasting the retries, fail
+    /// rather than leave the episode out, see `keep_cached`.
+    async fn fetch_episode(&self, episode: &Episode) -> Result<EpisodeEntry> {
+        let extended_url = format!("{}/episodes/{}/extended", TVDB_API_BASE, episode.id);
+        let extended_response = self.get(&extended_url, &[]).await?;
+        if !extended_response.status().is_success() {
+            bail!(
+                "TVDB episode {} lookup failed: HTTP {}",
+                episode.id,
+                extended_response.status()
+            );
+        }
+        let extended_resp: ExtendedEpisodeResponse =
+            serde_json::from_str(&extended_response.text().await?).with_context(|| {
+                format!(
+                    "TVDB returned an unreadable record of episode {}",
+                    episode.id
                 )
-                .send()?;
-
-            if extended_response.status().is_success() {
-                if let Ok(extended_resp) =
-                    serde_json::from_str::<ExtendedEpisodeResponse>(&extended_response.text()?)
-                {
-                    let entry: EpisodeEntry = EpisodeEntry {
-                        production_code: extended_resp.data.production_code.clone(),
-                        season_number: extended_resp.data.season_number as u64,
-                        episode_number: extended_resp.data.episode_number as u64,
-                        name: extended_resp.data.name,
-                    };
-                    cache.set_episode(series_id, &entry);
+            })?;
+
+        Ok(EpisodeEntry {
+            production_code: extended_resp.data.production_code.clone(),
+            season_number: episode
+                .season_number
+                .unwrap_or(extended_resp.data.season_number as u64),
+            episode_number: episode
+                .number
+                .unwrap_or(extended_resp.data.episode_number as u64),
+            name: extended_resp.data.name,
+            overview: extended_resp.data.overview,
+            aired: extended_resp.data.aired,
+            runtime: extended_resp.data.runtime,
+            id: Some(episode.id as u64),
+            absolute_number: extended_resp
+                .data
+                .absolute_number
+                .filter(|&number| number > 0),
+        })
+    }
+}
+
+/// The cached entry with the numbers of the listing, which has the requested season type.
+/// Whether the listing says the episode wasn't updated after `since` (Unix time). One
+/// without a readable `lastUpdated` counts as updated.
+fn unchanged_since(episode: &Episode, since: u64) -> bool {
+    episode
+        .last_updated
+        .as_deref()
+        .and_then(unix_time)
+        .is_some_and(|updated| updated <= since)
+}
+
+/// Unix time of a TVDB `YYYY-MM-DD HH:MM:SS` timestamp (UTC).
+fn unix_time(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp
+        .trim()
+        .split_once(' ')
+        .unwrap_or((timestamp, "00:00:00"));
+    let mut date = date.splitn(3, '-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date.next()??, date.next()??, date.next()??);
+    let mut time = time.splitn(3, ':').map(|part| part.parse::<u64>().ok());
+    let (hours, minutes, seconds) = (time.next()??, time.next()??, time.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Howard Hinnant's days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = u64::try_from(era * 146_097 + day_of_era - 719_468).ok()?;
+    Some(days * 86_400 + hours * 3600 + minutes * 60 + seconds)
+}
+
+fn renumbered(entry: &EpisodeEntry, episode: &Episode) -> EpisodeEntry {
+    let mut entry = entry.clone();
+    entry.season_number = episode.season_number.unwrap_or(entry.season_number);
+    entry.episode_number = episode.number.unwrap_or(entry.episode_number);
+    entry
+}
+
+/// The cached entry of an episode whose extended record couldn't be fetched. Without one
+/// the whole fetch fails, since the cache would lose the episode when it's replaced.
+fn keep_cached(
+    episode: &Episode,
+    cached: &HashMap<u64, EpisodeEntry>,
+    error: anyhow::Error,
+) -> Result<EpisodeEntry> {
+    match cached.get(&(episode.id as u64)) {
+        Some(entry) => {
+            eprintln!("Warning: {error:#}, keeping the cached episode");
+            Ok(renumbered(entry, episode))
+        }
+        None => Err(error),
+    }
+}
+
+impl MetadataProvider for TvdbClient {
+    fn series_key(&self, series_id: &str) -> String {
+        if self.season_type == DEFAULT_SEASON_TYPE {
+            series_id.to_string()
+        } else {
+            format!("{series_id}#{}", self.season_type)
+        }
+    }
+
+    fn search_series<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<Vec<SeriesMatch>>> {
+        Box::pin(async move {
+            let response = self
+                .get(
+                    &format!("{TVDB_API_BASE}/search"),
+                    &[("query", query.to_string()), ("type", "series".to_string())],
+                )
+                .await?;
+
+            if !response.status().is_success() {
+                bail!("TVDB search failed: HTTP {}", response.status());
+            }
+
+            let search_resp: SearchResponse = serde_json::from_str(&response.text().await?)?;
+            let results = search_resp
+                .data
+                .into_iter()
+                .map(|result| {
+                    let name = result
+                        .name
+                        .as_ref()
+                        .and_then(|n| n.get("eng"))
+                        .or_else(|| result.name.as_ref().and_then(|n| n.values().next()))
+                        .cloned()
+                        .unwrap_or_else(|| "Unknown".to_string());
+                    let overview = result
+                        .overviews
+                        .as_ref()
+                        .and_then(|overviews| overviews.get("eng"))
+                        .cloned()
+                        .or(result.overview);
+                    SeriesMatch {
+                        id: result.tvdb_id,
+                        name,
+                        year: result.year,
+                        network: result.network,
+                        country: result.country.map(|country| country.to_uppercase()),
+                        status: result.status,
+                        overview,
+                    }
+                })
+                .collect();
+            Ok(results)
+        })
+    }
+
+    fn get_series_name<'a>(&'a self, series_id: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let response = self
+                .get(&format!("{TVDB_API_BASE}/series/{series_id}"), &[])
+                .await?;
+
+            if !response.status().is_success() {
+                bail!("TVDB series lookup failed: HTTP {}", response.status());
+            }
+
+            let series_resp: SeriesResponse = serde_json::from_str(&response.text().await?)?;
+
+            Ok(series_resp.data.name)
+        })
+    }
+
+    fn fetch_episodes<'a>(
+        &'a self,
+        series_id: &'a str,
+        cached: Vec<EpisodeEntry>,
+        since: Option<u64>,
+        progress: &'a FetchProgress,
+    ) -> BoxFuture<'a, Result<Vec<EpisodeEntry>>> {
+        Box::pin(async move {
+            let all_episodes = self.list_episodes(series_id).await?;
+
+            // Cached episodes TVDB hasn't changed since they were fetched are kept, only new
+            // and updated ones need their extended record
+            let cached: HashMap<u64, EpisodeEntry> = cached
+                .into_iter()
+                .filter_map(|episode| Some((episode.id?, episode)))
+                .collect();
+            let mut entries = Vec::with_capacity(all_episodes.len());
+            let mut to_fetch = Vec::new();
+            for episode in &all_episodes {
+                match cached.get(&(episode.id as u64)) {
+                    Some(entry) if since.is_some_and(|since| unchanged_since(episode, since)) => {
+                        // The listing has the numbers of the season type, which may have changed
+                        entries.push(renumbered(entry, episode));
+                    }
+                    _ => to_fetch.push(episode),
+                }
+            }
+
+            // Fetch extended details for new and updated episodes
+            println!(
+                "Caching {} episodes, {} of them new or updated...",
+                all_episodes.len(),
+                to_fetch.len()
+            );
+            progress.start(to_fetch.len());
+            // Futures don't run until polled, `buffered` keeps that many of them in flight
+            let requests: Vec<_> = to_fetch
+                .iter()
+                .map(|episode| self.fetch_episode(episode))
+                .collect();
+            // `buffered` keeps the order, so results line up with `to_fetch`
+            let mut fetches = stream::iter(requests).buffered(FETCH_CONCURRENCY);
+            let mut done = 0;
+            let result = loop {
+                let Some(fetched) = fetches.next().await else {
+                    break Ok(entries);
+                };
+                let episode = to_fetch[done];
+                match fetched.or_else(|e| keep_cached(episode, &cached, e)) {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => break Err(e),
                 }
+                done += 1;
+                progress.advance();
+            };
+            result
+        })
+    }
+}
+
+// A fixed hash, so tokens stored by another build still match their key
+fn api_key_hash(api_key: &str) -> u64 {
+    xxhash_rust::xxh3::xxh3_64(api_key.as_bytes())
+}
+
+fn load_token(api_key: &str) -> Option<String> {
+    load_token_from(&get_tvdb_token_path(), api_key)
+}
+
+fn load_token_from(path: &Path, api_key: &str) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let stored: StoredToken = serde_json::from_str(&content).ok()?;
+    (stored.api_key_hash == api_key_hash(api_key) && stored.expires > unix_now())
+        .then_some(stored.token)
+}
+
+fn save_token(api_key: &str, token: &str) -> Result<()> {
+    save_token_to(&get_tvdb_token_path(), api_key, token, TOKEN_LIFETIME_SECS)
+}
+
+/// Stores the token for `lifetime` seconds, readable only by the user since it grants
+/// access to the API in their name.
+fn save_token_to(path: &Path, api_key: &str, token: &str, lifetime: u64) -> Result<()> {
+    let stored = StoredToken {
+        token: token.to_string(),
+        expires: unix_now() + lifetime,
+        api_key_hash: api_key_hash(api_key),
+    };
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(path)?;
+    // The mode only applies to new files, a token file of older versions is narrowed too
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+    file.write_all(serde_json::to_string(&stored)?.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+
+    /// A client with a token, so it doesn't log in or read the stored one.
+    fn logged_in() -> TvdbClient {
+        TvdbClient {
+            client: http::client().unwrap(),
+            api_key: "key".to_string(),
+            token: Mutex::new(Some("token".to_string())),
+            season_type: DEFAULT_SEASON_TYPE.to_string(),
+            request_interval: None,
+            next_request: Mutex::new(Instant::now()),
+        }
+    }
+
+    #[test]
+    fn test_get_retries() {
+        let response = |status: &str| {
+            format!("HTTP/1.1 {status}\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n")
+        };
+        let (base, server) = http::serve(vec![
+            response("429 Too Many Requests"),
+            response("503 Service Unavailable"),
+            response("200 OK"),
+        ]);
+
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let status = runtime.block_on(async {
+            let tvdb = logged_in();
+            let url = format!("{base}/episodes/1/extended");
+            tvdb.get(&url, &[]).await.unwrap().status()
+        });
+
+        // Rate limits and server errors are retried until TVDB answers
+        assert_eq!(status, StatusCode::OK);
+        let requests = server.join().unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests
+            .iter()
+            .all(|(_, request)| request.contains("Bearer token")));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let elapsed = runtime.block_on(async {
+            let tvdb = logged_in().with_rate_limit(20.0);
+            let start = Instant::now();
+            for _ in 0..3 {
+                tvdb.wait_for_slot().await;
             }
+            start.elapsed()
+        });
+        // The first request goes out at once, the others 50ms apart
+        assert!(elapsed >= Duration::from_millis(100), "{elapsed:?}");
+    }
+
+    #[test]
+    fn test_stored_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tvdb_token.json");
+        assert_eq!(load_token_from(&path, "key"), None);
+
+        save_token_to(&path, "key", "token", TOKEN_LIFETIME_SECS).unwrap();
+        assert_eq!(load_token_from(&path, "key").as_deref(), Some("token"));
+        // Only for the key it was issued for
+        assert_eq!(load_token_from(&path, "other key"), None);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
         }
 
-        Ok(())
+        // An expired token is logged in again
+        save_token_to(&path, "key", "token", 0).unwrap();
+        assert_eq!(load_token_from(&path, "key"), None);
+    }
+
+    #[test]
+    fn test_failed_fetch_keeps_cached_episodes() {
+        let listed = |id, number| Episode {
+            id,
+            season_number: Some(1),
+            number: Some(number),
+            last_updated: None,
+        };
+        let entry = |id: u64, name: &str| EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number: id,
+            name: name.to_string(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: Some(id),
+            absolute_number: None,
+        };
+        let cached = HashMap::from([(1, entry(1, "Pilot")), (2, entry(2, "Second"))]);
+
+        // A rate limit outlasting the retries keeps the cached entry, renumbered by the listing
+        let kept = keep_cached(&listed(2, 3), &cached, anyhow!("HTTP 429")).unwrap();
+        assert_eq!((kept.name.as_str(), kept.episode_number), ("Second", 3));
+
+        // The series doesn't shrink when one of its episodes fails to fetch
+        let fetched = [Ok(entry(1, "Pilot (Updated)")), Err(anyhow!("HTTP 503"))];
+        let entries: Vec<EpisodeEntry> = fetched
+            .into_iter()
+            .zip([listed(1, 1), listed(2, 2)])
+            .map(|(result, episode)| result.or_else(|e| keep_cached(&episode, &cached, e)))
+            .collect::<Result<_>>()
+            .unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, ["Pilot (Updated)", "Second"]);
+
+        // A new episode has nothing to fall back on, the fetch fails and the cache stays as is
+        assert!(keep_cached(&listed(3, 4), &cached, anyhow!("HTTP 429")).is_err());
+    }
+
+    #[test]
+    fn test_unchanged_since() {
+        assert_eq!(unix_time("1970-01-01 00:00:00"), Some(0));
+        assert_eq!(unix_time("2000-02-29 00:00:00"), Some(951_782_400));
+        assert_eq!(unix_time("2023-11-14 22:13:20"), Some(1_700_000_000));
+        assert_eq!(unix_time("2023-11-14"), Some(1_699_920_000));
+        assert_eq!(unix_time("yesterday"), None);
+
+        let listed = |last_updated: Option<&str>| Episode {
+            id: 1,
+            season_number: Some(1),
+            number: Some(1),
+            last_updated: last_updated.map(str::to_string),
+        };
+        let since = 1_700_000_000;
+        // Kept from the cache when TVDB last touched it before the cache was filled
+        assert!(unchanged_since(&listed(Some("2023-11-14 22:13:20")), since));
+        assert!(unchanged_since(&listed(Some("2021-06-01 08:00:00")), since));
+        // Fetched again when updated since, or when the listing doesn't say
+        assert!(!unchanged_since(
+            &listed(Some("2023-11-14 22:13:21")),
+            since
+        ));
+        assert!(!unchanged_since(&listed(None), since));
+        assert!(!unchanged_since(&listed(Some("")), since));
     }
 }
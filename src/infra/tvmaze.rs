@@ -0,0 +1,240 @@
+use anyhow::{bail, Result};
+use futures_util::future::BoxFuture;
+use serde::Deserialize;
+
+use crate::domain::models::EpisodeEntry;
+use crate::infra::http;
+use crate::infra::provider::{FetchProgress, MetadataProvider, SeriesMatch};
+
+const TVMAZE_API_BASE: &str = "https://api.tvmaze.com";
+
+/// Client for the public TVmaze API, which needs no API key. TVmaze has no production
+/// codes, so production code matching needs a `production_codes` table for these shows.
+#[derive(Debug, Clone)]
+pub struct TvmazeClient {
+    client: reqwest::Client,
+}
+
+#[derive(Debug, Deserialize)]
+struct SearchResult {
+    show: Show,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Show {
+    id: u64,
+    name: String,
+    premiered: Option<String>, // YYYY-MM-DD
+    network: Option<Network>,
+    web_channel: Option<Network>, // for streaming shows, which have no network
+    status: Option<String>,
+    summary: Option<String>, // HTML
+}
+
+#[derive(Debug, Deserialize)]
+struct Network {
+    name: String,
+    country: Option<Country>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Country {
+    code: String, // ISO 3166-1 alpha-2
+}
+
+#[derive(Debug, Deserialize)]
+struct Episode {
+    id: u64,
+    name: String,
+    season: u64,
+    number: Option<u64>, // missing for specials
+    summary: Option<String>,
+    airdate: Option<String>, // empty when unknown
+    runtime: Option<u64>,
+}
+
+impl TvmazeClient {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            client: http::client()?,
+        })
+    }
+
+    async fn get(&self, path: &str, query: &[(&str, &str)]) -> Result<String> {
+        let response = self
+            .client
+            .get(format!("{TVMAZE_API_BASE}{path}"))
+            .query(query)
+            .send()
+            .await?;
+
+        if !response.status().is_success() {
+            bail!("TVmaze request {path} failed: HTTP {}", response.status());
+        }
+        Ok(response.text().await?)
+    }
+}
+
+impl MetadataProvider for TvmazeClient {
+    fn series_key(&self, series_id: &str) -> String {
+        format!("tvmaze:{series_id}")
+    }
+
+    fn search_series<'a>(&'a self, query: &'a str) -> BoxFuture<'a, Result<Vec<SeriesMatch>>> {
+        Box::pin(async move {
+            let content = self.get("/search/shows", &[("q", query)]).await?;
+            parse_search(&content)
+        })
+    }
+
+    fn get_series_name<'a>(&'a self, series_id: &'a str) -> BoxFuture<'a, Result<String>> {
+        Box::pin(async move {
+            let content = self.get(&format!("/shows/{series_id}"), &[]).await?;
+            let show: Show = serde_json::from_str(&content)?;
+            Ok(show.name)
+        })
+    }
+
+    // TVmaze lists all episodes in one response, there's nothing to save by reusing them
+    fn fetch_episodes<'a>(
+        &'a self,
+        series_id: &'a str,
+        _cached: Vec<EpisodeEntry>,
+        _since: Option<u64>,
+        _progress: &'a FetchProgress,
+    ) -> BoxFuture<'a, Result<Vec<EpisodeEntry>>> {
+        Box::pin(async move {
+            let content = self
+                .get(&format!("/shows/{series_id}/episodes"), &[])
+                .await?;
+            let entries = parse_episodes(&content)?;
+            println!("Caching {} episodes...", entries.len());
+            Ok(entries)
+        })
+    }
+}
+
+fn parse_search(content: &str) -> Result<Vec<SeriesMatch>> {
+    let results: Vec<SearchResult> = serde_json::from_str(content)?;
+    Ok(results
+        .into_iter()
+        .map(|SearchResult { show }| {
+            let network = show.network.or(show.web_channel);
+            SeriesMatch {
+                id: show.id.to_string(),
+                name: show.name,
+                year: show
+                    .premiered
+                    .and_then(|date| date.get(..4).map(str::to_string)),
+                country: network
+                    .as_ref()
+                    .and_then(|network| network.country.as_ref())
+                    .map(|country| country.code.clone()),
+                network: network.map(|network| network.name),
+                status: show.status,
+                overview: show.summary.as_deref().map(strip_html),
+            }
+        })
+        .collect())
+}
+
+fn parse_episodes(content: &str) -> Result<Vec<EpisodeEntry>> {
+    let episodes: Vec<Episode> = serde_json::from_str(content)?;
+    Ok(episodes
+        .into_iter()
+        .filter_map(|episode| {
+            Some(EpisodeEntry {
+                production_code: None,
+                season_number: episode.season,
+                episode_number: episode.number?,
+                name: episode.name,
+                overview: episode.summary.as_deref().map(strip_html),
+                aired: episode.airdate.filter(|date| !date.is_empty()),
+                runtime: episode.runtime,
+                id: Some(episode.id),
+                absolute_number: None,
+            })
+        })
+        .collect())
+}
+
+/// TVmaze summaries are HTML, e.g. `<p>Mulder and <b>Scully</b> ...</p>`.
+fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut in_tag = false;
+    for c in html.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            c if !in_tag => text.push(c),
+            _ => {}
+        }
+    }
+    text.replace("&amp;", "&")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_episodes() {
+        let content = r#"[
+            {"id": 1, "name": "Pilot", "season": 1, "number": 1, "summary": "<p>Two <b>FBI</b> agents.</p>", "airdate": "1993-09-10", "runtime": 48},
+            {"id": 2, "name": "Behind the Scenes", "season": 1, "number": null, "summary": null},
+            {"id": 3, "name": "Deep Throat", "season": 1, "number": 2, "summary": null}
+        ]"#;
+
+        let episodes = parse_episodes(content).unwrap();
+        assert_eq!(episodes.len(), 2);
+        assert_eq!(episodes[0].name, "Pilot");
+        assert_eq!(episodes[0].overview.as_deref(), Some("Two FBI agents."));
+        assert_eq!(episodes[0].aired.as_deref(), Some("1993-09-10"));
+        assert_eq!(episodes[1].episode_number, 2);
+        assert!(episodes[1].overview.is_none());
+    }
+
+    #[test]
+    fn test_parse_search() {
+        let content = r#"[
+            {"score": 0.9, "show": {"id": 430, "name": "The X-Files", "premiered": "1993-09-10", "status": "Ended", "network": {"name": "FOX", "country": {"code": "US"}}, "webChannel": null, "summary": "<p>Two FBI agents.</p>"}},
+            {"score": 0.4, "show": {"id": 9, "name": "X Files Reopened", "premiered": null, "network": null, "webChannel": {"name": "YouTube", "country": null}}}
+        ]"#;
+        let results = parse_search(content).unwrap();
+        assert_eq!(
+            results[0],
+            SeriesMatch {
+                id: "430".to_string(),
+                name: "The X-Files".to_string(),
+                year: Some("1993".to_string()),
+                network: Some("FOX".to_string()),
+                country: Some("US".to_string()),
+                status: Some("Ended".to_string()),
+                overview: Some("Two FBI agents.".to_string()),
+            }
+        );
+        assert_eq!(results[1].network.as_deref(), Some("YouTube"));
+        assert_eq!(
+            (results[1].year.as_ref(), results[1].country.as_ref()),
+            (None, None)
+        );
+    }
+
+    #[test]
+    fn test_strip_html() {
+        assert_eq!(strip_html("<p>Tom &amp; Jerry</p>"), "Tom & Jerry");
+        assert_eq!(strip_html("plain"), "plain");
+    }
+
+    #[test]
+    fn test_series_key() {
+        assert_eq!(TvmazeClient::new().unwrap().series_key("123"), "tvmaze:123");
+    }
+}
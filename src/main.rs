@@ -5,20 +5,136 @@ mod infra;
 mod media;
 mod workflows;
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use clap::Parser;
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::env;
 use std::fs;
 use std::io::{self, Write};
+use std::ops::ControlFlow;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+use tokio::runtime::Runtime;
 
-use cli::Cli;
-use infra::cache::Cache;
-use infra::tvdb::TvdbClient;
-use workflows::matchers::{prod_code::ProductionCodeMatcher, subtitle::SubtitleMatcher, Matcher};
-use workflows::renamer;
+use cli::{
+    AuthAction, CacheAction, Cli, Command, ConfigAction, EpisodeOrder, ImportSource, LinkMode,
+    OcrBackend, OutputFormat, Provider, ScanPosition, SdhPreference,
+};
+use config::{
+    ConflictPolicy, DuplicatePolicy, KeySource, LowConfidencePolicy, MultiPartPolicy, PromptPolicy,
+    RenamePolicy, Settings, SpecialsPolicy, UnmatchedPolicy,
+};
+use domain::models::{
+    unix_now, EpisodeEntry, JournalEntry, MediaInfo, OcrCandidate, ProbedMedia, ProcessedFile,
+};
+use infra::cache::{Cache, CacheExport};
+use infra::catalog_db::CatalogDb;
+use infra::code_table;
+use infra::journal;
+use infra::opensubtitles::OpenSubtitlesClient;
+use infra::provider::{EpisodeFetch, MetadataProvider, SeriesMatch};
+use infra::secrets;
+use infra::sidecar;
+use infra::tvdb::{self, TvdbClient};
+use infra::tvmaze::TvmazeClient;
+use media::ffmpeg::{self, FrameWindow};
+use media::mkvpropedit;
+use media::ocr::{self, FrameOcrOptions, OcrEnginePool};
+use media::preprocess::CropRegion;
+use media::subtitles::TrackPreference;
+use media::tessdata::TessdataSource;
+use workflows::batch::{self, SeriesMap};
+use workflows::detect;
+use workflows::duplicates::{self, Claim, Keep, MatchedEpisodes};
+use workflows::gaps::GapReport;
+use workflows::library::{self, Decision, LibraryDuplicatePolicy};
+use workflows::mapping::FileMapping;
+use workflows::matchers::air_date::AirDateMatcher;
+use workflows::matchers::audio::{self, AudioMatcher};
+use workflows::matchers::chain::ChainMatcher;
+use workflows::matchers::manual::ManualMatcher;
+use workflows::matchers::opensubtitles::OpenSubtitlesMatcher;
+use workflows::matchers::overrides::{AssumedMatcher, MappedMatcher, OffsetMatcher};
+use workflows::matchers::prod_code::{self, ProductionCodeMatcher};
+use workflows::matchers::range::{EpisodeRange, RangeMatcher};
+use workflows::matchers::reference::{ReferenceMatcher, ReferenceSubtitles};
+use workflows::matchers::{self, subtitle::SubtitleMatcher, Matcher};
+use workflows::matchers::{
+    duration::DurationMatcher,
+    filename::{self, FilenameMatcher},
+};
+use workflows::plan::{self, PlannedRename};
+use workflows::renamer::{NameTemplate, RenameOutcome, RetryPolicy};
+use workflows::report::{self, Action, FileReport, JsonReporter, RunSummary};
+use workflows::scan::{self, ShowCatalog};
+use workflows::sonarr::{ImportedFile, SonarrEvent, Verdict};
+use workflows::space::{self, LowSpacePolicy, Preflight};
+use workflows::{import, nfo, parallel, prompt, renamer, search, sonarr, tui, undo, verify};
 
 use crate::cli::MatchMode;
 
+/// Per-file processing options shared by every input of a run.
+#[derive(Clone)]
+struct ProcessOptions {
+    prompts: PromptPolicy,
+    recursive: bool,
+    jobs: usize,
+    prompt_size: Option<u64>,
+    match_mode: MatchMode,
+    order: EpisodeOrder,
+    production_codes: Option<PathBuf>, // see `code_table::merge`
+    subtitle_language: String,
+    subtitle_tracks: TrackPreference,
+    subtitle_windows: Vec<FrameWindow>, // parts of the video to read subtitles from, all if empty
+    ocr_sidecar: bool,
+    ocr_options: FrameOcrOptions,
+    ocr_pool: Arc<OcrEnginePool>, // shared by the matchers of all files
+    ignored_candidates: Vec<Regex>,
+    min_ocr_confidence: i32,
+    episode_range: EpisodeRange, // matches of other episodes are dropped
+    assumed: Option<(u64, u64)>, // --assume, the season and episode of the only file
+    mapping: FileMapping,        // --mapping-file, episodes of files named without matching
+    episode_offset: i64,         // added to the matched episode numbers
+    audio_offsets: Vec<u64>,
+    opensubtitles: Option<Arc<OpenSubtitlesClient>>, // tried before the match mode
+    reference_subs: Option<Arc<ReferenceSubtitles>>, // for the reference match mode
+    learn_audio: bool, // fingerprint matched files for the audio matcher
+    name_template: NameTemplate,
+    link_mode: LinkMode, // move files to their new name, or keep them and link or copy them
+    rename_retry: RetryPolicy,
+    preview_dir: Option<PathBuf>,
+    library_root: Option<PathBuf>, // --organize files into season folders under it
+    library_duplicates: Option<LibraryDuplicatePolicy>, // look for copies already in it
+    catalog: Option<ShowCatalog>,  // --catalog, the series' files `scan` found
+    write_nfo: bool,               // write a Kodi NFO next to every renamed file
+    tag_file: bool,                // write the episode into the MKV tags of renamed files
+    dry_run: bool,
+    force: bool,       // match files again even if an earlier run identified them
+    strict: bool,      // stop the run at the first file that fails
+    interactive: bool, // false if nothing may prompt, e.g. for `--output json`
+    deferred_prompts: Option<PromptPolicy>, // put off until the end, see `deferring_prompts`
+    run_id: u64,       // journal run of the renames, see `undo`
+}
+
+/// State that accumulates while the files of a run are processed.
+#[derive(Default)]
+struct RunState {
+    deferred_renames: Vec<JournalEntry>, // journaled once they succeed
+    rename_all: bool,                    // answered "yes to all" at the rename prompt
+    quit: bool,
+    plan: Vec<PlannedRename>,       // renames a dry run would have made
+    reporter: Option<JsonReporter>, // --output json
+    prefetched: Option<(PathBuf, Result<Vec<OcrCandidate>>)>, // see `prefetch_first_file`
+    matched: MatchedEpisodes,       // to notice two files of the same episode
+    summary: RunSummary,
+    deferred_files: Vec<(PathBuf, Vec<OcrCandidate>)>, // --defer-prompts, with the OCR read
+}
+
 fn main() {
     let cli = Cli::parse();
 
@@ -26,252 +142,2625 @@ fn main() {
         eprintln!("Error: {e}");
         std::process::exit(1);
     }
-}
+}
+
+fn run(mut cli: Cli) -> Result<()> {
+    // Applying a plan and undoing only rename files, they need neither TVDB nor the cache
+    let retry = RetryPolicy {
+        retries: cli.rename_retries,
+        initial_backoff: Duration::from_millis(cli.rename_backoff),
+        verify_checksum: !cli.no_verify_checksum,
+    };
+    match &cli.command {
+        Some(Command::ApplyPlan { plan_file }) => {
+            let overwrite = cli.on_conflict == Some(ConflictPolicy::Overwrite);
+            return run_apply_plan(plan_file, cli.link_mode, cli.low_space, overwrite, &retry);
+        }
+        Some(Command::Undo { run, list }) => return run_undo(*run, *list, &retry),
+        Some(Command::Config { action }) => return run_config(&cli, action),
+        Some(Command::Auth { action }) => return run_auth(action),
+        _ => {}
+    }
+    let settings = config::get_settings(None, &cli.settings())?;
+
+    // Set up first, so that everything printed from here on goes to stderr
+    let reporter = match cli.output {
+        OutputFormat::Json => Some(JsonReporter::new(cli.output_file.as_deref())?),
+        OutputFormat::Text => None,
+    };
+    let lookup_json = match &cli.command {
+        Some(
+            Command::Search {
+                output: OutputFormat::Json,
+                ..
+            }
+            | Command::Episodes {
+                output: OutputFormat::Json,
+                ..
+            },
+        ) => Some(report::parseable_stdout()?),
+        _ => None,
+    };
+    // The JSON report of a Sonarr check is all that goes to stdout
+    let sonarr_out = match cli.sonarr && cli.report.is_none() {
+        true => Some(report::parseable_stdout()?),
+        false => None,
+    };
+
+    // As a Sonarr custom script the file and series come from Sonarr's environment
+    let sonarr_file = if cli.sonarr {
+        match sonarr::event_from_env()? {
+            SonarrEvent::Test => {
+                println!("Sonarr connection test succeeded");
+                return Ok(());
+            }
+            SonarrEvent::Other(event_type) => {
+                println!("Nothing to check for Sonarr {event_type} events");
+                return Ok(());
+            }
+            SonarrEvent::Download(file) => Some(file),
+        }
+    } else {
+        None
+    };
+    let searches = cli.show.is_some()
+        || cli.detect_show
+        || matches!(cli.command, Some(Command::Search { .. }));
+    if cli.year.is_some() && !searches {
+        bail!("--year needs --show or --detect-show");
+    }
+    if cli.forget_show && cli.show.is_none() && !cli.detect_show {
+        bail!("--forget-show needs --show or --detect-show");
+    }
+    if cli.library_duplicates.is_some() && !cli.organize && !cli.catalog {
+        bail!("--library-duplicates needs --organize or --catalog");
+    }
+    if cli.assume.is_some() && !matches!(cli.inputs.as_slice(), [input] if input.is_file()) {
+        bail!("--assume needs a single file as input");
+    }
+    if sonarr_file.is_some() && settings.provider != Provider::Tvdb {
+        bail!("--sonarr needs the TVDB provider, Sonarr identifies series by TVDB ID");
+    }
+    // The TUI checks the renames it was given the go-ahead for, see `apply_plan`
+    let renames = cli.command.is_none() && !cli.dry_run && !cli.tui && cli.preview_dir.is_none();
+    if renames && sonarr_file.is_none() {
+        cli.inputs = preflight_inputs(&cli)?;
+    }
+
+    // Load cache
+    let mut cache = Cache::load(settings.cache_backend);
+    // Provider requests run on it, so episodes can be fetched in the background
+    let runtime = Runtime::new()?;
+    if let Some(Command::Cache { action }) = &cli.command {
+        let result = run_cache(&cli, &settings, &runtime, action, &mut cache);
+        if let Err(e) = cache.save() {
+            eprintln!("Warning: Failed to save cache: {e}");
+        }
+        return result;
+    }
+    if let Some(Command::Scan { library }) = &cli.command {
+        return run_scan(library, &cache);
+    }
+    let provider = create_provider(&cli, &settings.provider, &run_order(&cli)?)?;
+    if let Some(Command::Search { .. } | Command::Episodes { .. }) = &cli.command {
+        let result = run_lookup(&cli, &runtime, &provider, &mut cache, lookup_json);
+        if let Err(e) = cache.save() {
+            eprintln!("Warning: Failed to save cache: {e}");
+        }
+        return result;
+    }
+
+    // Exit code of the run, set by a Sonarr check and by `--output json`
+    let result = match batch_mappings(&cli, sonarr_file.is_some())? {
+        Some(mappings) => run_batch(&cli, &settings, &runtime, &mut cache, reporter, &mappings),
+        None => run_series(
+            &cli,
+            &runtime,
+            &provider,
+            &mut cache,
+            reporter,
+            sonarr_file.as_ref(),
+            sonarr_out,
+        ),
+    };
+
+    // Save cache before exiting
+    if let Err(e) = cache.save() {
+        eprintln!("Warning: Failed to save cache: {e}");
+    }
+
+    let exit_code = result?;
+    if exit_code != 0 {
+        std::process::exit(exit_code);
+    }
+    Ok(())
+}
+
+/// Runs the command for the one series given by `--show`/`--show-id` or Sonarr.
+fn run_series(
+    cli: &Cli,
+    runtime: &Runtime,
+    provider: &Arc<dyn MetadataProvider>,
+    cache: &mut Cache,
+    reporter: Option<JsonReporter>,
+    sonarr_file: Option<&ImportedFile>,
+    sonarr_out: Option<Box<dyn Write>>, // stdout for the report, see `report::parseable_stdout`
+) -> Result<i32> {
+    let (show_id, show_name, fetch) = match sonarr_file {
+        Some(file) => resolve_series(
+            runtime,
+            provider,
+            cache,
+            None,
+            Some(file.tvdb_id.clone()),
+            cli.offline,
+        )?,
+        None => {
+            if let (Some(show), true) = (&cli.show, cli.forget_show) {
+                forget_show_choice(provider.as_ref(), cache, &with_year(show, cli.year));
+            }
+            let show_id = match cli.show_id.clone() {
+                None if cli.show.is_none() && cli.detect_show => {
+                    Some(detect_and_select_show(cli, runtime, provider, cache)?)
+                }
+                show_id => show_id,
+            };
+            resolve_series(
+                runtime,
+                provider,
+                cache,
+                cli.show.as_deref().map(|show| with_year(show, cli.year)),
+                show_id,
+                cli.offline,
+            )?
+        }
+    };
+
+    match &cli.command {
+        Some(Command::Import { from, paths }) => finish_fetch(fetch, &show_id, cache)
+            .and_then(|()| run_import(from, paths, &show_id, cache))
+            .map(|()| 0),
+        Some(Command::Verify { library_dir }) => {
+            run_verify(cli, library_dir, &show_id, &show_name, cache, fetch)
+        }
+        Some(Command::Gaps) => run_gaps(&show_id, &show_name, cache, fetch),
+        Some(
+            Command::ApplyPlan { .. }
+            | Command::Undo { .. }
+            | Command::Cache { .. }
+            | Command::Config { .. }
+            | Command::Auth { .. }
+            | Command::Scan { .. }
+            | Command::Search { .. }
+            | Command::Episodes { .. },
+        ) => {
+            unreachable!("handled before resolving the series")
+        }
+        None => match sonarr_file {
+            Some(file) => run_sonarr(cli, file, &show_id, &show_name, cache, fetch, sonarr_out)
+                .map(|verdict| verdict.exit_code()),
+            None if cli.tui => run_tui(cli, &show_id, &show_name, cache, fetch).map(|()| 0),
+            None => run_match(cli, &show_id, &show_name, cache, reporter, fetch),
+        },
+    }
+}
+
+/// The series mappings of a batch run, which matches the files of several shows at once
+/// because no show was given.
+fn batch_mappings(cli: &Cli, sonarr: bool) -> Result<Option<SeriesMap>> {
+    let single_series = sonarr || cli.show.is_some() || cli.show_id.is_some();
+    if single_series || cli.command.is_some() || cli.tui || cli.detect_show {
+        return Ok(None);
+    }
+    let mappings = SeriesMap::new(&cli.map, config::get_series_mappings()?)?;
+    Ok((!mappings.is_empty()).then_some(mappings))
+}
+
+/// Matches the files of several shows, each with the series its path is mapped to. The
+/// episodes of a series are fetched when its first file comes up.
+fn run_batch(
+    cli: &Cli,
+    settings: &Settings,
+    runtime: &Runtime,
+    cache: &mut Cache,
+    reporter: Option<JsonReporter>,
+    mappings: &SeriesMap,
+) -> Result<i32> {
+    for input_path in &cli.inputs {
+        if !input_path.exists() {
+            eprintln!("Error: Input path does not exist: {input_path:?}");
+        }
+    }
+    let (groups, unmapped) = mappings.group(input_files(&cli.inputs, cli.recursive)?);
+
+    let mut state = RunState {
+        reporter,
+        ..RunState::default()
+    };
+    for file_path in &unmapped {
+        eprintln!("Warning: No series mapping matches {file_path:?}, skipping it");
+        let mut report = FileReport::new(file_path);
+        report.action = Action::Skipped;
+        report.error = Some("No series mapping matches the file".to_string());
+        if let Some(reporter) = &mut state.reporter {
+            reporter.emit(&report)?;
+        }
+        state.summary.record(&report);
+    }
+
+    for (series_id, files) in groups {
+        if state.quit {
+            break;
+        }
+        println!("Series {series_id}: {} file(s)", files.len());
+        // Mappings may name shows of another provider, e.g. `tvmaze:83`
+        let (provider, id) = batch::mapped_provider(&series_id, &settings.provider);
+        // The series' settings are keyed like its cache entries, see `series_key`
+        let settings_key = match provider {
+            Provider::Tvmaze => format!("tvmaze:{id}"),
+            Provider::Tvdb => id.to_string(),
+        };
+        let resolved = series_order(cli, &settings_key, "")
+            .and_then(|order| create_provider(cli, &provider, &order))
+            .and_then(|provider| {
+                resolve_series(
+                    runtime,
+                    &provider,
+                    cache,
+                    None,
+                    Some(id.to_string()),
+                    cli.offline,
+                )
+            });
+        let result = resolved.and_then(|(show_id, show_name, fetch)| {
+            match_files(cli, &files, &show_id, &show_name, cache, &mut state, fetch)
+        });
+        if let Err(e) = result {
+            eprintln!("Error processing the files of series {series_id}: {e}");
+            state.summary.record_unprocessed(&files, &format!("{e:#}"));
+            state.quit |= cli.strict;
+        }
+    }
+
+    finish_run(cli, &state)
+}
+
+/// The client of `provider`, fetching the episodes in `order`.
+fn create_provider(
+    cli: &Cli,
+    provider: &Provider,
+    order: &EpisodeOrder,
+) -> Result<Arc<dyn MetadataProvider>> {
+    let provider: Arc<dyn MetadataProvider> = match provider {
+        Provider::Tvdb => {
+            // Nothing is requested offline, so the key may be missing
+            let api_key = match config::get_tvdb_api_key() {
+                Err(_) if cli.offline => String::new(),
+                api_key => api_key?,
+            };
+            let mut client = TvdbClient::new(api_key)?.with_season_type(tvdb_season_type(order));
+            if let Some(rate) = config::get_tvdb_requests_per_second()? {
+                client = client.with_rate_limit(rate);
+            }
+            Arc::new(client)
+        }
+        Provider::Tvmaze if tvdb_season_type(order) != tvdb::DEFAULT_SEASON_TYPE => {
+            bail!("DVD and alternate order need the TVDB provider, TVmaze only has aired order")
+        }
+        Provider::Tvmaze => Arc::new(TvmazeClient::new()?),
+    };
+    Ok(provider)
+}
+
+/// The episode order of the run: that of the series given by `--show-id` or `--show`, see
+/// [`series_order`].
+fn run_order(cli: &Cli) -> Result<EpisodeOrder> {
+    series_order(
+        cli,
+        cli.show_id.as_deref().unwrap_or_default(),
+        cli.show.as_deref().unwrap_or_default(),
+    )
+}
+
+/// The episode order of a series: `--order`, else the `order` in the series' settings.
+fn series_order(cli: &Cli, series_id: &str, show_name: &str) -> Result<EpisodeOrder> {
+    let series_order = match &cli.order {
+        None if !series_id.is_empty() || !show_name.is_empty() => {
+            config::get_series_config(tvdb::series_id(series_id), show_name)?.order
+        }
+        _ => None,
+    };
+    Ok(cli
+        .order
+        .clone()
+        .or(series_order)
+        .unwrap_or(EpisodeOrder::Aired))
+}
+
+/// Writes the starter config for `config init`, or prints the effective settings for
+/// `config show`.
+fn run_config(cli: &Cli, action: &ConfigAction) -> Result<()> {
+    match action {
+        ConfigAction::Init { force } => {
+            let path = config::write_starter_config(*force)?;
+            println!("Wrote {path:?}, uncomment the settings to change");
+            Ok(())
+        }
+        ConfigAction::Show => {
+            let series = match (&cli.show_id, &cli.show) {
+                (None, None) => None,
+                (id, name) => Some(config::get_series_config(
+                    id.as_deref().unwrap_or_default(),
+                    name.as_deref().unwrap_or_default(),
+                )?),
+            };
+            let settings = config::get_settings(series.as_ref(), &cli.settings())?;
+            let path = config::get_config_path();
+            if path.exists() {
+                println!("# Config file: {path:?}");
+            } else {
+                println!("# No config file at {path:?}, see `config init`");
+            }
+            print!("{}", toml::to_string_pretty(&settings)?);
+            Ok(())
+        }
+    }
+}
+
+/// Stores the TVDB API key in the keyring for `auth set-key`, or prints where it's read
+/// from for `auth status`.
+fn run_auth(action: &AuthAction) -> Result<()> {
+    match action {
+        AuthAction::SetKey => {
+            if !secrets::KEYRING_AVAILABLE {
+                bail!("This build has no keyring support, build it with `--features keyring`");
+            }
+            let key = secrets::read_key("Enter the TVDB API key: ")?;
+            let key = key.trim();
+            if key.is_empty() {
+                bail!("No key entered, nothing was stored");
+            }
+            if let Err(e) = secrets::set(secrets::TVDB_API_KEY, key) {
+                bail!("Failed to store the key in the keyring: {e}");
+            }
+            println!("Stored the TVDB API key in the keyring");
+            if env::var_os("TVDB_API_KEY").is_some() {
+                println!("TVDB_API_KEY is set too and takes precedence over the keyring");
+            }
+            Ok(())
+        }
+        AuthAction::Status => {
+            let source = match config::find_tvdb_api_key()? {
+                None => "not set".to_string(),
+                Some((_, KeySource::Environment)) => {
+                    "the TVDB_API_KEY environment variable".to_string()
+                }
+                Some((_, KeySource::Keyring)) => "the OS keyring".to_string(),
+                Some((_, KeySource::ConfigFile)) => {
+                    format!("the config file {:?}", config::get_config_path())
+                }
+            };
+            println!("TVDB API key: {source}");
+            if !secrets::KEYRING_AVAILABLE {
+                println!("This build has no keyring support, build it with `--features keyring`");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Catalogs the video files of a library for `scan`.
+fn run_scan(library: &Path, cache: &Cache) -> Result<()> {
+    let db = CatalogDb::open(&config::get_catalog_db_path())?;
+    println!("Scanning {library:?}...");
+    let summary = scan::scan(library, &db, cache)?;
+    println!(
+        "Cataloged {} file(s), {} of them new or changed",
+        summary.files, summary.checksummed
+    );
+    if summary.unnamed > 0 {
+        println!(
+            "{} file(s) have no episode number in their name and weren't matched before",
+            summary.unnamed
+        );
+    }
+    if summary.unreadable > 0 {
+        println!(
+            "Skipped {} file(s) that couldn't be read",
+            summary.unreadable
+        );
+    }
+    if summary.removed > 0 {
+        println!("Dropped {} file(s) no longer there", summary.removed);
+    }
+    Ok(())
+}
+
+fn run_cache(
+    cli: &Cli,
+    settings: &Settings,
+    runtime: &Runtime,
+    action: &CacheAction,
+    cache: &mut Cache,
+) -> Result<()> {
+    match action {
+        CacheAction::Refresh { .. } if cli.offline => {
+            bail!("Refreshing the cache needs network access, drop --offline")
+        }
+        CacheAction::Refresh { series_id } => {
+            let provider = create_provider(cli, &settings.provider, &run_order(cli)?)?;
+            get_show_name(runtime, provider.as_ref(), series_id, cache)?;
+            EpisodeFetch::start(runtime.handle(), &provider, series_id, cache).finish(cache)
+        }
+        CacheAction::Export { file, series } => {
+            let export = cache.export_metadata(series);
+            fs::write(file, serde_json::to_string_pretty(&export)?)
+                .with_context(|| format!("Failed to write {file:?}"))?;
+            println!("Exported {} series to {file:?}", export.series.len());
+            Ok(())
+        }
+        CacheAction::Import { file, series } => {
+            let content =
+                fs::read_to_string(file).with_context(|| format!("Failed to read {file:?}"))?;
+            let export: CacheExport = serde_json::from_str(&content)
+                .with_context(|| format!("{file:?} isn't a cache export"))?;
+            let imported = cache.import_metadata(export, series);
+            println!("Imported {imported} series from {file:?}");
+            Ok(())
+        }
+        CacheAction::Clear { all } => {
+            cache.clear_metadata();
+            if *all {
+                cache.clear_history();
+                println!("Cleared the cache");
+            } else {
+                println!("Cleared the cached series and episodes");
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Prints the series a `search` finds or the episodes of a series for `episodes`, either
+/// from the provider or, with `--offline`, the cache. What is fetched is cached. With
+/// `--output json`, the JSON is written to `json_out`.
+fn run_lookup(
+    cli: &Cli,
+    runtime: &Runtime,
+    provider: &Arc<dyn MetadataProvider>,
+    cache: &mut Cache,
+    json_out: Option<Box<dyn Write>>,
+) -> Result<()> {
+    match &cli.command {
+        Some(Command::Search { query, .. }) => {
+            let (name, year) = search::split_year(query);
+            let mut results = if cli.offline {
+                search_cached_series(provider.as_ref(), cache, name)
+            } else {
+                runtime.block_on(provider.search_series(name))?
+            };
+            for series in &results {
+                cache.set_series_name(provider.series_key(&series.id), series.name.clone());
+            }
+            if let Some(year) = year.or(cli.year) {
+                results = search::narrow_by_year(results, year);
+            }
+            match json_out {
+                Some(mut out) => writeln!(out, "{}", serde_json::to_string_pretty(&results)?)?,
+                None if results.is_empty() => println!("No shows found matching '{query}'"),
+                None => print!("{}", search::format_series(&results)),
+            }
+        }
+        Some(Command::Episodes { series_id, .. }) => {
+            let (series_key, show_name, fetch) = resolve_series(
+                runtime,
+                provider,
+                cache,
+                None,
+                Some(series_id.clone()),
+                cli.offline,
+            )?;
+            finish_fetch(fetch, &series_key, cache)?;
+            let episodes = cache.get_series_episodes(&series_key);
+            match json_out {
+                Some(mut out) => writeln!(out, "{}", serde_json::to_string_pretty(&episodes)?)?,
+                None => {
+                    println!("{show_name} ({} episodes)", episodes.len());
+                    print!("{}", search::format_episodes(&episodes));
+                }
+            }
+        }
+        _ => unreachable!("only called for the search and episodes commands"),
+    }
+    Ok(())
+}
+
+/// Resolves the series from `--show`/`--show-id` and starts fetching its episodes unless
+/// they're cached and current. Returns the series key (see `MetadataProvider::series_key`),
+/// name and the fetch, which the run finishes once it needs the episodes. `offline` takes
+/// everything from the cache.
+fn resolve_series(
+    runtime: &Runtime,
+    provider: &Arc<dyn MetadataProvider>,
+    cache: &mut Cache,
+    show: Option<String>,
+    show_id: Option<String>,
+    offline: bool,
+) -> Result<(String, String, Option<EpisodeFetch>)> {
+    // Determine show ID
+    let show_id = match (show, show_id) {
+        (Some(show_name), None) => {
+            match search_and_select_show(runtime, provider.as_ref(), cache, &show_name, offline) {
+                Ok(id) => id,
+                Err(e) => {
+                    bail!("Error searching for show: {e}");
+                }
+            }
+        }
+        (None, Some(id)) => id,
+        (Some(_), Some(_)) => {
+            bail!("Error: Cannot specify both --show and --show-id");
+        }
+        (None, None) => {
+            bail!("Error: Must specify either --show or --show-id");
+        }
+    };
+
+    let series_key = provider.series_key(&show_id);
+
+    // Fetch all episodes unless they're already cached
+    let ttl_days = config::get_cache_ttl_days()?;
+    let fetch = if offline {
+        if !cache.has_series_episodes(&series_key) {
+            bail!("No episodes of series {series_key} are cached, run once without --offline to fetch them");
+        }
+        if cache.is_expired(&series_key, ttl_days, unix_now()) {
+            println!("Using cached episode data for series {series_key}, which is older than {ttl_days} day(s)");
+        } else {
+            println!("Using cached episode data for series {series_key}");
+        }
+        None
+    } else if !cache.has_series_episodes(&series_key) {
+        Some(EpisodeFetch::start(
+            runtime.handle(),
+            provider,
+            &show_id,
+            cache,
+        ))
+    } else if cache.is_expired(&series_key, ttl_days, unix_now()) {
+        println!("Cached episode data for series {series_key} is older than {ttl_days} day(s)");
+        Some(EpisodeFetch::start(
+            runtime.handle(),
+            provider,
+            &show_id,
+            cache,
+        ))
+    } else {
+        println!("Using cached episode data for series {series_key}");
+        None
+    };
+
+    // Get show name from cache or API, while the episodes are fetched
+    if offline && cache.get_series_name(&series_key).is_none() {
+        bail!(
+            "The name of series {series_key} isn't cached, run once without --offline to fetch it"
+        );
+    }
+    let show_name = match get_show_name(runtime, provider.as_ref(), &show_id, cache) {
+        Ok(name) => name,
+        Err(e) => {
+            bail!("Error getting show name: {e}");
+        }
+    };
+
+    Ok((series_key, show_name, fetch))
+}
+
+/// Waits for the episodes fetched in the background, if any.
+fn finish_fetch(fetch: Option<EpisodeFetch>, series_id: &str, cache: &mut Cache) -> Result<()> {
+    let Some(fetch) = fetch else {
+        return Ok(());
+    };
+    match fetch.finish(cache) {
+        // Stale episodes are still better than none when the provider can't be reached
+        Err(e) if cache.has_series_episodes(series_id) => {
+            eprintln!("Warning: Failed to refresh the episodes, using the cached ones: {e}");
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Makes the episodes ready for matching: waits for the fetch and adds the production codes
+/// the user maintains for the series.
+fn prepare_episodes(
+    fetch: Option<EpisodeFetch>,
+    series_id: &str,
+    options: &ProcessOptions,
+    cache: &mut Cache,
+) -> Result<()> {
+    finish_fetch(fetch, series_id, cache)?;
+
+    // Production codes the user maintains for shows that have none in TVDB
+    if let Some(path) = &options.production_codes {
+        let merged = code_table::merge(path, series_id, cache)?;
+        println!("Loaded {merged} production code(s) from {path:?}");
+    }
+
+    if options.order == EpisodeOrder::Absolute
+        && cache
+            .get_series_episodes(series_id)
+            .iter()
+            .all(|episode| episode.absolute_number.is_none())
+    {
+        eprintln!("Warning: No absolute episode numbers are cached for series {series_id}. TVmaze has none, and TVDB series cached by older versions need `episode-matcher cache refresh {series_id}` to fetch them.");
+    }
+    Ok(())
+}
+
+/// Builds the processing options from the CLI flags and the series' config.
+fn load_options(cli: &Cli, show_id: &str, show_name: &str) -> Result<ProcessOptions> {
+    // Per-series overrides from the config file, explicit CLI flags still take precedence.
+    // Sections are keyed by the series, not by one of its orderings (`77398#dvd`).
+    let series_config = config::get_series_config(tvdb::series_id(show_id), show_name)?;
+    let settings = config::get_settings(Some(&series_config), &cli.settings())?;
+
+    let mut prompts = config::get_prompt_policy()?;
+    if cli.no_confirm {
+        prompts.rename = RenamePolicy::Auto;
+    }
+    if let Some(on_conflict) = cli.on_conflict {
+        prompts.overwrite = on_conflict;
+    }
+    if let Some(specials) = cli.specials {
+        prompts.specials = specials;
+    }
+    // Sonarr runs custom scripts without a terminal, JSON output is read by scripts and the
+    // TUI reviews all matches at once
+    let interactive = cli.output == OutputFormat::Text && !cli.sonarr && !cli.tui;
+    if !interactive {
+        prompts.rename = RenamePolicy::Auto;
+        if prompts.overwrite == ConflictPolicy::Prompt {
+            prompts.overwrite = ConflictPolicy::Suffix;
+        }
+        prompts.unmatched = UnmatchedPolicy::Skip;
+        if prompts.duplicates == DuplicatePolicy::Ask {
+            prompts.duplicates = DuplicatePolicy::KeepBoth;
+        }
+        if prompts.low_confidence == LowConfidencePolicy::Ask {
+            prompts.low_confidence = LowConfidencePolicy::Skip;
+        }
+        if prompts.specials == SpecialsPolicy::Prompt {
+            prompts.specials = SpecialsPolicy::Exclude;
+        }
+        if prompts.multi_part == MultiPartPolicy::Ask {
+            prompts.multi_part = MultiPartPolicy::Flag;
+        }
+    }
+    let mut episode_range = EpisodeRange::new(cli.season, cli.episodes.as_deref())?;
+    if prompts.specials == SpecialsPolicy::Exclude {
+        episode_range = episode_range.without_specials();
+    }
+
+    let library_root = match (cli.organize, &cli.library_root) {
+        (false, _) => None,
+        (true, Some(root)) => Some(root.clone()),
+        (true, None) => Some(config::get_library_root()?.context(
+            "--organize needs a library root, set --library-root or library_root in the config",
+        )?),
+    };
+
+    let catalog = if cli.catalog {
+        Some(load_catalog(show_id, show_name)?)
+    } else {
+        None
+    };
+
+    let match_mode = cli
+        .match_mode
+        .clone()
+        .or(series_config.match_mode)
+        .unwrap_or(MatchMode::ProductionCode);
+    // Only numbers read from file names are shifted, the other matchers' are the provider's
+    if cli.episode_offset.is_some_and(|offset| offset != 0)
+        && !matches!(match_mode, MatchMode::Filename | MatchMode::Auto)
+    {
+        bail!(
+            "--episode-offset only shifts episode numbers read from file names, use it with --match-mode filename or auto"
+        );
+    }
+    // The auto mode only compares with references at hand, it never downloads a season's worth
+    let reference_dir = cli.reference_subs.clone().or(series_config.reference_subs);
+    let reference_subs = match (&match_mode, reference_dir) {
+        (MatchMode::Reference | MatchMode::Auto, Some(dir)) => {
+            Some(Arc::new(ReferenceSubtitles::new(dir, None)))
+        }
+        // Offline, only the subtitles downloaded by earlier runs can be compared with
+        (MatchMode::Reference, None) if cli.offline => {
+            let dir = config::get_reference_subs_dir_path().join(show_id);
+            if !dir.is_dir() {
+                bail!("The reference match mode needs --reference-subs when offline, no subtitles of this show were downloaded before");
+            }
+            Some(Arc::new(ReferenceSubtitles::new(dir, None)))
+        }
+        (MatchMode::Reference, None) => {
+            let api_key = config::get_opensubtitles_api_key()
+                .context("The reference match mode needs --reference-subs or an OpenSubtitles API key to download them")?;
+            Some(Arc::new(ReferenceSubtitles::new(
+                config::get_reference_subs_dir_path().join(show_id),
+                Some(Arc::new(OpenSubtitlesClient::new(api_key)?)),
+            )))
+        }
+        _ => None,
+    };
+
+    let mut name_template = NameTemplate::parse(&settings.name_template)?;
+    if let Some(specials_template) = &settings.specials_template {
+        name_template = name_template.with_specials(NameTemplate::parse(specials_template)?);
+    }
+
+    let code_regex = cli
+        .code_regex
+        .clone()
+        .or(series_config.code_regex.clone())
+        .map(|pattern| {
+            Regex::new(&pattern).with_context(|| format!("Invalid code_regex pattern '{pattern}'"))
+        })
+        .transpose()?;
+    // The default whitelist is for the default code format, a show's own may need more
+    let default_whitelist = code_regex
+        .is_none()
+        .then(|| ocr::CODE_WHITELIST.to_string());
+    let options = ProcessOptions {
+        prompts,
+        recursive: cli.recursive,
+        jobs: settings.jobs,
+        prompt_size: cli.prompt_size,
+        match_mode,
+        order: cli
+            .order
+            .clone()
+            .or(series_config.order)
+            .unwrap_or(EpisodeOrder::Aired),
+        production_codes: series_config.production_codes,
+        subtitle_language: settings.subtitles.language,
+        subtitle_tracks: TrackPreference {
+            avoid_forced: settings.subtitles.avoid_forced,
+            sdh: settings
+                .subtitles
+                .sdh
+                .map(|sdh| sdh == SdhPreference::Prefer),
+        },
+        subtitle_windows: cli
+            .subtitle_window
+            .or(series_config.subtitle_window)
+            .map(|minutes| {
+                let position = cli
+                    .subtitle_position
+                    .clone()
+                    .or(series_config.subtitle_position)
+                    .unwrap_or(ScanPosition::Start);
+                let seconds = minutes.checked_mul(60).with_context(|| {
+                    format!("A subtitle window of {minutes} minutes is too long")
+                })?;
+                anyhow::Ok(scan_windows(position, seconds))
+            })
+            .transpose()?
+            .unwrap_or_default(),
+        ocr_sidecar: cli.ocr_sidecar,
+        reference_subs,
+        opensubtitles: if cli.opensubtitles {
+            let api_key = config::get_opensubtitles_api_key()?;
+            Some(Arc::new(OpenSubtitlesClient::new(api_key)?))
+        } else {
+            None
+        },
+        ocr_options: FrameOcrOptions {
+            windows: scan_windows(
+                cli.scan_position
+                    .clone()
+                    .or(series_config.scan_position)
+                    .unwrap_or(ScanPosition::End),
+                cli.scan_window.or(series_config.scan_window).unwrap_or(15),
+            ),
+            scan_fps: cli.scan_fps.or(series_config.scan_fps).unwrap_or(1),
+            burst_fps: cli.burst_fps.or(series_config.burst_fps),
+            crop: cli
+                .crop
+                .clone()
+                .or(series_config.crop)
+                .map(|fractions| CropRegion::from_fractions(&fractions))
+                .transpose()?,
+            deskew: cli.deskew.or(series_config.deskew).unwrap_or(false),
+            rotate: cli.ocr_rotate.or(series_config.ocr_rotate),
+            tesseract: ocr::TesseractSettings {
+                page_seg_mode: cli.ocr_psm.or(series_config.ocr_psm),
+                whitelist: cli
+                    .ocr_whitelist
+                    .clone()
+                    .or(series_config.ocr_whitelist)
+                    .or(default_whitelist)
+                    .filter(|whitelist| !whitelist.is_empty()),
+            },
+            code_regex,
+            frames_dir: cli.frames_dir.clone(),
+            single_video: matches!(cli.inputs.as_slice(), [input] if input.is_file()),
+        },
+        ocr_pool: Arc::new(OcrEnginePool::new(
+            ocr_backend(&settings.ocr.backend)?,
+            TessdataSource {
+                dir: cli.tessdata.clone(),
+                download: cli.download_tessdata,
+            },
+            settings.ocr.lang,
+        )),
+        ignored_candidates: prod_code::compile_candidate_blacklist(
+            &series_config.ignore_candidates,
+        )?,
+        min_ocr_confidence: settings.ocr.min_confidence,
+        episode_range,
+        assumed: cli
+            .assume
+            .as_deref()
+            .map(|assume| {
+                filename::parse_episode_numbers(assume)
+                    .with_context(|| format!("Invalid --assume '{assume}', e.g. S03E07"))
+            })
+            .transpose()?,
+        mapping: cli
+            .mapping_file
+            .as_deref()
+            .map(FileMapping::load)
+            .transpose()?
+            .unwrap_or_default(),
+        episode_offset: cli.episode_offset.unwrap_or(0),
+        audio_offsets: cli
+            .audio_offsets
+            .clone()
+            .or(series_config.audio_offsets)
+            .unwrap_or_else(|| audio::DEFAULT_OFFSETS.to_vec()),
+        learn_audio: cli.learn_audio,
+        name_template: name_template.with_filenames(config::get_filename_policy()?),
+        link_mode: cli.link_mode,
+        rename_retry: RetryPolicy {
+            retries: cli.rename_retries,
+            initial_backoff: Duration::from_millis(cli.rename_backoff),
+            verify_checksum: !cli.no_verify_checksum,
+        },
+        run_id: journal::new_run_id(),
+        preview_dir: cli.preview_dir.clone(),
+        library_root,
+        library_duplicates: cli.library_duplicates.map(|policy| match policy {
+            LibraryDuplicatePolicy::Ask if !interactive => LibraryDuplicatePolicy::KeepBetter,
+            policy => policy,
+        }),
+        catalog,
+        write_nfo: cli.nfo,
+        tag_file: cli.tag_file,
+        dry_run: cli.dry_run,
+        force: cli.force,
+        strict: cli.strict,
+        interactive,
+        deferred_prompts: None,
+    };
+    Ok(options)
+}
+
+/// The options of the automatic pass of `--defer-prompts`: files that would ask which
+/// episode they are are queued for the review afterwards instead. `None` if nothing asks.
+fn deferring_prompts(options: &ProcessOptions) -> Option<ProcessOptions> {
+    let prompts = &options.prompts;
+    let asks = asks_episode(prompts, &options.match_mode)
+        || prompts.specials == SpecialsPolicy::Prompt
+        || prompts.multi_part == MultiPartPolicy::Ask;
+    if !options.interactive || !asks {
+        return None;
+    }
+    let mut automatic = options.clone();
+    automatic.interactive = false;
+    automatic.deferred_prompts = Some(prompts.clone());
+    automatic.prompts.unmatched = UnmatchedPolicy::Skip;
+    if automatic.prompts.low_confidence == LowConfidencePolicy::Ask {
+        automatic.prompts.low_confidence = LowConfidencePolicy::Skip;
+    }
+    if automatic.prompts.multi_part == MultiPartPolicy::Ask {
+        automatic.prompts.multi_part = MultiPartPolicy::Flag;
+    }
+    Some(automatic)
+}
+
+/// Whether an interactive run asks which episode a file is when it can't tell.
+fn asks_episode(prompts: &PromptPolicy, match_mode: &MatchMode) -> bool {
+    prompts.unmatched == UnmatchedPolicy::Ask
+        || prompts.low_confidence == LowConfidencePolicy::Ask
+        || matches!(
+            match_mode,
+            MatchMode::Subtitles | MatchMode::SubtitlesAuto | MatchMode::Reference
+        )
+}
+
+fn run_match(
+    cli: &Cli,
+    show_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    reporter: Option<JsonReporter>,
+    fetch: Option<EpisodeFetch>,
+) -> Result<i32> {
+    let mut state = RunState {
+        reporter,
+        ..RunState::default()
+    };
+    match_files(
+        cli,
+        &cli.inputs,
+        show_id,
+        show_name,
+        cache,
+        &mut state,
+        fetch,
+    )?;
+    finish_run(cli, &state)
+}
+
+/// Matches and renames the files of `inputs`, which belong to one series.
+fn match_files(
+    cli: &Cli,
+    inputs: &[PathBuf],
+    show_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    state: &mut RunState,
+    fetch: Option<EpisodeFetch>,
+) -> Result<()> {
+    let options = load_options(cli, show_id, show_name)?;
+    let automatic = cli
+        .defer_prompts
+        .then(|| deferring_prompts(&options))
+        .flatten();
+    let first_pass = automatic.as_ref().unwrap_or(&options);
+    // Reading the first file doesn't need the episodes, so it needn't wait for them either
+    if fetch.is_some() {
+        state.prefetched = prefetch_first_file(inputs, show_id, cache, first_pass);
+    }
+    prepare_episodes(fetch, show_id, first_pass, cache)?;
+
+    // Validate and process all input paths
+    for input_path in inputs {
+        if state.quit {
+            break;
+        }
+        if !input_path.exists() {
+            eprintln!("Error: Input path does not exist: {input_path:?}");
+            continue;
+        }
+
+        if let Err(e) = process_input_path(input_path, show_id, show_name, cache, first_pass, state)
+        {
+            eprintln!("Error processing path {input_path:?}: {e}");
+            state
+                .summary
+                .record_unprocessed(std::slice::from_ref(input_path), &format!("{e:#}"));
+            // Continue processing other paths, unless --strict
+            state.quit |= options.strict;
+        }
+    }
+    review_deferred_files(show_id, show_name, cache, &options, state);
+
+    let failed = retry_deferred_renames(
+        &state.deferred_renames,
+        options.link_mode,
+        &options.rename_retry,
+    );
+    state.summary.settle_deferred(&failed);
+    state.deferred_renames.clear();
+    if cli.gaps || options.catalog.is_some() {
+        let mut held = options
+            .catalog
+            .as_ref()
+            .map(ShowCatalog::held)
+            .unwrap_or_default();
+        held.extend(state.matched.episodes(show_id));
+        println!();
+        print_gaps(show_id, show_name, cache, &held);
+    }
+    Ok(())
+}
+
+/// Prints the gap report of the series for the episodes `held`.
+fn print_gaps(
+    show_id: &str,
+    show_name: &str,
+    cache: &Cache,
+    held: &HashSet<(u64, u64)>,
+) -> GapReport {
+    let episodes = cache.get_series_episodes(show_id);
+    let report = GapReport::new(&episodes, held, &scan::iso_date(unix_now()));
+    print!("{}", report.format(show_name));
+    report
+}
+
+/// Prints which episodes the library catalog misses for `gaps`, the exit code is 2 if a
+/// season misses any.
+fn run_gaps(
+    show_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    fetch: Option<EpisodeFetch>,
+) -> Result<i32> {
+    finish_fetch(fetch, show_id, cache)?;
+    let catalog = load_catalog(show_id, show_name)?;
+    let report = print_gaps(show_id, show_name, cache, &catalog.held());
+    Ok(if report.is_complete() { 0 } else { 2 })
+}
+
+/// The cataloged files of the series, for `--catalog` and `gaps`.
+fn load_catalog(show_id: &str, show_name: &str) -> Result<ShowCatalog> {
+    let path = config::get_catalog_db_path();
+    if !path.exists() {
+        bail!("No library catalog yet, run `episode-matcher scan <library>` first");
+    }
+    ShowCatalog::load(&CatalogDb::open(&path)?, show_id, show_name)
+}
+
+/// Asks about the files `--defer-prompts` queued, now that every other file is matched. The
+/// OCR of the automatic pass is reused rather than run again.
+fn review_deferred_files(
+    series_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    options: &ProcessOptions,
+    state: &mut RunState,
+) {
+    let deferred = std::mem::take(&mut state.deferred_files);
+    if deferred.is_empty() || state.quit {
+        return;
+    }
+    println!("{} file(s) need your input:", deferred.len());
+    let ocr_ran = matches!(
+        options.match_mode,
+        MatchMode::ProductionCode | MatchMode::Auto
+    );
+    for (file_path, candidates) in deferred {
+        if state.quit {
+            break;
+        }
+        println!();
+        let prefetched = ocr_ran.then_some(Ok(candidates));
+        if let Err(e) = process_and_report(
+            &file_path, series_id, show_name, cache, options, state, prefetched,
+        ) {
+            eprintln!("Error processing {file_path:?}: {e}");
+        }
+    }
+}
+
+/// Prints the rename plan of a dry run and returns the exit code of the run.
+fn finish_run(cli: &Cli, state: &RunState) -> Result<i32> {
+    if cli.dry_run {
+        println!("Rename plan ({} file(s)):", state.plan.len());
+        print!("{}", plan::format_table(&state.plan));
+        if let Some(plan_file) = &cli.plan_file {
+            plan::save(plan_file, &state.plan)?;
+            println!("Wrote rename plan to {plan_file:?}");
+        }
+    }
+
+    if !state.summary.is_empty() {
+        let summary = state.summary.format();
+        println!();
+        print!("{summary}");
+        if let Some(summary_file) = &cli.summary_file {
+            fs::write(summary_file, &summary)
+                .with_context(|| format!("Failed to write {summary_file:?}"))?;
+            println!("Wrote summary to {summary_file:?}");
+        }
+    }
+
+    // Scripts learn from the exit code that files need attention
+    Ok(state.summary.exit_code())
+}
+
+/// Matches the files of all inputs in the background while they're reviewed in the TUI,
+/// then makes the approved renames.
+fn run_tui(
+    cli: &Cli,
+    show_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    fetch: Option<EpisodeFetch>,
+) -> Result<()> {
+    let mut options = load_options(cli, show_id, show_name)?;
+    options.dry_run = true; // nothing is renamed before the review
+    prepare_episodes(fetch, show_id, &options, cache)?;
+
+    let mut files = Vec::new();
+    for input_path in &cli.inputs {
+        if input_path.is_dir() {
+            files.extend(collect_mkv_files(input_path, options.recursive)?);
+        } else if input_path.is_file() {
+            files.push(input_path.clone());
+        } else {
+            eprintln!("Error: Input path does not exist: {input_path:?}");
+        }
+    }
+    if files.is_empty() {
+        bail!("No files to review");
+    }
+
+    let naming = tui::Naming {
+        series_id: show_id,
+        show_name,
+        template: &options.name_template,
+        library_root: options.library_root.as_deref(),
+        episodes: cache
+            .get_series_episodes(show_id)
+            .into_iter()
+            .cloned()
+            .collect(),
+    };
+    let log_path = std::env::temp_dir().join("episode-matcher-tui.log");
+    let cancel = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    let approved = thread::scope(|scope| {
+        let redirect = tui::OutputRedirect::to_file(&log_path)?;
+        let terminal = redirect.terminal()?;
+        let (files, options, cancel) = (&files, &options, &cancel);
+        let cache = &mut *cache;
+        scope.spawn(move || {
+            match_for_review(files, show_id, show_name, cache, options, &sender, cancel)
+        });
+        let approved = tui::review(terminal, files, &receiver, naming);
+        drop(redirect);
+        // The file being matched is finished when the scope ends
+        cancel.store(true, Ordering::Relaxed);
+        approved
+    })?;
+
+    println!("Matching output was written to {log_path:?}");
+    if approved.is_empty() {
+        println!("Nothing to rename");
+        return Ok(());
+    }
+    println!("Renaming {} approved file(s)", approved.len());
+    let overwrite = options.prompts.overwrite == ConflictPolicy::Overwrite;
+    // Approved files get the same NFO, tags and remembered episodes as renames of a match run
+    let result = apply_plan(
+        &approved,
+        options.link_mode,
+        cli.low_space,
+        overwrite,
+        &options.rename_retry,
+        |planned| {
+            let episodes = planned_episodes(cache, planned);
+            let series_id = &planned.series_id;
+            let nfo = finish_rename(
+                &planned.new_path,
+                show_name,
+                series_id,
+                &episodes,
+                cache,
+                &options,
+            );
+            remember_episodes(cache, &planned.new_path, series_id, &episodes);
+            nfo
+        },
+    );
+    save_progress(cache);
+    result
+}
+
+/// The cached episodes a planned rename names the file after.
+fn planned_episodes(cache: &Cache, planned: &PlannedRename) -> Vec<EpisodeEntry> {
+    let last = planned
+        .last_episode_number
+        .unwrap_or(planned.episode_number);
+    cache
+        .get_series_episodes(&planned.series_id)
+        .into_iter()
+        .filter(|episode| {
+            episode.season_number == planned.season_number
+                && (planned.episode_number..=last).contains(&episode.episode_number)
+        })
+        .cloned()
+        .collect()
+}
+
+/// Dry-runs `files` for the TUI, sending every result to the review as soon as it's known.
+fn match_for_review(
+    files: &[PathBuf],
+    series_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    options: &ProcessOptions,
+    matched: &mpsc::Sender<tui::Matched>,
+    cancel: &AtomicBool,
+) {
+    let mut state = RunState::default();
+    for file_path in files {
+        if cancel.load(Ordering::Relaxed) {
+            break;
+        }
+        let mut report = FileReport::new(file_path);
+        let result = process_file(
+            file_path,
+            series_id,
+            show_name,
+            cache,
+            options,
+            &mut state,
+            None,
+            &mut report,
+        );
+        if let Err(e) = result {
+            eprintln!("Error processing {file_path:?}: {e}");
+            report.action = Action::Failed;
+            report.error = Some(format!("{e:#}"));
+        }
+        save_progress(cache);
+        // The dry run planned a rename if it identified the file. The plan is kept, so later
+        // files aren't given the same name
+        let plan = state
+            .plan
+            .last()
+            .filter(|planned| planned.old_path == *file_path)
+            .cloned();
+        if matched.send(tui::Matched { report, plan }).is_err() {
+            break; // the review is over
+        }
+    }
+}
+
+/// Saves the cache after every file, so an interrupted run resumes where it stopped.
+fn save_progress(cache: &Cache) {
+    if let Err(e) = cache.save() {
+        eprintln!("Warning: Failed to save cache: {e}");
+    }
+}
+
+fn ocr_backend(choice: &OcrBackend) -> Result<ocr::Backend> {
+    match choice {
+        OcrBackend::Auto if ocr::VISION_AVAILABLE => Ok(ocr::Backend::Vision),
+        OcrBackend::Auto | OcrBackend::Tesseract => Ok(ocr::Backend::Tesseract),
+        OcrBackend::Vision if ocr::VISION_AVAILABLE => Ok(ocr::Backend::Vision),
+        OcrBackend::Vision => {
+            bail!("Apple Vision OCR needs a macOS build with `cargo build --features vision`")
+        }
+    }
+}
+
+/// The parts of the video to extract frames from for production code OCR.
+fn scan_windows(position: ScanPosition, seconds: u32) -> Vec<FrameWindow> {
+    match position {
+        ScanPosition::Start => vec![FrameWindow::Start(seconds)],
+        ScanPosition::End => vec![FrameWindow::End(seconds)],
+        ScanPosition::Both => vec![FrameWindow::Start(seconds), FrameWindow::End(seconds)],
+    }
+}
+
+/// Checks the episode Sonarr imported a file as against the one the matcher identifies,
+/// and reports the result as JSON on stdout or in the `--report` file.
+fn run_sonarr(
+    cli: &Cli,
+    file: &ImportedFile,
+    show_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    fetch: Option<EpisodeFetch>,
+    out: Option<Box<dyn Write>>,
+) -> Result<Verdict> {
+    let options = load_options(cli, show_id, show_name)?;
+    prepare_episodes(fetch, show_id, &options, cache)?;
+
+    println!("Checking {:?}", file.path);
+    let matcher = create_matcher(&options, None)?;
+    let found = matchers::resolve(
+        matcher.match_episode(&file.path, show_id, cache)?,
+        options.prompts.low_confidence,
+    );
+    let report = sonarr::check(file, &found, matcher.source());
+
+    let json = serde_json::to_string_pretty(&report)?;
+    match (&cli.report, out) {
+        (Some(path), _) => fs::write(path, json)?,
+        (None, Some(mut out)) => writeln!(out, "{json}")?,
+        (None, None) => println!("{json}"),
+    }
+    println!("Verdict: {:?}", report.verdict);
+    Ok(report.verdict)
+}
+
+/// Identifies the files of an already renamed library and reports those that don't show
+/// the episode their name says. Nothing is renamed and nothing prompts, so a file is only
+/// reported for a confident match. The exit code is that of a Sonarr mismatch if any.
+fn run_verify(
+    cli: &Cli,
+    library_dir: &Path,
+    show_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    fetch: Option<EpisodeFetch>,
+) -> Result<i32> {
+    let mut options = load_options(cli, show_id, show_name)?;
+    options.interactive = false;
+    options.prompts.unmatched = UnmatchedPolicy::Skip;
+    options.prompts.low_confidence = LowConfidencePolicy::Skip;
+    prepare_episodes(fetch, show_id, &options, cache)?;
+
+    let files = collect_mkv_files(library_dir, true)
+        .with_context(|| format!("Failed to read {library_dir:?}"))?;
+    let (mut confirmed, mut unverified) = (0, 0);
+    let mut mismatches = Vec::new();
+    for file_path in &files {
+        let file_name = file_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let Some((season, episodes)) = verify::named_episodes(file_name) else {
+            println!("Skipping {file_path:?}, its name has no episode number");
+            continue;
+        };
+        let named = verify::format_episodes(season, &episodes);
+        println!("Verifying {file_path:?} ({named})");
+
+        let matcher = content_matcher(&options)?;
+        let found = match matcher.match_episode(file_path, show_id, cache) {
+            Ok(matches) => matchers::resolve(matches, options.prompts.low_confidence),
+            Err(e) => {
+                eprintln!("Error: Failed to identify {file_path:?}: {e:#}");
+                Vec::new()
+            }
+        };
+        save_progress(cache);
+        match sonarr::verdict(season, &episodes, &found) {
+            Verdict::Confirmed => confirmed += 1,
+            Verdict::Unverified => unverified += 1,
+            Verdict::Mismatch => {
+                let mismatch = format!(
+                    "{file_path:?} is named {named}, but shows {} (by {})",
+                    matchers::describe(&found),
+                    matcher.source()
+                );
+                println!("Mismatch: {mismatch}");
+                mismatches.push(mismatch);
+            }
+        }
+    }
+
+    println!(
+        "{confirmed} file(s) confirmed, {} mismatched, {unverified} couldn't be identified",
+        mismatches.len()
+    );
+    for mismatch in &mismatches {
+        println!("  {mismatch}");
+    }
+    Ok(if mismatches.is_empty() {
+        0
+    } else {
+        Verdict::Mismatch.exit_code()
+    })
+}
+
+/// Gives renames that kept failing with transient errors one last chance at the end of
+/// the run, when the files have hopefully been released. Returns the files that still
+/// failed with their error.
+fn retry_deferred_renames(
+    deferred: &[JournalEntry],
+    link_mode: LinkMode,
+    retry: &RetryPolicy,
+) -> Vec<(PathBuf, String)> {
+    if deferred.is_empty() {
+        return Vec::new();
+    }
+
+    println!("Retrying {} deferred rename(s)...", deferred.len());
+    let mut failed = Vec::new();
+    for entry in deferred {
+        match renamer::transfer_with_retry(&entry.old_path, &entry.new_path, link_mode, retry) {
+            Ok(checksum) => {
+                println!("Renamed {:?} -> {:?}", entry.old_path, entry.new_path);
+                let entry = JournalEntry {
+                    checksum,
+                    ..entry.clone()
+                };
+                let sidecars = move_sidecars(&entry, link_mode, retry);
+                record_rename(entry);
+                sidecars.into_iter().for_each(record_rename);
+            }
+            Err(e) => failed.push((entry.old_path.clone(), e.to_string())),
+        }
+    }
+
+    if !failed.is_empty() {
+        eprintln!(
+            "Could not rename {} file(s), try again later:",
+            failed.len()
+        );
+        for (old_path, e) in &failed {
+            eprintln!("  {old_path:?}: {e}");
+        }
+    }
+    failed
+}
+
+/// Gives the subtitles, NFO and artwork next to a renamed file its new name, see
+/// [`renamer::move_sidecars`]. Returns the journal entries of the sidecars.
+fn move_sidecars(
+    entry: &JournalEntry,
+    link_mode: LinkMode,
+    retry: &RetryPolicy,
+) -> Vec<JournalEntry> {
+    renamer::move_sidecars(&entry.old_path, &entry.new_path, link_mode, retry)
+        .into_iter()
+        .map(|(old_path, new_path)| JournalEntry {
+            old_path,
+            new_path,
+            checksum: None,
+            ..entry.clone()
+        })
+        .collect()
+}
+
+/// The journal entry of a file written next to the renamed one, which undo deletes.
+fn created_entry(rename: &JournalEntry, path: PathBuf) -> JournalEntry {
+    JournalEntry {
+        old_path: path.clone(),
+        new_path: path,
+        kept_original: false,
+        checksum: None,
+        created: true,
+        ..rename.clone()
+    }
+}
+
+/// Adds a rename to the journal. A failure is only reported, the rename itself succeeded.
+fn record_rename(entry: JournalEntry) {
+    if let Err(e) = journal::append(&[entry]) {
+        eprintln!("Warning: Failed to record rename in the journal: {e}");
+    }
+}
+
+fn run_apply_plan(
+    plan_file: &Path,
+    link_mode: LinkMode,
+    low_space: LowSpacePolicy,
+    overwrite: bool,
+    retry: &RetryPolicy,
+) -> Result<()> {
+    let entries = plan::load(plan_file)?;
+    println!("Applying {} rename(s) from {plan_file:?}", entries.len());
+    apply_plan(&entries, link_mode, low_space, overwrite, retry, |_| None)
+}
+
+/// Makes planned renames after checking them again, see [`plan::validate`]. `renamed` is
+/// called with every rename that was made, after its sidecars were moved, and returns the
+/// NFO it wrote for it, if any, to be journaled.
+fn apply_plan(
+    entries: &[PlannedRename],
+    link_mode: LinkMode,
+    low_space: LowSpacePolicy,
+    overwrite: bool,
+    retry: &RetryPolicy,
+    mut renamed: impl FnMut(&PlannedRename) -> Option<PathBuf>,
+) -> Result<()> {
+    let run_id = journal::new_run_id();
+    let transfers: Vec<(PathBuf, PathBuf)> = entries
+        .iter()
+        .map(|entry| (entry.old_path.clone(), parent_dir(&entry.new_path)))
+        .collect();
+    let preflight = space::check(&transfers, link_mode);
+    report_shortfalls(&preflight, low_space)?;
+
+    let mut failed = 0;
+    for entry in entries {
+        if preflight.left_out.contains(&entry.old_path) {
+            continue;
+        }
+        let result = plan::validate(entry, overwrite).and_then(|()| {
+            renamer::transfer_with_retry(&entry.old_path, &entry.new_path, link_mode, retry)
+                .map_err(Into::into)
+        });
+        match result {
+            Ok(checksum) => {
+                println!("Renamed {:?} -> {:?}", entry.old_path, entry.new_path);
+                let planned = entry;
+                let entry = JournalEntry {
+                    timestamp: unix_now(),
+                    run_id,
+                    old_path: entry.old_path.clone(),
+                    new_path: entry.new_path.clone(),
+                    series_id: entry.series_id.clone(),
+                    season_number: entry.season_number,
+                    episode_number: entry.episode_number,
+                    last_episode_number: entry.last_episode_number,
+                    kept_original: link_mode != LinkMode::Rename,
+                    checksum,
+                    imported: false,
+                    created: false,
+                };
+                let sidecars = move_sidecars(&entry, link_mode, retry);
+                let nfo = renamed(planned).map(|nfo| created_entry(&entry, nfo));
+                record_rename(entry);
+                sidecars.into_iter().chain(nfo).for_each(record_rename);
+            }
+            Err(e) => {
+                eprintln!("Skipping {:?}: {e}", entry.old_path);
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {} rename(s) could not be made", entries.len());
+    }
+    Ok(())
+}
+
+fn run_undo(run_id: Option<u64>, list: bool, retry: &RetryPolicy) -> Result<()> {
+    let entries = journal::load()?;
+    let runs = undo::group_runs(&entries);
+
+    if list {
+        for run in &runs {
+            let imported = if run.is_imported() { " (imported)" } else { "" };
+            println!(
+                "Run {}: {} rename(s){imported}",
+                run.run_id,
+                run.entries.len()
+            );
+        }
+        return Ok(());
+    }
+
+    // Imported runs hold renames another tool made, they're only undone when asked for
+    let run = match run_id {
+        Some(run_id) => runs.iter().find(|run| run.run_id == run_id),
+        None => runs.iter().rev().find(|run| !run.is_imported()),
+    };
+    let Some(run) = run else {
+        bail!("No matching run in the rename journal");
+    };
+    if run.is_imported() {
+        println!(
+            "Run {} was imported from another tool's history, undoing the renames it made",
+            run.run_id
+        );
+    }
+
+    println!(
+        "Undoing {} rename(s) of run {}",
+        run.entries.len(),
+        run.run_id
+    );
+    let (reverted, skipped) = undo::revert(run, retry)?;
+    println!("Reverted {reverted} rename(s), skipped {skipped}");
+    Ok(())
+}
+
+fn run_import(
+    from: &ImportSource,
+    paths: &[PathBuf],
+    series_id: &str,
+    cache: &mut Cache,
+) -> Result<()> {
+    match from {
+        ImportSource::Filebot => {
+            for history_path in paths {
+                match import::import_filebot_history(history_path, series_id, cache) {
+                    Ok(count) => println!("Imported {count} rename(s) from {history_path:?}"),
+                    Err(e) => eprintln!("Error importing {history_path:?}: {e}"),
+                }
+            }
+        }
+        ImportSource::Nfo => {
+            let mut imported = 0;
+            for path in paths {
+                let files = if path.is_dir() {
+                    collect_mkv_files(path, true)?
+                } else {
+                    vec![path.clone()]
+                };
+                for file_path in files {
+                    match import::import_nfo(&file_path, series_id, cache) {
+                        Ok(true) => imported += 1,
+                        Ok(false) => {}
+                        Err(e) => eprintln!("Error importing {file_path:?}: {e}"),
+                    }
+                }
+            }
+            println!("Imported {imported} file(s) from NFOs");
+        }
+    }
+
+    Ok(())
+}
+
+fn process_input_path(
+    input_path: &Path,
+    series_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    options: &ProcessOptions,
+    state: &mut RunState,
+) -> Result<()> {
+    if input_path.is_file() {
+        process_and_report(
+            input_path, series_id, show_name, cache, options, state, None,
+        )?;
+    } else if input_path.is_dir() {
+        process_directory(input_path, series_id, show_name, cache, options, state)?;
+    } else {
+        bail!("Input path is neither a file nor a directory");
+    }
+
+    Ok(())
+}
+
+/// Searches the show named in the metadata of the first input file, or asks for a name to
+/// search if there's none or nothing is found.
+fn detect_and_select_show(
+    cli: &Cli,
+    runtime: &Runtime,
+    provider: &Arc<dyn MetadataProvider>,
+    cache: &mut Cache,
+) -> Result<String> {
+    let mut first_file = None;
+    for input_path in &cli.inputs {
+        if input_path.is_dir() {
+            first_file = collect_mkv_files(input_path, cli.recursive)?
+                .into_iter()
+                .next();
+        } else if input_path.is_file() {
+            first_file = Some(input_path.clone());
+        }
+        if first_file.is_some() {
+            break;
+        }
+    }
+
+    // The show chosen for the files of a directory before is used for it again
+    let directory_key = first_file
+        .as_deref()
+        .and_then(Path::parent)
+        .and_then(|directory| fs::canonicalize(directory).ok())
+        .map(|directory| directory_choice_key(provider.as_ref(), &directory));
+    if let Some(key) = &directory_key {
+        if cli.forget_show {
+            cache.forget_show_choice(key);
+        } else if let Some(id) = cache.get_show_choice(key) {
+            println!(
+                "Using show {id} chosen for this directory before (--forget-show to search again)"
+            );
+            return Ok(id.clone());
+        }
+    }
+    let id = detect_show_id(cli, runtime, provider, cache, first_file.as_deref())?;
+    if let Some(key) = directory_key {
+        cache.set_show_choice(key, id.clone());
+    }
+    Ok(id)
+}
+
+/// Searches the show named in the metadata of `first_file`, or typed in if there's none.
+fn detect_show_id(
+    cli: &Cli,
+    runtime: &Runtime,
+    provider: &Arc<dyn MetadataProvider>,
+    cache: &mut Cache,
+    first_file: Option<&Path>,
+) -> Result<String> {
+    match first_file.and_then(detect::detect_show) {
+        Some(query) => {
+            let query = with_year(&query, cli.year);
+            println!("Detected show '{query}' from the file metadata");
+            if cli.forget_show {
+                forget_show_choice(provider.as_ref(), cache, &query);
+            }
+            match search_and_select_show(runtime, provider.as_ref(), cache, &query, cli.offline) {
+                Ok(id) => return Ok(id),
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+        None => println!("No show name found in the file metadata"),
+    }
+
+    loop {
+        print!("Enter the show name to search: ");
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        let query = input.trim();
+        if query.is_empty() {
+            bail!("No show name given");
+        }
+        let query = &with_year(query, cli.year);
+        if cli.forget_show {
+            forget_show_choice(provider.as_ref(), cache, query);
+        }
+        match search_and_select_show(runtime, provider.as_ref(), cache, query, cli.offline) {
+            Ok(id) => return Ok(id),
+            Err(e) => eprintln!("{e}"),
+        }
+    }
+}
+
+/// The key the show chosen for the files of a directory is remembered by, apart from the
+/// searched names.
+fn directory_choice_key(provider: &dyn MetadataProvider, directory: &Path) -> String {
+    provider.series_key(&format!("dir:{}", directory.display()))
+}
+
+/// Searches the show and asks which one is meant if several match. The choice is
+/// remembered, later searches of the same name use it without asking.
+fn search_and_select_show(
+    runtime: &Runtime,
+    provider: &dyn MetadataProvider,
+    cache: &mut Cache,
+    query: &str,
+    offline: bool,
+) -> Result<String> {
+    let choice_key = show_choice_key(provider, query);
+    if let Some(id) = cache.get_show_choice(&choice_key) {
+        println!("Using show {id} chosen for '{query}' before (--forget-show to search again)");
+        return Ok(id.clone());
+    }
+
+    let (name, year) = search::split_year(query);
+    let mut results = if offline {
+        search_cached_series(provider, cache, name)
+    } else {
+        runtime.block_on(provider.search_series(name))?
+    };
+    if let (Some(year), false) = (year, results.is_empty()) {
+        results = search::narrow_by_year(results, year);
+    }
+
+    if results.is_empty() && offline {
+        bail!("No cached shows found matching '{query}', run once without --offline to search the provider");
+    }
+    if results.is_empty() {
+        bail!("No shows found matching '{query}'");
+    }
+
+    let id = if results.len() == 1 {
+        results[0].id.clone()
+    } else {
+        search::select_series(&results)?.id.clone()
+    };
+    cache.set_show_choice(choice_key, id.clone());
+    Ok(id)
+}
+
+/// The show name with `--year` as a suffix like `Doctor Who (2005)`, unless it has one.
+fn with_year(show: &str, year: Option<u16>) -> String {
+    match year {
+        Some(year) if search::split_year(show).1.is_none() => format!("{show} ({year})"),
+        _ => show.to_string(),
+    }
+}
+
+/// The key the show chosen for a searched name is remembered by: the name in lowercase with
+/// single spaces, keyed like a series so each provider has its own choices.
+fn show_choice_key(provider: &dyn MetadataProvider, query: &str) -> String {
+    let query = query.split_whitespace().collect::<Vec<_>>().join(" ");
+    provider.series_key(&query.to_lowercase())
+}
+
+fn forget_show_choice(provider: &dyn MetadataProvider, cache: &mut Cache, query: &str) {
+    if cache.forget_show_choice(&show_choice_key(provider, query)) {
+        println!("Forgot the show chosen for '{query}'");
+    }
+}
+
+/// The episodes of a file identified by an earlier run or an imported history, which
+/// don't need matching again unless it is to be `rematch`ed, see [`rematches`].
+fn known_episodes(
+    cache: &Cache,
+    file_path: &Path,
+    series_id: &str,
+    rematch: bool,
+) -> Option<Vec<EpisodeEntry>> {
+    if rematch {
+        return None;
+    }
+    cache
+        .get_processed_file(file_path)
+        .filter(|processed| processed.series_id == series_id && processed.is_current(file_path))
+        .map(ProcessedFile::episodes)
+}
+
+/// Whether the file is matched again although an earlier run identified it: with `--force`,
+/// or when its episode is given by `--assume` or the mapping file.
+fn rematches(file_path: &Path, options: &ProcessOptions) -> bool {
+    options.force || options.assumed.is_some() || options.mapping.contains(file_path)
+}
+
+/// Stores the episodes of a file, so later runs (e.g. after an interruption) skip matching it.
+fn remember_episodes(
+    cache: &mut Cache,
+    file_path: &Path,
+    series_id: &str,
+    episodes: &[EpisodeEntry],
+) {
+    let Some((episode, more_episodes)) = episodes.split_first() else {
+        return;
+    };
+    match ProcessedFile::from_path(file_path, series_id, episode.clone()) {
+        Ok(mut processed) => {
+            processed.more_episodes = more_episodes.to_vec();
+            cache.set_processed_file(file_path, processed);
+        }
+        Err(e) => eprintln!("Warning: Failed to remember the episode of {file_path:?}: {e}"),
+    }
+}
+
+/// TVDB's name of an episode ordering, see `TvdbClient::with_season_type`. Absolute numbers
+/// are read from the episodes of the aired order.
+fn tvdb_season_type(order: &EpisodeOrder) -> &'static str {
+    match order {
+        EpisodeOrder::Aired | EpisodeOrder::Absolute => tvdb::DEFAULT_SEASON_TYPE,
+        EpisodeOrder::Dvd => "dvd",
+        EpisodeOrder::Alternate => "alternate",
+    }
+}
+
+/// The matcher of the run's match mode, behind an OpenSubtitles lookup with
+/// `--opensubtitles`. `prefetched` is the OCR a worker thread already ran.
+fn create_matcher(
+    options: &ProcessOptions,
+    prefetched: Option<Result<Vec<OcrCandidate>>>,
+) -> Result<Box<dyn Matcher>> {
+    if let Some((season, episode)) = options.assumed {
+        return Ok(Box::new(AssumedMatcher { season, episode }));
+    }
+    let matcher: Box<dyn Matcher> = match options.match_mode {
+        MatchMode::ProductionCode => Box::new(production_code_matcher(
+            options,
+            options.prompts.unmatched,
+            prefetched,
+        )?),
+        MatchMode::Filename => filename_matcher(options),
+        MatchMode::Duration => Box::new(DurationMatcher {
+            range: options.episode_range.clone(),
+        }),
+        MatchMode::Audio => Box::new(AudioMatcher {
+            offsets: options.audio_offsets.clone(),
+        }),
+        MatchMode::Subtitles | MatchMode::SubtitlesAuto => Box::new(subtitle_matcher(
+            options,
+            options.match_mode == MatchMode::SubtitlesAuto,
+            options.interactive,
+        )),
+        MatchMode::Reference => Box::new(reference_matcher(
+            options,
+            options
+                .reference_subs
+                .as_ref()
+                .context("No reference subtitles")?,
+        )),
+        MatchMode::AirDate => Box::new(AirDateMatcher {
+            ocr_options: options.ocr_options.clone(),
+            ocr_pool: Arc::clone(&options.ocr_pool),
+            language: options.subtitle_language.clone(),
+            track_preference: options.subtitle_tracks,
+            windows: options.subtitle_windows.clone(),
+        }),
+        MatchMode::Auto => Box::new(ChainMatcher::new(auto_stages(options, prefetched, true)?)),
+    };
+    // The production code and runtime matchers leave out the episodes outside the range
+    // themselves, and the stages of `auto` are limited one by one
+    let matcher = match options.match_mode {
+        MatchMode::ProductionCode | MatchMode::Duration | MatchMode::Auto => matcher,
+        _ => in_range(options, matcher),
+    };
+    Ok(with_opensubtitles(options, matcher))
+}
+
+/// The matcher limited to `--season`/`--episodes`, if given.
+fn in_range(options: &ProcessOptions, matcher: Box<dyn Matcher>) -> Box<dyn Matcher> {
+    if options.episode_range.is_all() {
+        return matcher;
+    }
+    Box::new(RangeMatcher {
+        matcher,
+        range: options.episode_range.clone(),
+    })
+}
+
+/// The matcher of the run's match mode for `verify`, which mustn't go by the file names
+/// it checks.
+fn content_matcher(options: &ProcessOptions) -> Result<Box<dyn Matcher>> {
+    match options.match_mode {
+        MatchMode::Filename => {
+            bail!("Verifying needs a match mode that reads the files, not filename")
+        }
+        MatchMode::Auto => Ok(with_opensubtitles(
+            options,
+            Box::new(ChainMatcher::new(auto_stages(options, None, false)?)),
+        )),
+        _ => create_matcher(options, None),
+    }
+}
+
+fn with_opensubtitles(options: &ProcessOptions, matcher: Box<dyn Matcher>) -> Box<dyn Matcher> {
+    match &options.opensubtitles {
+        Some(client) => Box::new(ChainMatcher::new(vec![
+            in_range(
+                options,
+                Box::new(OpenSubtitlesMatcher {
+                    client: Arc::clone(client),
+                }),
+            ),
+            matcher,
+        ])),
+        None => matcher,
+    }
+}
+
+/// The stages of the `auto` match mode, cheapest first. Only the last one prompts, so
+/// earlier ones leave files they can't identify confidently to the next. `filename`
+/// starts with the file's name.
+fn auto_stages(
+    options: &ProcessOptions,
+    prefetched: Option<Result<Vec<OcrCandidate>>>,
+    filename: bool,
+) -> Result<Vec<Box<dyn Matcher>>> {
+    let absolute = options.order == EpisodeOrder::Absolute;
+    let mut stages: Vec<Box<dyn Matcher>> = Vec::new();
+    if filename {
+        stages.push(in_range(options, filename_matcher(options)));
+    }
+    stages.push(Box::new(production_code_matcher(
+        options,
+        UnmatchedPolicy::Skip,
+        prefetched,
+    )?));
+    stages.push(in_range(
+        options,
+        match &options.reference_subs {
+            Some(references) => Box::new(reference_matcher(options, references)),
+            None => Box::new(subtitle_matcher(options, true, false)),
+        },
+    ));
+    if options.interactive && options.prompts.unmatched == UnmatchedPolicy::Ask {
+        stages.push(Box::new(ManualMatcher { absolute }));
+    }
+    Ok(stages)
+}
+
+fn production_code_matcher(
+    options: &ProcessOptions,
+    unmatched: UnmatchedPolicy,
+    prefetched: Option<Result<Vec<OcrCandidate>>>,
+) -> Result<ProductionCodeMatcher> {
+    Ok(ProductionCodeMatcher {
+        prompt_size: options.prompt_size,
+        unmatched,
+        multi_part: options.prompts.multi_part,
+        ocr_sidecar: options.ocr_sidecar,
+        ocr_options: options.ocr_options.clone(),
+        ocr_pool: Arc::clone(&options.ocr_pool),
+        ignored_candidates: options.ignored_candidates.clone(),
+        min_confidence: options.min_ocr_confidence,
+        absolute: options.order == EpisodeOrder::Absolute,
+        range: options.episode_range.clone(),
+        prefetched: prefetched.transpose()?,
+        last_candidates: RefCell::default(),
+        last_flag: RefCell::default(),
+    })
+}
+
+/// The file name matcher, shifting the episode numbers by `--episode-offset`. The numbers
+/// of the other matchers are the provider's already.
+fn filename_matcher(options: &ProcessOptions) -> Box<dyn Matcher> {
+    let absolute = options.order == EpisodeOrder::Absolute;
+    let matcher = Box::new(FilenameMatcher { absolute });
+    match options.episode_offset {
+        0 => matcher,
+        offset => Box::new(OffsetMatcher {
+            matcher,
+            offset,
+            absolute,
+        }),
+    }
+}
+
+fn subtitle_matcher(options: &ProcessOptions, auto: bool, interactive: bool) -> SubtitleMatcher {
+    SubtitleMatcher {
+        language: options.subtitle_language.clone(),
+        track_preference: options.subtitle_tracks,
+        windows: options.subtitle_windows.clone(),
+        ocr_pool: Arc::clone(&options.ocr_pool),
+        auto,
+        absolute: options.order == EpisodeOrder::Absolute,
+        interactive,
+    }
+}
+
+fn reference_matcher(
+    options: &ProcessOptions,
+    references: &Arc<ReferenceSubtitles>,
+) -> ReferenceMatcher {
+    ReferenceMatcher {
+        references: Arc::clone(references),
+        language: options.subtitle_language.clone(),
+        track_preference: options.subtitle_tracks,
+        windows: options.subtitle_windows.clone(),
+        ocr_pool: Arc::clone(&options.ocr_pool),
+        interactive: options.interactive,
+    }
+}
+
+/// Processes a file and, with `--output json`, reports what happened to it.
+fn process_and_report(
+    file_path: &Path,
+    series_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    options: &ProcessOptions,
+    state: &mut RunState,
+    prefetched: Option<Result<Vec<OcrCandidate>>>,
+) -> Result<()> {
+    let prefetched = prefetched.or_else(|| {
+        let (path, _) = state.prefetched.as_ref()?;
+        if path != file_path {
+            return None;
+        }
+        state.prefetched.take().map(|(_, candidates)| candidates)
+    });
+    let mut report = FileReport::new(file_path);
+    let result = process_file(
+        file_path,
+        series_id,
+        show_name,
+        cache,
+        options,
+        state,
+        prefetched,
+        &mut report,
+    );
+    // The file is reported once it's been asked about
+    let deferred = state
+        .deferred_files
+        .last()
+        .is_some_and(|(path, _)| path == file_path);
+    if result.is_ok() && deferred {
+        return Ok(());
+    }
+    if let Err(e) = &result {
+        report.action = Action::Failed;
+        report.error = Some(format!("{e:#}"));
+        if options.strict {
+            eprintln!("Stopping the run at the first failure (--strict)");
+            state.quit = true;
+        }
+    }
+    if let Some(reporter) = &mut state.reporter {
+        reporter.emit(&report)?;
+    }
+    state.summary.record(&report);
+    save_progress(cache);
+    result
+}
+
+#[allow(clippy::too_many_arguments)]
+fn process_file(
+    file_path: &Path,
+    series_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    options: &ProcessOptions,
+    state: &mut RunState,
+    prefetched: Option<Result<Vec<OcrCandidate>>>,
+    report: &mut FileReport,
+) -> Result<()> {
+    if file_path.extension().and_then(|s| s.to_str()) != Some("mkv") {
+        bail!("Skipping non-MKV file: {file_path:?}");
+    }
 
-fn run(cli: Cli) -> Result<()> {
-    // Get TVDB API key
-    let api_key = config::get_tvdb_api_key()?;
+    println!("Processing: {file_path:?}");
 
-    // Load cache
-    let mut cache = Cache::load();
-    let mut client = TvdbClient::new(api_key.to_string());
+    let matcher: Box<dyn Matcher> = match options.mapping.get(file_path) {
+        Some(episode) => Box::new(MappedMatcher {
+            episode: episode.to_string(),
+        }),
+        None => create_matcher(options, prefetched)?,
+    };
 
-    // Determine show ID
-    let show_id = match (cli.show, cli.show_id) {
-        (Some(show_name), None) => match search_and_select_show(&mut client, &show_name) {
-            Ok(id) => id,
-            Err(e) => {
-                bail!("Error searching for show: {e}");
+    let rematch = rematches(file_path, options);
+    let (episodes, source) = match known_episodes(cache, file_path, series_id, rematch) {
+        Some(episodes) => (episodes, "earlier match"),
+        None => {
+            let mut episodes = matchers::resolve(
+                matcher.match_episode(file_path, series_id, cache)?,
+                options.prompts.low_confidence,
+            );
+            let special = episodes.iter().any(|episode| episode.season_number == 0);
+            let defer = options.deferred_prompts.as_ref().is_some_and(|deferred| {
+                (episodes.is_empty() && asks_episode(deferred, &options.match_mode))
+                    || (special && deferred.specials == SpecialsPolicy::Prompt)
+                    || (matcher.flagged().is_some() && deferred.multi_part == MultiPartPolicy::Ask)
+            });
+            if defer {
+                println!("Leaving {file_path:?} for the end of the run, it needs your input");
+                state
+                    .deferred_files
+                    .push((file_path.to_path_buf(), matcher.candidates()));
+                return Ok(());
             }
-        },
-        (None, Some(id)) => id,
-        (Some(_), Some(_)) => {
-            bail!("Error: Cannot specify both --show and --show-id");
+            if special
+                && options.prompts.specials == SpecialsPolicy::Prompt
+                && !prompt::confirm(&format!(
+                    "{} is a special, use it?",
+                    matchers::describe(&episodes)
+                ))
+            {
+                episodes.clear();
+            }
+            (episodes, matcher.source())
         }
-        (None, None) => {
-            bail!("Error: Must specify either --show or --show-id");
+    };
+    report.episodes = episodes.clone();
+    report.source = Some(source.to_string());
+    report.candidates = matcher.candidates();
+
+    let (Some(episode), Some(last)) = (episodes.first(), episodes.last()) else {
+        if let Some(reason) = matcher.flagged() {
+            eprintln!("Warning: {file_path:?} needs manual handling: {reason}");
+            report.action = Action::Flagged;
+            report.flagged = Some(reason);
+            return Ok(());
         }
+        eprintln!("Warning: No matching episode found for {file_path:?}");
+        return Ok(());
     };
+    let last_episode_number = (episodes.len() > 1).then_some(last.episode_number);
+    if options.name_template.uses_media() || state.reporter.is_some() {
+        report.media = media_info(cache, file_path);
+    }
+    let media = report.media.clone();
 
-    // Preload cache with series name and all episodes (only if not already cached)
-    if !cache.has_series_episodes(&show_id) {
-        preload_cache(&mut client, &show_id, &mut cache)?;
-    } else {
-        println!("Using cached episode data for series {show_id}");
+    for episode in &episodes {
+        println!(
+            "Found episode: S{}E{} - {}",
+            episode.season_number, episode.episode_number, episode.name
+        );
     }
 
-    // Get show name from cache or API
-    let show_name = match get_show_name(&mut client, &show_id, &mut cache) {
-        Ok(name) => name,
-        Err(e) => {
-            bail!("Error getting show name: {e}");
+    // The fingerprints at the offsets would only be of the first episode of a multi-episode file
+    if options.learn_audio && options.match_mode != MatchMode::Audio && episodes.len() == 1 {
+        if let Err(e) = audio::learn(file_path, series_id, episode, &options.audio_offsets, cache) {
+            eprintln!("Warning: Failed to fingerprint the audio of {file_path:?}: {e}");
         }
-    };
+    }
 
-    // Validate and process all input paths
-    for input_path in &cli.inputs {
-        if !input_path.exists() {
-            eprintln!("Error: Input path does not exist: {input_path:?}");
-            continue;
-        }
+    if let Some(preview_dir) = &options.preview_dir {
+        let link_dir =
+            options
+                .name_template
+                .season_dir(preview_dir, show_name, episode.season_number);
+        let new_filename =
+            options
+                .name_template
+                .render(&link_dir, show_name, &episodes, media.as_ref());
+        let link_path = renamer::link_preview(file_path, &link_dir, &new_filename)?;
+        println!("Linked preview: {link_path:?}");
+        report.action = Action::Linked;
+        report.new_path = Some(link_path);
+        return Ok(());
+    }
 
-        if let Err(e) = process_input_path(
-            input_path,
-            &show_id,
-            &show_name,
-            cli.no_confirm,
-            cli.recursive,
-            &mut cache,
-            cli.prompt_size,
-            &cli.match_mode,
-        ) {
-            eprintln!("Error processing path {input_path:?}: {e}");
-            // Continue processing other paths
+    if let Some(other) = state.matched.find(series_id, &episodes, file_path).cloned() {
+        println!(
+            "Warning: S{:02}E{:02} was already matched to {:?} in this run.",
+            episode.season_number,
+            episode.episode_number,
+            other.current()
+        );
+        match duplicates::decide(options.prompts.duplicates, file_path, other.current())? {
+            Keep::Both => {}
+            Keep::This => release_duplicate(&other, state, options)?,
+            Keep::Other => {
+                println!("Leaving {file_path:?} alone.");
+                report.action = Action::Skipped;
+                return Ok(());
+            }
         }
     }
 
-    // Save cache before exiting
-    if let Err(e) = cache.save() {
-        eprintln!("Warning: Failed to save cache: {e}");
+    // Find unique filename if needed
+    let season_dir = options.library_root.as_ref().map(|root| {
+        options
+            .name_template
+            .season_dir(root, show_name, episode.season_number)
+    });
+    let directory = season_dir
+        .as_deref()
+        .unwrap_or(file_path.parent().unwrap_or(Path::new(".")));
+    let new_filename =
+        options
+            .name_template
+            .render(directory, show_name, &episodes, media.as_ref());
+
+    let mut on_conflict = match options.prompts.overwrite {
+        // A dry run doesn't ask, it plans the name that keeps both files
+        ConflictPolicy::Prompt if options.dry_run => ConflictPolicy::Suffix,
+        policy => policy,
+    };
+    let mut replaced = Vec::new(); // the library's copies the file replaces
+    let mut existing = match (options.library_duplicates, &season_dir) {
+        (Some(_), Some(season_dir)) => {
+            library::episode_files(season_dir, series_id, &episodes, file_path, cache)
+        }
+        _ => Vec::new(),
+    };
+    if let Some(catalog) = &options.catalog {
+        for copy in catalog.copies(&episodes, file_path) {
+            if !existing.contains(&copy) {
+                existing.push(copy);
+            }
+        }
+    }
+    // Cataloged copies are only ever skipped unless a policy was given
+    let policy = options
+        .library_duplicates
+        .unwrap_or(LibraryDuplicatePolicy::Skip);
+    if !existing.is_empty() {
+        println!(
+            "The library already holds {}: {existing:?}",
+            matchers::describe(&episodes)
+        );
+        match library::decide(policy, file_path, &existing)? {
+            Decision::Add => {}
+            Decision::Replace(files) => {
+                on_conflict = ConflictPolicy::Overwrite;
+                replaced = files;
+            }
+            Decision::Skip => {
+                println!("Leaving {file_path:?} alone.");
+                report.action = Action::Skipped;
+                return Ok(());
+            }
+        }
     }
 
-    Ok(())
-}
+    // A dry run renames nothing, so the names it planned for other files aren't taken yet
+    let planned: Vec<PathBuf> = state
+        .plan
+        .iter()
+        .map(|planned| planned.new_path.clone())
+        .collect();
+    let Some(new_path) =
+        renamer::resolve_conflict(file_path, directory, &new_filename, on_conflict, &planned)
+    else {
+        report.action = Action::Skipped;
+        return Ok(());
+    };
 
-fn process_input_path(
-    input_path: &Path,
-    series_id: &str,
-    show_name: &str,
-    skip_confirm: bool,
-    recursive: bool,
-    cache: &mut Cache,
-    prompt_size: Option<u64>,
-    match_mode: &MatchMode,
-) -> Result<()> {
-    if input_path.is_file() {
-        process_file(
-            input_path,
+    if options.dry_run {
+        for path in &replaced {
+            println!("Would replace {path:?}");
+        }
+        report.action = Action::Planned;
+        report.new_path = Some(new_path.clone());
+        state.plan.push(PlannedRename {
+            old_path: file_path.to_path_buf(),
+            new_path,
+            series_id: series_id.to_string(),
+            season_number: episode.season_number,
+            episode_number: episode.episode_number,
+            last_episode_number,
+            episode_name: episodes
+                .iter()
+                .map(|episode| episode.name.as_str())
+                .collect::<Vec<_>>()
+                .join(" & "),
+            source: source.to_string(),
+        });
+        state.matched.claim(
             series_id,
+            &episodes,
+            Claim {
+                original: file_path.to_path_buf(),
+                rename: None,
+            },
+        );
+        return Ok(());
+    }
+
+    // Rename file
+    let mut skip_confirm = options.prompts.rename == RenamePolicy::Auto || state.rename_all;
+    let outcome = renamer::rename_file(
+        file_path,
+        &new_path,
+        &mut skip_confirm,
+        options.link_mode,
+        &options.rename_retry,
+    )?;
+    state.rename_all = skip_confirm;
+    let checksum = match &outcome {
+        RenameOutcome::Renamed { checksum } => checksum.clone(),
+        _ => None,
+    };
+    let entry = JournalEntry {
+        timestamp: unix_now(),
+        run_id: options.run_id,
+        old_path: file_path.to_path_buf(),
+        new_path,
+        series_id: series_id.to_string(),
+        season_number: episode.season_number,
+        episode_number: episode.episode_number,
+        last_episode_number,
+        kept_original: options.link_mode != LinkMode::Rename,
+        checksum,
+        imported: false,
+        created: false,
+    };
+    // Before the NFO is written, which would take the name of an existing one
+    let sidecars = match outcome {
+        RenameOutcome::Renamed { .. } => {
+            move_sidecars(&entry, options.link_mode, &options.rename_retry)
+        }
+        _ => Vec::new(),
+    };
+    let nfo = matches!(
+        outcome,
+        RenameOutcome::Renamed { .. } | RenameOutcome::Unchanged
+    )
+    .then(|| {
+        finish_rename(
+            &entry.new_path,
             show_name,
-            skip_confirm,
-            cache,
-            prompt_size,
-            match_mode,
-        )?;
-    } else if input_path.is_dir() {
-        process_directory(
-            input_path,
             series_id,
-            show_name,
-            skip_confirm,
-            recursive,
+            &episodes,
             cache,
-            prompt_size,
-            match_mode,
-        )?;
-    } else {
-        bail!("Input path is neither a file nor a directory");
+            options,
+        )
+    })
+    .flatten()
+    .map(|nfo| created_entry(&entry, nfo));
+    report.action = match outcome {
+        RenameOutcome::Renamed { .. } => Action::Renamed,
+        RenameOutcome::Unchanged => Action::Unchanged,
+        RenameOutcome::Deferred => Action::Deferred,
+        RenameOutcome::Skipped | RenameOutcome::Quit => Action::Skipped,
+    };
+    report.new_path = Some(entry.new_path.clone());
+    if !matches!(outcome, RenameOutcome::Skipped | RenameOutcome::Quit) {
+        // Also when the file already had the name, which it gives up if it's released
+        let rename = matches!(
+            outcome,
+            RenameOutcome::Renamed { .. } | RenameOutcome::Unchanged
+        )
+        .then(|| entry.clone());
+        state.matched.claim(
+            series_id,
+            &episodes,
+            Claim {
+                original: file_path.to_path_buf(),
+                rename,
+            },
+        );
     }
-
-    Ok(())
-}
-
-fn preload_cache(client: &mut TvdbClient, series_id: &str, cache: &mut Cache) -> Result<()> {
-    // Get series name if not cached
-    if cache.get_series_name(series_id).is_none() {
-        let series_name = client.get_series_name(series_id)?;
-        cache.set_series_name(series_id.to_string(), series_name);
+    match outcome {
+        RenameOutcome::Renamed { .. } => {
+            remove_replaced(&replaced, &entry.new_path);
+            remember_episodes(cache, &entry.new_path, series_id, &episodes);
+            if let Some(probed) =
+                media.and_then(|media| ProbedMedia::from_path(&entry.new_path, media))
+            {
+                cache.set_media_info(&entry.new_path, probed);
+            }
+            record_rename(entry);
+            sidecars.into_iter().chain(nfo).for_each(record_rename);
+        }
+        RenameOutcome::Unchanged => {
+            remember_episodes(cache, &entry.new_path, series_id, &episodes);
+            nfo.into_iter().for_each(record_rename);
+        }
+        RenameOutcome::Deferred => state.deferred_renames.push(entry),
+        RenameOutcome::Quit => state.quit = true,
+        _ => {}
     }
 
-    // Preload all episodes for this series
-    println!("Preloading episode cache for series {series_id}...");
-    client.preload_episodes(series_id, cache)?;
-    println!("Cache preloaded successfully.");
-
     Ok(())
 }
 
-fn search_and_select_show(client: &mut TvdbClient, query: &str) -> Result<String> {
-    let results = client.search_series(query)?;
-
-    if results.is_empty() {
-        bail!("No shows found matching '{query}'");
+/// What ffprobe reports about the file, probed once and cached until the file changes.
+fn media_info(cache: &mut Cache, file_path: &Path) -> Option<MediaInfo> {
+    if let Some(media) = cache.get_media_info(file_path) {
+        return Some(media.clone());
     }
-
-    if results.len() == 1 {
-        return Ok(results[0].tvdb_id.clone());
+    match ffmpeg::get_media_info(file_path) {
+        Ok(media) => {
+            if let Some(probed) = ProbedMedia::from_path(file_path, media.clone()) {
+                cache.set_media_info(file_path, probed);
+            }
+            Some(media)
+        }
+        Err(e) => {
+            eprintln!("Warning: Failed to probe {file_path:?}: {e}");
+            None
+        }
     }
+}
 
-    // Multiple results - let user select
-    println!("Multiple shows found. Please select one:");
-    for (i, result) in results.iter().enumerate() {
-        let name = result
-            .name
-            .as_ref()
-            .and_then(|n| n.get("eng"))
-            .or_else(|| result.name.as_ref().and_then(|n| n.values().next()))
-            .map(|s| s.as_str())
-            .unwrap_or("Unknown");
-        println!("  {}: {} (ID: {})", i + 1, name, result.tvdb_id);
+/// Removes the library's copies of an episode that a renamed file replaced. One of the
+/// same name is already gone.
+fn remove_replaced(replaced: &[PathBuf], new_path: &Path) {
+    for path in replaced.iter().filter(|path| path.as_path() != new_path) {
+        match fs::remove_file(path) {
+            Ok(()) => println!("Removed {path:?}, replaced by {new_path:?}"),
+            Err(e) => eprintln!("Warning: Failed to remove {path:?}: {e}"),
+        }
     }
+}
 
-    loop {
-        print!("Enter number (1-{}): ", results.len());
-        io::stdout().flush()?;
-
-        let mut input = String::new();
-        io::stdin().read_line(&mut input)?;
-        let choice: usize = match input.trim().parse() {
-            Ok(n) => n,
-            Err(_) => {
-                println!("Invalid input. Please enter a number.");
-                continue;
+/// Writes the NFO and the MKV tags of a file that has its new name, if asked to. Returns
+/// the NFO if the file had none before, which undoing the run deletes.
+fn finish_rename(
+    file_path: &Path,
+    show_name: &str,
+    series_id: &str,
+    episodes: &[EpisodeEntry],
+    cache: &mut Cache,
+    options: &ProcessOptions,
+) -> Option<PathBuf> {
+    let mut created = None;
+    if options.write_nfo {
+        let existed = file_path.with_extension("nfo").exists();
+        match nfo::write(file_path, show_name, series_id, episodes) {
+            Ok(nfo_path) => {
+                println!("Wrote {nfo_path:?}");
+                created = (!existed).then_some(nfo_path);
             }
-        };
-
-        if choice >= 1 && choice <= results.len() {
-            return Ok(results[choice - 1].tvdb_id.clone());
+            Err(e) => eprintln!("Warning: Failed to write the NFO of {file_path:?}: {e}"),
         }
-        println!(
-            "Invalid selection. Please enter a number between 1 and {}.",
-            results.len()
-        );
     }
+    if options.tag_file {
+        tag_file(file_path, show_name, series_id, episodes, cache, options);
+    }
+    created
 }
 
-fn process_file(
+/// Writes the episodes into the file's MKV tags. Links share their data with the original,
+/// which must stay untouched, e.g. for seeding.
+fn tag_file(
     file_path: &Path,
-    series_id: &str,
     show_name: &str,
-    skip_confirm: bool,
+    series_id: &str,
+    episodes: &[EpisodeEntry],
     cache: &mut Cache,
-    prompt_size: Option<u64>,
-    match_mode: &MatchMode,
-) -> Result<()> {
-    if file_path.extension().and_then(|s| s.to_str()) != Some("mkv") {
-        bail!("Skipping non-MKV file: {file_path:?}");
+    options: &ProcessOptions,
+) {
+    if matches!(options.link_mode, LinkMode::Hardlink | LinkMode::Symlink) {
+        eprintln!("Warning: Not tagging {file_path:?}, it shares its data with the original file");
+        return;
     }
+    match mkvpropedit::tag_file(file_path, show_name, series_id, episodes) {
+        Ok(()) => {
+            println!("Tagged {file_path:?}");
+            // Tagging changed the file, which would otherwise be matched again next time
+            remember_episodes(cache, file_path, series_id, episodes);
+        }
+        Err(e) => eprintln!("Warning: Failed to tag {file_path:?}: {e}"),
+    }
+}
 
-    println!("Processing: {file_path:?}");
-
-    let matcher: Box<dyn Matcher> = match match_mode {
-        MatchMode::ProductionCode => Box::new(ProductionCodeMatcher { prompt_size }),
-        MatchMode::Subtitles => Box::new(SubtitleMatcher),
-    };
-
-    let episode = matcher.match_episode(file_path, series_id, cache)?;
-
-    let Some(episode) = episode else {
-        eprintln!("Warning: No matching episode found for {file_path:?}");
+/// Gives up the episode a file was matched to earlier in the run, for another file of it:
+/// its rename (or link) is reverted, or dropped if it hasn't happened yet.
+fn release_duplicate(claim: &Claim, state: &mut RunState, options: &ProcessOptions) -> Result<()> {
+    state.matched.release(&claim.original);
+    state
+        .plan
+        .retain(|planned| planned.old_path != claim.original);
+    state
+        .deferred_renames
+        .retain(|entry| entry.old_path != claim.original);
+    let Some(rename) = &claim.rename else {
         return Ok(());
     };
+    let reverted = revert_claim(rename, options.write_nfo, &options.rename_retry)?;
+    reverted.into_iter().for_each(record_rename);
+    Ok(())
+}
 
-    println!(
-        "Found episode: S{}E{} - {}",
-        episode.season_number, episode.episode_number, episode.name
-    );
-
-    // Generate new filename
-    let new_filename = renamer::generate_filename(
-        show_name,
-        episode.season_number,
-        episode.episode_number,
-        &episode.name,
-    );
-
-    // Find unique filename if needed
-    let directory = file_path.parent().unwrap_or(Path::new("."));
-    let new_path = renamer::find_unique_filename(file_path, directory, &new_filename);
+/// Takes the episode's name away from a released file, see [`release_duplicate`]: a file
+/// renamed by the run goes back to its old name, a linked or copied one is deleted, and
+/// one that already had the name gets a `[copy N]` one. Its sidecars go along, the NFO
+/// written for it is deleted. Returns the journal entries of the files that were moved.
+fn revert_claim(
+    rename: &JournalEntry,
+    wrote_nfo: bool,
+    retry: &RetryPolicy,
+) -> Result<Vec<JournalEntry>> {
+    if wrote_nfo {
+        let nfo_path = rename.new_path.with_extension("nfo");
+        if nfo_path.is_file() {
+            println!("Deleting {nfo_path:?}");
+            fs::remove_file(&nfo_path)?;
+        }
+    }
 
-    // Rename file
-    renamer::rename_file(file_path, &new_path, skip_confirm)?;
+    let unchanged = rename.old_path == rename.new_path;
+    if rename.kept_original && !unchanged {
+        // The sidecars were linked or copied along with the file
+        let stem = rename.old_path.file_stem().unwrap_or_default();
+        for (sidecar, suffix) in renamer::sidecar_files(&rename.new_path) {
+            let mut original_name = stem.to_os_string();
+            original_name.push(&suffix);
+            let original = rename.old_path.with_file_name(original_name);
+            if original.is_file() {
+                println!("Deleting {sidecar:?}");
+                renamer::remove_link(&original, &sidecar)?;
+            }
+        }
+        println!("Deleting {:?}", rename.new_path);
+        renamer::remove_link(&rename.old_path, &rename.new_path)?;
+        return Ok(Vec::new());
+    }
 
-    Ok(())
+    let target = if unchanged {
+        let directory = parent_dir(&rename.new_path);
+        let filename = rename
+            .new_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let taken = [rename.new_path.clone()];
+        let aside = renamer::unique_filename(&rename.new_path, &directory, filename, &taken);
+        println!("Moving {:?} to {aside:?}", rename.new_path);
+        aside
+    } else {
+        println!("Moving {:?} back to {:?}", rename.new_path, rename.old_path);
+        rename.old_path.clone()
+    };
+    let outcome = renamer::rename_file(
+        &rename.new_path,
+        &target,
+        &mut true,
+        LinkMode::Rename,
+        retry,
+    )?;
+    let RenameOutcome::Renamed { checksum } = outcome else {
+        return Ok(Vec::new());
+    };
+    let entry = JournalEntry {
+        timestamp: unix_now(),
+        old_path: rename.new_path.clone(),
+        new_path: target,
+        kept_original: false,
+        checksum,
+        ..rename.clone()
+    };
+    let sidecars = move_sidecars(&entry, LinkMode::Rename, retry);
+    Ok(std::iter::once(entry).chain(sidecars).collect())
 }
 
 fn process_directory(
     dir_path: &Path,
     series_id: &str,
     show_name: &str,
-    skip_confirm: bool,
-    recursive: bool,
     cache: &mut Cache,
-    prompt_size: Option<u64>,
-    match_mode: &MatchMode,
+    options: &ProcessOptions,
+    state: &mut RunState,
 ) -> Result<()> {
-    let mkv_files = collect_mkv_files(dir_path, recursive)?;
+    let mkv_files = collect_mkv_files(dir_path, options.recursive)?;
 
     println!("Found {} MKV file(s) to process", mkv_files.len());
 
+    if options.jobs > 1 && options.match_mode == MatchMode::ProductionCode {
+        return process_files_parallel(&mkv_files, series_id, show_name, cache, options, state);
+    }
+
     for file_path in mkv_files {
-        if let Err(e) = process_file(
-            &file_path,
-            series_id,
-            show_name,
-            skip_confirm,
-            cache,
-            prompt_size,
-            match_mode,
+        if state.quit {
+            break;
+        }
+        if let Err(e) = process_and_report(
+            &file_path, series_id, show_name, cache, options, state, None,
         ) {
             eprintln!("Error processing {file_path:?}: {e}");
             // Continue processing other files
@@ -282,6 +2771,172 @@ fn process_directory(
     Ok(())
 }
 
+/// Runs frame extraction and OCR on `options.jobs` worker threads ahead of the main thread,
+/// which handles the files in order so cache updates, prompts and output stay serialized.
+fn process_files_parallel(
+    mkv_files: &[PathBuf],
+    series_id: &str,
+    show_name: &str,
+    cache: &mut Cache,
+    options: &ProcessOptions,
+    state: &mut RunState,
+) -> Result<()> {
+    // Files with a known episode, saved OCR evidence or OCR run ahead of the fetch don't
+    // need OCR
+    let files: Vec<(&PathBuf, bool)> = mkv_files
+        .iter()
+        .map(|file_path| {
+            let known = !needs_ocr(cache, file_path, series_id, options)
+                || state
+                    .prefetched
+                    .as_ref()
+                    .is_some_and(|(path, _)| path == file_path);
+            (file_path, !known)
+        })
+        .collect();
+
+    parallel::for_each_ordered(
+        &files,
+        options.jobs,
+        |(file_path, needs_ocr)| {
+            needs_ocr.then(|| {
+                ocr::extract_production_code_candidates(
+                    file_path.to_str().unwrap(),
+                    &options.ocr_options,
+                    &options.ocr_pool,
+                )
+            })
+        },
+        |(file_path, _), candidates| {
+            let result = process_and_report(
+                file_path, series_id, show_name, cache, options, state, candidates,
+            );
+            if let Err(e) = result {
+                eprintln!("Error processing {file_path:?}: {e}");
+            }
+            println!(); // Blank line between files
+
+            if state.quit {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        },
+    );
+
+    Ok(())
+}
+
+/// Whether matching the file takes OCR, i.e. it has neither a known or mapped episode nor
+/// saved OCR evidence.
+fn needs_ocr(cache: &Cache, file_path: &Path, series_id: &str, options: &ProcessOptions) -> bool {
+    options.match_mode == MatchMode::ProductionCode
+        && !options.mapping.contains(file_path)
+        && known_episodes(cache, file_path, series_id, rematches(file_path, options)).is_none()
+        && !(options.ocr_sidecar && sidecar::load(file_path).is_some())
+}
+
+/// Runs OCR on the first input file while the episodes are still being fetched, so the run
+/// starts with it as soon as they're there.
+fn prefetch_first_file(
+    inputs: &[PathBuf],
+    series_id: &str,
+    cache: &Cache,
+    options: &ProcessOptions,
+) -> Option<(PathBuf, Result<Vec<OcrCandidate>>)> {
+    let input_path = inputs.first()?;
+    let file_path = if input_path.is_dir() {
+        collect_mkv_files(input_path, options.recursive)
+            .ok()?
+            .into_iter()
+            .next()?
+    } else {
+        input_path.clone()
+    };
+    let is_mkv = file_path.extension().and_then(|s| s.to_str()) == Some("mkv");
+    if !is_mkv || !file_path.is_file() || !needs_ocr(cache, &file_path, series_id, options) {
+        return None;
+    }
+
+    let candidates = ocr::extract_production_code_candidates(
+        file_path.to_str()?,
+        &options.ocr_options,
+        &options.ocr_pool,
+    );
+    Some((file_path, candidates))
+}
+
+/// The files of the inputs, with the MKV files of the directories among them.
+fn input_files(inputs: &[PathBuf], recurse: bool) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for input_path in inputs {
+        if input_path.is_dir() {
+            files.extend(collect_mkv_files(input_path, recurse)?);
+        } else if input_path.is_file() {
+            files.push(input_path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Checks that the destinations of the run have the free space for the files it copies,
+/// before any file is touched. Returns the inputs to process: all of them, or with
+/// `--low-space fit` the files that fit.
+fn preflight_inputs(cli: &Cli) -> Result<Vec<PathBuf>> {
+    let library_root = match (cli.organize, &cli.library_root) {
+        (false, _) => None,
+        (true, Some(root)) => Some(root.clone()),
+        (true, None) => config::get_library_root()?,
+    };
+    let files = input_files(&cli.inputs, cli.recursive)?;
+    let transfers: Vec<(PathBuf, PathBuf)> = files
+        .iter()
+        .map(|file| {
+            let destination = library_root.clone().unwrap_or_else(|| parent_dir(file));
+            (file.clone(), destination)
+        })
+        .collect();
+    let preflight = space::check(&transfers, cli.link_mode);
+    if preflight.shortfalls.is_empty() {
+        return Ok(cli.inputs.clone());
+    }
+    report_shortfalls(&preflight, cli.low_space)?;
+    Ok(files
+        .into_iter()
+        .filter(|file| !preflight.left_out.contains(file))
+        .collect())
+}
+
+/// Warns about the destinations without the space for a batch, and stops it unless
+/// `--low-space fit` lets the files that fit through.
+fn report_shortfalls(preflight: &Preflight, policy: LowSpacePolicy) -> Result<()> {
+    if preflight.shortfalls.is_empty() {
+        return Ok(());
+    }
+    for shortfall in &preflight.shortfalls {
+        eprintln!("Warning: Not enough free space, {}", shortfall.describe());
+    }
+    if policy == LowSpacePolicy::Abort {
+        bail!("Not enough free space for the batch, no file was touched. Free some space or pass --low-space fit to process the files that fit");
+    }
+    println!(
+        "Leaving {} file(s) that don't fit alone:",
+        preflight.left_out.len()
+    );
+    for path in &preflight.left_out {
+        println!("  {path:?}");
+    }
+    Ok(())
+}
+
+/// The directory of a file, `.` for a bare file name.
+fn parent_dir(path: &Path) -> PathBuf {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    }
+}
+
 fn collect_mkv_files(dir_path: &Path, recurse: bool) -> Result<Vec<PathBuf>> {
     let mut mkv_files = Vec::new();
     collect_mkv_files_helper(dir_path, recurse, &mut mkv_files)?;
@@ -313,11 +2968,200 @@ fn collect_mkv_files_helper(
     Ok(())
 }
 
-fn get_show_name(client: &mut TvdbClient, show_id: &str, cache: &mut Cache) -> Result<String> {
-    if let Some(name) = cache.get_series_name(show_id) {
+/// The cached series of the provider whose name contains `query`, for `--offline`.
+fn search_cached_series(
+    provider: &dyn MetadataProvider,
+    cache: &Cache,
+    query: &str,
+) -> Vec<SeriesMatch> {
+    let query = query.to_lowercase();
+    let mut results: Vec<SeriesMatch> = cache
+        .series
+        .iter()
+        .filter(|(_, name)| name.to_lowercase().contains(&query))
+        .filter_map(|(series_key, name)| {
+            // Keys are IDs with a provider prefix or ordering suffix, see `series_key`
+            let id = series_key.strip_prefix("tvmaze:").unwrap_or(series_key);
+            let id = tvdb::series_id(id);
+            (provider.series_key(id) == *series_key).then(|| SeriesMatch {
+                id: id.to_string(),
+                name: name.clone(),
+                ..SeriesMatch::default()
+            })
+        })
+        .collect();
+    results.sort_by(|a, b| a.name.cmp(&b.name));
+    results
+}
+
+fn get_show_name(
+    runtime: &Runtime,
+    provider: &dyn MetadataProvider,
+    show_id: &str,
+    cache: &mut Cache,
+) -> Result<String> {
+    let series_key = provider.series_key(show_id);
+    if let Some(name) = cache.get_series_name(&series_key) {
         return Ok(name.clone());
     }
-    let name = client.get_series_name(show_id)?;
-    cache.set_series_name(show_id.to_string(), name.clone());
+    let name = runtime.block_on(provider.get_series_name(show_id))?;
+    cache.set_series_name(series_key, name.clone());
     Ok(name)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(episode_number: u64) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number,
+            name: format!("Episode {episode_number}"),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_known_episodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file_path = dir.path().join("title_t00.mkv");
+        fs::write(&file_path, "video").unwrap();
+        let mut cache = Cache::default();
+        assert!(known_episodes(&cache, &file_path, "77398", false).is_none());
+
+        remember_episodes(&mut cache, &file_path, "77398", &[episode(3), episode(4)]);
+        let known = known_episodes(&cache, &file_path, "77398", false).unwrap();
+        assert_eq!(
+            known.iter().map(|e| e.episode_number).collect::<Vec<_>>(),
+            [3, 4]
+        );
+        // Only for the series it was matched as
+        assert!(known_episodes(&cache, &file_path, "430", false).is_none());
+        // --force matches it again
+        assert!(known_episodes(&cache, &file_path, "77398", true).is_none());
+
+        // A changed file is matched again
+        fs::write(&file_path, "another video").unwrap();
+        assert!(known_episodes(&cache, &file_path, "77398", false).is_none());
+    }
+
+    #[test]
+    fn test_revert_claim() {
+        let dir = tempfile::tempdir().unwrap();
+        let retry = RetryPolicy {
+            retries: 0,
+            initial_backoff: Duration::ZERO,
+            verify_checksum: false,
+        };
+        let claimed = |old_path: &Path, new_path: &Path, kept_original| JournalEntry {
+            timestamp: 0,
+            run_id: 1,
+            old_path: old_path.to_path_buf(),
+            new_path: new_path.to_path_buf(),
+            series_id: "77398".to_string(),
+            season_number: 1,
+            episode_number: 1,
+            last_episode_number: None,
+            kept_original,
+            checksum: None,
+            imported: false,
+            created: false,
+        };
+        let named = dir.path().join("Show - S01E01.mkv");
+        let sidecar = dir.path().join("Show - S01E01.en.srt");
+        let nfo = dir.path().join("Show - S01E01.nfo");
+
+        // A file renamed by the run goes back with its sidecars, the NFO written for it goes
+        let original = dir.path().join("title_t00.mkv");
+        for path in [&named, &sidecar, &nfo] {
+            fs::write(path, "").unwrap();
+        }
+        let reverted = revert_claim(&claimed(&original, &named, false), true, &retry).unwrap();
+        assert_eq!(reverted.len(), 2);
+        assert!(original.is_file() && dir.path().join("title_t00.en.srt").is_file());
+        assert!(!named.exists() && !sidecar.exists() && !nfo.exists());
+
+        // One that already had the episode's name makes room for the other file
+        for path in [&named, &sidecar, &nfo] {
+            fs::write(path, "").unwrap();
+        }
+        let reverted = revert_claim(&claimed(&named, &named, false), true, &retry).unwrap();
+        let aside = dir.path().join("Show - S01E01 [copy 1].mkv");
+        assert_eq!(reverted[0].new_path, aside);
+        assert!(aside.is_file() && dir.path().join("Show - S01E01 [copy 1].en.srt").is_file());
+        assert!(!named.exists() && !sidecar.exists() && !nfo.exists());
+
+        // A copy is deleted along with its sidecars, the original is left as it was
+        fs::copy(&original, &named).unwrap();
+        fs::copy(dir.path().join("title_t00.en.srt"), &sidecar).unwrap();
+        let reverted = revert_claim(&claimed(&original, &named, true), false, &retry).unwrap();
+        assert!(reverted.is_empty());
+        assert!(original.is_file() && dir.path().join("title_t00.en.srt").is_file());
+        assert!(!named.exists() && !sidecar.exists());
+    }
+
+    #[test]
+    fn test_scan_windows() {
+        use FrameWindow::{End, Start};
+
+        assert_eq!(scan_windows(ScanPosition::Start, 30), [Start(30)]);
+        assert_eq!(scan_windows(ScanPosition::End, 15), [End(15)]);
+        let both = scan_windows(ScanPosition::Both, 15);
+        assert_eq!(both, [Start(15), End(15)]);
+
+        // A long file is scanned at both ends, a short one once as a whole
+        assert_eq!(ffmpeg::fitted_windows(&both, 1320.0), both);
+        assert_eq!(ffmpeg::fitted_windows(&both, 30.0), [Start(30)]);
+        assert_eq!(ffmpeg::fitted_windows(&both, 20.5), [Start(21)]);
+        // One window is never shortened, FFmpeg stops at the ends of the file
+        assert_eq!(ffmpeg::fitted_windows(&[End(15)], 10.0), [End(15)]);
+    }
+
+    #[test]
+    fn test_search_cached_series() {
+        let mut cache = Cache::default();
+        for (series_key, name) in [
+            ("81189", "Breaking Bad"),
+            ("81189#dvd", "Breaking Bad"),
+            ("tvmaze:169", "Breaking Bad"),
+            ("121361", "Game of Thrones"),
+            ("75760", "Better Call Saul"),
+        ] {
+            cache.set_series_name(series_key.to_string(), name.to_string());
+        }
+        let found = |provider: &dyn MetadataProvider, query| {
+            search_cached_series(provider, &cache, query)
+                .into_iter()
+                .map(|series| (series.id, series.name))
+                .collect::<Vec<_>>()
+        };
+
+        // Only the series cached by the provider, in its ordering
+        let tvmaze = TvmazeClient::new().unwrap();
+        assert_eq!(
+            found(&tvmaze, "breaking"),
+            [("169".to_string(), "Breaking Bad".to_string())]
+        );
+        // Sorted by name
+        let tvdb = TvdbClient::new("key".to_string()).unwrap();
+        assert_eq!(
+            found(&tvdb, "b"),
+            [
+                ("75760".to_string(), "Better Call Saul".to_string()),
+                ("81189".to_string(), "Breaking Bad".to_string())
+            ]
+        );
+        let tvdb = tvdb.with_season_type("dvd");
+        assert_eq!(
+            found(&tvdb, "BAD"),
+            [("81189".to_string(), "Breaking Bad".to_string())]
+        );
+        assert!(found(&tvdb, "thrones").is_empty());
+    }
+}
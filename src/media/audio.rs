@@ -0,0 +1,119 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+use std::process::Command;
+
+// Seconds of audio fingerprinted at every offset
+pub const FINGERPRINT_SECONDS: u64 = 20;
+
+// Chromaprint produces about 8 items per second, so this allows ~10 s of misalignment
+// between rips with different studio logos or recaps
+const MAX_SHIFT: usize = 80;
+
+/// Chromaprint fingerprint of `FINGERPRINT_SECONDS` of audio starting `offset` seconds
+/// into the file. Needs an ffmpeg built with `--enable-chromaprint`.
+pub fn fingerprint(path: &Path, offset: u64) -> Result<Vec<u32>> {
+    let output = Command::new("ffmpeg")
+        .args([
+            "-v",
+            "error",
+            "-ss",
+            &offset.to_string(),
+            "-t",
+            &FINGERPRINT_SECONDS.to_string(),
+            "-i",
+            path.to_str().context("Invalid path")?,
+            "-vn",
+            "-ac",
+            "1",
+            "-f",
+            "chromaprint",
+            "-fp_format",
+            "raw",
+            "-",
+        ])
+        .output()
+        .context("Failed to run ffmpeg")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffmpeg fingerprinting failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let fingerprint = parse_raw(&output.stdout);
+    if fingerprint.is_empty() {
+        bail!("No audio to fingerprint at {offset}s");
+    }
+    Ok(fingerprint)
+}
+
+/// Splits chromaprint's raw output into its little-endian 32-bit items.
+fn parse_raw(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+/// Fraction of matching bits (0.0-1.0) of two fingerprints at their best alignment.
+/// Unrelated audio scores around 0.5.
+pub fn similarity(a: &[u32], b: &[u32]) -> f64 {
+    let min_overlap = a.len().min(b.len()) / 2;
+    if min_overlap == 0 {
+        return 0.0;
+    }
+
+    let mut best = 0.0;
+    for shift in -(MAX_SHIFT as isize)..=MAX_SHIFT as isize {
+        let (a, b) = if shift >= 0 {
+            (a.get(shift as usize..).unwrap_or_default(), b)
+        } else {
+            (a, b.get(shift.unsigned_abs()..).unwrap_or_default())
+        };
+        let overlap = a.len().min(b.len());
+        if overlap < min_overlap {
+            continue;
+        }
+        let matching_bits: u32 = a
+            .iter()
+            .zip(b)
+            .map(|(x, y)| 32 - (x ^ y).count_ones())
+            .sum();
+        best = f64::max(best, matching_bits as f64 / (overlap * 32) as f64);
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_raw() {
+        assert_eq!(
+            parse_raw(&[1, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 7]),
+            vec![1, u32::MAX]
+        );
+    }
+
+    #[test]
+    fn test_similarity() {
+        // Deterministic pseudo-random items
+        let items: Vec<u32> = (0..200u32)
+            .map(|i| i.wrapping_mul(2_654_435_761).rotate_left(i % 32))
+            .collect();
+
+        assert_eq!(similarity(&items, &items), 1.0);
+        // Same audio, but the second rip starts 3 s later
+        assert_eq!(similarity(&items, &items[24..]), 1.0);
+        assert_eq!(similarity(&items[24..], &items), 1.0);
+
+        let other: Vec<u32> = items
+            .iter()
+            .map(|item| item.rotate_left(7) ^ 0x5555_5555)
+            .collect();
+        assert!(similarity(&items, &other) < 0.8);
+        assert_eq!(similarity(&items, &[]), 0.0);
+    }
+}
@@ -1,8 +1,111 @@
 use anyhow::{bail, Context, Result};
+use image::RgbImage;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::thread;
 
-pub fn get_streams_json(path: &Path) -> Result<Vec<u8>> {
+use crate::domain::models::MediaInfo;
+
+// Linearize, tone-map to SDR with Hable and convert back to BT.709 so that credits text
+// keeps its contrast instead of coming out washed-out
+const TONEMAP_FILTER: &str = "zscale=t=linear:npl=100,format=gbrpf32le,zscale=p=bt709,tonemap=tonemap=hable:desat=0,zscale=t=bt709:m=bt709:r=tv,format=yuv420p";
+
+#[derive(Debug, Deserialize)]
+struct StreamProbe {
+    streams: Vec<ProbedStream>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbedStream {
+    codec_type: Option<String>, // `video`, `audio`, `subtitle`...
+    codec_name: Option<String>,
+    channels: Option<u64>,
+    width: Option<u64>,
+    height: Option<u64>,
+    bit_rate: Option<String>, // bits per second, as a decimal string
+    color_transfer: Option<String>,
+    #[serde(default)]
+    side_data_list: Vec<SideData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SideData {
+    side_data_type: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FormatProbe {
+    format: Format,
+}
+
+#[derive(Debug, Deserialize)]
+struct Format {
+    duration: Option<String>, // seconds, as a decimal string
+    bit_rate: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChapterProbe {
+    #[serde(default)]
+    chapters: Vec<ProbedChapter>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ProbedChapter {
+    start_time: String, // seconds, as a decimal string
+    end_time: String,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+/// A chapter of the file, e.g. the recap or the opening credits of an episode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub start: f64, // seconds
+    pub end: f64,
+    pub title: Option<String>,
+}
+
+/// The resolution and bit rate of a file's video, to tell the better of two copies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoQuality {
+    pub width: u64,
+    pub height: u64,
+    pub bit_rate: Option<u64>, // of the video stream, else of the whole file (MKVs)
+}
+
+impl VideoQuality {
+    /// Compares the resolution, then the bit rate. An unknown bit rate ties.
+    pub fn compare(&self, other: &VideoQuality) -> Ordering {
+        let bit_rates = match (self.bit_rate, other.bit_rate) {
+            (Some(bit_rate), Some(other)) => bit_rate.cmp(&other),
+            _ => Ordering::Equal,
+        };
+        (self.width * self.height)
+            .cmp(&(other.width * other.height))
+            .then(bit_rates)
+    }
+}
+
+impl fmt::Display for VideoQuality {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)?;
+        if let Some(bit_rate) = self.bit_rate {
+            write!(f, ", {:.1} Mb/s", bit_rate as f64 / 1_000_000.0)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs ffprobe on the streams matching `stream_specifier` (e.g. `s` or `v:0`).
+pub fn get_streams_json(path: &Path, stream_specifier: &str) -> Result<Vec<u8>> {
     let output = Command::new("ffprobe")
         .args([
             "-v",
@@ -11,7 +114,61 @@ pub fn get_streams_json(path: &Path) -> Result<Vec<u8>> {
             "json",
             "-show_streams",
             "-select_streams",
-            "s",
+            stream_specifier,
+            path.to_str().context("Invalid path")?,
+        ])
+        .output()
+        .context("Failed to run ffprobe")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+/// Duration of the file in seconds.
+pub fn get_duration(path: &Path) -> Result<f64> {
+    parse_duration_probe(&get_format_json(path)?)
+}
+
+/// The global tags of the file, e.g. the segment title of an MKV, with lowercase keys.
+pub fn get_format_tags(path: &Path) -> Result<HashMap<String, String>> {
+    parse_tags_probe(&get_format_json(path)?)
+}
+
+/// The resolution and bit rate of the first video stream.
+pub fn get_video_quality(path: &Path) -> Result<VideoQuality> {
+    parse_video_quality_probe(&get_streams_json(path, "v:0")?, &get_format_json(path)?)
+}
+
+/// The resolution, codec and HDR flag of the first video stream and the channels of the
+/// first audio stream.
+pub fn get_media_info(path: &Path) -> Result<MediaInfo> {
+    parse_media_info_probe(&probe_json(path, "-show_streams")?)
+}
+
+/// The chapters of the file in order, none for most files but MKVs.
+pub fn get_chapters(path: &Path) -> Result<Vec<Chapter>> {
+    parse_chapters_probe(&probe_json(path, "-show_chapters")?)
+}
+
+/// Runs ffprobe on the container of the file.
+fn get_format_json(path: &Path) -> Result<Vec<u8>> {
+    probe_json(path, "-show_format")
+}
+
+fn probe_json(path: &Path, section: &str) -> Result<Vec<u8>> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "quiet",
+            "-print_format",
+            "json",
+            section,
             path.to_str().context("Invalid path")?,
         ])
         .output()
@@ -27,24 +184,120 @@ pub fn get_streams_json(path: &Path) -> Result<Vec<u8>> {
     Ok(output.stdout)
 }
 
+fn parse_duration_probe(json_output: &[u8]) -> Result<f64> {
+    let probe: FormatProbe = serde_json::from_slice(json_output)?;
+    let duration = probe
+        .format
+        .duration
+        .context("ffprobe reported no duration")?;
+    Ok(duration.parse()?)
+}
+
+fn parse_video_quality_probe(streams_json: &[u8], format_json: &[u8]) -> Result<VideoQuality> {
+    let probe: StreamProbe = serde_json::from_slice(streams_json)?;
+    let stream = probe
+        .streams
+        .first()
+        .context("The file has no video stream")?;
+    let (Some(width), Some(height)) = (stream.width, stream.height) else {
+        bail!("ffprobe reported no resolution");
+    };
+    // MKVs only have the bit rate of the whole file
+    let format: FormatProbe = serde_json::from_slice(format_json)?;
+    let bit_rate = stream
+        .bit_rate
+        .as_ref()
+        .or(format.format.bit_rate.as_ref())
+        .and_then(|bit_rate| bit_rate.parse().ok());
+    Ok(VideoQuality {
+        width,
+        height,
+        bit_rate,
+    })
+}
+
+fn parse_media_info_probe(json_output: &[u8]) -> Result<MediaInfo> {
+    let probe: StreamProbe = serde_json::from_slice(json_output)?;
+    let first = |codec_type: &str| {
+        probe
+            .streams
+            .iter()
+            .find(|stream| stream.codec_type.as_deref() == Some(codec_type))
+    };
+    let video = first("video");
+    Ok(MediaInfo {
+        width: video.and_then(|stream| stream.width),
+        height: video.and_then(|stream| stream.height),
+        video_codec: video.and_then(|stream| stream.codec_name.clone()),
+        audio_channels: first("audio").and_then(|stream| stream.channels),
+        hdr: video.is_some_and(is_hdr_stream),
+    })
+}
+
+fn parse_chapters_probe(json_output: &[u8]) -> Result<Vec<Chapter>> {
+    let probe: ChapterProbe = serde_json::from_slice(json_output)?;
+    probe
+        .chapters
+        .into_iter()
+        .map(|chapter| {
+            let title = chapter
+                .tags
+                .into_iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case("title"))
+                .map(|(_, title)| title);
+            Ok(Chapter {
+                start: chapter.start_time.parse()?,
+                end: chapter.end_time.parse()?,
+                title,
+            })
+        })
+        .collect()
+}
+
+fn parse_tags_probe(json_output: &[u8]) -> Result<HashMap<String, String>> {
+    let probe: FormatProbe = serde_json::from_slice(json_output)?;
+    // Matroska tag names are uppercase, MP4 ones lowercase
+    Ok(probe
+        .format
+        .tags
+        .into_iter()
+        .map(|(key, value)| (key.to_lowercase(), value))
+        .collect())
+}
+
+/// Extracts a subtitle track, converting it with `encoder` (`copy` keeps the codec).
+/// `decoder_options` go before the input, e.g. the text format of teletext.
 pub fn extract_subtitle_track(
     input_path: &Path,
     track_index: u32,
+    decoder_options: &[&str],
+    encoder: &str,
     output_path: &Path,
 ) -> Result<()> {
+    #[cfg(feature = "ffmpeg-lib")]
+    if encoder == "copy" && decoder_options.is_empty() {
+        match super::libav::copy_subtitle_track(input_path, track_index, output_path) {
+            Ok(()) => return Ok(()),
+            Err(e) => eprintln!(
+                "Warning: Failed to copy subtitles with the FFmpeg libraries, running ffmpeg instead: {e:#}"
+            ),
+        }
+    }
+
     let output_str = output_path
         .to_str()
         .context("Invalid output path for subtitles")?;
 
     let output = Command::new("ffmpeg")
+        .arg("-y")
+        .args(decoder_options)
         .args([
-            "-y",
             "-i",
             input_path.to_str().context("Invalid input path")?,
             "-map",
             &format!("0:{track_index}"),
             "-c:s",
-            "copy",
+            encoder,
             output_str,
         ])
         .output()
@@ -60,20 +313,113 @@ pub fn extract_subtitle_track(
     Ok(())
 }
 
-pub fn extract_frames(input_path: &str, output_pattern: &str) -> Result<()> {
-    let ffmpeg_output = Command::new("ffmpeg")
-        .arg("-sseof")
-        .arg("-15")
+/// Whether the first video stream is HDR10, HLG or Dolby Vision.
+pub fn is_hdr(path: &Path) -> Result<bool> {
+    let json_output = get_streams_json(path, "v:0")?;
+    Ok(is_hdr_probe(&json_output)?)
+}
+
+fn is_hdr_probe(json_output: &[u8]) -> serde_json::Result<bool> {
+    let probe: StreamProbe = serde_json::from_slice(json_output)?;
+    Ok(probe.streams.iter().any(is_hdr_stream))
+}
+
+fn is_hdr_stream(stream: &ProbedStream) -> bool {
+    let hdr_transfer = matches!(
+        stream.color_transfer.as_deref(),
+        Some("smpte2084") | Some("arib-std-b67")
+    );
+    let dolby_vision = stream
+        .side_data_list
+        .iter()
+        .any(|side_data| side_data.side_data_type.as_deref() == Some("DOVI configuration record"));
+    hdr_transfer || dolby_vision
+}
+
+/// Part of a video to extract frames from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameWindow {
+    Start(u32), // first seconds
+    End(u32),   // last seconds
+}
+
+/// The windows to extract from a video of `duration` seconds. Start and end windows that
+/// overlap in a short video become one over the whole video, so no frame is read twice.
+pub fn fitted_windows(windows: &[FrameWindow], duration: f64) -> Vec<FrameWindow> {
+    let covered: f64 = windows
+        .iter()
+        .map(|window| match window {
+            FrameWindow::Start(seconds) | FrameWindow::End(seconds) => *seconds as f64,
+        })
+        .sum();
+    if windows.len() > 1 && covered >= duration {
+        vec![FrameWindow::Start(duration.ceil() as u32)]
+    } else {
+        windows.to_vec()
+    }
+}
+
+/// Extracts `fps` frames per second of the window and passes them to `on_frame` as they're
+/// decoded. FFmpeg pipes them as PPM images, so nothing is written to disk.
+pub fn extract_frames(
+    input_path: &str,
+    fps: u32,
+    window: FrameWindow,
+    mut on_frame: impl FnMut(RgbImage) -> Result<()>,
+) -> Result<()> {
+    let hdr = match is_hdr(Path::new(input_path)) {
+        Ok(hdr) => hdr,
+        Err(e) => {
+            eprintln!("Warning: Failed to detect HDR, extracting frames without tone-mapping: {e}");
+            false
+        }
+    };
+
+    // The libraries can't tone-map, and a window that failed part way isn't started over
+    #[cfg(feature = "ffmpeg-lib")]
+    if !hdr {
+        let mut passed = 0;
+        let decoded =
+            super::libav::extract_frames(Path::new(input_path), fps, window, &mut |frame| {
+                passed += 1;
+                on_frame(frame)
+            });
+        match decoded {
+            Ok(()) => return Ok(()),
+            Err(e) if passed == 0 => eprintln!(
+                "Warning: Failed to decode frames with the FFmpeg libraries, running ffmpeg instead: {e:#}"
+            ),
+            Err(e) => return Err(e),
+        }
+    }
+
+    let mut filter = format!("fps={fps}");
+    if hdr {
+        println!("HDR video detected, tone-mapping extracted frames");
+        filter = format!("{filter},{TONEMAP_FILTER}");
+    }
+
+    let (seek, seconds) = match window {
+        FrameWindow::Start(seconds) => ("-t", seconds.to_string()),
+        FrameWindow::End(seconds) => ("-sseof", format!("-{seconds}")),
+    };
+
+    let child = Command::new("ffmpeg")
+        .args(["-loglevel", "error"])
+        .arg(seek)
+        .arg(seconds)
         .arg("-i")
         .arg(input_path)
         .arg("-vf")
-        .arg("fps=1")
-        .arg("-y")
-        .arg(output_pattern)
-        .output();
+        .arg(filter)
+        .args(["-f", "image2pipe", "-c:v", "ppm", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
 
-    let ffmpeg_output = match ffmpeg_output {
-        Ok(output) => output,
+    let mut child = match child {
+        Ok(child) => child,
         Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
             bail!("FFmpeg not found. Please install ffmpeg and ensure it's in your PATH.");
         }
@@ -82,11 +428,208 @@ pub fn extract_frames(input_path: &str, output_pattern: &str) -> Result<()> {
         }
     };
 
-    if !ffmpeg_output.status.success() {
-        let stderr = String::from_utf8_lossy(&ffmpeg_output.stderr);
+    // Drained while the frames are read, FFmpeg would block on a full stderr pipe and
+    // never finish writing them
+    let stderr_pipe = child.stderr.take();
+    let stderr_reader = thread::spawn(move || {
+        let mut stderr = String::new();
+        if let Some(mut pipe) = stderr_pipe {
+            let _ = pipe.read_to_string(&mut stderr);
+        }
+        stderr
+    });
+
+    let mut frames = BufReader::new(child.stdout.take().context("No ffmpeg output")?);
+    let read = read_frames(&mut frames, &mut on_frame);
+    if read.is_err() {
+        // Stops decoding the rest of the window
+        let _ = child.kill();
+    }
+    let status = child.wait()?;
+    let stderr = stderr_reader.join().unwrap_or_default();
+    read?;
+
+    if !status.success() {
         bail!("FFmpeg error: {stderr}");
     }
 
     Ok(())
 }
 
+fn read_frames(
+    reader: &mut impl BufRead,
+    on_frame: &mut impl FnMut(RgbImage) -> Result<()>,
+) -> Result<()> {
+    while let Some(frame) = read_ppm(reader)? {
+        on_frame(frame)?;
+    }
+    Ok(())
+}
+
+/// Reads the next binary PPM image of a stream, `None` at its end.
+fn read_ppm(reader: &mut impl BufRead) -> Result<Option<RgbImage>> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    // The magic number, width, height and maximum value, each followed by one whitespace
+    let mut header = Vec::new();
+    while header.len() < 4 {
+        let mut token = Vec::new();
+        loop {
+            let mut byte = [0];
+            reader.read_exact(&mut byte)?;
+            if !byte[0].is_ascii_whitespace() {
+                token.push(byte[0]);
+            } else if !token.is_empty() {
+                break;
+            }
+        }
+        header.push(String::from_utf8_lossy(&token).into_owned());
+    }
+    if header[0] != "P6" || header[3] != "255" {
+        bail!("Unexpected frame format {} {}", header[0], header[3]);
+    }
+    let width: u32 = header[1].parse().context("Invalid frame width")?;
+    let height: u32 = header[2].parse().context("Invalid frame height")?;
+
+    let mut pixels = vec![0; width as usize * height as usize * 3];
+    reader.read_exact(&mut pixels)?;
+    Ok(RgbImage::from_raw(width, height, pixels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_ppm() {
+        let mut stream = b"P6\n2 1\n255\n\x01\x02\x03\x04\x05\x06".to_vec();
+        stream.extend(b"P6 1 1 255\n\xff\x00\x00");
+        let mut reader = stream.as_slice();
+
+        let first = read_ppm(&mut reader).unwrap().unwrap();
+        assert_eq!(first.dimensions(), (2, 1));
+        assert_eq!(first.get_pixel(1, 0).0, [4, 5, 6]);
+        let second = read_ppm(&mut reader).unwrap().unwrap();
+        assert_eq!(second.get_pixel(0, 0).0, [255, 0, 0]);
+        assert!(read_ppm(&mut reader).unwrap().is_none());
+
+        // Cut off mid-frame
+        assert!(read_ppm(&mut &b"P6\n2 2\n255\n\x00"[..]).is_err());
+    }
+
+    #[test]
+    fn test_parse_duration_probe() {
+        let probe = br#"{"format": {"filename": "a.mkv", "duration": "2653.120000"}}"#;
+        assert_eq!(parse_duration_probe(probe).unwrap(), 2653.12);
+        assert!(parse_duration_probe(br#"{"format": {}}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_video_quality_probe() {
+        let streams = br#"{"streams": [{"codec_name": "h264", "width": 1920, "height": 1080}]}"#;
+        let format = br#"{"format": {"duration": "2653.120000", "bit_rate": "8123456"}}"#;
+        let quality = parse_video_quality_probe(streams, format).unwrap();
+        assert_eq!(
+            quality,
+            VideoQuality {
+                width: 1920,
+                height: 1080,
+                bit_rate: Some(8123456)
+            }
+        );
+        assert_eq!(quality.to_string(), "1920x1080, 8.1 Mb/s");
+
+        let dvd = br#"{"streams": [{"width": 720, "height": 480, "bit_rate": "9800000"}]}"#;
+        let dvd = parse_video_quality_probe(dvd, format).unwrap();
+        assert_eq!(dvd.bit_rate, Some(9800000));
+        // The resolution counts before the bit rate
+        assert_eq!(quality.compare(&dvd), Ordering::Greater);
+        let unknown = VideoQuality {
+            bit_rate: None,
+            ..quality
+        };
+        assert_eq!(quality.compare(&unknown), Ordering::Equal);
+
+        assert!(parse_video_quality_probe(br#"{"streams": []}"#, format).is_err());
+    }
+
+    #[test]
+    fn test_parse_media_info_probe() {
+        let probe = br#"{"streams": [
+            {"index": 0, "codec_name": "hevc", "codec_type": "video", "width": 3840, "height": 2160, "color_transfer": "smpte2084"},
+            {"index": 1, "codec_name": "eac3", "codec_type": "audio", "channels": 6},
+            {"index": 2, "codec_name": "aac", "codec_type": "audio", "channels": 2},
+            {"index": 3, "codec_name": "subrip", "codec_type": "subtitle"}
+        ]}"#;
+        assert_eq!(
+            parse_media_info_probe(probe).unwrap(),
+            MediaInfo {
+                width: Some(3840),
+                height: Some(2160),
+                video_codec: Some("hevc".to_string()),
+                audio_channels: Some(6),
+                hdr: true,
+            }
+        );
+
+        let audio_only =
+            br#"{"streams": [{"codec_name": "flac", "codec_type": "audio", "channels": 2}]}"#;
+        let info = parse_media_info_probe(audio_only).unwrap();
+        assert_eq!(
+            (info.width, info.audio_channels, info.hdr),
+            (None, Some(2), false)
+        );
+    }
+
+    #[test]
+    fn test_parse_tags_probe() {
+        let probe =
+            br#"{"format": {"tags": {"TITLE": "The X-Files S01E03", "ENCODER": "libebml"}}}"#;
+        let tags = parse_tags_probe(probe).unwrap();
+        assert_eq!(tags["title"], "The X-Files S01E03");
+        assert!(parse_tags_probe(br#"{"format": {}}"#).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_chapters_probe() {
+        let probe = br#"{"chapters": [
+            {"id": 1, "time_base": "1/1000000000", "start": 0, "start_time": "0.000000", "end": 95000000000, "end_time": "95.000000", "tags": {"title": "Recap"}},
+            {"id": 2, "time_base": "1/1000000000", "start": 95000000000, "start_time": "95.000000", "end": 1320500000000, "end_time": "1320.500000"}
+        ]}"#;
+        assert_eq!(
+            parse_chapters_probe(probe).unwrap(),
+            vec![
+                Chapter {
+                    start: 0.0,
+                    end: 95.0,
+                    title: Some("Recap".to_string()),
+                },
+                Chapter {
+                    start: 95.0,
+                    end: 1320.5,
+                    title: None,
+                },
+            ]
+        );
+        assert!(parse_chapters_probe(b"{}").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_is_hdr_probe() {
+        let sdr = br#"{"streams": [{"codec_name": "h264", "color_transfer": "bt709"}]}"#;
+        assert!(!is_hdr_probe(sdr).unwrap());
+
+        let untagged = br#"{"streams": [{"codec_name": "mpeg2video"}]}"#;
+        assert!(!is_hdr_probe(untagged).unwrap());
+
+        let hdr10 = br#"{"streams": [{"codec_name": "hevc", "color_transfer": "smpte2084"}]}"#;
+        assert!(is_hdr_probe(hdr10).unwrap());
+
+        let hlg = br#"{"streams": [{"codec_name": "hevc", "color_transfer": "arib-std-b67"}]}"#;
+        assert!(is_hdr_probe(hlg).unwrap());
+
+        let dolby_vision = br#"{"streams": [{"codec_name": "hevc", "side_data_list": [{"side_data_type": "DOVI configuration record", "dv_profile": 5}]}]}"#;
+        assert!(is_hdr_probe(dolby_vision).unwrap());
+    }
+}
@@ -0,0 +1,226 @@
+//! Frame and subtitle extraction with the FFmpeg libraries, only built with the `ffmpeg-lib`
+//! feature. Needs no ffmpeg binary on the PATH and drops the frames before the exact start
+//! of a window rather than starting at the keyframe before it.
+
+use anyhow::{bail, Context, Result};
+use ffmpeg_next::format::{self, Pixel};
+use ffmpeg_next::media::Type;
+use ffmpeg_next::software::scaling::{self, flag::Flags};
+use ffmpeg_next::util::frame::video::Video;
+use ffmpeg_next::{codec, decoder, encoder};
+use image::RgbImage;
+use std::path::Path;
+
+use super::ffmpeg::FrameWindow;
+
+// Container timestamps, e.g. the duration and seek targets, count microseconds
+const CONTAINER_TIME_BASE: f64 = 1_000_000.0;
+
+/// Decodes `fps` frames per second of the window and passes them to `on_frame` as they're
+/// decoded, like [`super::ffmpeg::extract_frames`] without tone-mapping.
+pub fn extract_frames(
+    input_path: &Path,
+    fps: u32,
+    window: FrameWindow,
+    on_frame: &mut impl FnMut(RgbImage) -> Result<()>,
+) -> Result<()> {
+    ffmpeg_next::init()?;
+    let mut input =
+        format::input(&input_path).with_context(|| format!("Failed to open {input_path:?}"))?;
+    let (stream_index, time_base, start_time, mut decoder) = {
+        let stream = input
+            .streams()
+            .best(Type::Video)
+            .context("No video stream")?;
+        // AV_NOPTS_VALUE if the stream doesn't say
+        let start_time = match stream.start_time() {
+            i64::MIN => 0,
+            start_time => start_time,
+        };
+        let decoder = codec::context::Context::from_parameters(stream.parameters())?
+            .decoder()
+            .video()?;
+        (
+            stream.index(),
+            f64::from(stream.time_base()),
+            start_time,
+            decoder,
+        )
+    };
+
+    let duration = input.duration() as f64 / CONTAINER_TIME_BASE;
+    let (from, to) = window_bounds(window, duration);
+    if let FrameWindow::End(_) = window {
+        // Lands on the keyframe before, the frames up to `from` are decoded and dropped
+        let target = (from * CONTAINER_TIME_BASE) as i64;
+        input.seek(target, ..target)?;
+    }
+
+    let mut scaler = scaling::Context::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGB24,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )?;
+    let mut next = from; // when the next frame to pass on is due
+    let mut pass_decoded = |decoder: &mut decoder::Video| -> Result<bool> {
+        let mut decoded = Video::empty();
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            let Some(timestamp) = decoded.timestamp() else {
+                continue;
+            };
+            let time = (timestamp - start_time) as f64 * time_base;
+            if time >= to {
+                return Ok(true);
+            }
+            if !is_sampled(time, &mut next, fps) {
+                continue;
+            }
+            let mut rgb = Video::empty();
+            scaler.run(&decoded, &mut rgb)?;
+            on_frame(rgb_image(&rgb)?)?;
+        }
+        Ok(false)
+    };
+
+    for (stream, packet) in input.packets() {
+        if stream.index() != stream_index {
+            continue;
+        }
+        decoder.send_packet(&packet)?;
+        if pass_decoded(&mut decoder)? {
+            return Ok(());
+        }
+    }
+    decoder.send_eof()?;
+    pass_decoded(&mut decoder)?;
+    Ok(())
+}
+
+/// The window in seconds into the stream, for a video of `duration` seconds.
+fn window_bounds(window: FrameWindow, duration: f64) -> (f64, f64) {
+    match window {
+        FrameWindow::Start(seconds) => (0.0, seconds as f64),
+        FrameWindow::End(seconds) => ((duration - seconds as f64).max(0.0), f64::INFINITY),
+    }
+}
+
+/// Whether the frame at `time` is passed on, `next` being when the next one is due. Like
+/// the fps filter, a frame stands in for every sampling time it covers.
+fn is_sampled(time: f64, next: &mut f64, fps: u32) -> bool {
+    if time < *next {
+        return false;
+    }
+    while *next <= time {
+        *next += 1.0 / fps as f64;
+    }
+    true
+}
+
+/// Copies an RGB24 frame, whose lines may be padded, into an image.
+fn rgb_image(frame: &Video) -> Result<RgbImage> {
+    let (width, height) = (frame.width(), frame.height());
+    let pixels = unpadded(
+        frame.data(0),
+        frame.stride(0),
+        width as usize * 3,
+        height as usize,
+    );
+    RgbImage::from_raw(width, height, pixels).context("Truncated frame")
+}
+
+/// The first `row` bytes of each of the `height` lines of `stride` bytes.
+fn unpadded(data: &[u8], stride: usize, row: usize, height: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(row * height);
+    for line in data.chunks(stride).take(height) {
+        pixels.extend_from_slice(&line[..row.min(line.len())]);
+    }
+    pixels
+}
+
+/// Copies a subtitle track into a file of the format its extension names, keeping the codec.
+pub fn copy_subtitle_track(input_path: &Path, track_index: u32, output_path: &Path) -> Result<()> {
+    ffmpeg_next::init()?;
+    let mut input =
+        format::input(&input_path).with_context(|| format!("Failed to open {input_path:?}"))?;
+    let mut output = format::output(&output_path)
+        .with_context(|| format!("Failed to create {output_path:?}"))?;
+    let track_index = track_index as usize;
+
+    let input_time_base = {
+        let stream = input
+            .stream(track_index)
+            .with_context(|| format!("No track {track_index}"))?;
+        if stream.parameters().medium() != Type::Subtitle {
+            bail!("Track {track_index} isn't a subtitle track");
+        }
+        let mut copy = output.add_stream(encoder::find(codec::Id::None))?;
+        copy.set_parameters(stream.parameters());
+        stream.time_base()
+    };
+    output.write_header()?;
+    let output_time_base = output.stream(0).context("No output stream")?.time_base();
+
+    for (stream, mut packet) in input.packets() {
+        if stream.index() != track_index {
+            continue;
+        }
+        packet.rescale_ts(input_time_base, output_time_base);
+        packet.set_position(-1);
+        packet.set_stream(0);
+        packet.write_interleaved(&mut output)?;
+    }
+    output.write_trailer()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_bounds() {
+        assert_eq!(window_bounds(FrameWindow::Start(60), 1320.0), (0.0, 60.0));
+        assert_eq!(
+            window_bounds(FrameWindow::End(60), 1320.0),
+            (1260.0, f64::INFINITY)
+        );
+        // A file shorter than the window is read whole
+        assert_eq!(
+            window_bounds(FrameWindow::End(60), 45.0),
+            (0.0, f64::INFINITY)
+        );
+    }
+
+    #[test]
+    fn test_is_sampled() {
+        // Two of the 25 frames a second, the first at or after each half second
+        let mut next = 0.0;
+        let sampled: Vec<u32> = (0..50)
+            .filter(|&frame| is_sampled(frame as f64 / 25.0, &mut next, 2))
+            .collect();
+        assert_eq!(sampled, [0, 13, 25, 38]);
+
+        // A frame held longer than the sampling interval stands in for all its times
+        let mut next = 0.0;
+        assert!(is_sampled(0.0, &mut next, 4));
+        assert!(is_sampled(1.0, &mut next, 4));
+        assert_eq!(next, 1.25);
+        assert!(!is_sampled(1.1, &mut next, 4));
+    }
+
+    #[test]
+    fn test_unpadded() {
+        // Lines of 2 RGB pixels padded to 8 bytes
+        let data = [1, 2, 3, 4, 5, 6, 0, 0, 7, 8, 9, 10, 11, 12, 0, 0];
+        assert_eq!(
+            unpadded(&data, 8, 6, 2),
+            [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]
+        );
+        // A truncated frame comes out short, which `RgbImage::from_raw` refuses
+        assert_eq!(unpadded(&data[..12], 8, 6, 2).len(), 10);
+    }
+}
@@ -0,0 +1,154 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::domain::models::EpisodeEntry;
+use crate::infra::tvdb;
+
+/// Writes the identification of the file's episodes into the MKV, so it travels with the
+/// file: the segment title and global tags with the series and episode IDs and production
+/// codes. mkvpropedit only edits the headers in place, the file isn't remuxed.
+pub fn tag_file(
+    path: &Path,
+    show_name: &str,
+    series_id: &str,
+    episodes: &[EpisodeEntry],
+) -> Result<()> {
+    let tags = tempfile::Builder::new().suffix(".xml").tempfile()?;
+    fs::write(tags.path(), tags_xml(series_id, episodes))?;
+    let mut tags_arg = std::ffi::OsString::from("global:");
+    tags_arg.push(tags.path());
+
+    let output = Command::new("mkvpropedit")
+        .arg(path)
+        .args(["--edit", "info", "--set"])
+        .arg(format!("title={}", segment_title(show_name, episodes)))
+        .arg("--tags")
+        .arg(tags_arg)
+        .output()
+        .context("Failed to run mkvpropedit, is MKVToolNix installed?")?;
+    if !output.status.success() {
+        // mkvpropedit reports errors on stdout
+        bail!(
+            "mkvpropedit failed: {}",
+            String::from_utf8_lossy(&output.stdout).trim()
+        );
+    }
+    Ok(())
+}
+
+/// `The X-Files - S01E03 - Squeeze`, or `S01E03-E04 - Squeeze & Conduit` for several
+/// episodes.
+fn segment_title(show_name: &str, episodes: &[EpisodeEntry]) -> String {
+    let (Some(first), Some(last)) = (episodes.first(), episodes.last()) else {
+        return show_name.to_string();
+    };
+    let numbers = if episodes.len() > 1 {
+        format!(
+            "S{:02}E{:02}-E{:02}",
+            first.season_number, first.episode_number, last.episode_number
+        )
+    } else {
+        format!("S{:02}E{:02}", first.season_number, first.episode_number)
+    };
+    let titles: Vec<&str> = episodes
+        .iter()
+        .map(|episode| episode.name.as_str())
+        .collect();
+    format!("{show_name} - {numbers} - {}", titles.join(" & "))
+}
+
+/// Matroska tags XML with the IDs of the series at its provider, its episodes (comma
+/// separated for a multi-episode file) and their production codes.
+fn tags_xml(series_id: &str, episodes: &[EpisodeEntry]) -> String {
+    // TVmaze series are cached as `tvmaze:<id>`, other episode orders of TVDB series as
+    // `<id>#<order>`
+    let series_id = tvdb::series_id(series_id);
+    let (provider, series_id) = match series_id.strip_prefix("tvmaze:") {
+        Some(id) => ("TVMAZE", id),
+        None => ("TVDB", series_id),
+    };
+    let join = |values: Vec<String>| values.join(",");
+    let episode_ids = join(
+        episodes
+            .iter()
+            .filter_map(|episode| episode.id)
+            .map(|id| id.to_string())
+            .collect(),
+    );
+    let codes = join(
+        episodes
+            .iter()
+            .filter_map(|episode| episode.production_code.clone())
+            .collect(),
+    );
+
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<Tags>\n  <Tag>\n");
+    let mut simple = |name: String, value: &str| {
+        if !value.is_empty() {
+            xml.push_str(&format!(
+                "    <Simple><Name>{name}</Name><String>{}</String></Simple>\n",
+                escape(value)
+            ));
+        }
+    };
+    simple(format!("{provider}_SERIES_ID"), series_id);
+    simple(format!("{provider}_EPISODE_ID"), &episode_ids);
+    simple("PRODUCTION_CODE".to_string(), &codes);
+    xml.push_str("  </Tag>\n</Tags>\n");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(episode_number: u64, name: &str, id: Option<u64>) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: Some(format!("1X0{episode_number}")),
+            season_number: 1,
+            episode_number,
+            name: name.to_string(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_segment_title() {
+        let squeeze = episode(3, "Squeeze", None);
+        let conduit = episode(4, "Conduit", None);
+        assert_eq!(
+            segment_title("The X-Files", std::slice::from_ref(&squeeze)),
+            "The X-Files - S01E03 - Squeeze"
+        );
+        assert_eq!(
+            segment_title("The X-Files", &[squeeze, conduit]),
+            "The X-Files - S01E03-E04 - Squeeze & Conduit"
+        );
+    }
+
+    #[test]
+    fn test_tags_xml() {
+        let xml = tags_xml("77398#dvd", &[episode(3, "Squeeze", Some(184601))]);
+        assert!(xml.contains("<Name>TVDB_SERIES_ID</Name><String>77398</String>"));
+        assert!(xml.contains("<Name>TVDB_EPISODE_ID</Name><String>184601</String>"));
+        assert!(xml.contains("<Name>PRODUCTION_CODE</Name><String>1X03</String>"));
+
+        let both = [episode(3, "Squeeze", None), episode(4, "Conduit", None)];
+        let xml = tags_xml("tvmaze:83", &both);
+        assert!(xml.contains("<Name>TVMAZE_SERIES_ID</Name><String>83</String>"));
+        assert!(!xml.contains("EPISODE_ID"));
+        assert!(xml.contains("<String>1X03,1X04</String>"));
+    }
+}
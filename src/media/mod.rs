@@ -1,4 +1,13 @@
+pub mod audio;
 pub mod ffmpeg;
+#[cfg(feature = "ffmpeg-lib")]
+pub mod libav;
+pub mod mkvpropedit;
 pub mod ocr;
+pub mod preprocess;
 pub mod subtitles;
+pub mod tessdata;
+#[cfg(all(target_os = "macos", feature = "vision"))]
+pub mod vision;
+pub mod vobsub;
 
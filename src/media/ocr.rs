@@ -1,151 +1,493 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
+use image::{imageops, GrayImage, RgbImage};
 use regex::Regex;
 use std::fs;
-use std::path::PathBuf;
-use tempfile::TempDir;
-use tesseract_rs::TesseractAPI;
-
-use crate::media::ffmpeg;
-
-#[cfg(target_os = "macos")]
-const ENG: &[u8] = include_bytes!(concat!(
-    env!("HOME"),
-    "/Library/Application Support/tesseract-rs/tessdata/eng.traineddata"
-));
-
-#[cfg(target_os = "linux")]
-const ENG: &[u8] = include_bytes!(concat!(
-    env!("HOME"),
-    "/.tesseract-rs/tessdata/eng.traineddata"
-));
-
-#[cfg(target_os = "windows")]
-const ENG: &[u8] = include_bytes!(concat!(
-    env!("APPDATA"),
-    "/tesseract-rs/tessdata/eng.traineddata"
-));
-
-pub fn extract_production_code_candidates(mkv_path: &str) -> Result<Vec<String>> {
-    // Create temporary directory for frames
-    let temp_dir = TempDir::new()?;
-    let temp_path = temp_dir.path();
-
-    // Extract frames from last 15 seconds at 1 fps
-    let output_pattern = temp_path.join("frame_%04d.png");
-    let Some(output_pattern_str) = output_pattern.to_str() else {
-        bail!("Invalid temp path");
-    };
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tesseract_rs::{TessPageSegMode, TesseractAPI};
 
-    ffmpeg::extract_frames(mkv_path, output_pattern_str)?;
-
-    // Initialize OCR engine
-    let api = create_ocr_engine()?;
-
-    // Regex pattern for production code format:
-    // - Seasons 1-5: #3X22 or #1X79 (season X episode)
-    // - Season 6-9: #6ABX08 (season 6, episode 6) - format: #<season>ABX<episode>
-    // - Season 10-11: #1AYW01, #2AYW01 - format: #<season>AYW<episode> (no X)
-    // Case-insensitive, whitespace is stripped before matching
-    // Matches: #<season>X<episode> or #<season><letters>X<episode> or #<season><letters><episode>
-    let re = Regex::new(r"(?i)(?:\d[A-Z]{1,3}[\d]{2,3})|(?:1?\d[0-3]\d)")?;
-
-    // Process extracted frames
-    let mut frame_files: Vec<PathBuf> = fs::read_dir(temp_path)?
-        .filter_map(|entry| {
-            let entry = entry.ok()?;
-            let path = entry.path();
-            if path.extension()?.to_str()? == "png" {
-                Some(path)
-            } else {
-                None
-            }
-        })
-        .collect();
+use crate::domain::models::OcrCandidate;
+use crate::media::tessdata::{self, TessdataSource};
+#[cfg(all(target_os = "macos", feature = "vision"))]
+use crate::media::vision;
+use crate::media::{ffmpeg, preprocess};
 
-    // Sort frames by name to process in order
-    frame_files.sort();
-
-    let mut candidates = Vec::new();
-    // Try OCR on each frame until we find the production code
-    for frame_path in frame_files {
-        // Load image from file
-        let img = match image::open(&frame_path) {
-            Ok(img) => img,
-            Err(e) => {
-                eprintln!("Warning: Failed to load image {frame_path:?}: {e}");
-                continue;
-            }
-        };
+// Regex pattern for production code format:
+// - Seasons 1-5: #3X22 or #1X79 (season X episode)
+// - Season 6-9: #6ABX08 (season 6, episode 6) - format: #<season>ABX<episode>
+// - Season 10-11: #1AYW01, #2AYW01 - format: #<season>AYW<episode> (no X)
+// Case-insensitive, whitespace is stripped before matching
+// Matches: #<season>X<episode> or #<season><letters>X<episode> or #<season><letters><episode>
+pub const DEFAULT_CODE_PATTERN: &str = r"(?i)(?:\d[A-Z]{1,3}[\d]{2,3})|(?:1?\d[0-3]\d)";
+
+// Characters tesseract may read on frames scanned for production codes, the rest of the
+// credits then can't turn into look-alike candidates
+pub const CODE_WHITELIST: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ#";
+
+// Page segmentation mode of the tesseract API unless configured: a single uniform block
+const DEFAULT_PAGE_SEG_MODE: u8 = 6;
+
+/// Tuning for production code OCR on extracted frames.
+#[derive(Debug, Clone, Default)]
+pub struct FrameOcrOptions {
+    pub windows: Vec<ffmpeg::FrameWindow>, // parts of the video to scan
+    pub scan_fps: u32,                     // frames OCRed per second of a window
+    pub burst_fps: Option<u32>,
+    pub crop: Option<preprocess::CropRegion>, // part of the frames the code appears in
+    pub deskew: bool,
+    pub rotate: Option<f32>,       // degrees clockwise, overrides deskew
+    pub code_regex: Option<Regex>, // production code pattern, DEFAULT_CODE_PATTERN if unset
+    pub tesseract: TesseractSettings,
+    pub frames_dir: Option<PathBuf>, // screenshots OCRed instead of the video's frames
+    pub single_video: bool, // the only video of the run may use the screenshots of `frames_dir`
+}
+
+/// How tesseract reads an image. Pooled engines are configured anew whenever they're
+/// borrowed, since frames and subtitles share them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TesseractSettings {
+    pub page_seg_mode: Option<u8>, // tesseract `--psm`, 0-13
+    pub whitelist: Option<String>, // the only characters to recognize, all if unset
+}
+
+/// Which OCR engine to read frames and PGS subtitles with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Tesseract,
+    Vision, // Apple Vision framework, see `VISION_AVAILABLE`
+}
+
+/// Whether this build has the Apple Vision backend (macOS with the `vision` feature).
+pub const VISION_AVAILABLE: bool = cfg!(all(target_os = "macos", feature = "vision"));
+
+/// Text an [`OcrEngine`] read from an image.
+#[derive(Debug, Clone, Default)]
+pub struct RecognizedText {
+    pub text: String,
+    pub word_confidences: Vec<i32>, // 0-100, one per whitespace-separated word of `text`
+    pub mean_confidence: i32,
+}
+
+/// An OCR backend. Engines are pooled and reused for many images.
+pub trait OcrEngine: Send {
+    /// Reads the text of an RGB image with 3 bytes per pixel and no row padding.
+    fn recognize(&self, rgb: &[u8], width: u32, height: u32) -> Result<RecognizedText>;
+
+    /// Applies tesseract settings to the next images, other backends ignore them.
+    fn configure(&self, _settings: &TesseractSettings) -> Result<()> {
+        Ok(())
+    }
 
-        // Convert to RGB8 for tesseract (tesseract expects RGB)
-        let rgb_img = img.to_rgb8();
-        let (width, height) = rgb_img.dimensions();
-        let image_data = rgb_img.into_raw();
+    /// Frees what the last image left behind before the engine goes back to the pool.
+    fn clear(&self) -> Result<()> {
+        Ok(())
+    }
+}
 
-        // Perform OCR
-        match api.set_image(
-            &image_data,
+struct TesseractEngine(TesseractAPI);
+
+impl OcrEngine for TesseractEngine {
+    fn recognize(&self, rgb: &[u8], width: u32, height: u32) -> Result<RecognizedText> {
+        self.0.set_image(
+            rgb,
             width as i32,
             height as i32,
             3,                // bytes per pixel (RGB)
             3 * width as i32, // bytes per line
-        ) {
-            Ok(_) => {
-                match api.get_utf8_text() {
-                    Ok(text) => {
-                        // Strip all whitespace from the text before matching
-                        let text_no_whitespace: String = text
-                            .chars()
-                            .filter(|c| !c.is_whitespace())
-                            .map(|c| match c {
-                                'O' => '0',
-                                'I' => '1',
-                                'S' => '5',
-                                '?' => 'X',
-                                _ => c,
-                            })
-                            .collect();
-
-                        // Search for production code pattern in the extracted text
-                        let matches = re.find_iter(&text_no_whitespace);
-                        for candidate in matches {
-                            candidates.push(candidate.as_str().to_owned());
-                        }
+        )?;
+        let text = self.0.get_utf8_text()?;
+        Ok(RecognizedText {
+            text,
+            word_confidences: self.0.all_word_confidences().unwrap_or_default(),
+            mean_confidence: self.0.mean_text_conf().unwrap_or(0),
+        })
+    }
+
+    fn configure(&self, settings: &TesseractSettings) -> Result<()> {
+        let mode = settings.page_seg_mode.unwrap_or(DEFAULT_PAGE_SEG_MODE);
+        self.0
+            .set_page_seg_mode(TessPageSegMode::from_int(mode as i32))?;
+        // An empty whitelist allows every character again
+        let whitelist = settings.whitelist.as_deref().unwrap_or_default();
+        self.0.set_variable("tessedit_char_whitelist", whitelist)?;
+        Ok(())
+    }
+
+    fn clear(&self) -> Result<()> {
+        // Frees the last image and its results, the loaded language data is kept
+        Ok(self.0.clear()?)
+    }
+}
+
+/// Initialized OCR engines that are reused for every file of a run, since initializing
+/// one loads the language data and takes longer than OCRing a file's frames.
+pub struct OcrEnginePool {
+    idle: Mutex<Vec<Box<dyn OcrEngine>>>,
+    backend: Backend,
+    tessdata: TessdataSource,
+    languages: String, // tesseract language selection, e.g. `eng+deu`
+}
+
+impl Default for OcrEnginePool {
+    fn default() -> Self {
+        Self::new(
+            Backend::Tesseract,
+            TessdataSource::default(),
+            tessdata::DEFAULT_LANGUAGE.to_string(),
+        )
+    }
+}
+
+impl OcrEnginePool {
+    pub fn new(backend: Backend, tessdata: TessdataSource, languages: String) -> Self {
+        Self {
+            idle: Mutex::default(),
+            backend,
+            tessdata,
+            languages,
+        }
+    }
+
+    /// Takes an idle engine with the default settings, or initializes a new one if all are
+    /// in use.
+    pub fn get(&self) -> Result<PooledEngine<'_>> {
+        self.get_configured(&TesseractSettings::default())
+    }
+
+    /// Takes an idle engine like [`Self::get`] and applies the settings to it.
+    pub fn get_configured(&self, settings: &TesseractSettings) -> Result<PooledEngine<'_>> {
+        let idle = self.idle.lock().unwrap().pop();
+        let engine = match idle {
+            Some(engine) => engine,
+            None => self.create_engine()?,
+        };
+        engine.configure(settings)?;
+        Ok(PooledEngine {
+            pool: self,
+            engine: Some(engine),
+        })
+    }
+
+    fn create_engine(&self) -> Result<Box<dyn OcrEngine>> {
+        match self.backend {
+            Backend::Tesseract => {
+                let dir = tessdata::locate(&self.tessdata, &self.languages)?;
+                let api = TesseractAPI::new();
+                api.init(&dir, &self.languages)?;
+                Ok(Box::new(TesseractEngine(api)))
+            }
+            #[cfg(all(target_os = "macos", feature = "vision"))]
+            Backend::Vision => Ok(Box::new(vision::VisionEngine::new(&self.languages))),
+            #[cfg(not(all(target_os = "macos", feature = "vision")))]
+            Backend::Vision => bail!("This build has no Apple Vision OCR backend"),
+        }
+    }
+}
+
+/// An engine borrowed from an [`OcrEnginePool`], returned to it when dropped.
+pub struct PooledEngine<'a> {
+    pool: &'a OcrEnginePool,
+    engine: Option<Box<dyn OcrEngine>>,
+}
+
+impl Deref for PooledEngine<'_> {
+    type Target = dyn OcrEngine;
+
+    fn deref(&self) -> &(dyn OcrEngine + 'static) {
+        self.engine.as_deref().unwrap()
+    }
+}
+
+impl Drop for PooledEngine<'_> {
+    fn drop(&mut self) {
+        if let Some(engine) = self.engine.take() {
+            if engine.clear().is_ok() {
+                self.pool.idle.lock().unwrap().push(engine);
+            }
+        }
+    }
+}
+
+pub fn extract_production_code_candidates(
+    mkv_path: &str,
+    options: &FrameOcrOptions,
+    ocr_pool: &OcrEnginePool,
+) -> Result<Vec<OcrCandidate>> {
+    // OCR `scan_fps` frames per second, in burst mode the sharpest of several extracted
+    // ones since the others are motion-blurred by the scrolling credits
+    let scan_fps = options.scan_fps.max(1);
+    let fps = options.burst_fps.unwrap_or(scan_fps).max(scan_fps);
+    let burst = (fps / scan_fps) as usize;
+
+    let mut scanner = FrameScanner {
+        // Borrow an initialized OCR engine
+        api: ocr_pool.get_configured(&options.tesseract)?,
+        re: match &options.code_regex {
+            Some(re) => re.clone(),
+            None => Regex::new(DEFAULT_CODE_PATTERN)?,
+        },
+        options,
+        last_read: None,
+        duplicates: 0,
+        candidates: Vec::new(),
+    };
+
+    if let Some(dir) = &options.frames_dir {
+        for path in frame_images(dir, Path::new(mkv_path), options.single_video)? {
+            let img = image::open(&path)
+                .with_context(|| format!("Failed to read {path:?}"))?
+                .to_rgb8();
+            let frame = path.file_name().unwrap_or_default().to_string_lossy();
+            scanner.scan(frame.into_owned(), img);
+        }
+        return Ok(scanner.candidates);
+    }
+
+    let windows = match options.windows.as_slice() {
+        [_, _, ..] => match ffmpeg::get_duration(Path::new(mkv_path)) {
+            Ok(duration) => ffmpeg::fitted_windows(&options.windows, duration),
+            Err(_) => options.windows.clone(),
+        },
+        windows => windows.to_vec(),
+    };
+    for window in &windows {
+        let prefix = match window {
+            ffmpeg::FrameWindow::Start(_) => "start",
+            ffmpeg::FrameWindow::End(_) => "end",
+        };
+        let mut number = 0;
+        let mut sharpest: Option<(String, RgbImage, f64)> = None; // of the current burst
+        let mut in_burst = 0;
+        ffmpeg::extract_frames(mkv_path, fps, *window, |img| {
+            number += 1;
+            let frame = format!("{prefix}_{number:04}");
+            if burst <= 1 {
+                scanner.scan(frame, img);
+                return Ok(());
+            }
+            let sharpness = laplacian_variance(&imageops::grayscale(&img));
+            if sharpest
+                .as_ref()
+                .is_none_or(|(_, _, best)| sharpness >= *best)
+            {
+                sharpest = Some((frame, img, sharpness));
+            }
+            in_burst += 1;
+            if in_burst == burst {
+                in_burst = 0;
+                if let Some((frame, img, _)) = sharpest.take() {
+                    scanner.scan(frame, img);
+                }
+            }
+            Ok(())
+        })?;
+        // The incomplete burst at the end of the window
+        if let Some((frame, img, _)) = sharpest.take() {
+            scanner.scan(frame, img);
+        }
+    }
+
+    if scanner.duplicates > 0 {
+        println!(
+            "Skipped OCR of {} frames identical to one read before",
+            scanner.duplicates
+        );
+    }
+    Ok(scanner.candidates)
+}
+
+/// The PNG and JPG screenshots of the video in `dir`, in the order of their names. Those of
+/// several videos are in subdirectories named after the video's file stem, only a
+/// `single_video` may use the ones in `dir` itself.
+fn frame_images(dir: &Path, video: &Path, single_video: bool) -> Result<Vec<PathBuf>> {
+    let own_dir = video.file_stem().map(|stem| dir.join(stem));
+    let dir = match own_dir {
+        Some(own_dir) if own_dir.is_dir() => own_dir,
+        _ if single_video => dir.to_path_buf(),
+        own_dir => bail!(
+            "No screenshots of {video:?} in {:?}, the screenshots of each of several videos need a subdirectory named after it",
+            own_dir.unwrap_or_else(|| dir.to_path_buf())
+        ),
+    };
+    let mut images: Vec<PathBuf> = fs::read_dir(&dir)
+        .with_context(|| format!("Failed to read the screenshots in {dir:?}"))?
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .is_some_and(|ext| ["png", "jpg", "jpeg"].contains(&ext.to_lowercase().as_str()))
+        })
+        .collect();
+    if images.is_empty() {
+        bail!("No PNG or JPG screenshots in {dir:?}");
+    }
+    images.sort();
+    Ok(images)
+}
+
+/// OCRs frames one at a time as they're extracted, collecting the production codes on them.
+struct FrameScanner<'a> {
+    api: PooledEngine<'a>,
+    re: Regex,
+    options: &'a FrameOcrOptions,
+    last_read: Option<(GrayImage, RecognizedText)>, // thumbnail and text of the last OCRed frame
+    duplicates: usize,
+    candidates: Vec<OcrCandidate>,
+}
+
+impl FrameScanner<'_> {
+    fn scan(&mut self, frame: String, mut rgb_img: RgbImage) {
+        // Less text to read is faster and leaves fewer false candidates
+        if let Some(region) = self.options.crop {
+            rgb_img = preprocess::crop(&rgb_img, region);
+        }
+
+        // A frame like the last OCRed one, e.g. of static credits, has its text, so it still
+        // counts for the candidates without being OCRed again. Comparing with the last OCRed
+        // frame rather than the one before lets slow fades add up.
+        let thumbnail = preprocess::thumbnail(&rgb_img);
+        let recognized = match &self.last_read {
+            Some((last, text)) if preprocess::same_frame(last, &thumbnail) => {
+                self.duplicates += 1;
+                text.clone()
+            }
+            _ => {
+                // Straighten angled credits, tesseract only reads horizontal text reliably
+                if let Some(degrees) = self.options.rotate {
+                    rgb_img = preprocess::rotate(&rgb_img, degrees);
+                } else if self.options.deskew {
+                    rgb_img = preprocess::deskew(&rgb_img);
+                }
+                let (width, height) = rgb_img.dimensions();
+                let image_data = rgb_img.into_raw();
+
+                // Perform OCR
+                match self.api.recognize(&image_data, width, height) {
+                    Ok(recognized) => {
+                        self.last_read = Some((thumbnail, recognized.clone()));
+                        recognized
                     }
                     Err(e) => {
-                        eprintln!("Warning: Failed to get OCR text from frame {frame_path:?}: {e}");
-                        continue;
+                        eprintln!("Warning: Failed to OCR frame {frame}: {e}");
+                        self.last_read = None;
+                        return;
                     }
                 }
             }
-            Err(e) => {
-                eprintln!("Warning: Failed to set image for OCR on frame {frame_path:?}: {e}");
-                continue;
-            }
+        };
+
+        // Score every candidate by the words it was read from
+        for (code, confidence) in find_candidates(
+            &recognized.text,
+            &recognized.word_confidences,
+            recognized.mean_confidence,
+            &self.re,
+        ) {
+            self.candidates.push(OcrCandidate {
+                code,
+                frame: frame.clone(),
+                confidence,
+            });
+        }
+    }
+}
+
+/// Finds the production codes in the OCR text of a frame, each with the lowest confidence of
+/// the words it spans. Whitespace is stripped before matching since tesseract often splits
+/// codes, and `word_confidences` (one per whitespace-separated word) falls back to
+/// `mean_confidence` if it doesn't line up with the text.
+fn find_candidates(
+    text: &str,
+    word_confidences: &[i32],
+    mean_confidence: i32,
+    re: &Regex,
+) -> Vec<(String, i32)> {
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let aligned = words.len() == word_confidences.len();
+
+    let mut cleaned = String::new();
+    let mut byte_confidences = Vec::new(); // confidence of every byte of `cleaned`
+    for (index, word) in words.iter().enumerate() {
+        let confidence = if aligned {
+            word_confidences[index]
+        } else {
+            mean_confidence
+        };
+        for c in word.chars() {
+            let c = match c {
+                'O' => '0',
+                'I' => '1',
+                'S' => '5',
+                '?' => 'X',
+                _ => c,
+            };
+            cleaned.push(c);
+            byte_confidences.extend(std::iter::repeat_n(confidence, c.len_utf8()));
         }
     }
-    eprintln!("Found candidates: {candidates:?}");
 
-    Ok(candidates)
+    re.find_iter(&cleaned)
+        .map(|candidate| {
+            let confidence = byte_confidences[candidate.range()]
+                .iter()
+                .copied()
+                .min()
+                .unwrap_or(mean_confidence);
+            (candidate.as_str().to_owned(), confidence)
+        })
+        .collect()
 }
 
-pub fn create_ocr_engine() -> Result<TesseractAPI> {
-    let api = TesseractAPI::new();
-    api.init_5(ENG, ENG.len() as i32, "eng", 3, &[])?;
+/// Variance of the Laplacian, a cheap focus measure: blurred frames have weak edges
+/// and therefore a low variance.
+fn laplacian_variance(img: &image::GrayImage) -> f64 {
+    let (width, height) = img.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
 
-    Ok(api)
+    let mut values = Vec::with_capacity(((width - 2) * (height - 2)) as usize);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let px = |x: u32, y: u32| img.get_pixel(x, y)[0] as f64;
+            values.push(px(x - 1, y) + px(x + 1, y) + px(x, y - 1) + px(x, y + 1) - 4.0 * px(x, y));
+        }
+    }
+
+    let mean = values.iter().sum::<f64>() / values.len() as f64;
+    values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_frame_images() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["b.png", "a.JPG", "notes.txt"] {
+            fs::write(dir.path().join(name), "").unwrap();
+        }
+        let episode_dir = dir.path().join("Episode 2");
+        fs::create_dir(&episode_dir).unwrap();
+        fs::write(episode_dir.join("credits.jpeg"), "").unwrap();
+
+        let images = frame_images(dir.path(), Path::new("/tv/Episode 1.mkv"), true).unwrap();
+        assert_eq!(
+            images,
+            vec![dir.path().join("a.JPG"), dir.path().join("b.png")]
+        );
+        let images = frame_images(dir.path(), Path::new("/tv/Episode 2.mkv"), false).unwrap();
+        assert_eq!(images, vec![episode_dir.join("credits.jpeg")]);
+        assert!(frame_images(&episode_dir.join("empty"), Path::new("a.mkv"), true).is_err());
+        // One of several videos doesn't get the screenshots meant for a single one
+        assert!(frame_images(dir.path(), Path::new("/tv/Episode 1.mkv"), false).is_err());
+    }
+
     #[test]
     fn test_production_code_regex() {
-        // This test verifies the regex logic used in the main function.
-        // Ideally the regex should be a shared constant or returned by a function,
-        // but since we can't refactor, we duplicate it here to verify the logic described in comments.
-        let re = Regex::new(r"(?i)(?:\d[A-Z]{1,3}[\d]{2,3})|(?:1?\d[0-3]\d)").unwrap();
+        let re = Regex::new(DEFAULT_CODE_PATTERN).unwrap();
 
         let valid_cases = vec![
             "3X22", // Season 1-5 format
@@ -181,4 +523,34 @@ mod tests {
 
         assert_eq!(cleaned, expected);
     }
+
+    #[test]
+    fn test_find_candidates() {
+        let re = Regex::new(DEFAULT_CODE_PATTERN).unwrap();
+        let text = "Production\n#3X 22\nCopyright\n";
+
+        // The code spans two words, the less certain one counts
+        assert_eq!(
+            find_candidates(text, &[91, 88, 47, 95], 80, &re),
+            vec![("3X22".to_string(), 47)]
+        );
+        // Confidences that don't line up with the words
+        assert_eq!(
+            find_candidates(text, &[91], 80, &re),
+            vec![("3X22".to_string(), 80)]
+        );
+        assert!(find_candidates("", &[], 0, &re).is_empty());
+    }
+
+    #[test]
+    fn test_laplacian_variance_prefers_sharp_frames() {
+        let sharp = image::GrayImage::from_fn(16, 16, |x, y| {
+            image::Luma([if (x + y) % 2 == 0 { 255 } else { 0 }])
+        });
+        let blurred = image::GrayImage::from_pixel(16, 16, image::Luma([128]));
+
+        assert!(laplacian_variance(&sharp) > laplacian_variance(&blurred));
+        assert_eq!(laplacian_variance(&blurred), 0.0);
+        assert_eq!(laplacian_variance(&image::GrayImage::new(2, 2)), 0.0);
+    }
 }
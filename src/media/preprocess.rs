@@ -0,0 +1,248 @@
+use anyhow::{bail, Result};
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Rgb, RgbImage};
+
+// Credits are rarely more than a few degrees off, anything larger is a stylistic choice
+const MAX_SKEW_DEGREES: f32 = 5.0;
+const SKEW_STEP_DEGREES: f32 = 0.5;
+// Skew detection runs on a downscaled copy, the angle does not depend on resolution
+const DETECTION_WIDTH: u32 = 480;
+// Frames are compared by grayscale thumbnails of this size, large enough that a small
+// production code still changes some of their pixels clearly
+const THUMBNAIL_WIDTH: u32 = 160;
+const THUMBNAIL_HEIGHT: u32 = 90;
+// Brightness change of a thumbnail pixel that is more than compression noise
+const PIXEL_DIFFERENCE: u8 = 16;
+
+/// Part of a frame, in fractions of its width and height so it fits every resolution.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CropRegion {
+    pub left: f32,
+    pub top: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl CropRegion {
+    /// Reads `[left, top, width, height]`, e.g. `[0, 0.75, 1, 0.25]` for the bottom quarter.
+    pub fn from_fractions(values: &[f32]) -> Result<Self> {
+        let &[left, top, width, height] = values else {
+            bail!("A crop region needs 4 values: left, top, width and height");
+        };
+        // With some slack for sums like 0.7 + 0.3, which aren't exactly 1 as floats
+        let within =
+            |start: f32, size: f32| start >= 0.0 && size > 0.0 && start + size <= 1.0 + 1e-4;
+        if !within(left, width) || !within(top, height) {
+            bail!("The crop region must lie within the frame, in fractions between 0 and 1");
+        }
+        Ok(Self {
+            left,
+            top,
+            width,
+            height,
+        })
+    }
+}
+
+/// Cuts the region out of the image, at least one pixel in each direction.
+pub fn crop(img: &RgbImage, region: CropRegion) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let scale = |fraction: f32, size: u32| (fraction * size as f32).round() as u32;
+    let x = scale(region.left, width).min(width.saturating_sub(1));
+    let y = scale(region.top, height).min(height.saturating_sub(1));
+    let crop_width = scale(region.width, width).clamp(1, width - x);
+    let crop_height = scale(region.height, height).clamp(1, height - y);
+    imageops::crop_imm(img, x, y, crop_width, crop_height).to_image()
+}
+
+/// Rotates the image clockwise by `degrees` around its center, keeping its size.
+/// Pixels that come from outside the source are filled with black.
+pub fn rotate(img: &RgbImage, degrees: f32) -> RgbImage {
+    let (width, height) = img.dimensions();
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let cx = width as f32 / 2.0;
+    let cy = height as f32 / 2.0;
+
+    RgbImage::from_fn(width, height, |x, y| {
+        // Inverse mapping: rotate the destination pixel back into the source
+        let dx = x as f32 - cx;
+        let dy = y as f32 - cy;
+        let sx = (dx * cos + dy * sin + cx).round();
+        let sy = (-dx * sin + dy * cos + cy).round();
+
+        if sx >= 0.0 && sy >= 0.0 && (sx as u32) < width && (sy as u32) < height {
+            *img.get_pixel(sx as u32, sy as u32)
+        } else {
+            Rgb([0, 0, 0])
+        }
+    })
+}
+
+/// Estimates how many degrees clockwise the text in the image is rotated, using the
+/// projection profile method: the angle at which the row sums of "ink" pixels are the
+/// most uneven is the one where text lines are horizontal.
+pub fn detect_skew(img: &GrayImage) -> f32 {
+    let img = if img.width() > DETECTION_WIDTH {
+        let height = img.height() * DETECTION_WIDTH / img.width();
+        imageops::resize(img, DETECTION_WIDTH, height.max(1), FilterType::Triangle)
+    } else {
+        img.clone()
+    };
+
+    // Text is whatever stands out from the average brightness, which works for both
+    // light-on-dark credits and dark-on-light title cards
+    let pixel_count = (img.width() * img.height()).max(1) as f32;
+    let mean = img.pixels().map(|p| p[0] as f32).sum::<f32>() / pixel_count;
+    let ink: Vec<(f32, f32)> = img
+        .enumerate_pixels()
+        .filter(|(_, _, p)| (p[0] as f32 - mean).abs() > 40.0)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect();
+
+    if ink.is_empty() {
+        return 0.0;
+    }
+
+    let diagonal = ((img.width().pow(2) + img.height().pow(2)) as f32).sqrt();
+    let rows = (diagonal * 2.0) as usize + 1;
+    let steps = (MAX_SKEW_DEGREES / SKEW_STEP_DEGREES) as i32;
+
+    let mut best_angle = 0.0f32;
+    let mut best_score = f32::MIN;
+    for step in -steps..=steps {
+        let angle = step as f32 * SKEW_STEP_DEGREES;
+        let (sin, cos) = angle.to_radians().sin_cos();
+
+        // Undo the candidate rotation and histogram the resulting row of every ink pixel
+        let mut profile = vec![0f32; rows];
+        for &(x, y) in &ink {
+            let row = (y * cos - x * sin + diagonal).round() as usize;
+            profile[row.min(rows - 1)] += 1.0;
+        }
+        let score = profile.iter().map(|count| count * count).sum::<f32>();
+
+        // Prefer the smallest correction when scores tie
+        if score > best_score || (score == best_score && angle.abs() < best_angle.abs()) {
+            best_score = score;
+            best_angle = angle;
+        }
+    }
+
+    best_angle
+}
+
+/// Straightens the image if it appears to be skewed.
+pub fn deskew(img: &RgbImage) -> RgbImage {
+    let skew = detect_skew(&imageops::grayscale(img));
+    if skew == 0.0 {
+        img.clone()
+    } else {
+        rotate(img, -skew)
+    }
+}
+
+/// A small grayscale copy of the image to compare frames by, see [`same_frame`].
+pub fn thumbnail(img: &RgbImage) -> GrayImage {
+    imageops::resize(
+        &imageops::grayscale(img),
+        THUMBNAIL_WIDTH,
+        THUMBNAIL_HEIGHT,
+        FilterType::Triangle,
+    )
+}
+
+/// Whether two [`thumbnail`]s show the same: none of their pixels differ by more than
+/// noise. Text appearing changes a few pixels a lot, which a perceptual hash would miss.
+pub fn same_frame(a: &GrayImage, b: &GrayImage) -> bool {
+    a.pixels()
+        .zip(b.pixels())
+        .all(|(a, b)| a[0].abs_diff(b[0]) <= PIXEL_DIFFERENCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_lines() -> RgbImage {
+        RgbImage::from_fn(200, 120, |x, y| {
+            let in_line = (30..34).contains(&y) || (60..64).contains(&y) || (90..94).contains(&y);
+            if in_line && (20..180).contains(&x) {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn test_detect_skew() {
+        let straight = text_lines();
+        assert_eq!(detect_skew(&imageops::grayscale(&straight)), 0.0);
+
+        let skewed = rotate(&straight, 3.0);
+        let detected = detect_skew(&imageops::grayscale(&skewed));
+        assert!((detected - 3.0).abs() <= SKEW_STEP_DEGREES, "{detected}");
+
+        let deskewed = deskew(&skewed);
+        assert!(detect_skew(&imageops::grayscale(&deskewed)).abs() <= SKEW_STEP_DEGREES);
+    }
+
+    #[test]
+    fn test_detect_skew_blank_image() {
+        assert_eq!(detect_skew(&GrayImage::new(64, 64)), 0.0);
+    }
+
+    #[test]
+    fn test_crop() {
+        let img = text_lines();
+        let bottom = CropRegion::from_fractions(&[0.0, 0.75, 1.0, 0.25]).unwrap();
+        let cropped = crop(&img, bottom);
+        assert_eq!(cropped.dimensions(), (200, 30));
+        // The last text line starts 90 pixels down, 0 in the crop
+        assert_eq!(*cropped.get_pixel(100, 0), Rgb([255, 255, 255]));
+
+        assert!(CropRegion::from_fractions(&[0.0, 0.75, 1.0]).is_err());
+        assert!(CropRegion::from_fractions(&[0.5, 0.0, 0.6, 1.0]).is_err());
+        assert!(CropRegion::from_fractions(&[0.0, 0.0, 0.0, 1.0]).is_err());
+    }
+
+    #[test]
+    fn test_same_frame() {
+        let img = text_lines();
+        let noisy = RgbImage::from_fn(200, 120, |x, y| {
+            let Rgb([value, ..]) = *img.get_pixel(x, y);
+            let noise = ((x + y) % 3) as u8 * 3;
+            let value = if value > 0 { value - noise } else { noise };
+            Rgb([value, value, value])
+        });
+        assert!(same_frame(&thumbnail(&img), &thumbnail(&noisy)));
+        assert!(!same_frame(
+            &thumbnail(&img),
+            &thumbnail(&rotate(&img, 90.0))
+        ));
+    }
+
+    #[test]
+    fn test_same_frame_small_text_on_black() {
+        // A production code in a corner of an otherwise black 1080p frame
+        let black = RgbImage::new(1920, 1080);
+        let mut code = black.clone();
+        for x in 1700..1800 {
+            for y in 1000..1016 {
+                if x % 4 != 0 {
+                    code.put_pixel(x, y, Rgb([230, 230, 230]));
+                }
+            }
+        }
+        assert!(same_frame(&thumbnail(&black), &thumbnail(&black.clone())));
+        assert!(!same_frame(&thumbnail(&black), &thumbnail(&code)));
+    }
+
+    #[test]
+    fn test_rotate_keeps_dimensions() {
+        let img = text_lines();
+        let rotated = rotate(&img, 90.0);
+        assert_eq!(rotated.dimensions(), img.dimensions());
+        assert_eq!(rotate(&img, 0.0), img);
+    }
+}
@@ -1,13 +1,19 @@
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use pgs_rs::parse::parse_pgs;
-use pgs_rs::render::{render_display_set, DisplaySetIterator};
+use pgs_rs::render::{render_display_set, DisplaySet, DisplaySetIterator};
+use regex::Regex;
 use serde::Deserialize;
-use std::fs::{self, File};
-use std::io::{BufRead, BufReader, Write};
+use std::fs;
+use std::io::{self, Write};
+use std::num::NonZeroUsize;
+use std::ops::ControlFlow;
 use std::path::Path;
 use std::process::{Command, Stdio};
+use std::sync::LazyLock;
+use std::thread;
 
-use crate::media::ffmpeg;
+use crate::media::{ffmpeg, ocr, vobsub};
+use crate::workflows::parallel;
 
 #[derive(Debug, Deserialize)]
 struct FfprobeOutput {
@@ -19,68 +25,527 @@ struct Stream {
     index: u32,
     codec_name: String,
     tags: Option<Tags>,
+    #[serde(default)]
+    disposition: Disposition,
 }
 
 #[derive(Debug, Deserialize)]
 struct Tags {
     language: Option<String>,
+    title: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Default)]
+struct Disposition {
+    #[serde(default)]
+    forced: u8,
+    #[serde(default)]
+    hearing_impaired: u8,
 }
 
 #[derive(Debug)]
 pub enum SubtitleCodec {
-    Srt, // subrip
-    Pgs, // hdmv_pgs_subtitle
+    Srt,      // subrip
+    Ass,      // ass or ssa, styled text
+    Pgs,      // hdmv_pgs_subtitle
+    VobSub,   // dvd_subtitle
+    Dvb,      // dvb_subtitle, bitmaps of broadcasts
+    Teletext, // dvb_teletext
 }
 
 pub struct SubtitleTrack {
     pub index: u32,
     pub codec: SubtitleCodec,
+    pub title: Option<String>,
+    pub forced: bool, // only subtitles foreign-language scenes
+    pub sdh: bool,    // for the deaf and hard of hearing, with sound descriptions
 }
 
-pub fn find_best_subtitle_track(path: &Path) -> Result<SubtitleTrack> {
-    let json_output = ffmpeg::get_streams_json(path)?;
-    let info: FfprobeOutput = serde_json::from_slice(&json_output)?;
+/// Which of several subtitle tracks of the language to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrackPreference {
+    pub avoid_forced: bool,
+    pub sdh: Option<bool>, // prefer (true) or avoid (false) SDH tracks, or don't care
+}
 
-    let mut best_track: Option<SubtitleTrack> = None;
+impl Default for TrackPreference {
+    fn default() -> Self {
+        Self {
+            avoid_forced: true,
+            sdh: None,
+        }
+    }
+}
 
-    for stream in info.streams {
-        // Check for English language
-        let is_eng = stream
-            .tags
-            .as_ref()
-            .and_then(|t| t.language.as_ref())
-            .map(|l| l == "eng")
-            .unwrap_or(false);
+impl TrackPreference {
+    /// Lower is better. Text needs no OCR, and Blu-ray subtitles are sharper than DVD ones.
+    fn rank(&self, track: &SubtitleTrack) -> (bool, bool, u8) {
+        let codec = match track.codec {
+            SubtitleCodec::Srt => 0,
+            SubtitleCodec::Ass => 1,
+            SubtitleCodec::Teletext => 2,
+            SubtitleCodec::Pgs => 3,
+            SubtitleCodec::Dvb => 4,
+            SubtitleCodec::VobSub => 5,
+        };
+        (
+            self.avoid_forced && track.forced,
+            self.sdh.is_some_and(|sdh| sdh != track.sdh),
+            codec,
+        )
+    }
+}
 
-        if !is_eng {
-            continue;
+// Titles of the chapters whose subtitles are never read: recaps are dialogue of earlier
+// episodes, previews that of the next one and the openings and endings are song lyrics
+const SKIPPED_CHAPTER_PATTERN: &str = r"(?i)^(?:intro|opening(?: credits| song)?|op|recap|previously\b.*|outro|ending(?: credits| song)?|ed|(?:end )?credits|preview|next (?:episode|time)\b.*)\s*\d*$";
+
+/// The parts of a video whose subtitles are read, all of it if there are none, without
+/// its intro, recap and outro chapters.
+#[derive(Debug, Clone, Default)]
+pub struct SubtitleWindow {
+    ranges: Vec<(f64, f64)>,  // start and end in seconds
+    skipped: Vec<(f64, f64)>, // chapters left out
+}
+
+impl SubtitleWindow {
+    /// The first or last seconds of the video given by `windows`. Only the last ones need
+    /// the duration of the file.
+    pub fn new(path: &Path, windows: &[ffmpeg::FrameWindow]) -> Result<Self> {
+        let skipped = match ffmpeg::get_chapters(path) {
+            Ok(chapters) => skipped_chapters(&chapters),
+            Err(e) => {
+                eprintln!("Warning: Failed to read the chapters of {path:?}: {e:#}");
+                Vec::new()
+            }
+        };
+        let mut ranges = Vec::new();
+        for window in windows {
+            match *window {
+                ffmpeg::FrameWindow::Start(seconds) => ranges.push((0.0, seconds as f64)),
+                ffmpeg::FrameWindow::End(seconds) => {
+                    let duration = ffmpeg::get_duration(path)?;
+                    ranges.push((duration - seconds as f64, f64::INFINITY));
+                }
+            }
         }
+        Ok(Self { ranges, skipped })
+    }
 
-        let codec = match stream.codec_name.as_str() {
-            "subrip" => SubtitleCodec::Srt,
-            "hdmv_pgs_subtitle" => SubtitleCodec::Pgs,
-            _ => continue,
+    fn contains(&self, seconds: f64) -> bool {
+        let within = |ranges: &[(f64, f64)]| {
+            ranges
+                .iter()
+                .any(|&(start, end)| (start..end).contains(&seconds))
         };
+        (self.ranges.is_empty() || within(&self.ranges)) && !within(&self.skipped)
+    }
+}
 
-        // Prioritize SRT over PGS
-        match (codec, &best_track) {
-            (SubtitleCodec::Srt, _) => {
-                return Ok(SubtitleTrack {
-                    index: stream.index,
-                    codec: SubtitleCodec::Srt,
-                });
+/// The start and end of the chapters whose titles say they're a recap, intro or outro.
+fn skipped_chapters(chapters: &[ffmpeg::Chapter]) -> Vec<(f64, f64)> {
+    let re = Regex::new(SKIPPED_CHAPTER_PATTERN).unwrap();
+    chapters
+        .iter()
+        .filter_map(|chapter| {
+            let title = chapter.title.as_deref()?.trim();
+            if !re.is_match(title) {
+                return None;
             }
-            (SubtitleCodec::Pgs, None) => {
-                best_track = Some(SubtitleTrack {
-                    index: stream.index,
-                    codec: SubtitleCodec::Pgs,
-                });
+            println!(
+                "Skipping the subtitles of the {title:?} chapter ({}-{})",
+                format_seconds(chapter.start),
+                format_seconds(chapter.end)
+            );
+            Some((chapter.start, chapter.end))
+        })
+        .collect()
+}
+
+fn format_seconds(seconds: f64) -> String {
+    let seconds = seconds as u64;
+    format!("{}:{:02}", seconds / 60, seconds % 60)
+}
+
+// Cues read from a poorly tagged track to guess its language
+const SAMPLE_CUES: usize = 20;
+
+// Common short words per language, keyed by every ISO 639-2 code of the language
+const STOPWORDS: &[(&[&str], &[&str])] = &[
+    (
+        &["eng"],
+        &[
+            "the", "and", "you", "is", "to", "it", "of", "what", "that", "this", "i'm", "don't",
+        ],
+    ),
+    (
+        &["ger", "deu"],
+        &[
+            "der", "die", "das", "und", "ich", "nicht", "ist", "sie", "du", "was", "ein", "wir",
+        ],
+    ),
+    (
+        &["fre", "fra"],
+        &[
+            "le", "la", "les", "et", "je", "vous", "est", "pas", "que", "une", "c'est", "il",
+        ],
+    ),
+    (
+        &["spa"],
+        &[
+            "el", "la", "los", "que", "y", "es", "no", "por", "qué", "está", "una", "lo",
+        ],
+    ),
+    (
+        &["ita"],
+        &[
+            "il", "che", "non", "è", "di", "un", "una", "per", "sono", "cosa", "lo", "gli",
+        ],
+    ),
+    (
+        &["dut", "nld"],
+        &[
+            "de", "het", "een", "en", "ik", "niet", "je", "is", "dat", "wat", "we", "zijn",
+        ],
+    ),
+];
+
+/// Picks the subtitle track to read for the language. Tracks tagged with the language
+/// come first, then untagged (`und`) tracks and finally any other track, preferring
+/// those whose first cues look like the language since remuxes are often mis-tagged.
+/// If `interactive`, the user picks among tagged tracks the preference can't tell apart.
+pub fn find_best_subtitle_track(
+    path: &Path,
+    language: &str,
+    preference: &TrackPreference,
+    interactive: bool,
+    ocr_pool: &ocr::OcrEnginePool,
+) -> Result<SubtitleTrack> {
+    let json_output = ffmpeg::get_streams_json(path, "s")?;
+    let tracks = parse_subtitle_probe(&json_output)?;
+
+    let (tagged, fallback): (Vec<_>, Vec<_>) = tracks
+        .into_iter()
+        .partition(|(_, tag)| tag.as_deref().map(language_key) == Some(language_key(language)));
+    let mut tagged: Vec<SubtitleTrack> = tagged.into_iter().map(|(track, _)| track).collect();
+    tagged.sort_by_key(|track| preference.rank(track));
+    if let Some(best) = tagged.first() {
+        let tied = tagged
+            .iter()
+            .filter(|track| preference.rank(track) == preference.rank(best))
+            .count();
+        let choice = if interactive && tied > 1 {
+            pick_track(&tagged)?
+        } else {
+            0
+        };
+        return Ok(tagged.swap_remove(choice));
+    }
+
+    // Untagged tracks are the likelier match, so they are tried first
+    let (untagged, other): (Vec<_>, Vec<_>) =
+        fallback.into_iter().partition(|(_, tag)| tag.is_none());
+    let candidates: Vec<SubtitleTrack> = untagged
+        .into_iter()
+        .chain(other)
+        .map(|(track, _)| track)
+        .collect();
+
+    let mut first = None;
+    for track in candidates {
+        match sample_text(path, &track, ocr_pool) {
+            Ok(sample) if guess_language(&sample) == Some(language_key(language)) => {
+                println!(
+                    "No '{language}' subtitle track, track {} looks like '{language}'",
+                    track.index
+                );
+                return Ok(track);
             }
-            _ => {}
+            Ok(_) => {}
+            Err(e) => eprintln!(
+                "Warning: Failed to sample subtitle track {}: {e}",
+                track.index
+            ),
+        }
+        first.get_or_insert(track);
+    }
+
+    let track = first.with_context(|| {
+        format!("No suitable '{language}' subtitle track found (SRT, ASS, teletext, PGS, DVB or VobSub)")
+    })?;
+    eprintln!(
+        "Warning: No subtitle track looks like '{language}', falling back to track {}",
+        track.index
+    );
+    Ok(track)
+}
+
+/// The subtitle tracks of a file the matchers can read, each with its language tag unless
+/// it's missing or `und`.
+fn parse_subtitle_probe(json_output: &[u8]) -> Result<Vec<(SubtitleTrack, Option<String>)>> {
+    let info: FfprobeOutput = serde_json::from_slice(json_output)?;
+    Ok(info
+        .streams
+        .into_iter()
+        .filter_map(|stream| {
+            let codec = subtitle_codec(&stream.codec_name)?;
+            let tags = stream.tags.unwrap_or(Tags {
+                language: None,
+                title: None,
+            });
+            let tag = tags.language.filter(|l| l != "und");
+            let sdh = stream.disposition.hearing_impaired != 0
+                || tags
+                    .title
+                    .as_deref()
+                    .is_some_and(|title| title.to_uppercase().contains("SDH"));
+            Some((
+                SubtitleTrack {
+                    index: stream.index,
+                    codec,
+                    title: tags.title,
+                    forced: stream.disposition.forced != 0,
+                    sdh,
+                },
+                tag,
+            ))
+        })
+        .collect())
+}
+
+/// The codec of an ffprobe `codec_name`, `None` for those that can't be read.
+fn subtitle_codec(codec_name: &str) -> Option<SubtitleCodec> {
+    Some(match codec_name {
+        "subrip" => SubtitleCodec::Srt,
+        "ass" | "ssa" => SubtitleCodec::Ass,
+        "hdmv_pgs_subtitle" => SubtitleCodec::Pgs,
+        "dvd_subtitle" => SubtitleCodec::VobSub,
+        "dvb_subtitle" => SubtitleCodec::Dvb,
+        "dvb_teletext" => SubtitleCodec::Teletext,
+        _ => return None,
+    })
+}
+
+/// Asks which of the tracks to read, the first one by default.
+fn pick_track(tracks: &[SubtitleTrack]) -> Result<usize> {
+    println!("Several subtitle tracks match. Please select one:");
+    for (i, track) in tracks.iter().enumerate() {
+        let mut details = vec![format!("{:?}", track.codec)];
+        details.extend(track.title.clone());
+        if track.forced {
+            details.push("forced".to_string());
+        }
+        if track.sdh {
+            details.push("SDH".to_string());
+        }
+        println!(
+            "  {}: track {} ({})",
+            i + 1,
+            track.index,
+            details.join(", ")
+        );
+    }
+
+    loop {
+        print!("Enter number (1-{}) [1]: ", tracks.len());
+        io::stdout().flush()?;
+
+        let mut input = String::new();
+        io::stdin().read_line(&mut input)?;
+        let input = input.trim();
+        if input.is_empty() {
+            return Ok(0);
+        }
+        match input.parse::<usize>() {
+            Ok(choice) if (1..=tracks.len()).contains(&choice) => return Ok(choice - 1),
+            _ => println!(
+                "Invalid selection. Please enter a number between 1 and {}.",
+                tracks.len()
+            ),
+        }
+    }
+}
+
+/// Reads the text of the first cues of a track.
+fn sample_text(
+    path: &Path,
+    track: &SubtitleTrack,
+    ocr_pool: &ocr::OcrEnginePool,
+) -> Result<String> {
+    let temp_dir = tempfile::TempDir::new()?;
+    let subtitle_path = extract_subtitles(path, track.index, &track.codec, temp_dir.path())?;
+
+    let mut cues = Vec::new();
+    match track.codec {
+        SubtitleCodec::Srt | SubtitleCodec::Teletext => {
+            let content = fs::read_to_string(&subtitle_path)?;
+            cues.extend(
+                srt_text_lines(&content)
+                    .take(SAMPLE_CUES)
+                    .map(str::to_string),
+            );
+        }
+        SubtitleCodec::Ass => {
+            let content = fs::read_to_string(&subtitle_path)?;
+            let dialogue = ass_dialogue(&content, &SubtitleWindow::default());
+            cues.extend(dialogue.into_iter().take(SAMPLE_CUES));
+        }
+        SubtitleCodec::Pgs | SubtitleCodec::VobSub | SubtitleCodec::Dvb => {
+            ocr_bitmaps(
+                &subtitle_path,
+                &track.codec,
+                ocr_pool,
+                &SubtitleWindow::default(),
+                |text| {
+                    cues.push(text.to_string());
+                    cues.len() < SAMPLE_CUES
+                },
+            )?;
         }
     }
+    Ok(cues.join("\n"))
+}
+
+/// The dialogue lines of an SRT file, without cue numbers and timestamps.
+fn srt_text_lines(content: &str) -> impl Iterator<Item = &str> {
+    content.lines().map(str::trim).filter(|line| {
+        !line.is_empty()
+            && !line.contains("-->")
+            && !line.chars().all(|c| c.is_ascii_digit() || c == '\u{feff}')
+    })
+}
 
-    best_track.context("No suitable English subtitle track found (SRT or PGS)")
+/// The cues of an SRT file whose start is in the window, each with its number and timing.
+fn srt_cues_in<'a>(content: &'a str, window: &SubtitleWindow) -> Vec<&'a str> {
+    content
+        .split("\n\n")
+        .map(str::trim)
+        .filter(|cue| !cue.is_empty())
+        .filter(|cue| {
+            // Cues without a readable timing are kept
+            let start = cue
+                .lines()
+                .find_map(|line| line.split_once("-->"))
+                .and_then(|(start, _)| parse_timestamp(start.trim()));
+            start.is_none_or(|start| window.contains(start))
+        })
+        .collect()
+}
+
+/// Seconds of an SRT or ASS timestamp, e.g. `00:01:02,500` or `0:01:02.50`.
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    let (time, fraction) = timestamp.split_once([',', '.'])?;
+    let mut seconds = 0.0;
+    for part in time.split(':') {
+        seconds = seconds * 60.0 + part.parse::<f64>().ok()?;
+    }
+    Some(seconds + format!("0.{fraction}").parse::<f64>().ok()?)
+}
+
+/// The plain text of the `Dialogue` events of an ASS/SSA file starting in the window, in
+/// order of their start. Override tags like `{\i1}` are removed and vector drawings
+/// dropped.
+fn ass_dialogue(content: &str, window: &SubtitleWindow) -> Vec<String> {
+    // The columns of the events, `Text` is the last one and may contain commas
+    let mut columns: Vec<String> = Vec::new();
+    let mut events = Vec::new();
+    for line in content.lines().map(str::trim) {
+        if let Some(format) = line.strip_prefix("Format:") {
+            columns = format
+                .split(',')
+                .map(|column| column.trim().to_lowercase())
+                .collect();
+            continue;
+        }
+        let Some(event) = line.strip_prefix("Dialogue:") else {
+            continue;
+        };
+        let fields: Vec<&str> = event.trim().splitn(columns.len().max(1), ',').collect();
+        let field = |name: &str| {
+            let index = columns.iter().position(|column| column == name)?;
+            fields.get(index).copied()
+        };
+        let (Some(start), Some(text)) = (field("start"), field("text")) else {
+            continue;
+        };
+        let Some(start) = parse_timestamp(start.trim()) else {
+            continue;
+        };
+        if !window.contains(start) {
+            continue;
+        }
+        if let Some(text) = strip_ass_tags(text) {
+            events.push((start, text));
+        }
+    }
+    events.sort_by(|a, b| a.0.total_cmp(&b.0));
+    events.into_iter().map(|(_, text)| text).collect()
+}
+
+/// The `\p1` and up tags that switch to drawing mode, whose text is vector commands.
+static DRAWING_TAG: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\\p[1-9]").unwrap());
+
+/// Removes the override tags of an ASS text, `None` for drawings and empty text.
+fn strip_ass_tags(text: &str) -> Option<String> {
+    let mut plain = String::new();
+    let mut rest = text;
+    while let Some(open) = rest.find('{') {
+        plain.push_str(&rest[..open]);
+        let Some(close) = rest[open..].find('}') else {
+            rest = "";
+            break;
+        };
+        let tags = &rest[open + 1..open + close];
+        if DRAWING_TAG.is_match(tags) {
+            return None;
+        }
+        rest = &rest[open + close + 1..];
+    }
+    plain.push_str(rest);
+
+    let plain = plain
+        .replace("\\N", "\n")
+        .replace("\\n", "\n")
+        .replace("\\h", " ");
+    let plain = plain
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n");
+    (!plain.is_empty()).then_some(plain)
+}
+
+/// Guesses the language of subtitle text from its most frequent common words. Returns
+/// the first ISO 639-2 code of the language, or `None` when nothing stands out.
+fn guess_language(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text
+        .split(|c: char| !(c.is_alphabetic() || c == '\''))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    let (codes, hits) = STOPWORDS
+        .iter()
+        .map(|(codes, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|word| stopwords.contains(&word.as_str()))
+                .count();
+            (codes, hits)
+        })
+        .max_by_key(|(_, hits)| *hits)?;
+
+    // A handful of hits is needed before trusting the guess
+    (hits >= 3).then_some(codes[0])
+}
+
+/// Maps both ISO 639-2 codes of a language (e.g. `ger` and `deu`) to the same key.
+fn language_key(language: &str) -> &str {
+    STOPWORDS
+        .iter()
+        .find(|(codes, _)| codes.contains(&language))
+        .map_or(language, |(codes, _)| codes[0])
 }
 
 pub fn extract_subtitles(
@@ -89,21 +554,100 @@ pub fn extract_subtitles(
     codec: &SubtitleCodec,
     temp_dir: &Path,
 ) -> Result<std::path::PathBuf> {
-    let ext = match codec {
-        SubtitleCodec::Srt => "srt",
+    let output_path = temp_dir.join(format!("extracted.{}", extracted_extension(codec)));
+    let (decoder_options, output_codec) = ffmpeg_conversion(codec);
+    match codec {
+        SubtitleCodec::VobSub => fs::write(&output_path, vobsub::dump_packets(path, track_index)?)?,
+        SubtitleCodec::Dvb => {
+            let converted = temp_dir.join("converted.mks");
+            ffmpeg::extract_subtitle_track(
+                path,
+                track_index,
+                decoder_options,
+                output_codec,
+                &converted,
+            )?;
+            fs::write(&output_path, vobsub::dump_packets(&converted, 0)?)?;
+        }
+        SubtitleCodec::Teletext => {
+            ffmpeg::extract_subtitle_track(
+                path,
+                track_index,
+                decoder_options,
+                output_codec,
+                &output_path,
+            )
+            .context("Failed to convert teletext subtitles, which needs an FFmpeg built with libzvbi (--enable-libzvbi)")?;
+        }
+        _ => ffmpeg::extract_subtitle_track(
+            path,
+            track_index,
+            decoder_options,
+            output_codec,
+            &output_path,
+        )?,
+    }
+
+    Ok(output_path)
+}
+
+/// The extension of the file [`extract_subtitles`] writes, bitmaps are dumped as JSON.
+fn extracted_extension(codec: &SubtitleCodec) -> &'static str {
+    match codec {
+        SubtitleCodec::Srt | SubtitleCodec::Teletext => "srt",
+        SubtitleCodec::Ass => "ass",
         SubtitleCodec::Pgs => "sup",
-    };
+        SubtitleCodec::VobSub | SubtitleCodec::Dvb => "json",
+    }
+}
 
-    let output_path = temp_dir.join(format!("extracted.{ext}"));
-    ffmpeg::extract_subtitle_track(path, track_index, &output_path)?;
+/// The decoder options and output codec FFmpeg extracts the track with. DVB bitmaps are
+/// converted to VobSub, which FFmpeg can encode, and decoding teletext to text needs an
+/// FFmpeg built with libzvbi. VobSub is read without FFmpeg.
+fn ffmpeg_conversion(codec: &SubtitleCodec) -> (&'static [&'static str], &'static str) {
+    match codec {
+        SubtitleCodec::Dvb => (&[], "dvdsub"),
+        SubtitleCodec::Teletext => (&["-txt_format", "text"], "subrip"),
+        _ => (&[], "copy"),
+    }
+}
 
-    Ok(output_path)
+/// Reads the dialogue of an extracted subtitle file, OCRing image-based subtitles.
+pub fn read_text(
+    subtitle_path: &Path,
+    codec: &SubtitleCodec,
+    ocr_pool: &ocr::OcrEnginePool,
+    window: &SubtitleWindow,
+) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    match codec {
+        SubtitleCodec::Srt | SubtitleCodec::Teletext => {
+            let content = fs::read_to_string(subtitle_path)?.replace("\r\n", "\n");
+            for cue in srt_cues_in(&content, window) {
+                lines.extend(srt_text_lines(cue).map(str::to_string));
+            }
+        }
+        SubtitleCodec::Ass => {
+            let content = fs::read_to_string(subtitle_path)?;
+            for text in ass_dialogue(&content, window) {
+                lines.extend(text.lines().map(str::to_string));
+            }
+        }
+        SubtitleCodec::Pgs | SubtitleCodec::VobSub | SubtitleCodec::Dvb => {
+            ocr_bitmaps(subtitle_path, codec, ocr_pool, window, |text| {
+                lines.push(text.to_string());
+                true
+            })?;
+        }
+    }
+    Ok(lines)
 }
 
 pub fn process_and_display(
     subtitle_path: &Path,
     codec: &SubtitleCodec,
-    ocr_engine: Option<tesseract_rs::TesseractAPI>,
+    ocr_pool: &ocr::OcrEnginePool,
+    window: &SubtitleWindow,
 ) -> Result<()> {
     let pager = std::env::var("PAGER").unwrap_or_else(|_| "less".to_string());
 
@@ -115,79 +659,26 @@ pub fn process_and_display(
     let mut stdin = child.stdin.take().context("Failed to open pager stdin")?;
 
     match codec {
-        SubtitleCodec::Srt => {
-            let file = File::open(subtitle_path)?;
-            let reader = BufReader::new(file);
-            for line in reader.lines() {
-                let line = line?;
-                if writeln!(stdin, "{line}").is_err() {
+        SubtitleCodec::Srt | SubtitleCodec::Teletext => {
+            let content = fs::read_to_string(subtitle_path)?.replace("\r\n", "\n");
+            for cue in srt_cues_in(&content, window) {
+                if writeln!(stdin, "{cue}\n").is_err() {
                     break; // Pager closed
                 }
             }
         }
-        SubtitleCodec::Pgs => {
-            let mut data = fs::read(subtitle_path)?;
-            let pgs =
-                parse_pgs(&mut data).map_err(|e| anyhow::anyhow!("Failed to parse PGS: {e:?}"))?;
-
-            // We need the OCR engine for PGS
-            let api = ocr_engine.context("OCR engine required for PGS subtitles")?;
-
-            for ds in DisplaySetIterator::new(&pgs) {
-                if ds.is_empty() {
-                    continue;
-                }
-
-                if let Ok(rgba_buffer) = render_display_set(&ds) {
-                    let width = ds.width as i32;
-                    let height = ds.height as i32;
-
-                    // Convert RGBA to RGB, compositing over a black background
-                    let rgb_data: Vec<u8> = rgba_buffer
-                        .chunks(4)
-                        .flat_map(|chunk| {
-                            // chunk is [r, g, b, a]
-                            // Alpha composition: output = color * alpha + background * (1 - alpha)
-                            // Since background is black (0), output = color * alpha
-                            let r = chunk[0] as u16;
-                            let g = chunk[1] as u16;
-                            let b = chunk[2] as u16;
-                            let a = chunk[3] as u16;
-
-                            [
-                                ((r * a) / 255) as u8,
-                                ((g * a) / 255) as u8,
-                                ((b * a) / 255) as u8,
-                            ]
-                        })
-                        .collect();
-
-                    if api
-                        .set_image(&rgb_data, width, height, 3, 3 * width)
-                        .is_ok()
-                    {
-                        if let Ok(text) = api.get_utf8_text() {
-                            let cleaned_text: String = text
-                                .chars()
-                                .map(|c| match c {
-                                    '|' => 'I', // Replace pipe with capital I
-                                    _ => c,
-                                })
-                                .filter(|c| {
-                                    c.is_alphanumeric()
-                                        || c.is_whitespace()
-                                        || "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~".contains(*c)
-                                })
-                                .collect();
-
-                            let trimmed = cleaned_text.trim();
-                            if !trimmed.is_empty() && writeln!(stdin, "{trimmed}\n").is_err() {
-                                break;
-                            }
-                        }
-                    }
+        SubtitleCodec::Ass => {
+            let content = fs::read_to_string(subtitle_path)?;
+            for text in ass_dialogue(&content, window) {
+                if writeln!(stdin, "{text}\n").is_err() {
+                    break; // Pager closed
                 }
             }
+        }
+        SubtitleCodec::Pgs | SubtitleCodec::VobSub | SubtitleCodec::Dvb => {
+            ocr_bitmaps(subtitle_path, codec, ocr_pool, window, |text| {
+                writeln!(stdin, "{text}\n").is_ok()
+            })?;
             // Drop stdin to close the pipe and signal EOF to the pager
             drop(stdin);
         }
@@ -197,8 +688,352 @@ pub fn process_and_display(
     Ok(())
 }
 
+/// OCRs the images of a PGS or VobSub (also converted DVB) file shown in the window, passing the text of each
+/// to `on_text` in presentation order until it returns false.
+fn ocr_bitmaps(
+    subtitle_path: &Path,
+    codec: &SubtitleCodec,
+    ocr_pool: &ocr::OcrEnginePool,
+    window: &SubtitleWindow,
+    on_text: impl FnMut(&str) -> bool,
+) -> Result<()> {
+    match codec {
+        SubtitleCodec::Pgs => {
+            let mut data = fs::read(subtitle_path)?;
+            let pgs =
+                parse_pgs(&mut data).map_err(|e| anyhow::anyhow!("Failed to parse PGS: {e:?}"))?;
+            // Timestamps are in 90 kHz ticks
+            let display_sets: Vec<DisplaySet> = DisplaySetIterator::new(&pgs)
+                .filter(|ds| !ds.is_empty())
+                .filter(|ds| window.contains(ds.presentation_timestamp as f64 / 90_000.0))
+                .collect();
+            ocr_images(&display_sets, render_pgs, pooled_ocr(ocr_pool), on_text)
+        }
+        SubtitleCodec::VobSub | SubtitleCodec::Dvb => {
+            let track = vobsub::parse_probe(&fs::read(subtitle_path)?)?;
+            let packets: Vec<&(f64, Vec<u8>)> = track
+                .packets
+                .iter()
+                .filter(|(pts, _)| window.contains(*pts))
+                .collect();
+            let render = |&&(pts, ref packet): &&(f64, Vec<u8>)| {
+                let picture = vobsub::decode(packet, pts, &track.palette).ok()??;
+                Some((picture.rgb, picture.width, picture.height))
+            };
+            ocr_images(&packets, render, pooled_ocr(ocr_pool), on_text)
+        }
+        SubtitleCodec::Srt | SubtitleCodec::Ass | SubtitleCodec::Teletext => {
+            bail!("Text subtitles need no OCR")
+        }
+    }
+}
+
+/// Reads a subtitle image with an engine of the pool, see `ocr_text`.
+fn pooled_ocr(
+    ocr_pool: &ocr::OcrEnginePool,
+) -> impl Fn(&[u8], u32, u32) -> Result<Option<String>> + Sync + '_ {
+    move |rgb, width, height| ocr_text(rgb, width, height, ocr_pool)
+}
+
+/// OCRs the images rendered from `items` and passes their text to `on_text` in order until
+/// it returns false. A full episode has hundreds of subtitle images, so they're rendered
+/// and OCRed in parallel, `ocr` taking an engine of the pool per thread.
+fn ocr_images<T: Sync>(
+    items: &[T],
+    render: impl Fn(&T) -> Option<(Vec<u8>, u32, u32)> + Sync,
+    ocr: impl Fn(&[u8], u32, u32) -> Result<Option<String>> + Sync,
+    mut on_text: impl FnMut(&str) -> bool,
+) -> Result<()> {
+    let jobs = thread::available_parallelism().map_or(1, NonZeroUsize::get);
+    let mut result = Ok(());
+    parallel::for_each_ordered(
+        items,
+        jobs,
+        |item| match render(item) {
+            Some((rgb, width, height)) => ocr(&rgb, width, height),
+            None => Ok(None),
+        },
+        |_, text| match text {
+            Ok(Some(text)) if !on_text(&text) => ControlFlow::Break(()),
+            Ok(_) => ControlFlow::Continue(()),
+            Err(e) => {
+                result = Err(e);
+                ControlFlow::Break(())
+            }
+        },
+    );
+    result
+}
+
+/// The RGB image of a display set, `None` if it can't be rendered.
+fn render_pgs(ds: &DisplaySet) -> Option<(Vec<u8>, u32, u32)> {
+    let rgba_buffer = render_display_set(ds).ok()?;
+    let width = ds.width as u32;
+    let height = ds.height as u32;
+
+    // Convert RGBA to RGB, compositing over a black background
+    let rgb_data: Vec<u8> = rgba_buffer
+        .chunks(4)
+        .flat_map(|chunk| {
+            // chunk is [r, g, b, a]
+            // Alpha composition: output = color * alpha + background * (1 - alpha)
+            // Since background is black (0), output = color * alpha
+            let r = chunk[0] as u16;
+            let g = chunk[1] as u16;
+            let b = chunk[2] as u16;
+            let a = chunk[3] as u16;
+
+            [
+                ((r * a) / 255) as u8,
+                ((g * a) / 255) as u8,
+                ((b * a) / 255) as u8,
+            ]
+        })
+        .collect();
+    Some((rgb_data, width, height))
+}
+
+/// The cleaned-up text of a subtitle image, `None` if nothing could be read.
+fn ocr_text(
+    rgb_data: &[u8],
+    width: u32,
+    height: u32,
+    ocr_pool: &ocr::OcrEnginePool,
+) -> Result<Option<String>> {
+    let api = ocr_pool.get()?;
+    let Ok(recognized) = api.recognize(rgb_data, width, height) else {
+        return Ok(None);
+    };
+    let cleaned_text: String = recognized
+        .text
+        .chars()
+        .map(|c| match c {
+            '|' => 'I', // Replace pipe with capital I
+            _ => c,
+        })
+        .filter(|c| {
+            c.is_alphanumeric()
+                || c.is_whitespace()
+                || "!\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~".contains(*c)
+        })
+        .collect();
+
+    let trimmed = cleaned_text.trim();
+    Ok((!trimmed.is_empty()).then(|| trimmed.to_string()))
+}
+
 #[cfg(test)]
 mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guess_language() {
+        let english = "What are you doing here?\nI don't know, it's the only way to find out.";
+        assert_eq!(guess_language(english), Some("eng"));
+
+        let german = "Was machst du hier?\nIch weiß es nicht, das ist der einzige Weg.";
+        assert_eq!(guess_language(german), Some("ger"));
+
+        assert_eq!(guess_language("Mulder! Scully!"), None);
+        assert_eq!(language_key("deu"), "ger");
+        assert_eq!(language_key("jpn"), "jpn");
+    }
+
+    #[test]
+    fn test_track_preference() {
+        let track = |index, codec, forced, sdh| SubtitleTrack {
+            index,
+            codec,
+            title: None,
+            forced,
+            sdh,
+        };
+        let mut tracks = [
+            track(2, SubtitleCodec::Srt, true, false),
+            track(3, SubtitleCodec::Pgs, false, true),
+            track(4, SubtitleCodec::Pgs, false, false),
+        ];
+
+        let avoid_sdh = TrackPreference {
+            avoid_forced: true,
+            sdh: Some(false),
+        };
+        tracks.sort_by_key(|track| avoid_sdh.rank(track));
+        let order: Vec<u32> = tracks.iter().map(|track| track.index).collect();
+        assert_eq!(order, vec![4, 3, 2]);
+
+        // Without preferences the SRT track wins, it needs no OCR
+        let anything = TrackPreference {
+            avoid_forced: false,
+            sdh: None,
+        };
+        tracks.sort_by_key(|track| anything.rank(track));
+        assert_eq!(tracks[0].index, 2);
+
+        // Teletext is text, it beats the bitmaps, and DVB ones come before DVD ones
+        let mut broadcast = [
+            track(5, SubtitleCodec::VobSub, false, false),
+            track(6, SubtitleCodec::Dvb, false, false),
+            track(7, SubtitleCodec::Pgs, false, false),
+            track(8, SubtitleCodec::Teletext, false, false),
+        ];
+        broadcast.sort_by_key(|track| anything.rank(track));
+        let order: Vec<u32> = broadcast.iter().map(|track| track.index).collect();
+        assert_eq!(order, vec![8, 7, 6, 5]);
+    }
+
+    #[test]
+    fn test_parse_subtitle_probe() {
+        let probe = br#"{"streams": [
+            {"index": 2, "codec_name": "dvb_teletext", "tags": {"language": "ger"}},
+            {"index": 3, "codec_name": "dvb_subtitle", "tags": {"language": "und", "title": "SDH"}},
+            {"index": 4, "codec_name": "eia_608"},
+            {"index": 5, "codec_name": "subrip", "disposition": {"forced": 1, "hearing_impaired": 0}}
+        ]}"#;
+        let tracks = parse_subtitle_probe(probe).unwrap();
+        // Codecs that can't be read are left out
+        assert_eq!(tracks.len(), 3);
+
+        let (teletext, tag) = &tracks[0];
+        assert!(matches!(teletext.codec, SubtitleCodec::Teletext));
+        assert_eq!((teletext.index, tag.as_deref()), (2, Some("ger")));
+        let (dvb, tag) = &tracks[1];
+        assert!(matches!(dvb.codec, SubtitleCodec::Dvb));
+        assert_eq!((dvb.sdh, tag.as_deref()), (true, None));
+        let (srt, tag) = &tracks[2];
+        assert!(matches!(srt.codec, SubtitleCodec::Srt));
+        assert_eq!((srt.forced, srt.sdh, tag.as_deref()), (true, false, None));
+    }
+
+    #[test]
+    fn test_extraction() {
+        let teletext = SubtitleCodec::Teletext;
+        assert_eq!(extracted_extension(&teletext), "srt");
+        assert_eq!(
+            ffmpeg_conversion(&teletext),
+            (&["-txt_format", "text"][..], "subrip")
+        );
+
+        let dvb = SubtitleCodec::Dvb;
+        assert_eq!(extracted_extension(&dvb), "json");
+        assert_eq!(ffmpeg_conversion(&dvb), (&[][..], "dvdsub"));
+
+        assert_eq!(extracted_extension(&SubtitleCodec::Pgs), "sup");
+        assert_eq!(ffmpeg_conversion(&SubtitleCodec::Pgs), (&[][..], "copy"));
+    }
+
+    #[test]
+    fn test_srt_text_lines() {
+        let content = "\u{feff}1\n00:00:01,000 --> 00:00:02,000\nHello there.\n\n2\n00:00:03,000 --> 00:00:04,000\nGeneral Kenobi!\n";
+        assert_eq!(
+            srt_text_lines(content).collect::<Vec<_>>(),
+            vec!["Hello there.", "General Kenobi!"]
+        );
+    }
+
+    #[test]
+    fn test_srt_cues_in() {
+        let content = "1\n00:00:01,000 --> 00:00:02,000\nHello there.\n\n2\n00:10:03,500 --> 00:10:04,000\nGeneral Kenobi!\n";
+        let first_minutes = SubtitleWindow {
+            ranges: vec![(0.0, 300.0)],
+            skipped: Vec::new(),
+        };
+        assert_eq!(
+            srt_cues_in(content, &first_minutes),
+            vec!["1\n00:00:01,000 --> 00:00:02,000\nHello there."]
+        );
+        assert_eq!(srt_cues_in(content, &SubtitleWindow::default()).len(), 2);
+        assert_eq!(parse_timestamp("00:10:03,500"), Some(603.5));
+        assert_eq!(parse_timestamp("0:10:03.50"), Some(603.5));
+    }
+
+    #[test]
+    fn test_skipped_chapters() {
+        let chapter = |start: f64, end: f64, title: Option<&str>| ffmpeg::Chapter {
+            start,
+            end,
+            title: title.map(str::to_string),
+        };
+        let chapters = [
+            chapter(0.0, 60.0, Some("Previously on Lost")),
+            chapter(60.0, 150.0, Some("Opening Credits")),
+            chapter(150.0, 600.0, Some("Chapter 3")),
+            chapter(600.0, 1200.0, Some("The Opening of the Hatch")),
+            chapter(1200.0, 1290.0, Some("ED")),
+            chapter(1290.0, 1320.0, None),
+        ];
+        let skipped = skipped_chapters(&chapters);
+        assert_eq!(skipped, vec![(0.0, 60.0), (60.0, 150.0), (1200.0, 1290.0)]);
+
+        let window = SubtitleWindow {
+            ranges: Vec::new(),
+            skipped,
+        };
+        assert!(!window.contains(30.0));
+        assert!(window.contains(150.0));
+        assert!(window.contains(700.0));
+        assert!(!window.contains(1250.0));
+    }
+
+    #[test]
+    fn test_ass_dialogue() {
+        let content = "[Script Info]\nTitle: Test\n\n[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\nDialogue: 0,0:00:05.00,0:00:07.00,Default,,0,0,0,,Where are we,{\\i1} exactly{\\i0}?\\NNo idea.\nDialogue: 0,0:00:01.00,0:00:03.00,Sign,,0,0,0,,{\\an8\\pos(320,50)}Tokyo\nDialogue: 0,0:00:02.00,0:00:03.00,Sign,,0,0,0,,{\\p1}m 0 0 l 100 0 100 100{\\p0}\nDialogue: 0,0:20:00.00,0:20:02.00,Default,,0,0,0,,Later.\n";
+        let first_minutes = SubtitleWindow {
+            ranges: vec![(0.0, 300.0)],
+            skipped: Vec::new(),
+        };
+        assert_eq!(
+            ass_dialogue(content, &first_minutes),
+            vec!["Tokyo", "Where are we, exactly?\nNo idea."]
+        );
+        assert_eq!(ass_dialogue(content, &SubtitleWindow::default()).len(), 3);
+    }
+
+    #[test]
+    fn test_ocr_images() {
+        // Later images are read sooner, the text still comes in the order of the images
+        let items: Vec<u8> = (0..12).collect();
+        let render = |&item: &u8| (item != 5).then(|| (vec![item], 1, 1));
+        let ocr = |rgb: &[u8], _, _| {
+            std::thread::sleep(std::time::Duration::from_millis(12 - rgb[0] as u64));
+            Ok((rgb[0] != 8).then(|| format!("line {}", rgb[0])))
+        };
+        let mut lines = Vec::new();
+        ocr_images(&items, render, ocr, |text| {
+            lines.push(text.to_string());
+            true
+        })
+        .unwrap();
+        // Images that can't be rendered or have no text are left out
+        let expected: Vec<String> = [0, 1, 2, 3, 4, 6, 7, 9, 10, 11]
+            .iter()
+            .map(|item| format!("line {item}"))
+            .collect();
+        assert_eq!(lines, expected);
+
+        // Stops once `on_text` has seen enough
+        let mut lines = Vec::new();
+        ocr_images(&items, render, ocr, |text| {
+            lines.push(text.to_string());
+            lines.len() < 3
+        })
+        .unwrap();
+        assert_eq!(lines, ["line 0", "line 1", "line 2"]);
+
+        // An engine that fails to start ends the OCR after the text read before it
+        let failing = |rgb: &[u8], _, _| match rgb[0] {
+            3 => Err(anyhow::anyhow!("no tessdata")),
+            item => Ok(Some(format!("line {item}"))),
+        };
+        let mut lines = Vec::new();
+        let result = ocr_images(&items, render, failing, |text| {
+            lines.push(text.to_string());
+            true
+        });
+        assert!(result.is_err());
+        assert_eq!(lines, ["line 0", "line 1", "line 2"]);
+    }
+
     #[test]
     fn test_subtitle_text_cleaning() {
         let input = "Hello | World! @#$%^&*()";
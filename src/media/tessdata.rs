@@ -0,0 +1,152 @@
+use anyhow::{bail, Context, Result};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config;
+
+pub const DEFAULT_LANGUAGE: &str = "eng";
+const DOWNLOAD_URL: &str = "https://github.com/tesseract-ocr/tessdata_best/raw/main";
+
+// Where distributions and package managers install tessdata
+const SYSTEM_DIRS: &[&str] = &[
+    "/usr/share/tesseract-ocr/5/tessdata",
+    "/usr/share/tesseract-ocr/4.00/tessdata",
+    "/usr/share/tessdata",
+    "/usr/local/share/tessdata",
+    "/opt/homebrew/share/tessdata",
+    "/opt/local/share/tessdata",
+];
+
+/// Where to look for the traineddata, see [`locate`].
+#[derive(Debug, Clone, Default)]
+pub struct TessdataSource {
+    pub dir: Option<PathBuf>, // --tessdata, the only directory tried if set
+    pub download: bool,       // download the data into the config dir if none is found
+}
+
+/// Finds a directory containing the traineddata of every language of `languages` (tesseract
+/// syntax, e.g. `eng+deu`): the `--tessdata` directory, then `$TESSDATA_PREFIX`, the config
+/// dir, the directories the tesseract-rs build downloads to and common system paths.
+/// Downloads missing data into the config dir as a last resort if the source allows it.
+pub fn locate(source: &TessdataSource, languages: &str) -> Result<PathBuf> {
+    let languages = parse_languages(languages)?;
+    let has_languages = |dir: &Path| languages.iter().all(|language| has_language(dir, language));
+
+    if let Some(dir) = &source.dir {
+        if !has_languages(dir) {
+            bail!("Traineddata of {languages:?} not found in --tessdata directory {dir:?}");
+        }
+        return Ok(dir.clone());
+    }
+
+    if let Some(dir) = search_dirs().into_iter().find(|dir| has_languages(dir)) {
+        return Ok(dir);
+    }
+
+    let dir = config::get_tessdata_dir_path();
+    if !source.download {
+        bail!(
+            "Traineddata of {languages:?} not found. Install tesseract's language data, set TESSDATA_PREFIX or --tessdata, or run with --download-tessdata to download it to {dir:?}"
+        );
+    }
+    for language in languages {
+        if !has_language(&dir, language) {
+            download(&dir, language)?;
+        }
+    }
+    Ok(dir)
+}
+
+/// Splits a tesseract language selection like `eng+deu` into its languages.
+fn parse_languages(languages: &str) -> Result<Vec<&str>> {
+    let languages: Vec<&str> = languages.split('+').map(str::trim).collect();
+    for language in &languages {
+        if language.is_empty()
+            || !language
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            bail!("Invalid OCR language {language:?}, expected e.g. eng or eng+deu");
+        }
+    }
+    Ok(languages)
+}
+
+fn search_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+
+    // Tesseract 4+ expects the tessdata directory itself, older versions its parent
+    if let Some(prefix) = env::var_os("TESSDATA_PREFIX") {
+        let prefix = PathBuf::from(prefix);
+        dirs.push(prefix.join("tessdata"));
+        dirs.push(prefix);
+    }
+
+    dirs.push(config::get_tessdata_dir_path());
+
+    if let Some(home) = env::var_os("HOME") {
+        let home = PathBuf::from(home);
+        dirs.push(home.join(".tesseract-rs/tessdata"));
+        dirs.push(home.join("Library/Application Support/tesseract-rs/tessdata"));
+    }
+    if let Some(app_data) = env::var_os("APPDATA") {
+        dirs.push(PathBuf::from(app_data).join("tesseract-rs/tessdata"));
+    }
+
+    dirs.extend(SYSTEM_DIRS.iter().map(PathBuf::from));
+    dirs
+}
+
+fn has_language(dir: &Path, language: &str) -> bool {
+    dir.join(format!("{language}.traineddata")).is_file()
+}
+
+fn download(dir: &Path, language: &str) -> Result<()> {
+    println!("Downloading {language}.traineddata to {dir:?}...");
+    fs::create_dir_all(dir)?;
+
+    let url = format!("{DOWNLOAD_URL}/{language}.traineddata");
+    let response =
+        reqwest::blocking::get(&url).with_context(|| format!("Failed to download {url}"))?;
+    if !response.status().is_success() {
+        bail!("Downloading tessdata failed: HTTP {}", response.status());
+    }
+    let bytes = response.bytes()?;
+
+    // Write to a temporary name first so that an interrupted download isn't picked up
+    let path = dir.join(format!("{language}.traineddata"));
+    let partial = path.with_extension("traineddata.part");
+    fs::write(&partial, &bytes)?;
+    fs::rename(&partial, &path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locate_explicit_dir() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let source = TessdataSource {
+            dir: Some(temp_dir.path().to_path_buf()),
+            download: false,
+        };
+        assert!(locate(&source, "eng").is_err());
+
+        fs::write(temp_dir.path().join("eng.traineddata"), "").unwrap();
+        assert_eq!(locate(&source, "eng").unwrap(), temp_dir.path());
+        // Every language must be there
+        assert!(locate(&source, "eng+deu").is_err());
+    }
+
+    #[test]
+    fn test_parse_languages() {
+        assert_eq!(parse_languages("eng").unwrap(), ["eng"]);
+        assert_eq!(parse_languages("eng+deu").unwrap(), ["eng", "deu"]);
+        assert_eq!(parse_languages("chi_sim").unwrap(), ["chi_sim"]);
+        assert!(parse_languages("eng+").is_err());
+        assert!(parse_languages("../eng").is_err());
+    }
+}
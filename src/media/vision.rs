@@ -0,0 +1,188 @@
+//! OCR with the Apple Vision framework, only built on macOS with the `vision` feature.
+//! Vision reads stylized credit fonts much more reliably than tesseract.
+
+use anyhow::{anyhow, bail, Result};
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::io::Cursor;
+
+use super::ocr::{OcrEngine, RecognizedText};
+
+type Id = *mut c_void;
+type Sel = *const c_void;
+
+#[link(name = "objc")]
+extern "C" {
+    fn objc_getClass(name: *const c_char) -> Id;
+    fn sel_registerName(name: *const c_char) -> Sel;
+    fn objc_msgSend();
+    fn objc_autoreleasePoolPush() -> *mut c_void;
+    fn objc_autoreleasePoolPop(pool: *mut c_void);
+}
+
+#[link(name = "Foundation", kind = "framework")]
+extern "C" {}
+
+#[link(name = "Vision", kind = "framework")]
+extern "C" {}
+
+// VNRequestTextRecognitionLevelAccurate
+const RECOGNITION_LEVEL_ACCURATE: isize = 0;
+
+/// Sends an Objective-C message: `msg_send!(receiver, "selector:", arg => Type, ...; Return)`.
+macro_rules! msg_send {
+    ($receiver:expr, $selector:literal $(, $arg:expr => $ty:ty)* ; $ret:ty) => {{
+        let send: unsafe extern "C" fn(Id, Sel $(, $ty)*) -> $ret =
+            std::mem::transmute(objc_msgSend as unsafe extern "C" fn());
+        send($receiver, selector($selector) $(, $arg)*)
+    }};
+}
+
+unsafe fn class(name: &str) -> Id {
+    let name = CString::new(name).unwrap();
+    objc_getClass(name.as_ptr())
+}
+
+unsafe fn selector(name: &str) -> Sel {
+    let name = CString::new(name).unwrap();
+    sel_registerName(name.as_ptr())
+}
+
+pub struct VisionEngine {
+    languages: Vec<CString>, // BCP 47 tags, Vision's default if empty
+}
+
+impl VisionEngine {
+    /// Creates an engine for a tesseract language selection like `eng+deu`.
+    pub fn new(languages: &str) -> Self {
+        Self {
+            languages: languages
+                .split('+')
+                .filter_map(bcp47_tag)
+                .filter_map(|tag| CString::new(tag).ok())
+                .collect(),
+        }
+    }
+}
+
+impl OcrEngine for VisionEngine {
+    fn recognize(&self, rgb: &[u8], width: u32, height: u32) -> Result<RecognizedText> {
+        // Vision takes encoded images, which spares building a CGImage by hand
+        let Some(image) = image::RgbImage::from_raw(width, height, rgb.to_vec()) else {
+            bail!("Image data doesn't match its {width}x{height} size");
+        };
+        let mut png = Vec::new();
+        image.write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+
+        unsafe {
+            let pool = objc_autoreleasePoolPush();
+            let result = recognize_png(&png, &self.languages);
+            objc_autoreleasePoolPop(pool);
+            result
+        }
+    }
+}
+
+unsafe fn recognize_png(png: &[u8], languages: &[CString]) -> Result<RecognizedText> {
+    let bytes = png.as_ptr() as *const c_void;
+    let data: Id = msg_send!(class("NSData"), "dataWithBytes:length:", bytes => *const c_void, png.len() => usize; Id);
+    let options: Id = msg_send!(class("NSDictionary"), "dictionary"; Id);
+    let handler: Id = msg_send!(class("VNImageRequestHandler"), "alloc"; Id);
+    let handler: Id = msg_send!(handler, "initWithData:options:", data => Id, options => Id; Id);
+
+    let request: Id = msg_send!(class("VNRecognizeTextRequest"), "new"; Id);
+    msg_send!(request, "setRecognitionLevel:", RECOGNITION_LEVEL_ACCURATE => isize; ());
+    // Production codes aren't dictionary words
+    msg_send!(request, "setUsesLanguageCorrection:", false => bool; ());
+    if !languages.is_empty() {
+        let tags: Id = msg_send!(class("NSMutableArray"), "array"; Id);
+        for language in languages {
+            let tag: Id = msg_send!(class("NSString"), "stringWithUTF8String:", language.as_ptr() => *const c_char; Id);
+            msg_send!(tags, "addObject:", tag => Id; ());
+        }
+        msg_send!(request, "setRecognitionLanguages:", tags => Id; ());
+    }
+
+    let requests: Id = msg_send!(class("NSArray"), "arrayWithObject:", request => Id; Id);
+    let mut error: Id = std::ptr::null_mut();
+    let performed: bool = msg_send!(handler, "performRequests:error:", requests => Id, &mut error as *mut Id => *mut Id; bool);
+    let result = if performed {
+        Ok(read_observations(msg_send!(request, "results"; Id)))
+    } else {
+        Err(anyhow!("Vision text recognition failed"))
+    };
+
+    msg_send!(request, "release"; ());
+    msg_send!(handler, "release"; ());
+    result
+}
+
+/// Collects the best reading of every recognized line. Vision rates whole lines, so every
+/// word of a line gets the line's confidence.
+unsafe fn read_observations(observations: Id) -> RecognizedText {
+    let mut lines = Vec::new();
+    let mut word_confidences = Vec::new();
+
+    let count: usize = msg_send!(observations, "count"; usize);
+    for index in 0..count {
+        let observation: Id = msg_send!(observations, "objectAtIndex:", index => usize; Id);
+        let candidates: Id = msg_send!(observation, "topCandidates:", 1usize => usize; Id);
+        let candidate_count: usize = msg_send!(candidates, "count"; usize);
+        if candidate_count == 0 {
+            continue;
+        }
+        let candidate: Id = msg_send!(candidates, "objectAtIndex:", 0usize => usize; Id);
+        let string: Id = msg_send!(candidate, "string"; Id);
+        let utf8: *const c_char = msg_send!(string, "UTF8String"; *const c_char);
+        if utf8.is_null() {
+            continue;
+        }
+        let line = CStr::from_ptr(utf8).to_string_lossy().into_owned();
+        let confidence: f32 = msg_send!(candidate, "confidence"; f32);
+        let confidence = (confidence * 100.0).round() as i32;
+
+        word_confidences.extend(line.split_whitespace().map(|_| confidence));
+        lines.push(line);
+    }
+
+    let mean_confidence = match word_confidences.len() {
+        0 => 0,
+        words => word_confidences.iter().sum::<i32>() / words as i32,
+    };
+    RecognizedText {
+        text: lines.join("\n"),
+        word_confidences,
+        mean_confidence,
+    }
+}
+
+/// Vision's language tag for a tesseract language, `None` for ones Vision can't read.
+fn bcp47_tag(language: &str) -> Option<&'static str> {
+    Some(match language {
+        "eng" => "en-US",
+        "deu" => "de-DE",
+        "fra" => "fr-FR",
+        "spa" => "es-ES",
+        "ita" => "it-IT",
+        "por" => "pt-BR",
+        "rus" => "ru-RU",
+        "ukr" => "uk-UA",
+        "kor" => "ko-KR",
+        "jpn" => "ja-JP",
+        "chi_sim" => "zh-Hans",
+        "chi_tra" => "zh-Hant",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bcp47_tag() {
+        assert_eq!(bcp47_tag("deu"), Some("de-DE"));
+        assert_eq!(bcp47_tag("chi_sim"), Some("zh-Hans"));
+        assert_eq!(bcp47_tag("osd"), None);
+        assert_eq!(VisionEngine::new("eng+osd+deu").languages.len(), 2);
+    }
+}
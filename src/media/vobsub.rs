@@ -0,0 +1,346 @@
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+use std::process::Command;
+
+// Transparent border around a subtitle, Tesseract misses text touching the image edge
+const MARGIN: usize = 10;
+
+/// RGB colors of a track, indexed by the subpictures' color maps.
+pub type Palette = [[u8; 3]; 16];
+
+/// The subpictures of a DVD subtitle track with the track's palette.
+#[derive(Debug)]
+pub struct VobSubTrack {
+    pub palette: Palette,
+    pub packets: Vec<(f64, Vec<u8>)>, // presentation time in seconds and subpicture unit
+}
+
+/// A decoded subpicture.
+#[derive(Debug, PartialEq)]
+pub struct Subpicture {
+    pub start: f64, // seconds
+    pub width: u32,
+    pub height: u32,
+    pub rgb: Vec<u8>, // composited over black, 3 bytes per pixel
+}
+
+#[derive(Debug, Deserialize)]
+struct PacketProbe {
+    #[serde(default)]
+    packets: Vec<Packet>,
+    #[serde(default)]
+    streams: Vec<StreamData>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Packet {
+    pts_time: Option<String>, // seconds, as a decimal string
+    data: Option<String>,     // hex dump
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamData {
+    extradata: Option<String>, // hex dump of the idx header
+}
+
+/// Dumps the packets of a `dvd_subtitle` stream with ffprobe, since FFmpeg can't write
+/// them to an idx/sub pair. The output is read back with [`parse_probe`].
+pub fn dump_packets(path: &Path, track_index: u32) -> Result<Vec<u8>> {
+    let output = Command::new("ffprobe")
+        .args([
+            "-v",
+            "quiet",
+            "-print_format",
+            "json",
+            "-show_streams",
+            "-show_packets",
+            "-show_data",
+            "-select_streams",
+            &track_index.to_string(),
+            path.to_str().context("Invalid path")?,
+        ])
+        .output()
+        .context("Failed to run ffprobe")?;
+
+    if !output.status.success() {
+        bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(output.stdout)
+}
+
+pub fn parse_probe(json_output: &[u8]) -> Result<VobSubTrack> {
+    let probe: PacketProbe = serde_json::from_slice(json_output)?;
+    let palette = probe
+        .streams
+        .first()
+        .and_then(|stream| stream.extradata.as_deref())
+        .and_then(|extradata| parse_palette(&String::from_utf8_lossy(&parse_hex_dump(extradata))))
+        .unwrap_or_else(grey_palette);
+
+    let packets = probe
+        .packets
+        .into_iter()
+        .filter_map(|packet| {
+            let pts = packet.pts_time?.parse().ok()?;
+            Some((pts, parse_hex_dump(&packet.data?)))
+        })
+        .collect();
+    Ok(VobSubTrack { palette, packets })
+}
+
+/// Reads the bytes of an ffprobe hex dump, whose lines hold an offset, up to 16 bytes as
+/// hex in groups of two and their ASCII.
+fn parse_hex_dump(dump: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for line in dump.lines() {
+        // `00000000: ` and 41 columns of hex and padding
+        let Some(hex) = line.get(10..).map(|rest| &rest[..rest.len().min(41)]) else {
+            continue;
+        };
+        let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        bytes.extend(
+            digits
+                .chunks_exact(2)
+                .filter_map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()),
+        );
+    }
+    bytes
+}
+
+/// The `palette:` line of an idx header, 16 RGB colors in hex.
+fn parse_palette(idx_header: &str) -> Option<Palette> {
+    let line = idx_header
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("palette:"))?;
+    let colors: Vec<[u8; 3]> = line
+        .split(',')
+        .map(|color| {
+            let rgb = u32::from_str_radix(color.trim(), 16).ok()?;
+            Some([(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+        })
+        .collect::<Option<_>>()?;
+    colors.try_into().ok()
+}
+
+/// Stand-in for tracks without a palette, brighter for higher indexes.
+fn grey_palette() -> Palette {
+    std::array::from_fn(|i| [(i * 17) as u8; 3])
+}
+
+/// Decodes a subpicture unit shown `pts` seconds into the video, `None` if it shows
+/// nothing, e.g. one that only clears the screen.
+pub fn decode(packet: &[u8], pts: f64, palette: &Palette) -> Result<Option<Subpicture>> {
+    let read_u16 = |pos: usize| -> Result<usize> {
+        let bytes = packet.get(pos..pos + 2).context("Truncated subpicture")?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+    };
+
+    let mut start = None;
+    let mut colormap = [0u8; 4];
+    let mut alpha = [0u8; 4];
+    let mut area = None;
+    let mut fields = None;
+
+    // Control sequences: a delay, the offset of the next sequence and commands up to 0xff
+    let mut command_pos = read_u16(2)?;
+    loop {
+        let delay = read_u16(command_pos)?;
+        let next_pos = read_u16(command_pos + 2)?;
+        let mut pos = command_pos + 4;
+        while let Some(&command) = packet.get(pos) {
+            pos += 1;
+            match command {
+                0x00 | 0x02 => {} // forced display, stop display
+                0x01 => start = Some(pts + delay as f64 * 1024.0 / 90_000.0),
+                0x03 | 0x04 => {
+                    let bytes = packet.get(pos..pos + 2).context("Truncated subpicture")?;
+                    let nibbles = [
+                        bytes[1] & 0x0f,
+                        bytes[1] >> 4,
+                        bytes[0] & 0x0f,
+                        bytes[0] >> 4,
+                    ];
+                    if command == 0x03 {
+                        colormap = nibbles;
+                    } else {
+                        alpha = nibbles;
+                    }
+                    pos += 2;
+                }
+                0x05 => {
+                    let b = packet.get(pos..pos + 6).context("Truncated subpicture")?;
+                    let b: Vec<usize> = b.iter().map(|&byte| byte as usize).collect();
+                    let x1 = (b[0] << 4) | (b[1] >> 4);
+                    let x2 = ((b[1] & 0x0f) << 8) | b[2];
+                    let y1 = (b[3] << 4) | (b[4] >> 4);
+                    let y2 = ((b[4] & 0x0f) << 8) | b[5];
+                    area = Some((x2.saturating_sub(x1) + 1, y2.saturating_sub(y1) + 1));
+                    pos += 6;
+                }
+                0x06 => {
+                    fields = Some((read_u16(pos)?, read_u16(pos + 2)?));
+                    pos += 4;
+                }
+                _ => break, // 0xff ends the sequence
+            }
+        }
+        if next_pos <= command_pos {
+            break;
+        }
+        command_pos = next_pos;
+    }
+
+    let (Some(start), Some((width, height)), Some((top, bottom))) = (start, area, fields) else {
+        return Ok(None);
+    };
+
+    // Interlaced: the even lines come from the top field, the odd ones from the bottom one
+    let mut indexes = vec![0u8; width * height];
+    decode_field(packet, top, width, &mut indexes, 0, height);
+    decode_field(packet, bottom, width, &mut indexes, 1, height);
+
+    let padded_width = width + 2 * MARGIN;
+    let padded_height = height + 2 * MARGIN;
+    let mut rgb = vec![0u8; padded_width * padded_height * 3];
+    for (i, &index) in indexes.iter().enumerate() {
+        let color = palette[colormap[index as usize] as usize];
+        let opacity = alpha[index as usize] as u16;
+        let offset = ((i / width + MARGIN) * padded_width + i % width + MARGIN) * 3;
+        for (out, channel) in rgb[offset..offset + 3].iter_mut().zip(color) {
+            *out = (channel as u16 * opacity / 15) as u8;
+        }
+    }
+
+    Ok(Some(Subpicture {
+        start,
+        width: padded_width as u32,
+        height: padded_height as u32,
+        rgb,
+    }))
+}
+
+/// Decodes the run-length encoded lines of one field, starting at `offset` in the packet,
+/// into every other row of `indexes` from `first_row`.
+fn decode_field(
+    packet: &[u8],
+    offset: usize,
+    width: usize,
+    indexes: &mut [u8],
+    first_row: usize,
+    height: usize,
+) {
+    let mut nibbles = Nibbles {
+        data: packet,
+        pos: offset * 2,
+    };
+    for row in (first_row..height).step_by(2) {
+        let mut x = 0;
+        while x < width {
+            let Some(code) = nibbles.next_code() else {
+                return;
+            };
+            let run = match code >> 2 {
+                0 => width - x, // the rest of the line
+                run => run.min(width - x),
+            };
+            indexes[row * width + x..row * width + x + run].fill((code & 3) as u8);
+            x += run;
+        }
+        nibbles.align();
+    }
+}
+
+/// Reads run-length codes nibble by nibble.
+struct Nibbles<'a> {
+    data: &'a [u8],
+    pos: usize, // in nibbles
+}
+
+impl Nibbles<'_> {
+    fn next(&mut self) -> Option<usize> {
+        let byte = self.data.get(self.pos / 2)?;
+        let nibble = if self.pos % 2 == 0 {
+            byte >> 4
+        } else {
+            byte & 0x0f
+        };
+        self.pos += 1;
+        Some(nibble as usize)
+    }
+
+    /// A code of 4 to 16 bits: a run length and two bits of color, with leading zero
+    /// nibbles for the longer runs.
+    fn next_code(&mut self) -> Option<usize> {
+        let mut code = self.next()?;
+        for threshold in [0x4, 0x10, 0x40] {
+            if code >= threshold {
+                break;
+            }
+            code = (code << 4) | self.next()?;
+        }
+        Some(code)
+    }
+
+    /// Lines start on a byte boundary.
+    fn align(&mut self) {
+        self.pos += self.pos % 2;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_hex_dump() {
+        let dump = "\n00000000: 7369 7a65 3a20 3732 3078 3438 300a 7061  size: 720x480.pa\n00000010: 6c  l\n";
+        assert_eq!(parse_hex_dump(dump), b"size: 720x480\npal");
+    }
+
+    #[test]
+    fn test_parse_palette() {
+        let header = "size: 720x480\npalette: 000000, ffffff, 808080, 000000, 000000, 000000, 000000, 000000, 000000, 000000, 000000, 000000, 000000, 000000, 000000, ff0000\n";
+        let palette = parse_palette(header).unwrap();
+        assert_eq!(palette[1], [255, 255, 255]);
+        assert_eq!(palette[15], [255, 0, 0]);
+        assert!(parse_palette("size: 720x480").is_none());
+    }
+
+    #[test]
+    fn test_decode() {
+        // A 4x2 subpicture: a white run of 2 and a line fill on top, a line fill below
+        let mut packet = vec![0, 0, 0, 0];
+        let top = packet.len();
+        packet.extend([0x90, 0x00, 0x00]); // run 2 of color 1, then fill with color 0
+        let bottom = packet.len();
+        packet.extend([0x00, 0x01]); // fill with color 1
+        let control = packet.len();
+        packet.extend([0x00, 0x00]); // delay
+        packet.extend((control as u16).to_be_bytes()); // last sequence
+        packet.extend([0x01, 0x03, 0x00, 0x10, 0x04, 0x00, 0xf0]); // start, colors, alpha
+        packet.extend([0x05, 0x00, 0x00, 0x03, 0x00, 0x00, 0x01]); // x 0-3, y 0-1
+        packet.push(0x06);
+        packet.extend((top as u16).to_be_bytes());
+        packet.extend((bottom as u16).to_be_bytes());
+        packet.push(0xff);
+        packet[2..4].copy_from_slice(&(control as u16).to_be_bytes());
+
+        let mut palette = grey_palette();
+        palette[1] = [255, 255, 255];
+        let picture = decode(&packet, 1.5, &palette).unwrap().unwrap();
+        assert_eq!(picture.start, 1.5);
+        assert_eq!((picture.width, picture.height), (4 + 20, 2 + 20));
+
+        let pixel = |x: usize, y: usize| {
+            let offset = ((y + MARGIN) * picture.width as usize + x + MARGIN) * 3;
+            picture.rgb[offset]
+        };
+        assert_eq!([pixel(0, 0), pixel(1, 0), pixel(2, 0)], [255, 255, 0]);
+        assert_eq!([pixel(0, 1), pixel(3, 1)], [255, 255]);
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use std::path::{Path, PathBuf};
+
+use crate::cli::Provider;
+use crate::config::SeriesMapping;
+
+/// Routes the files of a directory with several shows to their series, by the first pattern
+/// that matches somewhere in a file's path.
+#[derive(Debug, Default)]
+pub struct SeriesMap {
+    rules: Vec<(Regex, String)>, // case-insensitive pattern -> show ID
+}
+
+impl SeriesMap {
+    /// The `--map 'pattern=show-id'` rules, followed by the `[[mappings]]` of the config.
+    pub fn new(cli_rules: &[String], config_rules: Vec<SeriesMapping>) -> Result<Self> {
+        let cli_rules = cli_rules.iter().map(|rule| {
+            let (pattern, series) = rule
+                .rsplit_once('=')
+                .with_context(|| format!("Invalid --map '{rule}', expected 'pattern=show-id'"))?;
+            Ok((pattern.to_string(), series.to_string()))
+        });
+        let config_rules = config_rules
+            .into_iter()
+            .map(|mapping| Ok((mapping.pattern, mapping.series)));
+
+        let rules = cli_rules
+            .chain(config_rules)
+            .map(|rule: Result<(String, String)>| {
+                let (pattern, series) = rule?;
+                let regex = RegexBuilder::new(&pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .with_context(|| format!("Invalid series mapping pattern '{pattern}'"))?;
+                Ok((regex, series.trim().to_string()))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn series_for(&self, path: &Path) -> Option<&str> {
+        let path = path.to_string_lossy();
+        self.rules
+            .iter()
+            .find(|(regex, _)| regex.is_match(&path))
+            .map(|(_, series)| series.as_str())
+    }
+
+    /// Groups the files by series, in the order each series first appears, and returns the
+    /// files no pattern matches separately.
+    pub fn group(&self, files: Vec<PathBuf>) -> (Vec<(String, Vec<PathBuf>)>, Vec<PathBuf>) {
+        let mut groups: Vec<(String, Vec<PathBuf>)> = Vec::new();
+        let mut unmapped = Vec::new();
+        for file in files {
+            let Some(series) = self.series_for(&file) else {
+                unmapped.push(file);
+                continue;
+            };
+            match groups.iter_mut().find(|(id, _)| id == series) {
+                Some((_, group)) => group.push(file),
+                None => groups.push((series.to_string(), vec![file])),
+            }
+        }
+        (groups, unmapped)
+    }
+}
+
+/// The provider and its show ID a mapped series names: `tvmaze:83` is show 83 at TVmaze,
+/// `tvdb:77398` and a plain `77398` are shows of the `default` provider.
+pub fn mapped_provider<'a>(series: &'a str, default: &Provider) -> (Provider, &'a str) {
+    if let Some(id) = series.strip_prefix("tvmaze:") {
+        (Provider::Tvmaze, id)
+    } else if let Some(id) = series.strip_prefix("tvdb:") {
+        (Provider::Tvdb, id)
+    } else {
+        (default.clone(), series)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_group() {
+        let map = SeriesMap::new(
+            &["x[ .-]?files=77398".to_string()],
+            vec![
+                SeriesMapping {
+                    pattern: "/Millennium/".to_string(),
+                    series: "71166".to_string(),
+                },
+                SeriesMapping {
+                    pattern: "files".to_string(),
+                    series: "never-used".to_string(),
+                },
+            ],
+        )
+        .unwrap();
+
+        let files = vec![
+            PathBuf::from("/downloads/The.X-Files.S01E01.mkv"),
+            PathBuf::from("/downloads/Millennium/Pilot.mkv"),
+            PathBuf::from("/downloads/x files 1x02.mkv"),
+            PathBuf::from("/downloads/Unknown.mkv"),
+        ];
+        let (groups, unmapped) = map.group(files);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].0, "77398");
+        assert_eq!(groups[0].1.len(), 2);
+        assert_eq!(groups[1].0, "71166");
+        assert_eq!(unmapped, vec![PathBuf::from("/downloads/Unknown.mkv")]);
+
+        assert!(SeriesMap::new(&["no-separator".to_string()], Vec::new()).is_err());
+        assert!(SeriesMap::default().is_empty());
+    }
+
+    #[test]
+    fn test_mapped_provider() {
+        assert_eq!(
+            mapped_provider("tvmaze:83", &Provider::Tvdb),
+            (Provider::Tvmaze, "83")
+        );
+        assert_eq!(
+            mapped_provider("tvdb:77398", &Provider::Tvmaze),
+            (Provider::Tvdb, "77398")
+        );
+        assert_eq!(
+            mapped_provider("77398", &Provider::Tvdb),
+            (Provider::Tvdb, "77398")
+        );
+        assert_eq!(
+            mapped_provider("83", &Provider::Tvmaze),
+            (Provider::Tvmaze, "83")
+        );
+    }
+}
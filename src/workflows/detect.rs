@@ -0,0 +1,75 @@
+use regex::Regex;
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::media::ffmpeg;
+
+/// The show named in the metadata of the file, to search at the provider.
+pub fn detect_show(file_path: &Path) -> Option<String> {
+    match ffmpeg::get_format_tags(file_path) {
+        Ok(tags) => show_from_tags(&tags),
+        Err(e) => {
+            eprintln!("Failed to read the metadata of {file_path:?}: {e}");
+            None
+        }
+    }
+}
+
+/// A show tag (set by MP4 and some taggers) is taken as is. A title is cut before the
+/// episode number, or before the episode title, e.g. `The X-Files - S01E03 - Squeeze`.
+fn show_from_tags(tags: &HashMap<String, String>) -> Option<String> {
+    if let Some(show) = ["show", "series"].iter().find_map(|key| tags.get(*key)) {
+        return clean(show);
+    }
+    let title = tags.get("title")?;
+    let numbering = Regex::new(r"(?i)\bS\d{1,2}\s?E\d{1,3}|\b\d{1,2}x\d{2,3}\b").unwrap();
+    let show = match numbering.find(title) {
+        Some(found) => &title[..found.start()],
+        None => title.split(" - ").next().unwrap_or_default(),
+    };
+    // Scene names separate words with dots instead of spaces
+    if show.contains(' ') {
+        clean(show)
+    } else {
+        clean(&show.replace(['.', '_'], " "))
+    }
+}
+
+fn clean(name: &str) -> Option<String> {
+    let name = name
+        .trim_matches(|c: char| c.is_whitespace() || "-:|".contains(c))
+        .to_string();
+    (!name.is_empty()).then_some(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tags(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_show_from_tags() {
+        let title = |title| show_from_tags(&tags(&[("title", title)]));
+        assert_eq!(
+            title("The X-Files - S01E03 - Squeeze").as_deref(),
+            Some("The X-Files")
+        );
+        assert_eq!(
+            title("The.X-Files.S01E03.Squeeze").as_deref(),
+            Some("The X-Files")
+        );
+        assert_eq!(title("Millennium 1x04").as_deref(), Some("Millennium"));
+        assert_eq!(title("Millennium - Gehenna").as_deref(), Some("Millennium"));
+        assert_eq!(title("S01E03 - Squeeze"), None);
+
+        let show = tags(&[("show", "The X-Files"), ("title", "Squeeze")]);
+        assert_eq!(show_from_tags(&show).as_deref(), Some("The X-Files"));
+        assert_eq!(show_from_tags(&tags(&[("encoder", "libebml")])), None);
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::DuplicatePolicy;
+use crate::domain::models::{EpisodeEntry, JournalEntry};
+use crate::workflows::prompt;
+
+/// A file that was matched to an episode during the run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Claim {
+    pub original: PathBuf,            // where the file was when the run found it
+    pub rename: Option<JournalEntry>, // how the run renamed it, or that it kept its name
+}
+
+impl Claim {
+    /// Where the file is now.
+    pub fn current(&self) -> &Path {
+        self.rename
+            .as_ref()
+            .map_or(&self.original, |rename| &rename.new_path)
+    }
+}
+
+/// The episodes matched during a run, to notice two files of the same episode.
+#[derive(Debug, Default)]
+pub struct MatchedEpisodes {
+    claims: HashMap<(String, u64, u64), Claim>, // series_id, season and episode -> file
+}
+
+impl MatchedEpisodes {
+    /// The file other than `file_path` that one of the episodes was matched to.
+    pub fn find(
+        &self,
+        series_id: &str,
+        episodes: &[EpisodeEntry],
+        file_path: &Path,
+    ) -> Option<&Claim> {
+        episodes.iter().find_map(|episode| {
+            self.claims
+                .get(&key(series_id, episode))
+                .filter(|claim| claim.original != file_path)
+        })
+    }
+
+    pub fn claim(&mut self, series_id: &str, episodes: &[EpisodeEntry], claim: Claim) {
+        for episode in episodes {
+            self.claims.insert(key(series_id, episode), claim.clone());
+        }
+    }
+
+    /// The season and episode numbers matched for the series.
+    pub fn episodes(&self, series_id: &str) -> Vec<(u64, u64)> {
+        self.claims
+            .keys()
+            .filter(|(id, _, _)| id == series_id)
+            .map(|&(_, season, episode)| (season, episode))
+            .collect()
+    }
+
+    /// Forgets the episodes of a file that gave them up to another one.
+    pub fn release(&mut self, original: &Path) {
+        self.claims.retain(|_, claim| claim.original != original);
+    }
+}
+
+fn key(series_id: &str, episode: &EpisodeEntry) -> (String, u64, u64) {
+    (
+        series_id.to_string(),
+        episode.season_number,
+        episode.episode_number,
+    )
+}
+
+/// Which of two files of the same episode get its name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    /// Both, the second one with a `[copy N]` suffix
+    Both,
+    /// The file being processed, the other one goes back to its original name (or gets a
+    /// `[copy N]` one if it already had the episode's)
+    This,
+    /// The other file, the one being processed is left alone
+    Other,
+}
+
+/// Decides between `file_path` and `other`, which was matched to the same episode earlier
+/// in the run.
+pub fn decide(policy: DuplicatePolicy, file_path: &Path, other: &Path) -> Result<Keep> {
+    Ok(match policy {
+        DuplicatePolicy::KeepBoth => Keep::Both,
+        DuplicatePolicy::KeepLarger => larger(file_path, other)?,
+        DuplicatePolicy::Ask => {
+            let choices = [
+                "Keep both, the second one as a copy".to_string(),
+                format!("Keep {file_path:?}, the other one gives up the name"),
+                format!("Keep {other:?}, leave this one alone"),
+            ];
+            match prompt::choose("Which file should get the episode's name?", &choices) {
+                Some(0) => Keep::Both,
+                Some(1) => Keep::This,
+                _ => Keep::Other,
+            }
+        }
+    })
+}
+
+/// The larger file is kept, usually the better rip. The earlier one wins a tie.
+fn larger(file_path: &Path, other: &Path) -> Result<Keep> {
+    let size = fs::metadata(file_path)?.len();
+    let other_size = fs::metadata(other)?.len();
+    Ok(if size > other_size {
+        Keep::This
+    } else {
+        Keep::Other
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(episode_number: u64) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number,
+            name: format!("Episode {episode_number}"),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_matched_episodes() {
+        let mut matched = MatchedEpisodes::default();
+        let first = Path::new("/tv/a.mkv");
+        let claim = Claim {
+            original: first.to_path_buf(),
+            rename: None,
+        };
+        // A two-parter claims both episodes
+        matched.claim("1", &[episode(1), episode(2)], claim.clone());
+
+        let second = Path::new("/tv/b.mkv");
+        assert_eq!(matched.find("1", &[episode(2)], second), Some(&claim));
+        assert_eq!(matched.find("1", &[episode(3)], second), None);
+        assert_eq!(matched.find("2", &[episode(1)], second), None);
+        // Matching the same file again isn't a duplicate
+        assert_eq!(matched.find("1", &[episode(1)], first), None);
+
+        matched.release(first);
+        assert_eq!(matched.find("1", &[episode(1)], second), None);
+    }
+
+    #[test]
+    fn test_larger() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let small = dir.path().join("small.mkv");
+        let large = dir.path().join("large.mkv");
+        fs::write(&small, [0; 10]).unwrap();
+        fs::write(&large, [0; 20]).unwrap();
+
+        assert_eq!(larger(&large, &small).unwrap(), Keep::This);
+        assert_eq!(larger(&small, &large).unwrap(), Keep::Other);
+        assert_eq!(larger(&small, &small).unwrap(), Keep::Other);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::{BTreeMap, HashSet};
+
+use crate::domain::models::EpisodeEntry;
+use crate::workflows::verify;
+
+/// How complete one season is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SeasonGaps {
+    pub season: u64,
+    pub aired: usize,      // episodes aired so far
+    pub held: usize,       // aired episodes there's a file of
+    pub missing: Vec<u64>, // aired episodes there's no file of
+}
+
+/// The episodes missing from the seasons there are files of, for `--gaps` and `gaps`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GapReport {
+    pub seasons: Vec<SeasonGaps>,
+    pub absent: Vec<u64>, // seasons with aired episodes but no file at all
+}
+
+impl GapReport {
+    /// Compares the episodes aired by `today` (`YYYY-MM-DD`) with the ones `held` (season and
+    /// episode numbers). Episodes without an air date haven't aired as far as is known, and
+    /// specials are left out, few libraries hold all of them.
+    pub fn new(episodes: &[&EpisodeEntry], held: &HashSet<(u64, u64)>, today: &str) -> Self {
+        let mut aired: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+        for episode in episodes {
+            let has_aired = episode.aired.as_deref().is_some_and(|aired| aired <= today);
+            if episode.season_number != 0 && has_aired {
+                aired
+                    .entry(episode.season_number)
+                    .or_default()
+                    .push(episode.episode_number);
+            }
+        }
+
+        let mut report = Self::default();
+        for (season, mut numbers) in aired {
+            numbers.sort_unstable();
+            numbers.dedup();
+            let (held_numbers, missing): (Vec<u64>, Vec<u64>) = numbers
+                .iter()
+                .partition(|&&number| held.contains(&(season, number)));
+            if held_numbers.is_empty() {
+                report.absent.push(season);
+            } else {
+                report.seasons.push(SeasonGaps {
+                    season,
+                    aired: numbers.len(),
+                    held: held_numbers.len(),
+                    missing,
+                });
+            }
+        }
+        report
+    }
+
+    /// Whether no season there are files of misses an episode.
+    pub fn is_complete(&self) -> bool {
+        self.seasons.iter().all(|season| season.missing.is_empty())
+    }
+
+    /// One line per season, like `Season 01: 22 of 24 aired episodes, missing S01E03, S01E07`.
+    pub fn format(&self, show_name: &str) -> String {
+        if self.seasons.is_empty() {
+            return format!("No files of any aired season of {show_name}\n");
+        }
+        let mut out = format!("Episode gaps of {show_name}:\n");
+        for season in &self.seasons {
+            out += &format!("  Season {:02}: ", season.season);
+            if season.missing.is_empty() {
+                out += &format!("complete ({} episodes)\n", season.aired);
+            } else {
+                let missing: Vec<String> = season
+                    .missing
+                    .iter()
+                    .map(|&number| verify::format_episodes(season.season, &[number]))
+                    .collect();
+                out += &format!(
+                    "{} of {} aired episodes, missing {}\n",
+                    season.held,
+                    season.aired,
+                    missing.join(", ")
+                );
+            }
+        }
+        if !self.absent.is_empty() {
+            let absent: Vec<String> = self.absent.iter().map(u64::to_string).collect();
+            out += &format!("  No files of season(s) {}\n", absent.join(", "));
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(season: u64, number: u64, aired: Option<&str>) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: season,
+            episode_number: number,
+            name: format!("Episode {number}"),
+            overview: None,
+            aired: aired.map(String::from),
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_gap_report() {
+        let episodes = [
+            episode(0, 1, Some("2020-01-01")),
+            episode(1, 1, Some("2020-01-01")),
+            episode(1, 2, Some("2020-01-08")),
+            episode(1, 3, Some("2020-01-15")),
+            episode(1, 4, Some("2030-01-01")),
+            episode(1, 5, None),
+            episode(2, 1, Some("2021-01-01")),
+            episode(3, 1, Some("2022-01-01")),
+            episode(3, 2, Some("2022-01-08")),
+        ];
+        let episodes: Vec<&EpisodeEntry> = episodes.iter().collect();
+        let held = HashSet::from([(1, 1), (1, 4), (3, 1), (3, 2)]);
+        let report = GapReport::new(&episodes, &held, "2026-10-16");
+        assert_eq!(
+            report.seasons,
+            vec![
+                SeasonGaps {
+                    season: 1,
+                    aired: 3,
+                    held: 1,
+                    missing: vec![2, 3]
+                },
+                SeasonGaps {
+                    season: 3,
+                    aired: 2,
+                    held: 2,
+                    missing: vec![]
+                },
+            ]
+        );
+        assert_eq!(report.absent, vec![2]);
+        assert!(!report.is_complete());
+        assert_eq!(
+            report.format("Show"),
+            "Episode gaps of Show:\n  \
+             Season 01: 1 of 3 aired episodes, missing S01E02, S01E03\n  \
+             Season 03: complete (2 episodes)\n  \
+             No files of season(s) 2\n"
+        );
+    }
+}
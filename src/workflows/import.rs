@@ -0,0 +1,228 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::domain::models::{unix_now, EpisodeEntry, JournalEntry, ProcessedFile};
+use crate::infra::cache::Cache;
+use crate::infra::journal;
+use crate::workflows::matchers::filename::parse_episode_numbers;
+
+#[derive(Debug, PartialEq)]
+struct HistoryRename {
+    from: PathBuf,
+    to: PathBuf,
+}
+
+/// Imports the renames in a FileBot `history.xml` that belong to the series.
+/// Returns the number of imported renames.
+pub fn import_filebot_history(
+    history_path: &Path,
+    series_id: &str,
+    cache: &mut Cache,
+) -> Result<usize> {
+    let content = fs::read_to_string(history_path)
+        .with_context(|| format!("Failed to read FileBot history {history_path:?}"))?;
+
+    let run_id = journal::new_run_id();
+    let mut entries = Vec::new();
+    for rename in parse_filebot_history(&content) {
+        let Some(episode) = episode_for_filename(&rename.to, series_id, cache) else {
+            continue;
+        };
+
+        // The file may have been moved or deleted since FileBot renamed it
+        if let Ok(processed) = ProcessedFile::from_path(&rename.to, series_id, episode.clone()) {
+            cache.set_processed_file(&rename.to, processed);
+        }
+
+        entries.push(JournalEntry {
+            timestamp: unix_now(),
+            run_id,
+            old_path: rename.from,
+            new_path: rename.to,
+            series_id: series_id.to_string(),
+            season_number: episode.season_number,
+            episode_number: episode.episode_number,
+            last_episode_number: None,
+            kept_original: false,
+            checksum: None,
+            imported: true,
+            created: false,
+        });
+    }
+
+    journal::append(&entries)?;
+    Ok(entries.len())
+}
+
+/// Imports the episode from the tinyMediaManager/Kodi `<stem>.nfo` next to a video file.
+/// Returns whether an NFO was found and matched a cached episode.
+pub fn import_nfo(video_path: &Path, series_id: &str, cache: &mut Cache) -> Result<bool> {
+    let nfo_path = video_path.with_extension("nfo");
+    let Ok(content) = fs::read_to_string(&nfo_path) else {
+        return Ok(false);
+    };
+
+    let Some((season, episode)) = parse_episode_nfo(&content) else {
+        eprintln!("Warning: No season/episode found in {nfo_path:?}");
+        return Ok(false);
+    };
+
+    let Some(episode) = cache
+        .get_episode_by_sxxexx(series_id, season, episode)
+        .cloned()
+    else {
+        eprintln!("Warning: S{season}E{episode} from {nfo_path:?} is not in the cache for series {series_id}");
+        return Ok(false);
+    };
+
+    let processed = ProcessedFile::from_path(video_path, series_id, episode)?;
+    cache.set_processed_file(video_path, processed);
+    Ok(true)
+}
+
+fn parse_filebot_history(content: &str) -> Vec<HistoryRename> {
+    let rename_re = Regex::new(r"<rename\s[^>]*>").unwrap();
+
+    rename_re
+        .find_iter(content)
+        .filter_map(|element| {
+            let element = element.as_str();
+            let dir = PathBuf::from(xml_attribute(element, "dir")?);
+            let from = xml_attribute(element, "from")?;
+            let to = xml_attribute(element, "to")?;
+            // `to` is relative to `dir` unless FileBot moved the file elsewhere
+            Some(HistoryRename {
+                from: dir.join(from),
+                to: dir.join(to),
+            })
+        })
+        .collect()
+}
+
+fn parse_episode_nfo(content: &str) -> Option<(u64, u64)> {
+    let season_re = Regex::new(r"<season>\s*(\d+)\s*</season>").unwrap();
+    let episode_re = Regex::new(r"<episode>\s*(\d+)\s*</episode>").unwrap();
+
+    let season = season_re.captures(content)?.get(1)?.as_str().parse().ok()?;
+    let episode = episode_re
+        .captures(content)?
+        .get(1)?
+        .as_str()
+        .parse()
+        .ok()?;
+    Some((season, episode))
+}
+
+fn xml_attribute(element: &str, name: &str) -> Option<String> {
+    let re = Regex::new(&format!(r#"\s{name}="([^"]*)""#)).ok()?;
+    let value = re.captures(element)?.get(1)?.as_str();
+    Some(
+        value
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&amp;", "&"),
+    )
+}
+
+/// Finds the cached episode a renamed file refers to. The episode title must appear in
+/// the filename, since a FileBot history usually covers many different shows.
+fn episode_for_filename(path: &Path, series_id: &str, cache: &Cache) -> Option<EpisodeEntry> {
+    let filename = path.file_stem()?.to_str()?;
+    let (season, episode) = parse_episode_numbers(filename)?;
+    let entry = cache.get_episode_by_sxxexx(series_id, season, episode)?;
+
+    if normalize(filename).contains(&normalize(&entry.name)) {
+        Some(entry.clone())
+    } else {
+        None
+    }
+}
+
+fn normalize(text: &str) -> String {
+    text.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_filebot_history() {
+        let content = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<history>
+    <sequence date="2019-05-04T12:00:00Z">
+        <rename dir="/media/tv" from="title_t00.mkv" to="The X-Files - S01E01 - Pilot.mkv"/>
+        <rename dir="/media/tv" from="title_t01.mkv" to="/library/The X-Files - 1x02 - Deep Throat.mkv"/>
+        <rename dir="/media/tv" from="Tom &amp; Jerry.mkv"/>
+    </sequence>
+</history>"#;
+
+        let renames = parse_filebot_history(content);
+        assert_eq!(
+            renames,
+            vec![
+                HistoryRename {
+                    from: PathBuf::from("/media/tv/title_t00.mkv"),
+                    to: PathBuf::from("/media/tv/The X-Files - S01E01 - Pilot.mkv"),
+                },
+                HistoryRename {
+                    from: PathBuf::from("/media/tv/title_t01.mkv"),
+                    to: PathBuf::from("/library/The X-Files - 1x02 - Deep Throat.mkv"),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_episode_nfo() {
+        let content = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes" ?>
+<episodedetails>
+  <title>Squeeze</title>
+  <season>1</season>
+  <episode> 3 </episode>
+</episodedetails>"#;
+        assert_eq!(parse_episode_nfo(content), Some((1, 3)));
+        assert_eq!(parse_episode_nfo("<episodedetails/>"), None);
+    }
+
+    #[test]
+    fn test_episode_for_filename() {
+        let mut cache = Cache::default();
+        cache.set_episode(
+            "77398",
+            &EpisodeEntry {
+                production_code: Some("1X79".to_string()),
+                season_number: 1,
+                episode_number: 1,
+                name: "Pilot".to_string(),
+                overview: None,
+                aired: None,
+                runtime: None,
+                id: None,
+                absolute_number: None,
+            },
+        );
+
+        let matched = episode_for_filename(
+            Path::new("/tv/The X-Files - S01E01 - Pilot.mkv"),
+            "77398",
+            &cache,
+        );
+        assert_eq!(matched.unwrap().name, "Pilot");
+
+        // Same numbering, different show
+        assert!(episode_for_filename(
+            Path::new("/tv/Millennium - S01E01 - Gehenna.mkv"),
+            "77398",
+            &cache
+        )
+        .is_none());
+    }
+}
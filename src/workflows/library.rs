@@ -0,0 +1,245 @@
+use anyhow::Result;
+use clap::ValueEnum;
+use std::cmp::Ordering;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::domain::models::EpisodeEntry;
+use crate::infra::cache::Cache;
+use crate::media::ffmpeg::{self, VideoQuality};
+use crate::workflows::{prompt, renamer, verify};
+
+/// What to do when the library already holds a file of an episode that a file of the run
+/// is organized into it as, also `--library-duplicates`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LibraryDuplicatePolicy {
+    /// Replace the library's copy if the new file has a higher resolution or bit rate,
+    /// otherwise leave the new file alone
+    KeepBetter,
+    /// Always replace the library's copy
+    Replace,
+    /// Never add a second copy
+    Skip,
+    /// Ask, showing the quality of both copies
+    Ask,
+}
+
+/// What happens to a file whose episode the library already holds.
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    /// Add it next to the library's copies
+    Add,
+    /// Add it and remove these copies
+    Replace(Vec<PathBuf>),
+    /// Leave it alone
+    Skip,
+}
+
+/// The video files in a season directory of the library holding one of the episodes,
+/// other than `file_path`. A file is known by the episodes an earlier run matched it to,
+/// compared by their provider ID, or else by the episode numbers in its name.
+pub fn episode_files(
+    season_dir: &Path,
+    series_id: &str,
+    episodes: &[EpisodeEntry],
+    file_path: &Path,
+    cache: &Cache,
+) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(season_dir) else {
+        return Vec::new(); // a season new to the library
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .filter(|path| path.extension().and_then(|s| s.to_str()) == Some("mkv"))
+        .filter(|path| !renamer::is_same_file(file_path, path))
+        .filter(|path| {
+            let matched = cache
+                .get_processed_file(path)
+                .filter(|processed| processed.series_id == series_id && processed.is_current(path));
+            match matched {
+                Some(processed) => processed
+                    .episodes()
+                    .iter()
+                    .any(|held| episodes.iter().any(|episode| same_episode(held, episode))),
+                None => holds_named(path, episodes),
+            }
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+fn same_episode(a: &EpisodeEntry, b: &EpisodeEntry) -> bool {
+    match (&a.id, &b.id) {
+        (Some(a), Some(b)) => a == b,
+        _ => (a.season_number, a.episode_number) == (b.season_number, b.episode_number),
+    }
+}
+
+/// Whether the `S01E03` in the file's name is one of the episodes.
+fn holds_named(path: &Path, episodes: &[EpisodeEntry]) -> bool {
+    let Some((season, numbers)) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(verify::named_episodes)
+    else {
+        return false;
+    };
+    episodes
+        .iter()
+        .any(|episode| episode.season_number == season && numbers.contains(&episode.episode_number))
+}
+
+/// Decides between `file_path` and the library's copies of its episodes. A file whose
+/// content is the same as a copy's is left alone whatever the policy.
+pub fn decide(
+    policy: LibraryDuplicatePolicy,
+    file_path: &Path,
+    existing: &[PathBuf],
+) -> Result<Decision> {
+    for other in existing {
+        if is_identical(file_path, other)? {
+            println!("The library already holds an identical copy at {other:?}");
+            return Ok(Decision::Skip);
+        }
+    }
+    Ok(match policy {
+        LibraryDuplicatePolicy::Replace => Decision::Replace(existing.to_vec()),
+        LibraryDuplicatePolicy::Skip => Decision::Skip,
+        LibraryDuplicatePolicy::KeepBetter => {
+            let quality = video_quality(file_path);
+            let better = existing.iter().all(|other| {
+                match (&quality, video_quality(other)) {
+                    (Some(quality), Some(other)) => quality.compare(&other) == Ordering::Greater,
+                    _ => false, // the library's copy stays unless it's known to be worse
+                }
+            });
+            if better {
+                Decision::Replace(existing.to_vec())
+            } else {
+                println!("The library's copy is as good or better");
+                Decision::Skip
+            }
+        }
+        LibraryDuplicatePolicy::Ask => {
+            for other in existing {
+                println!("  In the library: {other:?} ({})", describe_quality(other));
+            }
+            println!("  New: {file_path:?} ({})", describe_quality(file_path));
+            let choices = [
+                "Replace the library's copy".to_string(),
+                "Leave the new file alone".to_string(),
+                "Keep both".to_string(),
+            ];
+            match prompt::choose("The library already holds this episode.", &choices) {
+                Some(0) => Decision::Replace(existing.to_vec()),
+                Some(2) => Decision::Add,
+                _ => Decision::Skip,
+            }
+        }
+    })
+}
+
+/// Whether the files have the same content, compared by checksum if their sizes match.
+fn is_identical(path: &Path, other: &Path) -> Result<bool> {
+    if fs::metadata(path)?.len() != fs::metadata(other)?.len() {
+        return Ok(false);
+    }
+    Ok(renamer::checksum(path)? == renamer::checksum(other)?)
+}
+
+fn video_quality(path: &Path) -> Option<VideoQuality> {
+    ffmpeg::get_video_quality(path)
+        .inspect_err(|e| eprintln!("Warning: Failed to read the quality of {path:?}: {e}"))
+        .ok()
+}
+
+fn describe_quality(path: &Path) -> String {
+    video_quality(path).map_or_else(|| "unknown quality".to_string(), |q| q.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::ProcessedFile;
+
+    fn episode(episode_number: u64, id: Option<u64>) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number,
+            name: format!("Episode {episode_number}"),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_episode_files() {
+        let library = tempfile::tempdir().unwrap();
+        let season_dir = library.path();
+        for name in [
+            "Show - S01E03 - Squeeze.mkv",
+            "Show - S01E04-E05 - Two Parts.mkv",
+            "Show - S01E03 - Squeeze.nfo",
+            "renamed by hand.mkv",
+        ] {
+            fs::write(season_dir.join(name), name).unwrap();
+        }
+        let mut cache = Cache::default();
+        let by_hand = season_dir.join("renamed by hand.mkv");
+        let processed = ProcessedFile::from_path(&by_hand, "1", episode(7, Some(77))).unwrap();
+        cache.set_processed_file(&by_hand, processed);
+
+        let new = Path::new("/downloads/title_t00.mkv");
+        let found = |episodes: &[EpisodeEntry]| -> Vec<String> {
+            episode_files(season_dir, "1", episodes, new, &cache)
+                .iter()
+                .map(|path| path.file_name().unwrap().to_string_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(found(&[episode(3, None)]), ["Show - S01E03 - Squeeze.mkv"]);
+        assert_eq!(
+            found(&[episode(5, None)]),
+            ["Show - S01E04-E05 - Two Parts.mkv"]
+        );
+        assert_eq!(found(&[episode(7, Some(77))]), ["renamed by hand.mkv"]);
+        assert!(found(&[episode(7, Some(78))]).is_empty());
+        assert!(found(&[episode(6, None)]).is_empty());
+
+        // The file being organized isn't a copy of itself
+        let own = season_dir.join("Show - S01E03 - Squeeze.mkv");
+        assert!(episode_files(season_dir, "1", &[episode(3, None)], &own, &cache).is_empty());
+        assert!(episode_files(&season_dir.join("Season 02"), "1", &[], new, &cache).is_empty());
+    }
+
+    #[test]
+    fn test_decide_identical() {
+        let dir = tempfile::tempdir().unwrap();
+        let (new, copy, other) = (
+            dir.path().join("new.mkv"),
+            dir.path().join("copy.mkv"),
+            dir.path().join("other.mkv"),
+        );
+        fs::write(&new, "episode").unwrap();
+        fs::write(&copy, "episode").unwrap();
+        fs::write(&other, "episodf").unwrap();
+
+        let replace = LibraryDuplicatePolicy::Replace;
+        assert_eq!(
+            decide(replace, &new, &[other.clone(), copy]).unwrap(),
+            Decision::Skip
+        );
+        assert_eq!(
+            decide(replace, &new, std::slice::from_ref(&other)).unwrap(),
+            Decision::Replace(vec![other.clone()])
+        );
+        assert_eq!(
+            decide(LibraryDuplicatePolicy::Skip, &new, &[other]).unwrap(),
+            Decision::Skip
+        );
+    }
+}
@@ -0,0 +1,125 @@
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Headers a spreadsheet export may start with
+const HEADERS: &[&str] = &["file", "filename", "path"];
+
+/// The episodes of files given with `--mapping-file`, which are named without matching.
+/// Each file is listed with its production code or `SXXEXX`, e.g. to correct the misses of
+/// a dry run in a spreadsheet.
+#[derive(Debug, Clone, Default)]
+pub struct FileMapping {
+    entries: HashMap<String, String>, // file name or path -> production code or SXXEXX
+}
+
+impl FileMapping {
+    /// Reads a JSON object of files and episodes if the file ends in `.json`, otherwise
+    /// `filename,SXXEXX` rows.
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read the mapping file {path:?}"))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let entries = if is_json {
+            serde_json::from_str(&content)
+                .with_context(|| format!("Invalid mapping file {path:?}"))?
+        } else {
+            parse_csv(&content).with_context(|| format!("Invalid mapping file {path:?}"))?
+        };
+        Ok(Self { entries })
+    }
+
+    /// The production code or SXXEXX of the file, listed by its path or its name.
+    pub fn get(&self, file_path: &Path) -> Option<&str> {
+        let by_path = self.entries.iter().find_map(|(listed, episode)| {
+            let listed = Path::new(listed);
+            (listed.components().count() > 1 && file_path.ends_with(listed))
+                .then_some(episode.as_str())
+        });
+        let name = file_path.file_name()?.to_str()?;
+        by_path.or_else(|| self.entries.get(name).map(String::as_str))
+    }
+
+    pub fn contains(&self, file_path: &Path) -> bool {
+        self.get(file_path).is_some()
+    }
+}
+
+/// Rows of `filename,episode`. File names may contain commas, so the episode is what
+/// follows the last one, and may be quoted like spreadsheets export them.
+fn parse_csv(content: &str) -> Result<HashMap<String, String>> {
+    let mut entries = HashMap::new();
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some((file, episode)) = line.rsplit_once(',') else {
+            bail!("Line {} isn't `filename,SXXEXX`: {line}", index + 1);
+        };
+        let (file, episode) = (unquote(file), unquote(episode));
+        if index == 0 && HEADERS.contains(&file.to_lowercase().as_str()) {
+            continue;
+        }
+        if file.is_empty() || episode.is_empty() {
+            bail!("Line {} isn't `filename,SXXEXX`: {line}", index + 1);
+        }
+        entries.insert(file, episode);
+    }
+    Ok(entries)
+}
+
+fn unquote(field: &str) -> String {
+    let field = field.trim();
+    match field.strip_prefix('"').and_then(|f| f.strip_suffix('"')) {
+        Some(quoted) => quoted.replace("\"\"", "\""),
+        None => field.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv() {
+        let entries = parse_csv(
+            "filename,episode\n\
+             title_t00.mkv,S01E03\n\
+             \"Pilot, Part 1.mkv\",1X79\n\
+             \n\
+             # checked by hand\n\
+             disc2/title_t01.mkv , s01e04\n",
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries["title_t00.mkv"], "S01E03");
+        assert_eq!(entries["Pilot, Part 1.mkv"], "1X79");
+        assert_eq!(entries["disc2/title_t01.mkv"], "s01e04");
+
+        assert!(parse_csv("title_t00.mkv").is_err());
+        assert!(parse_csv("title_t00.mkv,").is_err());
+    }
+
+    #[test]
+    fn test_file_mapping_get() {
+        let mapping = FileMapping {
+            entries: HashMap::from([
+                ("title_t00.mkv".to_string(), "S01E03".to_string()),
+                ("disc2/title_t00.mkv".to_string(), "S01E09".to_string()),
+            ]),
+        };
+        assert_eq!(
+            mapping.get(Path::new("/rips/disc1/title_t00.mkv")),
+            Some("S01E03")
+        );
+        assert_eq!(
+            mapping.get(Path::new("/rips/disc2/title_t00.mkv")),
+            Some("S01E09")
+        );
+        assert!(!mapping.contains(Path::new("/rips/disc1/title_t01.mkv")));
+    }
+}
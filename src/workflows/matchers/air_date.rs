@@ -0,0 +1,350 @@
+use anyhow::Result;
+use regex::Regex;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::filename::parse_air_date;
+use super::{Matcher, ScoredMatch, AMBIGUOUS_SCORE};
+use crate::domain::models::EpisodeEntry;
+use crate::infra::cache::Cache;
+use crate::media::{ffmpeg, ocr, subtitles};
+
+// Scores of an episode aired on a date found in the file. Tags are usually set from the
+// broadcast, but a file's creation time may be that of a later remux. On-screen text and
+// dialogue may mention other days, so a date read there is only accepted outright when it's
+// mentioned more often than any other.
+const TAG_DATE_SCORE: f32 = 0.9;
+const CREATION_TIME_SCORE: f32 = 0.6;
+const READ_DATE_SCORE: f32 = 0.7;
+const DOMINANT_DATE_SCORE: f32 = 0.85;
+
+// Tags holding the broadcast date, in the order they're trusted
+const DATE_TAGS: &[&str] = &["date_released", "date_recorded", "date"];
+
+// A numeric date with the year first, possibly an ISO 8601 time's, or last after the day
+// and month in either order
+const NUMERIC_DATE: &str = r"\b((?:19|20)\d{2})[-./](\d{1,2})[-./](\d{1,2})(?:\b|T)|\b(\d{1,2})[-./](\d{1,2})[-./]((?:19|20)\d{2})\b";
+// `May 4, 2019` or `4 May 2019`
+const NAMED_MONTH_DATE: &str = r"(?i)\b(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?\s+(\d{1,2})(?:st|nd|rd|th)?,?\s+((?:19|20)\d{2})\b|\b(\d{1,2})(?:st|nd|rd|th)?\s+(jan|feb|mar|apr|may|jun|jul|aug|sep|oct|nov|dec)[a-z]*\.?,?\s+((?:19|20)\d{2})\b";
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// Identifies episodes of daily and news shows by the day they aired, read from the file
+/// name, the MKV tags, a date shown on screen or the subtitles, cheapest first.
+pub struct AirDateMatcher {
+    pub ocr_options: ocr::FrameOcrOptions, // frames to look for an on-screen date on
+    pub ocr_pool: Arc<ocr::OcrEnginePool>,
+    pub language: String, // ISO 639-2 code of the subtitle track to read
+    pub track_preference: subtitles::TrackPreference,
+    pub windows: Vec<ffmpeg::FrameWindow>, // parts of the subtitles to read, all if empty
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DateSource {
+    Filename,
+    Tags,
+    Frames,
+    Subtitles,
+}
+
+impl DateSource {
+    const ALL: [DateSource; 4] = [Self::Filename, Self::Tags, Self::Frames, Self::Subtitles];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Filename => "file name",
+            Self::Tags => "MKV tags",
+            Self::Frames => "frames",
+            Self::Subtitles => "subtitles",
+        }
+    }
+}
+
+impl Matcher for AirDateMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        for source in DateSource::ALL {
+            let dates = match self.read_dates(source, file_path) {
+                Ok(dates) => dates,
+                Err(e) => {
+                    eprintln!(
+                        "Warning: Failed to read a date from the {}: {e:#}",
+                        source.label()
+                    );
+                    continue;
+                }
+            };
+            if dates.is_empty() {
+                continue;
+            }
+            let matches = aired_on(cache, series_id, &dates);
+            let days: Vec<&str> = dates.iter().map(|(date, _)| date.as_str()).collect();
+            if !matches.is_empty() {
+                println!(
+                    "Found air date {} in the {}",
+                    days.join(", "),
+                    source.label()
+                );
+                return Ok(matches);
+            }
+            println!(
+                "No episode aired on {} from the {}",
+                days.join(", "),
+                source.label()
+            );
+        }
+        println!("No air date of an episode found.");
+        Ok(Vec::new())
+    }
+
+    fn source(&self) -> &'static str {
+        "air date"
+    }
+}
+
+impl AirDateMatcher {
+    /// The days (YYYY-MM-DD) the source dates the file to, each with the score of an
+    /// episode aired then.
+    fn read_dates(&self, source: DateSource, file_path: &Path) -> Result<Vec<(String, f32)>> {
+        Ok(match source {
+            DateSource::Filename => file_path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(parse_air_date)
+                .map(|date| (date, 1.0))
+                .into_iter()
+                .collect(),
+            DateSource::Tags => {
+                let tags = ffmpeg::get_format_tags(file_path)?;
+                let tagged = DATE_TAGS.iter().find_map(|tag| {
+                    let date = dates_in(tags.get(*tag)?).into_iter().next()?;
+                    Some((date, TAG_DATE_SCORE))
+                });
+                let created = || {
+                    let date = dates_in(tags.get("creation_time")?).into_iter().next()?;
+                    Some((date, CREATION_TIME_SCORE))
+                };
+                tagged.or_else(created).into_iter().collect()
+            }
+            DateSource::Frames => {
+                // Dates are numbers and separators, words would only be misread as such
+                let mut options = self.ocr_options.clone();
+                options.code_regex = Some(Regex::new(NUMERIC_DATE)?);
+                options.tesseract.whitelist = Some("0123456789-./".to_string());
+                let path = file_path.to_str().unwrap_or_default();
+                let candidates =
+                    ocr::extract_production_code_candidates(path, &options, &self.ocr_pool)?;
+                let text: Vec<&str> = candidates.iter().map(|c| c.code.as_str()).collect();
+                read_dates(&text.join("\n"))
+            }
+            DateSource::Subtitles => {
+                let track = subtitles::find_best_subtitle_track(
+                    file_path,
+                    &self.language,
+                    &self.track_preference,
+                    false,
+                    &self.ocr_pool,
+                )?;
+                let temp_dir = tempfile::TempDir::new()?;
+                let subtitle_path = subtitles::extract_subtitles(
+                    file_path,
+                    track.index,
+                    &track.codec,
+                    temp_dir.path(),
+                )?;
+                let window = subtitles::SubtitleWindow::new(file_path, &self.windows)?;
+                let text =
+                    subtitles::read_text(&subtitle_path, &track.codec, &self.ocr_pool, &window)?;
+                read_dates(&text.join("\n"))
+            }
+        })
+    }
+}
+
+/// The episodes that aired on the dates. Those of a day with several episodes can't be
+/// told apart and score [`AMBIGUOUS_SCORE`] at most.
+fn aired_on(cache: &Cache, series_id: &str, dates: &[(String, f32)]) -> Vec<ScoredMatch> {
+    let mut matches: Vec<ScoredMatch> = Vec::new();
+    for (date, score) in dates {
+        let episodes: Vec<&EpisodeEntry> = cache.get_episodes_by_aired(series_id, date);
+        let score = if episodes.len() > 1 {
+            println!("{} episodes aired on {date}.", episodes.len());
+            score.min(AMBIGUOUS_SCORE)
+        } else {
+            *score
+        };
+        for episode in episodes {
+            let seen = matches.iter().any(|found| {
+                found.episodes.iter().any(|matched| {
+                    (matched.season_number, matched.episode_number)
+                        == (episode.season_number, episode.episode_number)
+                })
+            });
+            if !seen {
+                matches.push(ScoredMatch::new(vec![episode.clone()], score));
+            }
+        }
+    }
+    matches
+}
+
+/// The days of the dates read from on-screen text or dialogue, each with its score. Only
+/// a day mentioned several times, and more often than any other, scores above
+/// [`ACCEPT_SCORE`](super::ACCEPT_SCORE).
+fn read_dates(text: &str) -> Vec<(String, f32)> {
+    let counted = counted_dates(text);
+    let dominant = match counted.as_slice() {
+        [(_, first), (_, second), ..] => *first > 1 && first > second,
+        [(_, first)] => *first > 1,
+        [] => false,
+    };
+    counted
+        .into_iter()
+        .enumerate()
+        .map(|(index, (date, _))| {
+            let score = if index == 0 && dominant {
+                DOMINANT_DATE_SCORE
+            } else {
+                READ_DATE_SCORE
+            };
+            (date, score)
+        })
+        .collect()
+}
+
+/// The days the dates in the text may be, as YYYY-MM-DD, most often mentioned first. A
+/// numeric date with the day and month first may be either way round, so it's both
+/// unless one of them can't be a month.
+fn dates_in(text: &str) -> Vec<String> {
+    counted_dates(text)
+        .into_iter()
+        .map(|(date, _)| date)
+        .collect()
+}
+
+/// [`dates_in`] with the number of times each day is mentioned.
+fn counted_dates(text: &str) -> Vec<(String, usize)> {
+    let mut found: Vec<(u32, u32, u32)> = Vec::new();
+    for caps in Regex::new(NUMERIC_DATE).unwrap().captures_iter(text) {
+        let number = |index: usize| caps.get(index)?.as_str().parse::<u32>().ok();
+        if let (Some(year), Some(month), Some(day)) = (number(1), number(2), number(3)) {
+            found.push((year, month, day));
+        } else if let (Some(first), Some(second), Some(year)) = (number(4), number(5), number(6)) {
+            found.push((year, first, second)); // US style
+            found.push((year, second, first));
+        }
+    }
+    for caps in Regex::new(NAMED_MONTH_DATE).unwrap().captures_iter(text) {
+        let month = caps.get(1).or_else(|| caps.get(5)).and_then(|name| {
+            let name = name.as_str().to_lowercase();
+            MONTHS.iter().position(|month| *month == name)
+        });
+        let day = caps.get(2).or_else(|| caps.get(4));
+        let year = caps.get(3).or_else(|| caps.get(6));
+        if let (Some(month), Some(day), Some(year)) = (month, day, year) {
+            if let (Ok(day), Ok(year)) = (day.as_str().parse(), year.as_str().parse()) {
+                found.push((year, month as u32 + 1, day));
+            }
+        }
+    }
+
+    let mut counted: Vec<(String, usize)> = Vec::new();
+    for (year, month, day) in found {
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            continue;
+        }
+        let date = format!("{year:04}-{month:02}-{day:02}");
+        match counted.iter_mut().find(|(seen, _)| *seen == date) {
+            Some((_, count)) => *count += 1,
+            None => counted.push((date, 1)),
+        }
+    }
+    counted.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+    counted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dates_in() {
+        assert_eq!(dates_in("Recorded 2019-05-04T20:00:00Z"), ["2019-05-04"]);
+        assert_eq!(dates_in("05/04/2019"), ["2019-05-04", "2019-04-05"]);
+        assert_eq!(dates_in("25.12.2019"), ["2019-12-25"]);
+        assert_eq!(
+            dates_in("Good evening, it's Saturday, May 4th, 2019.\nOn 4 May 2019 we..."),
+            ["2019-05-04"]
+        );
+        // The most mentioned date comes first
+        assert_eq!(
+            dates_in("2019-05-03\n2019-05-04\n2019-05-04"),
+            ["2019-05-04", "2019-05-03"]
+        );
+        assert!(dates_in("2019-13-40 and 1080p").is_empty());
+    }
+
+    #[test]
+    fn test_read_dates() {
+        // A single mention could be any day the show talks about
+        assert_eq!(
+            read_dates("2019-05-04"),
+            [("2019-05-04".to_string(), READ_DATE_SCORE)]
+        );
+        const { assert!(READ_DATE_SCORE < super::super::ACCEPT_SCORE) };
+
+        // A day mentioned more often than any other is accepted
+        let dates = read_dates("2019-05-04\n2019-05-03\n2019-05-04");
+        assert_eq!(
+            dates,
+            [
+                ("2019-05-04".to_string(), DOMINANT_DATE_SCORE),
+                ("2019-05-03".to_string(), READ_DATE_SCORE)
+            ]
+        );
+        const { assert!(DOMINANT_DATE_SCORE >= super::super::ACCEPT_SCORE) };
+
+        // Not when another is as common, both readings of a numeric date included
+        let dates = read_dates("05/04/2019\n05/04/2019");
+        assert!(dates.iter().all(|(_, score)| *score == READ_DATE_SCORE));
+        assert!(read_dates("no date").is_empty());
+    }
+
+    #[test]
+    fn test_aired_on() {
+        let episode = |number: u64, aired: &str| EpisodeEntry {
+            production_code: None,
+            season_number: 2019,
+            episode_number: number,
+            name: format!("Episode {number}"),
+            overview: None,
+            aired: Some(aired.to_string()),
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        };
+        let mut cache = Cache::default();
+        cache.set_episode("1", &episode(85, "2019-05-03"));
+        cache.set_episode("1", &episode(86, "2019-05-04"));
+        cache.set_episode("1", &episode(87, "2019-05-04"));
+
+        let matches = aired_on(&cache, "1", &[("2019-05-03".to_string(), 0.9)]);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(
+            (matches[0].episodes[0].episode_number, matches[0].score),
+            (85, 0.9)
+        );
+
+        // A double bill
+        let matches = aired_on(&cache, "1", &[("2019-05-04".to_string(), 1.0)]);
+        assert_eq!(matches.len(), 2);
+        assert!(matches.iter().all(|found| found.score == AMBIGUOUS_SCORE));
+
+        assert!(aired_on(&cache, "1", &[("2019-05-05".to_string(), 1.0)]).is_empty());
+    }
+}
@@ -0,0 +1,151 @@
+use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::{Matcher, ScoredMatch};
+use crate::domain::models::{AudioFingerprint, EpisodeEntry};
+use crate::infra::cache::Cache;
+use crate::media::audio;
+
+// Minimum similarity of the best episode, and how far ahead of the runner-up it must be.
+// Theme music shared by every episode makes all of them score high, only the lead counts then.
+const MIN_SIMILARITY: f64 = 0.75;
+const MIN_LEAD: f64 = 0.05;
+
+// Past most cold opens' first seconds, and well into the episode
+pub const DEFAULT_OFFSETS: [u64; 2] = [60, 300];
+
+/// Experimental: identifies an episode by comparing audio fingerprints at fixed offsets
+/// with those of episodes identified earlier (see `--learn-audio`).
+pub struct AudioMatcher {
+    pub offsets: Vec<u64>, // seconds into the file
+}
+
+impl Matcher for AudioMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let known = cache.get_fingerprints(series_id);
+        if known.is_empty() {
+            println!("No audio fingerprints for this series yet, match some files with --learn-audio first.");
+            return Ok(Vec::new());
+        }
+
+        let mut samples = Vec::new();
+        for &offset in &self.offsets {
+            samples.push((offset, audio::fingerprint(file_path, offset)?));
+        }
+
+        let Some(((season, episode), score)) = best_fingerprint_match(&samples, known) else {
+            println!("No fingerprinted episode matches the audio confidently.");
+            return Ok(Vec::new());
+        };
+        println!("Audio matches S{season}E{episode} (similarity {score:.2})");
+        Ok(cache
+            .get_episode_by_sxxexx(series_id, season, episode)
+            .map(|episode| ScoredMatch::new(vec![episode.clone()], score as f32))
+            .into_iter()
+            .collect())
+    }
+
+    fn source(&self) -> &'static str {
+        "audio fingerprint"
+    }
+}
+
+/// Averages the similarity of the samples with every episode's fingerprints at the same
+/// offsets and returns the best episode if it clearly beats the rest.
+fn best_fingerprint_match(
+    samples: &[(u64, Vec<u32>)],
+    known: &[AudioFingerprint],
+) -> Option<((u64, u64), f64)> {
+    let mut scores: HashMap<(u64, u64), Vec<f64>> = HashMap::new();
+    for stored in known {
+        for (offset, sample) in samples {
+            if *offset == stored.offset {
+                scores
+                    .entry((stored.season_number, stored.episode_number))
+                    .or_default()
+                    .push(audio::similarity(sample, &stored.fingerprint));
+            }
+        }
+    }
+
+    let mut averages: Vec<((u64, u64), f64)> = scores
+        .into_iter()
+        .map(|(episode, scores)| (episode, scores.iter().sum::<f64>() / scores.len() as f64))
+        .collect();
+    averages.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let (best, best_score) = *averages.first()?;
+    let runner_up = averages.get(1).map_or(0.0, |(_, score)| *score);
+    (best_score >= MIN_SIMILARITY && best_score - runner_up >= MIN_LEAD)
+        .then_some((best, best_score))
+}
+
+/// Fingerprints an identified file so that the audio matcher can recognize other rips of
+/// the episode later.
+pub fn learn(
+    file_path: &Path,
+    series_id: &str,
+    episode: &EpisodeEntry,
+    offsets: &[u64],
+    cache: &mut Cache,
+) -> Result<()> {
+    for &offset in offsets {
+        let fingerprint = audio::fingerprint(file_path, offset)?;
+        cache.add_fingerprint(
+            series_id,
+            AudioFingerprint {
+                season_number: episode.season_number,
+                episode_number: episode.episode_number,
+                offset,
+                fingerprint,
+            },
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(seed: u32) -> Vec<u32> {
+        (0..160u32)
+            .map(|i| (i ^ seed).wrapping_mul(2_654_435_761).rotate_left(i % 32))
+            .collect()
+    }
+
+    fn stored(episode_number: u64, offset: u64, fingerprint: Vec<u32>) -> AudioFingerprint {
+        AudioFingerprint {
+            season_number: 1,
+            episode_number,
+            offset,
+            fingerprint,
+        }
+    }
+
+    #[test]
+    fn test_best_fingerprint_match() {
+        let known = vec![
+            stored(1, 60, items(1)),
+            stored(1, 300, items(2)),
+            stored(2, 60, items(3)),
+            stored(2, 300, items(4)),
+        ];
+
+        let samples = vec![(60, items(3)), (300, items(4))];
+        assert_eq!(
+            best_fingerprint_match(&samples, &known).map(|(episode, _)| episode),
+            Some((1, 2))
+        );
+
+        // Unknown audio, and samples at offsets nothing was fingerprinted at
+        assert!(best_fingerprint_match(&[(60, items(5))], &known).is_none());
+        assert!(best_fingerprint_match(&[(120, items(3))], &known).is_none());
+    }
+}
@@ -0,0 +1,181 @@
+use anyhow::Result;
+use std::cell::Cell;
+use std::path::Path;
+
+use super::{Matcher, ScoredMatch, ACCEPT_SCORE};
+use crate::domain::models::OcrCandidate;
+use crate::infra::cache::Cache;
+
+/// Runs matchers in order until one identifies the file confidently, e.g. cheap lookups
+/// before OCR. Without a confident match, the stage with the best one is used. A stage that
+/// fails is reported and skipped, the file only fails if every stage does.
+pub struct ChainMatcher {
+    pub stages: Vec<Box<dyn Matcher>>,
+    pub matched: Cell<Option<usize>>, // the stage whose matches were returned for the last file
+}
+
+impl ChainMatcher {
+    pub fn new(stages: Vec<Box<dyn Matcher>>) -> Self {
+        Self {
+            stages,
+            matched: Cell::new(None),
+        }
+    }
+}
+
+impl Matcher for ChainMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        self.matched.set(None);
+        let mut fallback: Option<(usize, Vec<ScoredMatch>)> = None;
+        let mut first_error = None;
+        let mut failures = 0;
+        for (index, stage) in self.stages.iter().enumerate() {
+            if index > 0 {
+                println!("Trying to match by {}...", stage.source());
+            }
+            let matches = match stage.match_episode(file_path, series_id, cache) {
+                Ok(matches) => matches,
+                Err(e) => {
+                    eprintln!("Warning: Matching by {} failed: {e:#}", stage.source());
+                    failures += 1;
+                    first_error.get_or_insert(e);
+                    continue;
+                }
+            };
+            let best = best_score(&matches);
+            if best >= ACCEPT_SCORE {
+                self.matched.set(Some(index));
+                return Ok(matches);
+            }
+            if !matches.is_empty()
+                && fallback
+                    .as_ref()
+                    .is_none_or(|(_, kept)| best > best_score(kept))
+            {
+                fallback = Some((index, matches));
+            }
+        }
+        if let Some((index, matches)) = fallback {
+            self.matched.set(Some(index));
+            return Ok(matches);
+        }
+        match first_error {
+            Some(e) if failures == self.stages.len() => Err(e),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// The stage whose matches were returned for the last file, or the last stage.
+    fn source(&self) -> &'static str {
+        let stage = self
+            .matched
+            .get()
+            .and_then(|index| self.stages.get(index))
+            .or(self.stages.last());
+        stage.map_or("no matcher", |stage| stage.source())
+    }
+
+    fn candidates(&self) -> Vec<OcrCandidate> {
+        self.stages
+            .iter()
+            .flat_map(|stage| stage.candidates())
+            .collect()
+    }
+
+    fn flagged(&self) -> Option<String> {
+        self.stages.iter().find_map(|stage| stage.flagged())
+    }
+}
+
+fn best_score(matches: &[ScoredMatch]) -> f32 {
+    matches.iter().map(|found| found.score).fold(0.0, f32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::EpisodeEntry;
+    use anyhow::bail;
+
+    enum Stub {
+        Finds(u64, f32),
+        Nothing,
+        Fails,
+    }
+
+    impl Matcher for Stub {
+        fn match_episode(&self, _: &Path, _: &str, _: &mut Cache) -> Result<Vec<ScoredMatch>> {
+            match self {
+                Stub::Finds(episode_number, score) => Ok(vec![ScoredMatch::new(
+                    vec![EpisodeEntry {
+                        production_code: None,
+                        season_number: 1,
+                        episode_number: *episode_number,
+                        name: String::new(),
+                        overview: None,
+                        aired: None,
+                        runtime: None,
+                        id: None,
+                        absolute_number: None,
+                    }],
+                    *score,
+                )]),
+                Stub::Nothing => Ok(Vec::new()),
+                Stub::Fails => bail!("no subtitles"),
+            }
+        }
+
+        fn source(&self) -> &'static str {
+            match self {
+                Stub::Finds(..) => "finds",
+                Stub::Nothing => "nothing",
+                Stub::Fails => "fails",
+            }
+        }
+    }
+
+    fn run(stages: Vec<Stub>) -> (Result<Vec<ScoredMatch>>, &'static str) {
+        let stages = stages
+            .into_iter()
+            .map(|stage| Box::new(stage) as Box<dyn Matcher>)
+            .collect();
+        let chain = ChainMatcher::new(stages);
+        let result = chain.match_episode(Path::new("a.mkv"), "1", &mut Cache::default());
+        (result, chain.source())
+    }
+
+    #[test]
+    fn test_chain_matcher() {
+        let (result, source) = run(vec![
+            Stub::Nothing,
+            Stub::Fails,
+            Stub::Finds(3, 1.0),
+            Stub::Finds(4, 1.0),
+        ]);
+        assert_eq!(result.unwrap()[0].episodes[0].episode_number, 3);
+        assert_eq!(source, "finds");
+
+        // Low scores don't stop the chain, but are used if nothing scores higher
+        let (result, _) = run(vec![Stub::Finds(3, 0.5), Stub::Finds(4, 0.9)]);
+        assert_eq!(result.unwrap()[0].episodes[0].episode_number, 4);
+        let (result, _) = run(vec![
+            Stub::Finds(3, 0.6),
+            Stub::Nothing,
+            Stub::Finds(4, 0.5),
+        ]);
+        assert_eq!(result.unwrap()[0].episodes[0].episode_number, 3);
+
+        let (result, source) = run(vec![Stub::Fails, Stub::Nothing]);
+        assert!(result.unwrap().is_empty());
+        assert_eq!(source, "nothing");
+
+        // Only fails if every stage does
+        let (result, _) = run(vec![Stub::Fails, Stub::Fails]);
+        assert!(result.is_err());
+    }
+}
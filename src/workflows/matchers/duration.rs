@@ -0,0 +1,193 @@
+use anyhow::Result;
+use std::path::Path;
+
+use super::range::EpisodeRange;
+use super::{Matcher, ScoredMatch};
+use crate::domain::models::EpisodeEntry;
+use crate::infra::cache::Cache;
+use crate::media::ffmpeg;
+
+// Listed runtimes are rounded and exclude or include recaps and credits inconsistently
+const MIN_TOLERANCE_MINUTES: f64 = 2.0;
+const TOLERANCE_FRACTION: f64 = 0.08;
+const RUNTIME_SCORE: f32 = 0.9;
+
+/// Identifies an episode by its runtime, which only works when it is unique within the
+/// series (or within the `range`), e.g. for specials and double-length episodes.
+pub struct DurationMatcher {
+    pub range: EpisodeRange, // episodes outside it aren't considered
+}
+
+impl Matcher for DurationMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let minutes = ffmpeg::get_duration(file_path)? / 60.0;
+        let fitting = fitting_in_range(minutes, cache.get_series_episodes(series_id), &self.range);
+
+        match fitting.as_slice() {
+            // Runtimes are rounded, a unique fit is likely but not certain
+            [episode] => Ok(vec![ScoredMatch::new(
+                vec![(*episode).clone()],
+                RUNTIME_SCORE,
+            )]),
+            _ => {
+                println!(
+                    "{} episodes have a runtime of about {minutes:.0} minutes, can't pick one.",
+                    fitting.len()
+                );
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn source(&self) -> &'static str {
+        "runtime"
+    }
+}
+
+/// The episodes within the range whose runtime fits, the range narrowed down first so an
+/// episode outside it can't make the pick ambiguous.
+fn fitting_in_range<'a>(
+    minutes: f64,
+    episodes: Vec<&'a EpisodeEntry>,
+    range: &EpisodeRange,
+) -> Vec<&'a EpisodeEntry> {
+    episodes
+        .into_iter()
+        .filter(|episode| range.contains(episode) && fits_runtime(minutes, episode))
+        .collect()
+}
+
+fn fits_runtime(minutes: f64, episode: &EpisodeEntry) -> bool {
+    episode.runtime.is_some_and(|runtime| {
+        let runtime = runtime as f64;
+        (minutes - runtime).abs() <= MIN_TOLERANCE_MINUTES.max(runtime * TOLERANCE_FRACTION)
+    })
+}
+
+/// Picks the one episode whose runtime fits the file's duration, to decide between several
+/// plausible matches. Returns `None` when none or more than one fits.
+pub fn pick_by_runtime<'a>(
+    file_path: &Path,
+    episodes: &[&'a EpisodeEntry],
+) -> Option<&'a EpisodeEntry> {
+    let minutes = match ffmpeg::get_duration(file_path) {
+        Ok(seconds) => seconds / 60.0,
+        Err(e) => {
+            eprintln!("Warning: Failed to read the duration of {file_path:?}: {e}");
+            return None;
+        }
+    };
+    pick_fitting(minutes, episodes)
+}
+
+/// Whether the file's duration fits the combined runtime of the episodes, i.e. whether it
+/// can hold them back to back. Without a readable duration and the runtimes of all episodes
+/// that can't be told, so it isn't assumed.
+pub fn holds_episodes(file_path: &Path, episodes: &[&EpisodeEntry]) -> bool {
+    match ffmpeg::get_duration(file_path) {
+        Ok(seconds) => fits_total_runtime(seconds / 60.0, episodes),
+        Err(e) => {
+            eprintln!("Warning: Failed to read the duration of {file_path:?}: {e}");
+            false
+        }
+    }
+}
+
+fn fits_total_runtime(minutes: f64, episodes: &[&EpisodeEntry]) -> bool {
+    let Some(total) = episodes
+        .iter()
+        .map(|episode| episode.runtime)
+        .sum::<Option<u64>>()
+    else {
+        return false;
+    };
+    let total = total as f64;
+    (minutes - total).abs() <= MIN_TOLERANCE_MINUTES.max(total * TOLERANCE_FRACTION)
+}
+
+fn pick_fitting<'a>(minutes: f64, episodes: &[&'a EpisodeEntry]) -> Option<&'a EpisodeEntry> {
+    let mut fitting = episodes
+        .iter()
+        .filter(|episode| fits_runtime(minutes, episode));
+    let first = fitting.next()?;
+    fitting.next().is_none().then_some(*first)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(episode_number: u64, runtime: Option<u64>) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number,
+            name: format!("Episode {episode_number}"),
+            overview: None,
+            aired: None,
+            runtime,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_fits_runtime() {
+        assert!(fits_runtime(43.2, &episode(1, Some(44))));
+        assert!(fits_runtime(84.0, &episode(1, Some(88))));
+        assert!(!fits_runtime(43.2, &episode(1, Some(88))));
+        assert!(!fits_runtime(43.2, &episode(1, None)));
+    }
+
+    #[test]
+    fn test_pick_fitting() {
+        let regular = episode(1, Some(44));
+        let double = episode(2, Some(88));
+        let unknown = episode(3, None);
+
+        assert_eq!(
+            pick_fitting(86.5, &[&regular, &double, &unknown]).map(|e| e.episode_number),
+            Some(2)
+        );
+        // Ambiguous
+        let regular2 = episode(4, Some(45));
+        assert!(pick_fitting(44.0, &[&regular, &regular2]).is_none());
+        assert!(pick_fitting(60.0, &[&regular, &double]).is_none());
+    }
+
+    #[test]
+    fn test_fitting_in_range() {
+        let regular = episode(1, Some(44));
+        let other_season = EpisodeEntry {
+            season_number: 2,
+            ..episode(1, Some(45))
+        };
+        let both = vec![&regular, &other_season];
+        assert_eq!(
+            fitting_in_range(44.0, both.clone(), &EpisodeRange::default()).len(),
+            2
+        );
+        let season_one = EpisodeRange::new(Some(1), None).unwrap();
+        let fitting = fitting_in_range(44.0, both, &season_one);
+        assert_eq!(
+            fitting.iter().map(|e| e.season_number).collect::<Vec<_>>(),
+            [1]
+        );
+    }
+
+    #[test]
+    fn test_fits_total_runtime() {
+        let first = episode(1, Some(44));
+        let second = episode(2, Some(45));
+        assert!(fits_total_runtime(88.5, &[&first, &second]));
+        assert!(!fits_total_runtime(44.0, &[&first, &second]));
+        // An unknown runtime can't show the file holds both
+        assert!(!fits_total_runtime(44.0, &[&first, &episode(2, None)]));
+        assert!(!fits_total_runtime(88.5, &[&first, &episode(2, None)]));
+    }
+}
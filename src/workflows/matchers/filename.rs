@@ -0,0 +1,247 @@
+use anyhow::Result;
+use regex::Regex;
+use std::path::Path;
+
+use super::{Matcher, ScoredMatch, AMBIGUOUS_SCORE};
+use crate::infra::cache::Cache;
+
+/// Identifies episodes from numbering or air dates already present in the file path.
+pub struct FilenameMatcher {
+    pub absolute: bool, // files are numbered in absolute order, like most anime releases
+}
+
+#[derive(Debug, PartialEq)]
+enum PathEpisode {
+    Numbered { season: u64, episode: u64 },
+    Absolute(u64),
+    Aired(String), // YYYY-MM-DD
+}
+
+impl Matcher for FilenameMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let absolute = self
+            .absolute
+            .then(|| {
+                file_path
+                    .file_stem()?
+                    .to_str()
+                    .and_then(parse_absolute_number)
+            })
+            .flatten();
+        let Some(parsed) = absolute
+            .map(PathEpisode::Absolute)
+            .or_else(|| parse_path(file_path))
+        else {
+            println!("No episode numbering or air date found in the file path.");
+            return Ok(Vec::new());
+        };
+
+        let matches: Vec<ScoredMatch> = match &parsed {
+            PathEpisode::Numbered { season, episode } => cache
+                .get_episode_by_sxxexx(series_id, *season, *episode)
+                .map(|episode| ScoredMatch::certain(episode.clone()))
+                .into_iter()
+                .collect(),
+            PathEpisode::Absolute(number) => cache
+                .get_episode_by_absolute(series_id, *number)
+                .map(|episode| ScoredMatch::certain(episode.clone()))
+                .into_iter()
+                .collect(),
+            PathEpisode::Aired(date) => {
+                let aired = cache.get_episodes_by_aired(series_id, date);
+                // Several episodes on one day (e.g. a double bill) can't be told apart
+                let score = match aired.len() {
+                    1 => 1.0,
+                    count => {
+                        if count > 1 {
+                            println!("{count} episodes aired on {date}.");
+                        }
+                        AMBIGUOUS_SCORE
+                    }
+                };
+                aired
+                    .into_iter()
+                    .map(|episode| ScoredMatch::new(vec![episode.clone()], score))
+                    .collect()
+            }
+        };
+
+        if matches.is_empty() {
+            println!("{parsed:?} from the file path is not in the cache for series {series_id}");
+        }
+        Ok(matches)
+    }
+
+    fn source(&self) -> &'static str {
+        "filename"
+    }
+}
+
+fn parse_path(path: &Path) -> Option<PathEpisode> {
+    let filename = path.file_stem()?.to_str()?;
+
+    if let Some((season, episode)) = parse_episode_numbers(filename) {
+        return Some(PathEpisode::Numbered { season, episode });
+    }
+    if let Some(date) = parse_air_date(filename) {
+        return Some(PathEpisode::Aired(date));
+    }
+
+    // `Season 02/E05.mkv` or `S02/05 - Title.mkv`
+    let season_dir = path.parent()?.file_name()?.to_str()?;
+    let season = parse_season_dir(season_dir)?;
+    let episode = parse_bare_episode(filename)?;
+    Some(PathEpisode::Numbered { season, episode })
+}
+
+/// Parses `S03E07` or `3x07` style numbering.
+pub fn parse_episode_numbers(filename: &str) -> Option<(u64, u64)> {
+    let re = Regex::new(r"(?i)\bS(\d{1,2})\s?E(\d{1,3})|\b(\d{1,2})x(\d{2,3})\b").unwrap();
+    let caps = re.captures(filename)?;
+    let season = caps.get(1).or_else(|| caps.get(3))?.as_str().parse().ok()?;
+    let episode = caps.get(2).or_else(|| caps.get(4))?.as_str().parse().ok()?;
+    Some((season, episode))
+}
+
+/// Parses absolute numbering: `E0153`, `Ep 153`, the `Show - 153 [1080p]` of anime
+/// releases or a bare `153`. A year like the `Show - 2019` of a release isn't one.
+pub fn parse_absolute_number(filename: &str) -> Option<u64> {
+    let re = Regex::new(
+        r"(?i)(?:^|[^a-z0-9])(?:e|ep|episode)[\s.]?(\d{1,4})\b|\s-\s(\d{1,4})(?:v\d)?\b|^(\d{1,4})$",
+    )
+    .unwrap();
+    let number = re
+        .captures_iter(filename.trim())
+        .filter(|caps| !caps.get(2).is_some_and(|number| is_year(number.as_str())))
+        .find_map(|caps| caps.iter().skip(1).flatten().next()?.as_str().parse().ok());
+    number
+}
+
+fn is_year(number: &str) -> bool {
+    number.len() == 4 && (number.starts_with("19") || number.starts_with("20"))
+}
+
+/// Parses `2019-05-04`, `2019.05.04` or `2019 05 04`.
+pub fn parse_air_date(filename: &str) -> Option<String> {
+    let re = Regex::new(r"\b((?:19|20)\d{2})[-. ](\d{2})[-. ](\d{2})\b").unwrap();
+    let caps = re.captures(filename)?;
+    Some(format!("{}-{}-{}", &caps[1], &caps[2], &caps[3]))
+}
+
+fn parse_season_dir(dir_name: &str) -> Option<u64> {
+    let re = Regex::new(r"(?i)^(?:season|series|staffel|s)\s*(\d{1,2})$").unwrap();
+    re.captures(dir_name.trim())?[1].parse().ok()
+}
+
+fn parse_bare_episode(filename: &str) -> Option<u64> {
+    let re = Regex::new(r"(?i)^(?:e|ep|episode)?\s*(\d{1,3})\b").unwrap();
+    re.captures(filename.trim())?[1].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::models::EpisodeEntry;
+
+    fn numbered(season: u64, episode: u64) -> Option<PathEpisode> {
+        Some(PathEpisode::Numbered { season, episode })
+    }
+
+    #[test]
+    fn test_parse_path() {
+        assert_eq!(
+            parse_path(Path::new("/tv/The.X-Files.S02E05.720p.mkv")),
+            numbered(2, 5)
+        );
+        assert_eq!(
+            parse_path(Path::new("/tv/X-Files - 2x05 - Duane Barry.mkv")),
+            numbered(2, 5)
+        );
+        assert_eq!(
+            parse_path(Path::new("/tv/Show.2019.05.04.mkv")),
+            Some(PathEpisode::Aired("2019-05-04".to_string()))
+        );
+        assert_eq!(
+            parse_path(Path::new("/tv/Season 02/E05.mkv")),
+            numbered(2, 5)
+        );
+        assert_eq!(
+            parse_path(Path::new("/tv/S2/05 - Duane Barry.mkv")),
+            numbered(2, 5)
+        );
+        assert_eq!(parse_path(Path::new("/tv/Extras/05 - Bloopers.mkv")), None);
+        assert_eq!(parse_path(Path::new("/tv/title_t00.mkv")), None);
+    }
+
+    #[test]
+    fn test_parse_episode_numbers() {
+        assert_eq!(parse_episode_numbers("Show - S03E07 - Title"), Some((3, 7)));
+        assert_eq!(parse_episode_numbers("Show - 3x07 - Title"), Some((3, 7)));
+        assert_eq!(parse_episode_numbers("Show - Title"), None);
+    }
+
+    #[test]
+    fn test_parse_absolute_number() {
+        assert_eq!(parse_absolute_number("One Piece E0153"), Some(153));
+        assert_eq!(
+            parse_absolute_number("One Piece - Ep 153 - Title"),
+            Some(153)
+        );
+        assert_eq!(
+            parse_absolute_number("[Group] One Piece - 153v2 [1080p]"),
+            Some(153)
+        );
+        assert_eq!(parse_absolute_number("153"), Some(153));
+        assert_eq!(parse_absolute_number("e153"), Some(153));
+        // Season numbering and resolutions aren't absolute numbers
+        assert_eq!(parse_absolute_number("One Piece - S01E05"), None);
+        assert_eq!(parse_absolute_number("One Piece 1080p"), None);
+        // Nor are years
+        assert_eq!(parse_absolute_number("Show - 2019"), None);
+        assert_eq!(parse_absolute_number("Show - 1999 - 153"), Some(153));
+        assert_eq!(parse_absolute_number("Show - 1153"), Some(1153));
+        assert_eq!(parse_absolute_number("One Piece E2019"), Some(2019));
+    }
+
+    #[test]
+    fn test_match_by_air_date() {
+        let mut cache = Cache::default();
+        cache.set_episode(
+            "1",
+            &EpisodeEntry {
+                production_code: None,
+                season_number: 1,
+                episode_number: 2,
+                name: "Deep Throat".to_string(),
+                overview: None,
+                aired: Some("1993-09-17".to_string()),
+                runtime: None,
+                id: None,
+                absolute_number: Some(2),
+            },
+        );
+
+        let matcher = FilenameMatcher { absolute: false };
+        let matched = matcher
+            .match_episode(Path::new("/tv/Show 1993-09-17.mkv"), "1", &mut cache)
+            .unwrap();
+        assert_eq!(matched[0].episodes[0].name, "Deep Throat");
+        assert_eq!(matched[0].score, 1.0);
+
+        let missing = matcher
+            .match_episode(Path::new("/tv/Show 1993-09-24.mkv"), "1", &mut cache)
+            .unwrap();
+        assert!(missing.is_empty());
+
+        // Absolute numbering
+        let absolute = FilenameMatcher { absolute: true }
+            .match_episode(Path::new("/tv/Show - 002 [720p].mkv"), "1", &mut cache)
+            .unwrap();
+        assert_eq!(absolute[0].episodes[0].name, "Deep Throat");
+    }
+}
@@ -0,0 +1,27 @@
+use anyhow::Result;
+use std::path::Path;
+
+use super::prod_code::ask_for_episode;
+use super::{Matcher, ScoredMatch};
+use crate::infra::cache::Cache;
+
+/// Asks the user for the episode, the last resort of the `auto` match mode.
+pub struct ManualMatcher {
+    pub absolute: bool, // accept absolute episode numbers at the prompt
+}
+
+impl Matcher for ManualMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        println!("No stage identified {file_path:?}.");
+        ask_for_episode(series_id, cache, self.absolute)
+    }
+
+    fn source(&self) -> &'static str {
+        "manual entry"
+    }
+}
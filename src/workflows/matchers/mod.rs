@@ -1,18 +1,185 @@
 use anyhow::Result;
 use std::path::Path;
 
-use crate::domain::models::EpisodeEntry;
+use crate::config::LowConfidencePolicy;
+use crate::domain::models::{EpisodeEntry, OcrCandidate};
 use crate::infra::cache::Cache;
+use crate::workflows::prompt;
+
+// Matches scoring at least this are accepted without asking, unless another one ties
+pub const ACCEPT_SCORE: f32 = 0.8;
+// Matches scoring less are never used
+pub const MIN_SCORE: f32 = 0.4;
+// Score of matches the matcher can't tell apart from each other
+pub const AMBIGUOUS_SCORE: f32 = 0.5;
+// Matches offered when asking
+const PROMPT_CHOICES: usize = 3;
+
+/// A possible identification of a file: the episodes it holds (several consecutive ones
+/// for a multi-episode file) and how sure the matcher is of it, from 0 to 1.
+#[derive(Debug, Clone)]
+pub struct ScoredMatch {
+    pub episodes: Vec<EpisodeEntry>,
+    pub score: f32,
+}
+
+impl ScoredMatch {
+    pub fn new(episodes: Vec<EpisodeEntry>, score: f32) -> Self {
+        Self { episodes, score }
+    }
+
+    /// A match the user entered or that can't be wrong, like numbering in the file name.
+    pub fn certain(episode: EpisodeEntry) -> Self {
+        Self::new(vec![episode], 1.0)
+    }
+}
 
 pub trait Matcher {
+    /// The ways the file may be identified, none if it couldn't be. See [`resolve`] for how
+    /// the scores decide between them.
     fn match_episode(
         &self,
         file_path: &Path,
         series_id: &str,
         cache: &mut Cache,
-    ) -> Result<Option<EpisodeEntry>>;
+    ) -> Result<Vec<ScoredMatch>>;
+
+    /// How this matcher identifies episodes, shown in rename plans.
+    fn source(&self) -> &'static str;
+
+    /// The OCR candidates the last `match_episode` call read, for `--output json`.
+    fn candidates(&self) -> Vec<OcrCandidate> {
+        Vec::new()
+    }
+
+    /// Why the last `match_episode` call left the file for manual handling, if it did.
+    fn flagged(&self) -> Option<String> {
+        None
+    }
 }
 
+/// Picks the episodes of a file from its matches. The best one is accepted if it scores at
+/// least [`ACCEPT_SCORE`] and nothing ties with it. Otherwise `policy` decides: accept the
+/// best one (unless another ties with it), ask with the top choices or skip the file.
+pub fn resolve(mut matches: Vec<ScoredMatch>, policy: LowConfidencePolicy) -> Vec<EpisodeEntry> {
+    matches.retain(|found| found.score >= MIN_SCORE && !found.episodes.is_empty());
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let Some(best) = matches.first() else {
+        return Vec::new();
+    };
+    let tied = matches
+        .get(1)
+        .is_some_and(|runner_up| runner_up.score >= best.score);
+    if best.score >= ACCEPT_SCORE && !tied {
+        return best.episodes.clone();
+    }
+
+    match policy {
+        LowConfidencePolicy::Accept if !tied => best.episodes.clone(),
+        LowConfidencePolicy::Ask => {
+            let choices: Vec<String> = matches
+                .iter()
+                .take(PROMPT_CHOICES)
+                .map(|found| format!("{} (score {:.2})", describe(&found.episodes), found.score))
+                .collect();
+            match prompt::choose("Which episode is it?", &choices) {
+                Some(index) => matches.swap_remove(index).episodes,
+                None => Vec::new(),
+            }
+        }
+        _ if tied => {
+            println!(
+                "Several episodes match equally well, skipping: {}",
+                matches
+                    .iter()
+                    .take_while(|found| found.score >= best.score)
+                    .map(|found| describe(&found.episodes))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+            Vec::new()
+        }
+        _ => {
+            println!(
+                "Ignoring low-confidence match {} (score {:.2})",
+                describe(&best.episodes),
+                best.score
+            );
+            Vec::new()
+        }
+    }
+}
+
+/// `S01E03 - Squeeze`, or `S01E01-E02` for several episodes.
+pub fn describe(episodes: &[EpisodeEntry]) -> String {
+    match episodes {
+        [episode] => format!(
+            "S{:02}E{:02} - {}",
+            episode.season_number, episode.episode_number, episode.name
+        ),
+        [first, .., last] => format!(
+            "S{:02}E{:02}-E{:02}",
+            first.season_number, first.episode_number, last.episode_number
+        ),
+        [] => String::new(),
+    }
+}
+
+pub mod air_date;
+pub mod audio;
+pub mod chain;
+pub mod duration;
+pub mod filename;
+pub mod manual;
+pub mod opensubtitles;
+pub mod overrides;
 pub mod prod_code;
+pub mod range;
+pub mod reference;
 pub mod subtitle;
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scored(episode_number: u64, score: f32) -> ScoredMatch {
+        ScoredMatch::new(
+            vec![EpisodeEntry {
+                production_code: None,
+                season_number: 1,
+                episode_number,
+                name: format!("Episode {episode_number}"),
+                overview: None,
+                aired: None,
+                runtime: None,
+                id: None,
+                absolute_number: None,
+            }],
+            score,
+        )
+    }
+
+    fn resolved(matches: Vec<ScoredMatch>, policy: LowConfidencePolicy) -> Vec<u64> {
+        resolve(matches, policy)
+            .iter()
+            .map(|episode| episode.episode_number)
+            .collect()
+    }
+
+    #[test]
+    fn test_resolve() {
+        use LowConfidencePolicy::{Accept, Skip};
+
+        // Confident matches are used whatever the policy
+        assert_eq!(resolved(vec![scored(2, 0.7), scored(3, 0.9)], Skip), [3]);
+        // In between, the policy decides
+        assert_eq!(resolved(vec![scored(3, 0.6)], Accept), [3]);
+        assert!(resolved(vec![scored(3, 0.6)], Skip).is_empty());
+        // Ties are never accepted without asking
+        assert!(resolved(vec![scored(3, 0.9), scored(4, 0.9)], Accept).is_empty());
+        assert!(resolved(vec![scored(3, 0.5), scored(4, 0.5)], Accept).is_empty());
+        // Too low to consider
+        assert!(resolved(vec![scored(3, 0.3)], Accept).is_empty());
+        assert!(resolved(Vec::new(), Accept).is_empty());
+    }
+}
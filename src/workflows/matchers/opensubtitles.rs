@@ -0,0 +1,43 @@
+use anyhow::Result;
+use std::path::Path;
+use std::sync::Arc;
+
+use super::{Matcher, ScoredMatch};
+use crate::infra::cache::Cache;
+use crate::infra::opensubtitles::{self, OpenSubtitlesClient};
+
+/// Identifies well-known releases by the OpenSubtitles hash of the file, without reading
+/// anything from it. Run ahead of the match mode in a [`super::chain::ChainMatcher`].
+pub struct OpenSubtitlesMatcher {
+    pub client: Arc<OpenSubtitlesClient>,
+}
+
+impl Matcher for OpenSubtitlesMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let Some(show_name) = cache.get_series_name(series_id) else {
+            return Ok(Vec::new());
+        };
+        let hash = opensubtitles::moviehash(file_path)?;
+        let Some((season, episode)) = self.client.find_episode(&hash, show_name)? else {
+            println!("No match by OpenSubtitles hash.");
+            return Ok(Vec::new());
+        };
+        match cache.get_episode_by_sxxexx(series_id, season, episode) {
+            // Hashes aren't unique, but a collision within the show is unlikely
+            Some(entry) => Ok(vec![ScoredMatch::certain(entry.clone())]),
+            None => {
+                println!("OpenSubtitles knows the file as S{season:02}E{episode:02}, which isn't in the episode list.");
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn source(&self) -> &'static str {
+        "OpenSubtitles hash"
+    }
+}
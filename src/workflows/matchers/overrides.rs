@@ -0,0 +1,249 @@
+use anyhow::{bail, Result};
+use std::path::Path;
+
+use super::filename::parse_episode_numbers;
+use super::{describe, Matcher, ScoredMatch};
+use crate::domain::models::{EpisodeEntry, OcrCandidate};
+use crate::infra::cache::Cache;
+
+/// The episode given with `--assume`, for a file no matcher identifies.
+pub struct AssumedMatcher {
+    pub season: u64,
+    pub episode: u64,
+}
+
+impl Matcher for AssumedMatcher {
+    fn match_episode(
+        &self,
+        _file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let Some(episode) = cache.get_episode_by_sxxexx(series_id, self.season, self.episode)
+        else {
+            bail!(
+                "S{:02}E{:02} is not an episode of series {series_id}",
+                self.season,
+                self.episode
+            );
+        };
+        Ok(vec![ScoredMatch::certain(episode.clone())])
+    }
+
+    fn source(&self) -> &'static str {
+        "assumed"
+    }
+}
+
+/// The episode a file is listed with in the `--mapping-file`, a production code or SXXEXX.
+pub struct MappedMatcher {
+    pub episode: String,
+}
+
+impl Matcher for MappedMatcher {
+    fn match_episode(
+        &self,
+        _file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let episode = cache.get_episode(series_id, &self.episode).or_else(|| {
+            let (season, episode) = parse_episode_numbers(&self.episode)?;
+            cache.get_episode_by_sxxexx(series_id, season, episode)
+        });
+        let Some(episode) = episode else {
+            bail!(
+                "'{}' of the mapping file is neither a production code nor an episode of series {series_id}",
+                self.episode
+            );
+        };
+        Ok(vec![ScoredMatch::certain(episode.clone())])
+    }
+
+    fn source(&self) -> &'static str {
+        "mapping file"
+    }
+}
+
+/// Shifts the episode numbers another matcher reads by `--episode-offset`, for releases
+/// numbered differently from the provider, e.g. with the two-part pilot as one episode.
+pub struct OffsetMatcher {
+    pub matcher: Box<dyn Matcher>,
+    pub offset: i64,
+    pub absolute: bool, // shift the absolute numbers rather than those within the season
+}
+
+impl Matcher for OffsetMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let matches = self.matcher.match_episode(file_path, series_id, cache)?;
+        Ok(matches
+            .into_iter()
+            .filter_map(|found| {
+                let shifted: Option<Vec<EpisodeEntry>> = found
+                    .episodes
+                    .iter()
+                    .map(|episode| self.shift(episode, series_id, cache))
+                    .collect();
+                match shifted {
+                    Some(episodes) => Some(ScoredMatch::new(episodes, found.score)),
+                    None => {
+                        println!(
+                            "Ignoring match {}, there's no episode {:+} from it",
+                            describe(&found.episodes),
+                            self.offset
+                        );
+                        None
+                    }
+                }
+            })
+            .collect())
+    }
+
+    fn source(&self) -> &'static str {
+        self.matcher.source()
+    }
+
+    fn candidates(&self) -> Vec<OcrCandidate> {
+        self.matcher.candidates()
+    }
+
+    fn flagged(&self) -> Option<String> {
+        self.matcher.flagged()
+    }
+}
+
+impl OffsetMatcher {
+    fn shift(
+        &self,
+        episode: &EpisodeEntry,
+        series_id: &str,
+        cache: &Cache,
+    ) -> Option<EpisodeEntry> {
+        let shifted = if self.absolute {
+            let number = episode.absolute_number?.checked_add_signed(self.offset)?;
+            cache.get_episode_by_absolute(series_id, number)
+        } else {
+            let number = episode.episode_number.checked_add_signed(self.offset)?;
+            cache.get_episode_by_sxxexx(series_id, episode.season_number, number)
+        };
+        shifted.cloned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Finds(u64);
+
+    impl Matcher for Finds {
+        fn match_episode(
+            &self,
+            _: &Path,
+            series_id: &str,
+            cache: &mut Cache,
+        ) -> Result<Vec<ScoredMatch>> {
+            let episode = cache.get_episode_by_sxxexx(series_id, 1, self.0).unwrap();
+            Ok(vec![ScoredMatch::new(vec![episode.clone()], 0.9)])
+        }
+
+        fn source(&self) -> &'static str {
+            "finds"
+        }
+    }
+
+    fn episode(episode_number: u64) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number,
+            name: format!("Episode {episode_number}"),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: Some(episode_number),
+        }
+    }
+
+    #[test]
+    fn test_offset_matcher() {
+        let mut cache = Cache::default();
+        for number in 1..=3 {
+            cache.set_episode("1", &episode(number));
+        }
+        let run = |found: u64, offset: i64, absolute: bool, cache: &mut Cache| {
+            let matcher = OffsetMatcher {
+                matcher: Box::new(Finds(found)),
+                offset,
+                absolute,
+            };
+            matcher
+                .match_episode(Path::new("a.mkv"), "1", cache)
+                .unwrap()
+        };
+
+        let matches = run(2, -1, false, &mut cache);
+        assert_eq!(matches[0].episodes[0].episode_number, 1);
+        assert_eq!(matches[0].score, 0.9);
+        let matches = run(2, 1, true, &mut cache);
+        assert_eq!(matches[0].episodes[0].episode_number, 3);
+        assert!(run(1, -1, false, &mut cache).is_empty());
+        assert!(run(3, 1, false, &mut cache).is_empty());
+    }
+
+    #[test]
+    fn test_mapped_matcher() {
+        let mut cache = Cache::default();
+        let mut coded = episode(3);
+        coded.production_code = Some("1X02".to_string());
+        cache.set_episode("1", &coded);
+        cache.set_episode("1", &episode(4));
+        let run = |listed: &str, cache: &mut Cache| {
+            let mapped = MappedMatcher {
+                episode: listed.to_string(),
+            };
+            mapped.match_episode(Path::new("a.mkv"), "1", cache)
+        };
+
+        assert_eq!(
+            run("1X02", &mut cache).unwrap()[0].episodes[0].episode_number,
+            3
+        );
+        assert_eq!(
+            run("s01e04", &mut cache).unwrap()[0].episodes[0].episode_number,
+            4
+        );
+        assert!(run("S01E09", &mut cache).is_err());
+    }
+
+    #[test]
+    fn test_assumed_matcher() {
+        let mut cache = Cache::default();
+        cache.set_episode("1", &episode(2));
+        let assumed = AssumedMatcher {
+            season: 1,
+            episode: 2,
+        };
+        let matches = assumed
+            .match_episode(Path::new("a.mkv"), "1", &mut cache)
+            .unwrap();
+        assert_eq!(
+            (matches[0].episodes[0].episode_number, matches[0].score),
+            (2, 1.0)
+        );
+
+        let missing = AssumedMatcher {
+            season: 1,
+            episode: 5,
+        };
+        assert!(missing
+            .match_episode(Path::new("a.mkv"), "1", &mut cache)
+            .is_err());
+    }
+}
@@ -1,14 +1,39 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
 use rustyline::DefaultEditor;
+use std::cell::RefCell;
 use std::path::Path;
+use std::sync::Arc;
 
-use super::Matcher;
-use crate::domain::models::EpisodeEntry;
+use super::filename::parse_absolute_number;
+use super::range::EpisodeRange;
+use super::{describe, duration, Matcher, ScoredMatch, ACCEPT_SCORE, MIN_SCORE};
+use crate::config::{MultiPartPolicy, UnmatchedPolicy};
+use crate::domain::models::{EpisodeEntry, OcrCandidate};
 use crate::infra::cache::Cache;
+use crate::infra::sidecar::{self, OcrSidecar};
 use crate::media::ocr;
+use crate::workflows::prompt;
+
+// Tesseract word confidence of a candidate below which a match counts as low-confidence
+const LOW_CONFIDENCE: i32 = 60;
+// Share of the typed words that must appear in an episode title for it to be suggested
+const MIN_TITLE_SCORE: f64 = 0.5;
 
 pub struct ProductionCodeMatcher {
     pub prompt_size: Option<u64>,
+    pub ocr_sidecar: bool,
+    pub ocr_options: ocr::FrameOcrOptions,
+    pub ocr_pool: Arc<ocr::OcrEnginePool>,
+    pub unmatched: UnmatchedPolicy,
+    pub multi_part: MultiPartPolicy,
+    pub ignored_candidates: Vec<Regex>, // see `compile_candidate_blacklist`
+    pub min_confidence: i32,            // candidates read with less confidence are dropped
+    pub absolute: bool,                 // accept absolute episode numbers at the prompt
+    pub range: EpisodeRange,            // candidates of other episodes are dropped
+    pub prefetched: Option<Vec<OcrCandidate>>, // OCR already run by a worker thread
+    pub last_candidates: RefCell<Vec<OcrCandidate>>, // see `Matcher::candidates`
+    pub last_flag: RefCell<Option<String>>, // see `Matcher::flagged`
 }
 
 impl Matcher for ProductionCodeMatcher {
@@ -17,44 +42,324 @@ impl Matcher for ProductionCodeMatcher {
         file_path: &Path,
         series_id: &str,
         cache: &mut Cache,
-    ) -> Result<Option<EpisodeEntry>> {
-        // Extract production code
-        let production_code_candidates =
-            ocr::extract_production_code_candidates(file_path.to_str().unwrap())?;
-
-        if let Some(episode) = production_code_candidates
-            .into_iter()
-            .find_map(|code| cache.get_episode(series_id, &code).cloned())
-        {
-            return Ok(Some(episode));
+    ) -> Result<Vec<ScoredMatch>> {
+        self.last_flag.replace(None);
+        // Reuse the evidence from a previous run if there is any, otherwise run OCR
+        let existing = if self.ocr_sidecar {
+            sidecar::load(file_path)
+        } else {
+            None
+        };
+        let production_code_candidates = match existing {
+            Some(existing) => {
+                println!(
+                    "Using OCR candidates from {:?}",
+                    sidecar::sidecar_path(file_path)
+                );
+                existing.candidates
+            }
+            None => match &self.prefetched {
+                Some(candidates) => candidates.clone(),
+                None => ocr::extract_production_code_candidates(
+                    file_path.to_str().unwrap(),
+                    &self.ocr_options,
+                    &self.ocr_pool,
+                )?,
+            },
+        };
+        eprintln!(
+            "Found candidates: {:?}",
+            production_code_candidates
+                .iter()
+                .map(|c| &c.code)
+                .collect::<Vec<_>>()
+        );
+
+        let (ranked, rejected) = rank_candidates(&production_code_candidates, self.min_confidence);
+        for candidate in rejected {
+            eprintln!(
+                "Rejected low-confidence candidate {} from {} (OCR confidence {})",
+                candidate.code, candidate.frame, candidate.confidence
+            );
+        }
+
+        // Every distinct episode a candidate resolves to, most confidently read first
+        let mut matches: Vec<(&EpisodeEntry, i32)> = Vec::new();
+        for candidate in ranked {
+            if self.is_ignored(&candidate.code) {
+                continue;
+            }
+            let Some(episode) = cache.get_episode(series_id, &candidate.code) else {
+                continue;
+            };
+            if !self.range.contains(episode) {
+                eprintln!(
+                    "Rejected candidate {} of S{:02}E{:02}, outside {}",
+                    candidate.code, episode.season_number, episode.episode_number, self.range
+                );
+                continue;
+            }
+            let seen = matches.iter().any(|(matched, _)| {
+                matched.season_number == episode.season_number
+                    && matched.episode_number == episode.episode_number
+            });
+            if !seen {
+                matches.push((episode, candidate.confidence));
+            }
         }
 
-        if self.prompt_size.is_some() && file_path.metadata()?.len() > self.prompt_size.unwrap() {
-            println!("Please enter the production code or SXXEXX manually.");
-            let mut rl = DefaultEditor::new()?;
-            loop {
-                let input = rl.readline(">> ")?;
-                let input = input.trim().to_string();
-
-                let episode = cache.get_episode(series_id, &input).cloned().or_else(|| {
-                    parse_sxxexx(&input).ok().and_then(|(season, episode)| {
-                        cache
-                            .get_episode_by_sxxexx(series_id, season, episode)
-                            .cloned()
+        let confidence_of = |episode: &EpisodeEntry| {
+            matches
+                .iter()
+                .find(|(matched, _)| std::ptr::eq(*matched, episode))
+                .map_or(0, |(_, confidence)| *confidence)
+        };
+        let episodes: Vec<_> = matches.iter().map(|(episode, _)| *episode).collect();
+        let scored: Vec<ScoredMatch> = if let Some(pair) =
+            consecutive_pair(&episodes).filter(|pair| duration::holds_episodes(file_path, pair))
+        {
+            // Codes of two consecutive episodes: a file holding both, e.g. a two-parter
+            println!(
+                "Found codes of consecutive episodes, the file holds S{}E{}-E{}",
+                pair[0].season_number, pair[0].episode_number, pair[1].episode_number
+            );
+            let confidence = confidence_of(pair[0]).min(confidence_of(pair[1]));
+            vec![ScoredMatch::new(
+                pair.iter().map(|episode| (*episode).clone()).collect(),
+                ocr_score(confidence),
+            )]
+        } else if let Some(pair) =
+            split_pair(&episodes).filter(|pair| duration::holds_episodes(file_path, pair))
+        {
+            // Codes of two episodes apart: a movie edit of a story told in two parts, or a
+            // misread code. Taking either one would hide the other.
+            let confidence = confidence_of(pair[0]).min(confidence_of(pair[1]));
+            self.multi_part(pair, ocr_score(confidence))
+        } else if matches.len() > 1 {
+            // Several plausible codes: the runtime may tell which one is right
+            match duration::pick_by_runtime(file_path, &episodes) {
+                Some(episode) => {
+                    println!(
+                        "{} candidate episodes, picked S{}E{} by runtime",
+                        matches.len(),
+                        episode.season_number,
+                        episode.episode_number
+                    );
+                    vec![ScoredMatch::new(
+                        vec![episode.clone()],
+                        ocr_score(confidence_of(episode)),
+                    )]
+                }
+                None => matches
+                    .iter()
+                    .map(|(episode, confidence)| {
+                        ScoredMatch::new(vec![(*episode).clone()], ocr_score(*confidence))
                     })
-                });
+                    .collect(),
+            }
+        } else {
+            matches
+                .iter()
+                .map(|(episode, confidence)| {
+                    ScoredMatch::new(vec![(*episode).clone()], ocr_score(*confidence))
+                })
+                .collect()
+        };
+        let matched: Vec<EpisodeEntry> = scored
+            .first()
+            .map(|best| best.episodes.clone())
+            .unwrap_or_default();
+
+        self.last_candidates
+            .replace(production_code_candidates.clone());
+        if self.ocr_sidecar {
+            let evidence = OcrSidecar {
+                file: file_path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_default(),
+                candidates: production_code_candidates,
+                matched: matched.clone(),
+            };
+            if let Err(e) = sidecar::save(file_path, &evidence) {
+                eprintln!("Warning: Failed to write OCR sidecar for {file_path:?}: {e}");
+            }
+        }
+
+        if !scored.is_empty() || self.last_flag.borrow().is_some() {
+            return Ok(scored);
+        }
+
+        if self.unmatched == UnmatchedPolicy::Ask
+            && self.prompt_size.is_some()
+            && file_path.metadata()?.len() > self.prompt_size.unwrap()
+        {
+            return ask_for_episode(series_id, cache, self.absolute);
+        }
+
+        Ok(Vec::new())
+    }
 
-                if let Some(episode) = episode {
-                    return Ok(Some(episode));
+    fn source(&self) -> &'static str {
+        "production code"
+    }
+
+    fn candidates(&self) -> Vec<OcrCandidate> {
+        self.last_candidates.borrow().clone()
+    }
+
+    fn flagged(&self) -> Option<String> {
+        self.last_flag.borrow().clone()
+    }
+}
+
+/// Asks for the production code, SXXEXX, absolute number or part of the title of the
+/// episode until one is found.
+pub fn ask_for_episode(series_id: &str, cache: &Cache, absolute: bool) -> Result<Vec<ScoredMatch>> {
+    if absolute {
+        println!("Please enter the production code, SXXEXX, absolute number (e.g. E0153) or part of the episode title manually.");
+    } else {
+        println!("Please enter the production code, SXXEXX or part of the episode title manually.");
+    }
+    let mut rl = DefaultEditor::new()?;
+    loop {
+        let input = rl.readline(">> ")?;
+        let input = input.trim().to_string();
+
+        let episode = cache.get_episode(series_id, &input).cloned().or_else(|| {
+            parse_sxxexx(&input).ok().and_then(|(season, episode)| {
+                cache
+                    .get_episode_by_sxxexx(series_id, season, episode)
+                    .cloned()
+            })
+        });
+        let episode = episode.or_else(|| {
+            let number = parse_absolute_number(&input).filter(|_| absolute)?;
+            cache.get_episode_by_absolute(series_id, number).cloned()
+        });
+
+        if let Some(episode) = episode {
+            return Ok(vec![ScoredMatch::certain(episode)]);
+        }
+
+        let episodes = cache.get_series_episodes(series_id);
+        if let Some(episode) = best_title_match(&input, &episodes) {
+            if prompt::confirm(&format!(
+                "Did you mean S{}E{} - {}?",
+                episode.season_number, episode.episode_number, episode.name
+            )) {
+                return Ok(vec![ScoredMatch::certain(episode.clone())]);
+            }
+            println!("Please try again.");
+            continue;
+        }
+        println!("Episode not found or invalid format. Please try again.");
+    }
+}
+
+impl ProductionCodeMatcher {
+    fn is_ignored(&self, code: &str) -> bool {
+        self.ignored_candidates.iter().any(|re| re.is_match(code))
+    }
+
+    /// The matches of a file holding the codes of two episodes that aren't consecutive,
+    /// named as both, as one of them or none and flagged, see [`MultiPartPolicy`].
+    fn multi_part(&self, pair: [&EpisodeEntry; 2], score: f32) -> Vec<ScoredMatch> {
+        let both: Vec<EpisodeEntry> = pair.iter().map(|episode| (*episode).clone()).collect();
+        let found = format!(
+            "codes of S{:02}E{:02} and S{:02}E{:02}",
+            pair[0].season_number,
+            pair[0].episode_number,
+            pair[1].season_number,
+            pair[1].episode_number
+        );
+        println!("Found {found}, which aren't consecutive episodes");
+        let chosen = match self.multi_part {
+            MultiPartPolicy::Combine => Some(ScoredMatch::new(both, score)),
+            MultiPartPolicy::Ask => {
+                let choices = [
+                    format!("Both, as {}", describe(&both)),
+                    describe(&both[..1]),
+                    describe(&both[1..]),
+                ];
+                match prompt::choose("Which episodes does the file hold?", &choices) {
+                    Some(0) => Some(ScoredMatch::new(both, 1.0)),
+                    Some(index) => Some(ScoredMatch::certain(both[index - 1].clone())),
+                    None => None,
                 }
-                println!("Episode not found or invalid format. Please try again.");
             }
+            MultiPartPolicy::Flag => None,
+        };
+        if chosen.is_none() {
+            println!("Leaving the file for manual handling");
+            self.last_flag
+                .replace(Some(format!("{found}, possibly a multi-part file")));
         }
+        chosen.into_iter().collect()
+    }
+}
 
-        Ok(None)
+/// The score of a match read with a Tesseract word confidence (0-100). Confidences below
+/// [`LOW_CONFIDENCE`] score below [`ACCEPT_SCORE`], but never below [`MIN_SCORE`].
+fn ocr_score(confidence: i32) -> f32 {
+    let confidence = confidence.clamp(0, 100) as f32;
+    let low = LOW_CONFIDENCE as f32;
+    if confidence >= low {
+        ACCEPT_SCORE + (1.0 - ACCEPT_SCORE) * (confidence - low) / (100.0 - low)
+    } else {
+        MIN_SCORE + (ACCEPT_SCORE - MIN_SCORE) * confidence / low
     }
 }
 
+/// Sorts the candidates by confidence, most confident first and in frame order among equals,
+/// and splits off those below `min_confidence`.
+fn rank_candidates(
+    candidates: &[OcrCandidate],
+    min_confidence: i32,
+) -> (Vec<&OcrCandidate>, Vec<&OcrCandidate>) {
+    let mut ranked: Vec<&OcrCandidate> = candidates.iter().collect();
+    ranked.sort_by_key(|candidate| std::cmp::Reverse(candidate.confidence));
+    ranked
+        .into_iter()
+        .partition(|candidate| candidate.confidence >= min_confidence)
+}
+
+/// The two episodes in episode order if they are consecutive episodes of one season.
+fn consecutive_pair<'a>(episodes: &[&'a EpisodeEntry]) -> Option<[&'a EpisodeEntry; 2]> {
+    season_pair(episodes)
+        .filter(|[first, second]| first.episode_number + 1 == second.episode_number)
+}
+
+/// The two episodes in episode order if they are episodes of one season with others
+/// between them.
+fn split_pair<'a>(episodes: &[&'a EpisodeEntry]) -> Option<[&'a EpisodeEntry; 2]> {
+    season_pair(episodes).filter(|[first, second]| first.episode_number + 1 < second.episode_number)
+}
+
+fn season_pair<'a>(episodes: &[&'a EpisodeEntry]) -> Option<[&'a EpisodeEntry; 2]> {
+    let [first, second] = episodes else {
+        return None;
+    };
+    let (first, second) = if second.episode_number < first.episode_number {
+        (*second, *first)
+    } else {
+        (*first, *second)
+    };
+    (first.season_number == second.season_number).then_some([first, second])
+}
+
+/// Compiles the per-show candidate blacklist. Each pattern is a case-insensitive regex that
+/// must match the whole candidate, so a plain string like `2015` only blocks that exact text.
+pub fn compile_candidate_blacklist(patterns: &[String]) -> Result<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            Regex::new(&format!("(?i)^(?:{pattern})$"))
+                .with_context(|| format!("Invalid ignore_candidates pattern '{pattern}'"))
+        })
+        .collect()
+}
+
 fn parse_sxxexx(input: &str) -> Result<(u64, u64)> {
     let re = regex::Regex::new(r"(?i)^s(\d{1,2})e(\d{1,2})$").unwrap();
     let caps = re.captures(input).ok_or(anyhow!("Invalid SXXEXX format"))?;
@@ -71,10 +376,203 @@ fn parse_sxxexx(input: &str) -> Result<(u64, u64)> {
     Ok((season, episode))
 }
 
+/// Finds the episode whose title best matches a partially remembered title. Typed words
+/// count when they start a word of the title, so "squee" finds "Squeeze".
+fn best_title_match<'a>(query: &str, episodes: &[&'a EpisodeEntry]) -> Option<&'a EpisodeEntry> {
+    let query_words = title_words(query);
+    if query_words.is_empty() {
+        return None;
+    }
+
+    episodes
+        .iter()
+        .map(|episode| {
+            let title_words = title_words(&episode.name);
+            let hits = query_words
+                .iter()
+                .filter(|word| {
+                    title_words
+                        .iter()
+                        .any(|title| title.starts_with(word.as_str()))
+                })
+                .count();
+            let score = hits as f64 / query_words.len() as f64;
+            // Among equal scores prefer the title with the fewest extra words
+            (score, usize::MAX - title_words.len(), *episode)
+        })
+        .filter(|(score, _, _)| *score >= MIN_TITLE_SCORE)
+        .max_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)))
+        .map(|(_, _, episode)| episode)
+}
+
+fn title_words(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn episode(season_number: u64, episode_number: u64, name: &str) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number,
+            episode_number,
+            name: name.to_string(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_ocr_score() {
+        assert_eq!(ocr_score(100), 1.0);
+        assert_eq!(ocr_score(LOW_CONFIDENCE), ACCEPT_SCORE);
+        assert!(ocr_score(LOW_CONFIDENCE - 1) < ACCEPT_SCORE);
+        assert_eq!(ocr_score(0), MIN_SCORE);
+        assert_eq!(ocr_score(-1), MIN_SCORE);
+    }
+
+    #[test]
+    fn test_compile_candidate_blacklist() {
+        let blacklist =
+            compile_candidate_blacklist(&["2015".to_string(), r"9\d{3}".to_string()]).unwrap();
+        let matcher = ProductionCodeMatcher {
+            prompt_size: None,
+            ocr_sidecar: false,
+            ocr_options: ocr::FrameOcrOptions::default(),
+            ocr_pool: Arc::default(),
+            unmatched: UnmatchedPolicy::Skip,
+            multi_part: MultiPartPolicy::Flag,
+            ignored_candidates: blacklist,
+            min_confidence: 0,
+            absolute: false,
+            range: EpisodeRange::default(),
+            prefetched: None,
+            last_candidates: RefCell::default(),
+            last_flag: RefCell::default(),
+        };
+
+        assert!(matcher.is_ignored("2015"));
+        assert!(matcher.is_ignored("9120"));
+        assert!(!matcher.is_ignored("12015"));
+        assert!(!matcher.is_ignored("3X22"));
+        assert!(compile_candidate_blacklist(&["(".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_rank_candidates() {
+        let candidate = |code: &str, frame: &str, confidence| OcrCandidate {
+            code: code.to_string(),
+            frame: frame.to_string(),
+            confidence,
+        };
+        let candidates = vec![
+            candidate("1993", "end_0001.png", 40),
+            candidate("3X22", "end_0002.png", 85),
+            candidate("3X23", "end_0003.png", 85),
+            candidate("8X22", "end_0004.png", 92),
+        ];
+
+        let (ranked, rejected) = rank_candidates(&candidates, 50);
+        let codes: Vec<_> = ranked.iter().map(|c| c.code.as_str()).collect();
+        assert_eq!(codes, ["8X22", "3X22", "3X23"]);
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].code, "1993");
+
+        let (ranked, rejected) = rank_candidates(&candidates, 0);
+        assert_eq!(ranked.len(), 4);
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn test_best_title_match() {
+        let pilot = episode(1, 1, "Pilot");
+        let squeeze = episode(1, 3, "Squeeze");
+        let tooms = episode(1, 21, "Tooms");
+        let beyond = episode(1, 13, "Beyond the Sea");
+        let episodes = vec![&pilot, &squeeze, &tooms, &beyond];
+
+        assert_eq!(
+            best_title_match("squee", &episodes).unwrap().name,
+            "Squeeze"
+        );
+        assert_eq!(
+            best_title_match("the sea", &episodes).unwrap().name,
+            "Beyond the Sea"
+        );
+        assert_eq!(
+            best_title_match("BEYOND", &episodes).unwrap().name,
+            "Beyond the Sea"
+        );
+        assert!(best_title_match("ice", &episodes).is_none());
+        assert!(best_title_match("  ", &episodes).is_none());
+    }
+
+    #[test]
+    fn test_consecutive_pair() {
+        let first = episode(1, 1, "Pilot");
+        let second = episode(1, 2, "Deep Throat");
+        let fourth = episode(1, 4, "Conduit");
+        let next_season = episode(2, 2, "The Host");
+
+        let numbers = |pair: Option<[&EpisodeEntry; 2]>| {
+            pair.map(|pair| pair.map(|episode| episode.episode_number))
+        };
+        assert_eq!(numbers(consecutive_pair(&[&second, &first])), Some([1, 2]));
+        assert_eq!(numbers(consecutive_pair(&[&second, &fourth])), None);
+        assert_eq!(numbers(consecutive_pair(&[&second, &next_season])), None);
+        assert_eq!(numbers(consecutive_pair(&[&first, &second, &fourth])), None);
+
+        assert_eq!(numbers(split_pair(&[&fourth, &second])), Some([2, 4]));
+        assert_eq!(numbers(split_pair(&[&first, &second])), None);
+        assert_eq!(numbers(split_pair(&[&second, &next_season])), None);
+    }
+
+    #[test]
+    fn test_multi_part() {
+        let matcher = |multi_part| ProductionCodeMatcher {
+            prompt_size: None,
+            ocr_sidecar: false,
+            ocr_options: ocr::FrameOcrOptions::default(),
+            ocr_pool: Arc::default(),
+            unmatched: UnmatchedPolicy::Skip,
+            multi_part,
+            ignored_candidates: Vec::new(),
+            min_confidence: 0,
+            absolute: false,
+            range: EpisodeRange::default(),
+            prefetched: None,
+            last_candidates: RefCell::default(),
+            last_flag: RefCell::default(),
+        };
+        let first = episode(1, 3, "Part One");
+        let second = episode(1, 7, "Part Two");
+
+        let combine = matcher(MultiPartPolicy::Combine);
+        let matches = combine.multi_part([&first, &second], 0.9);
+        let numbers: Vec<_> = matches[0]
+            .episodes
+            .iter()
+            .map(|e| e.episode_number)
+            .collect();
+        assert_eq!((numbers, matches[0].score), (vec![3, 7], 0.9));
+        assert!(combine.flagged().is_none());
+
+        let flag = matcher(MultiPartPolicy::Flag);
+        assert!(flag.multi_part([&first, &second], 0.9).is_empty());
+        assert_eq!(
+            flag.flagged().unwrap(),
+            "codes of S01E03 and S01E07, possibly a multi-part file"
+        );
+    }
+
     #[test]
     fn test_parse_sxxexx_valid() {
         assert_eq!(parse_sxxexx("S01E01").unwrap(), (1, 1));
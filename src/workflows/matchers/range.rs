@@ -0,0 +1,167 @@
+use anyhow::{bail, Context, Result};
+use std::fmt;
+use std::path::Path;
+
+use super::{describe, Matcher, ScoredMatch};
+use crate::domain::models::{EpisodeEntry, OcrCandidate};
+use crate::infra::cache::Cache;
+
+/// The episodes files may be matched to, from `--season`, `--episodes` and
+/// `--specials exclude`. Matching only these makes misread codes of other seasons harmless
+/// when a directory holds one season.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EpisodeRange {
+    season: Option<u64>,
+    episodes: Option<(u64, u64)>, // first and last episode number within the season
+    no_specials: bool,            // season 0 is left out
+}
+
+impl EpisodeRange {
+    /// `episodes` is a number like `5` or a range like `1-12`.
+    pub fn new(season: Option<u64>, episodes: Option<&str>) -> Result<Self> {
+        let episodes = episodes
+            .map(|episodes| {
+                parse_episodes(episodes)
+                    .with_context(|| format!("Invalid --episodes '{episodes}', e.g. 1-12"))
+            })
+            .transpose()?;
+        Ok(Self {
+            season,
+            episodes,
+            no_specials: false,
+        })
+    }
+
+    /// Leaves out the specials of season 0.
+    pub fn without_specials(mut self) -> Self {
+        self.no_specials = true;
+        self
+    }
+
+    /// Whether every episode may be matched.
+    pub fn is_all(&self) -> bool {
+        self.season.is_none() && self.episodes.is_none() && !self.no_specials
+    }
+
+    pub fn contains(&self, episode: &EpisodeEntry) -> bool {
+        !(self.no_specials && episode.season_number == 0)
+            && self
+                .season
+                .is_none_or(|season| episode.season_number == season)
+            && self
+                .episodes
+                .is_none_or(|(first, last)| (first..=last).contains(&episode.episode_number))
+    }
+}
+
+impl fmt::Display for EpisodeRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.season, self.episodes) {
+            (Some(season), Some((first, last))) => {
+                write!(f, "season {season}, episodes {first}-{last}")
+            }
+            (Some(season), None) => write!(f, "season {season}"),
+            (None, Some((first, last))) => write!(f, "episodes {first}-{last}"),
+            (None, None) => write!(f, "every episode"),
+        }?;
+        if self.no_specials {
+            write!(f, " but specials")?;
+        }
+        Ok(())
+    }
+}
+
+fn parse_episodes(episodes: &str) -> Result<(u64, u64)> {
+    let (first, last) = episodes.split_once('-').unwrap_or((episodes, episodes));
+    let (first, last): (u64, u64) = (first.trim().parse()?, last.trim().parse()?);
+    if first > last {
+        bail!("The first episode comes after the last");
+    }
+    Ok((first, last))
+}
+
+/// Drops the matches of another matcher that hold episodes outside the range.
+pub struct RangeMatcher {
+    pub matcher: Box<dyn Matcher>,
+    pub range: EpisodeRange,
+}
+
+impl Matcher for RangeMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let mut matches = self.matcher.match_episode(file_path, series_id, cache)?;
+        matches.retain(|found| {
+            let inside = found
+                .episodes
+                .iter()
+                .all(|episode| self.range.contains(episode));
+            if !inside {
+                eprintln!(
+                    "Ignoring match {}, outside {}",
+                    describe(&found.episodes),
+                    self.range
+                );
+            }
+            inside
+        });
+        Ok(matches)
+    }
+
+    fn source(&self) -> &'static str {
+        self.matcher.source()
+    }
+
+    fn candidates(&self) -> Vec<OcrCandidate> {
+        self.matcher.candidates()
+    }
+
+    fn flagged(&self) -> Option<String> {
+        self.matcher.flagged()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(season_number: u64, episode_number: u64) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number,
+            episode_number,
+            name: String::new(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_episode_range() {
+        let range = EpisodeRange::new(Some(4), Some("1-12")).unwrap();
+        assert!(range.contains(&episode(4, 1)));
+        assert!(range.contains(&episode(4, 12)));
+        assert!(!range.contains(&episode(4, 13)));
+        assert!(!range.contains(&episode(3, 5)));
+        assert_eq!(range.to_string(), "season 4, episodes 1-12");
+
+        let range = EpisodeRange::new(None, Some("5")).unwrap();
+        assert!(range.contains(&episode(2, 5)));
+        assert!(!range.contains(&episode(2, 6)));
+
+        let range = EpisodeRange::new(None, None).unwrap().without_specials();
+        assert!(range.contains(&episode(1, 3)));
+        assert!(!range.contains(&episode(0, 3)));
+        assert_eq!(range.to_string(), "every episode but specials");
+
+        assert!(EpisodeRange::new(None, None).unwrap().is_all());
+        assert!(EpisodeRange::new(None, Some("12-1")).is_err());
+        assert!(EpisodeRange::new(None, Some("x")).is_err());
+    }
+}
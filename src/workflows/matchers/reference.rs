@@ -0,0 +1,367 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use super::filename::parse_episode_numbers;
+use super::{Matcher, ScoredMatch};
+use crate::domain::models::EpisodeEntry;
+use crate::infra::cache::Cache;
+use crate::infra::opensubtitles::OpenSubtitlesClient;
+use crate::media::{ffmpeg, ocr, subtitles};
+
+// Share of the file's shingles the best reference must contain, and how far ahead of the
+// runner-up it must be. Translations and OCR errors keep real matches well below 1.
+const MIN_SIMILARITY: f64 = 0.1;
+const MIN_LEAD: f64 = 1.5;
+// Score of a clear match, which is hard to get by chance
+const REFERENCE_MATCH_SCORE: f32 = 0.9;
+
+// Words per shingle, few enough to survive OCR errors but too many to be common phrases
+const SHINGLE_WORDS: usize = 3;
+
+type Shingles = HashSet<String>;
+type Reference = ((u64, u64), Shingles); // season and episode
+type ReferenceMatch = ((u64, u64), f64); // episode and similarity
+
+/// The reference subtitles of a series' episodes, read once per run. Missing ones are
+/// downloaded only as long as no reference matches a file, since every download counts
+/// against a small daily quota.
+pub struct ReferenceSubtitles {
+    dir: PathBuf,                               // SRT files with `SxxExx` in their names
+    download: Option<Arc<OpenSubtitlesClient>>, // fetches the missing episodes into `dir`
+    loaded: Mutex<Option<Vec<Arc<Reference>>>>,
+    downloads: Mutex<Downloads>, // one file downloads at a time
+}
+
+#[derive(Default)]
+struct Downloads {
+    tried: HashSet<(u64, u64)>, // episodes downloaded or found missing this run
+    quota_used: bool,           // or downloads failed, nothing more is downloaded this run
+}
+
+impl ReferenceSubtitles {
+    pub fn new(dir: PathBuf, download: Option<Arc<OpenSubtitlesClient>>) -> Self {
+        Self {
+            dir,
+            download,
+            loaded: Mutex::new(None),
+            downloads: Mutex::new(Downloads::default()),
+        }
+    }
+
+    /// The references read so far, those in the directory on first use. The lock is only
+    /// held to copy the list, so files are matched in parallel.
+    fn references(&self, ocr_pool: &ocr::OcrEnginePool) -> Result<Vec<Arc<Reference>>> {
+        let mut loaded = self.loaded.lock().unwrap();
+        if loaded.is_none() {
+            let references = load_references(&self.dir, ocr_pool)?;
+            *loaded = Some(references.into_iter().map(Arc::new).collect());
+        }
+        Ok(loaded.clone().unwrap_or_default())
+    }
+
+    /// Downloads the references of the episodes missing one, one at a time, until one of
+    /// them matches the file. Stops at the first failure, e.g. once the daily download
+    /// quota is used up, or when the quota says it is.
+    fn download_until_match(
+        &self,
+        client: &OpenSubtitlesClient,
+        show_name: &str,
+        episodes: &[&EpisodeEntry],
+        language: &str,
+        file: &Shingles,
+        ocr_pool: &ocr::OcrEnginePool,
+    ) -> Result<(Vec<Arc<Reference>>, Option<ReferenceMatch>)> {
+        let mut downloads = self.downloads.lock().unwrap();
+        // Another file may have downloaded more while this one waited
+        let mut references = self.references(ocr_pool)?;
+        if let Some(found) = best_reference_match(file, references.iter().map(|r| &**r)) {
+            return Ok((references, Some(found)));
+        }
+
+        fs::create_dir_all(&self.dir)?;
+        for episode in episodes {
+            if downloads.quota_used {
+                break;
+            }
+            let (season, number) = (episode.season_number, episode.episode_number);
+            let present = references
+                .iter()
+                .any(|reference| reference.0 == (season, number));
+            if present || !downloads.tried.insert((season, number)) {
+                continue;
+            }
+
+            println!("Downloading reference subtitles of S{season:02}E{number:02}...");
+            let download =
+                match client.download_episode_subtitle(show_name, season, number, language) {
+                    Ok(Some(download)) => download,
+                    Ok(None) => {
+                        println!("OpenSubtitles has no subtitles of S{season:02}E{number:02}.");
+                        continue;
+                    }
+                    Err(e) => {
+                        eprintln!("Warning: Stopped downloading reference subtitles: {e}");
+                        downloads.quota_used = true;
+                        break;
+                    }
+                };
+            if download.remaining.is_some_and(|remaining| remaining <= 0) {
+                println!("The OpenSubtitles download quota is used up for today.");
+                downloads.quota_used = true;
+            }
+            let path = self.dir.join(format!("S{season:02}E{number:02}.srt"));
+            fs::write(&path, &download.srt)?;
+            let reference = Arc::new(((season, number), read_reference(&path, ocr_pool)?));
+            self.loaded
+                .lock()
+                .unwrap()
+                .get_or_insert_with(Vec::new)
+                .push(reference.clone());
+            references.push(reference);
+
+            if let Some(found) = best_reference_match(file, references.iter().map(|r| &**r)) {
+                return Ok((references, Some(found)));
+            }
+        }
+        Ok((references, None))
+    }
+}
+
+/// Identifies an episode by comparing the dialogue of its subtitles with reference
+/// subtitles of every episode, which works for shows without production codes on screen.
+pub struct ReferenceMatcher {
+    pub references: Arc<ReferenceSubtitles>,
+    pub language: String, // ISO 639-2 code of the subtitle track to read
+    pub track_preference: subtitles::TrackPreference,
+    pub windows: Vec<ffmpeg::FrameWindow>, // parts of the video to read, all if empty
+    pub ocr_pool: Arc<ocr::OcrEnginePool>,
+    pub interactive: bool, // ask when several tracks fit equally well
+}
+
+impl Matcher for ReferenceMatcher {
+    fn match_episode(
+        &self,
+        file_path: &Path,
+        series_id: &str,
+        cache: &mut Cache,
+    ) -> Result<Vec<ScoredMatch>> {
+        let mut references = self.references.references(&self.ocr_pool)?;
+        if references.is_empty() && self.references.download.is_none() {
+            println!("No reference subtitles in {:?}.", self.references.dir);
+            return Ok(Vec::new());
+        }
+
+        let track = subtitles::find_best_subtitle_track(
+            file_path,
+            &self.language,
+            &self.track_preference,
+            self.interactive,
+            &self.ocr_pool,
+        )?;
+        println!("Using subtitle track {} ({:?})", track.index, track.codec);
+
+        let temp_dir = tempfile::TempDir::new()?;
+        let subtitle_path =
+            subtitles::extract_subtitles(file_path, track.index, &track.codec, temp_dir.path())?;
+        let window = subtitles::SubtitleWindow::new(file_path, &self.windows)?;
+        let text = subtitles::read_text(&subtitle_path, &track.codec, &self.ocr_pool, &window)?;
+        let file = shingles(&text.join("\n"));
+
+        let episodes = cache.get_series_episodes(series_id);
+        let mut found = best_reference_match(&file, references.iter().map(|r| &**r));
+        if let (None, Some(client)) = (found, &self.references.download) {
+            let show_name = cache
+                .get_series_name(series_id)
+                .cloned()
+                .unwrap_or_default();
+            (references, found) = self.references.download_until_match(
+                client,
+                &show_name,
+                &episodes,
+                &self.language,
+                &file,
+                &self.ocr_pool,
+            )?;
+        }
+        let covered = episodes
+            .iter()
+            .filter(|episode| {
+                let episode = (episode.season_number, episode.episode_number);
+                references.iter().any(|reference| reference.0 == episode)
+            })
+            .count();
+        if covered < episodes.len() {
+            println!(
+                "Only {covered} of {} episodes have reference subtitles, the file may be one of the others.",
+                episodes.len()
+            );
+        }
+
+        let Some(((season, episode), similarity)) = found else {
+            println!("No reference subtitles match the file's confidently.");
+            return Ok(Vec::new());
+        };
+        println!(
+            "Subtitles match the reference of S{season:02}E{episode:02} ({:.0}% similar)",
+            similarity * 100.0
+        );
+        match cache.get_episode_by_sxxexx(series_id, season, episode) {
+            Some(entry) => Ok(vec![ScoredMatch::new(
+                vec![entry.clone()],
+                REFERENCE_MATCH_SCORE,
+            )]),
+            None => {
+                println!("S{season:02}E{episode:02} isn't in the episode list.");
+                Ok(Vec::new())
+            }
+        }
+    }
+
+    fn source(&self) -> &'static str {
+        "reference subtitles"
+    }
+}
+
+/// The SRT files of the directory by the episode their name holds.
+fn reference_files(dir: &Path) -> Result<Vec<((u64, u64), PathBuf)>> {
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir).with_context(|| format!("Failed to read {dir:?}"))? {
+        let path = entry?.path();
+        if !path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("srt"))
+        {
+            continue;
+        }
+        let episode = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(parse_episode_numbers);
+        if let Some(episode) = episode {
+            files.push((episode, path));
+        }
+    }
+    Ok(files)
+}
+
+fn load_references(dir: &Path, ocr_pool: &ocr::OcrEnginePool) -> Result<Vec<Reference>> {
+    let mut references = Vec::new();
+    for (episode, path) in reference_files(dir)? {
+        match read_reference(&path, ocr_pool) {
+            Ok(shingles) => references.push((episode, shingles)),
+            Err(e) => eprintln!("Warning: Failed to read {path:?}: {e}"),
+        }
+    }
+    Ok(references)
+}
+
+fn read_reference(path: &Path, ocr_pool: &ocr::OcrEnginePool) -> Result<Shingles> {
+    let all = subtitles::SubtitleWindow::default();
+    let text = subtitles::read_text(path, &subtitles::SubtitleCodec::Srt, ocr_pool, &all)?;
+    Ok(shingles(&text.join("\n")))
+}
+
+/// The reference containing the largest share of the file's shingles, if it clearly beats
+/// the rest. Containment rather than overlap, since the file may be read only in part.
+fn best_reference_match<'a>(
+    file: &Shingles,
+    references: impl IntoIterator<Item = &'a Reference>,
+) -> Option<ReferenceMatch> {
+    if file.is_empty() {
+        return None;
+    }
+    let mut scores: Vec<((u64, u64), f64)> = references
+        .into_iter()
+        .map(|(episode, shingles)| {
+            let shared = file.intersection(shingles).count();
+            (*episode, shared as f64 / file.len() as f64)
+        })
+        .collect();
+    scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    let (best, similarity) = *scores.first()?;
+    let runner_up = scores.get(1).map_or(0.0, |(_, similarity)| *similarity);
+    (similarity >= MIN_SIMILARITY && similarity >= runner_up * MIN_LEAD)
+        .then_some((best, similarity))
+}
+
+/// Runs of consecutive lowercase words of the dialogue. Sound descriptions of SDH
+/// subtitles like `[door slams]` are left out, since only some tracks have them.
+fn shingles(text: &str) -> Shingles {
+    let mut dialogue = String::new();
+    let mut depth = 0;
+    for c in text.chars() {
+        match c {
+            // An unclosed bracket doesn't hide the rest of the subtitles
+            '\n' => {
+                depth = 0;
+                dialogue.push(c);
+            }
+            '[' | '(' => depth += 1,
+            ']' | ')' => depth = (depth - 1).max(0),
+            c if depth == 0 => dialogue.push(c),
+            _ => {}
+        }
+    }
+    let words: Vec<String> = dialogue
+        .split(|c: char| !(c.is_alphanumeric() || c == '\''))
+        .map(|word| word.replace('\'', "").to_lowercase())
+        .filter(|word| !word.is_empty())
+        .collect();
+    words
+        .windows(SHINGLE_WORDS)
+        .map(|window| window.join(" "))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shingles() {
+        let shingles = shingles("I don't know.\n[door slams]\nWho's there?");
+        assert!(shingles.contains("i dont know"));
+        assert!(shingles.contains("dont know whos"));
+        assert!(shingles.contains("know whos there"));
+        assert!(!shingles.iter().any(|shingle| shingle.contains("door")));
+    }
+
+    #[test]
+    fn test_shingles_unclosed_bracket() {
+        let shingles = shingles("[door slams\nWho's there?\n(laughs) I don't know.");
+        assert!(shingles.contains("whos there i"));
+        assert!(shingles.contains("i dont know"));
+        assert!(!shingles.iter().any(|shingle| shingle.contains("door")));
+        assert!(!shingles.iter().any(|shingle| shingle.contains("laughs")));
+    }
+
+    #[test]
+    fn test_best_reference_match() {
+        let references = vec![
+            (
+                (1, 1),
+                shingles("The truth is out there. Trust no one, Mulder."),
+            ),
+            (
+                (1, 3),
+                shingles("He got in through the air vents. The livers were missing."),
+            ),
+        ];
+        let file = shingles("He got in through the vents. The livers were missing.");
+        let ((season, episode), similarity) = best_reference_match(&file, &references).unwrap();
+        assert_eq!((season, episode), (1, 3));
+        assert!(similarity > 0.5);
+
+        assert!(
+            best_reference_match(&shingles("Nothing alike at all here."), &references).is_none()
+        );
+        assert!(best_reference_match(&Shingles::new(), &references).is_none());
+    }
+}
@@ -1,14 +1,41 @@
 use anyhow::{anyhow, bail, Result};
 use rustyline::error::ReadlineError;
 use rustyline::DefaultEditor;
+use std::collections::{HashMap, HashSet};
 use std::path::Path;
+use std::sync::Arc;
 
-use super::Matcher;
+use super::filename::parse_absolute_number;
+use super::{Matcher, ScoredMatch};
 use crate::domain::models::EpisodeEntry;
 use crate::infra::cache::Cache;
-use crate::media::{ocr, subtitles};
+use crate::media::{ffmpeg, ocr, subtitles};
 
-pub struct SubtitleMatcher;
+// Minimum score of the best episode, and how far ahead of the runner-up it must be
+const MIN_SCORE: f64 = 3.0;
+const MIN_LEAD: f64 = 1.5;
+// Score of an episode that clearly matches, overviews only summarize an episode
+const TEXT_MATCH_SCORE: f32 = 0.85;
+
+// Words too common in dialogue and overviews to tell episodes apart
+const COMMON_WORDS: &[&str] = &[
+    "about", "after", "again", "also", "back", "been", "before", "being", "come", "could", "does",
+    "doing", "down", "even", "from", "going", "have", "here", "into", "just", "know", "like",
+    "look", "make", "more", "much", "must", "never", "only", "other", "over", "really", "right",
+    "should", "some", "take", "tell", "than", "that", "their", "them", "then", "there", "these",
+    "they", "thing", "think", "this", "those", "through", "time", "want", "well", "were", "what",
+    "when", "where", "which", "while", "will", "with", "would", "your",
+];
+
+pub struct SubtitleMatcher {
+    pub language: String, // ISO 639-2 code of the subtitle track to read
+    pub track_preference: subtitles::TrackPreference,
+    pub windows: Vec<ffmpeg::FrameWindow>, // parts of the video to read, all if empty
+    pub ocr_pool: Arc<ocr::OcrEnginePool>,
+    pub auto: bool,     // propose the episode from the subtitle text before prompting
+    pub absolute: bool, // accept absolute episode numbers at the prompt
+    pub interactive: bool, // show the subtitles and ask when the text doesn't tell
+}
 
 impl Matcher for SubtitleMatcher {
     fn match_episode(
@@ -16,42 +43,157 @@ impl Matcher for SubtitleMatcher {
         file_path: &Path,
         series_id: &str,
         cache: &mut Cache,
-    ) -> Result<Option<EpisodeEntry>> {
-        let track = subtitles::find_best_subtitle_track(file_path)?;
+    ) -> Result<Vec<ScoredMatch>> {
+        let track = subtitles::find_best_subtitle_track(
+            file_path,
+            &self.language,
+            &self.track_preference,
+            self.interactive,
+            &self.ocr_pool,
+        )?;
         println!("Using subtitle track {} ({:?})", track.index, track.codec);
 
         let temp_dir = tempfile::TempDir::new()?;
         let subtitle_path =
             subtitles::extract_subtitles(file_path, track.index, &track.codec, temp_dir.path())?;
         println!("Extracted subtitle to {subtitle_path:?}");
+        let window = subtitles::SubtitleWindow::new(file_path, &self.windows)?;
 
-        let ocr_engine = match track.codec {
-            subtitles::SubtitleCodec::Pgs => Some(ocr::create_ocr_engine()?),
-            _ => None,
-        };
+        if self.auto || !self.interactive {
+            let text = subtitles::read_text(&subtitle_path, &track.codec, &self.ocr_pool, &window)?;
+            let episodes = cache.get_series_episodes(series_id);
+            if let Some((episode, score)) = best_subtitle_match(&text.join("\n"), &episodes) {
+                println!(
+                    "Subtitles match S{}E{} - {} (score {score:.1})",
+                    episode.season_number, episode.episode_number, episode.name
+                );
+                return Ok(vec![ScoredMatch::new(
+                    vec![episode.clone()],
+                    TEXT_MATCH_SCORE,
+                )]);
+            }
+            if !self.interactive {
+                println!("No episode matches the subtitles confidently.");
+                return Ok(Vec::new());
+            }
+            println!("No episode matches the subtitles confidently, please identify it manually.");
+        }
 
-        subtitles::process_and_display(&subtitle_path, &track.codec, ocr_engine)?;
+        subtitles::process_and_display(&subtitle_path, &track.codec, &self.ocr_pool, &window)?;
 
-        let (season, episode) = get_sxxexx_from_stdin()?;
+        // Absolute number -> season and episode, for shows numbered in absolute order
+        let absolute: HashMap<u64, (u64, u64)> = if self.absolute {
+            cache
+                .get_series_episodes(series_id)
+                .into_iter()
+                .filter_map(|episode| {
+                    Some((
+                        episode.absolute_number?,
+                        (episode.season_number, episode.episode_number),
+                    ))
+                })
+                .collect()
+        } else {
+            HashMap::new()
+        };
+        let (season, episode) = get_sxxexx_from_stdin(&absolute)?;
         match cache.get_episode_by_sxxexx(series_id, season, episode) {
-            Some(ep) => Ok(Some(ep.clone())),
+            Some(ep) => Ok(vec![ScoredMatch::certain(ep.clone())]),
             None => {
                 eprintln!(
                     "Failed to find episode matching 'S{season}E{episode}' in cache for series {series_id}"
                 );
-                Ok(None)
+                Ok(Vec::new())
             }
         }
     }
+
+    fn source(&self) -> &'static str {
+        if self.auto {
+            "subtitle text"
+        } else {
+            "subtitles (manual)"
+        }
+    }
 }
 
-fn get_sxxexx_from_stdin() -> Result<(u64, u64)> {
-    println!("Please enter SXXEXX (e.g. S01E01):");
+/// Scores every episode by the words of its name and overview that occur in the subtitle
+/// text, weighting words that appear in few episodes higher (inverse document frequency),
+/// and returns the best one if it clearly beats the rest.
+fn best_subtitle_match<'a>(
+    subtitle_text: &str,
+    episodes: &[&'a EpisodeEntry],
+) -> Option<(&'a EpisodeEntry, f64)> {
+    let dialogue = terms(subtitle_text);
+    let episode_terms: Vec<HashSet<String>> = episodes
+        .iter()
+        .map(|episode| {
+            let overview = episode.overview.as_deref().unwrap_or_default();
+            terms(&format!("{} {overview}", episode.name))
+        })
+        .collect();
+
+    let mut document_frequency: HashMap<&str, usize> = HashMap::new();
+    for term in episode_terms.iter().flatten() {
+        *document_frequency.entry(term).or_default() += 1;
+    }
+
+    let mut scores: Vec<(f64, &EpisodeEntry)> = episodes
+        .iter()
+        .zip(&episode_terms)
+        .map(|(episode, terms)| {
+            let score = terms
+                .iter()
+                .filter(|term| dialogue.contains(*term))
+                .map(|term| (episodes.len() as f64 / document_frequency[term.as_str()] as f64).ln())
+                .sum::<f64>();
+            (score, *episode)
+        })
+        .collect();
+    scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+    let (best_score, best) = *scores.first()?;
+    let runner_up = scores.get(1).map_or(0.0, |(score, _)| *score);
+    (best_score >= MIN_SCORE && best_score >= runner_up * MIN_LEAD).then_some((best, best_score))
+}
+
+/// Distinctive lowercase words of a text, with a plural "s" removed.
+fn terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() >= 4)
+        .map(|word| {
+            let word = word.to_lowercase();
+            match word.strip_suffix('s') {
+                Some(stem) if !stem.ends_with('s') => stem.to_string(),
+                _ => word,
+            }
+        })
+        .filter(|word| !COMMON_WORDS.contains(&word.as_str()))
+        .collect()
+}
+
+/// Reads SXXEXX, or an absolute number if `absolute` maps them to season and episode.
+fn get_sxxexx_from_stdin(absolute: &HashMap<u64, (u64, u64)>) -> Result<(u64, u64)> {
+    if absolute.is_empty() {
+        println!("Please enter SXXEXX (e.g. S01E01):");
+    } else {
+        println!("Please enter SXXEXX (e.g. S01E01) or the absolute number (e.g. E0153):");
+    }
     let mut rl = DefaultEditor::new()?;
     loop {
         let readline = rl.readline(">> ");
         match readline {
             Ok(line) => match parse_sxxexx(line.trim()) {
+                Err(_) if !absolute.is_empty() => {
+                    match parse_absolute_number(line.trim())
+                        .and_then(|number| absolute.get(&number))
+                    {
+                        Some(&sxxexx) => return Ok(sxxexx),
+                        None => {
+                            println!("Invalid format or unknown absolute number. Please try again.")
+                        }
+                    }
+                }
                 Ok((season, episode)) => return Ok((season, episode)),
                 Err(_) => println!("Invalid format. Please try again (e.g. S01E01)."),
             },
@@ -81,3 +223,60 @@ fn parse_sxxexx(input: &str) -> Result<(u64, u64)> {
         .parse()?;
     Ok((season, episode))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(episode_number: u64, name: &str, overview: &str) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number,
+            name: name.to_string(),
+            overview: Some(overview.to_string()),
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_best_subtitle_match() {
+        let pilot = episode(
+            1,
+            "Pilot",
+            "Agents investigate the deaths of teenagers in Oregon who may be victims of abductions.",
+        );
+        let squeeze = episode(
+            3,
+            "Squeeze",
+            "Agents investigate a killer who can stretch through vents and eats human livers.",
+        );
+        let ice = episode(
+            8,
+            "Ice",
+            "Agents investigate the deaths of a research team at an Arctic outpost infected by a worm.",
+        );
+        let episodes = vec![&pilot, &squeeze, &ice];
+
+        let dialogue =
+            "He got in through the air vents.\nThe livers were missing.\nWho squeezes into a vent?";
+        let (matched, _) = best_subtitle_match(dialogue, &episodes).unwrap();
+        assert_eq!(matched.name, "Squeeze");
+
+        // Words every overview shares don't identify anything
+        assert!(best_subtitle_match("The agents investigate the deaths.", &episodes).is_none());
+        assert!(best_subtitle_match("", &episodes).is_none());
+    }
+
+    #[test]
+    fn test_terms() {
+        let terms = terms("The Livers, and VENTS! Was this gas?");
+        assert!(terms.contains("liver"));
+        assert!(terms.contains("vent"));
+        assert!(!terms.contains("this"));
+        assert!(!terms.contains("the"));
+    }
+}
@@ -1,3 +1,22 @@
+pub mod batch;
+pub mod detect;
+pub mod duplicates;
+pub mod gaps;
+pub mod import;
+pub mod library;
+pub mod mapping;
 pub mod matchers;
+pub mod nfo;
+pub mod parallel;
+pub mod plan;
+pub mod prompt;
 pub mod renamer;
+pub mod report;
+pub mod scan;
+pub mod search;
+pub mod sonarr;
+pub mod space;
+pub mod tui;
+pub mod undo;
+pub mod verify;
 
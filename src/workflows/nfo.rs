@@ -0,0 +1,104 @@
+use anyhow::Result;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::domain::models::EpisodeEntry;
+
+/// Writes a Kodi `<stem>.nfo` with the metadata of the file's episodes next to a video
+/// file, so media centers don't have to scrape it again. Returns the path of the NFO.
+pub fn write(
+    video_path: &Path,
+    show_name: &str,
+    series_id: &str,
+    episodes: &[EpisodeEntry],
+) -> Result<PathBuf> {
+    // Kodi reads one <episodedetails> per episode of a multi-episode file
+    let mut nfo = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n");
+    for episode in episodes {
+        nfo.push_str(&episode_details(show_name, series_id, episode));
+    }
+
+    let nfo_path = video_path.with_extension("nfo");
+    fs::write(&nfo_path, nfo)?;
+    Ok(nfo_path)
+}
+
+fn episode_details(show_name: &str, series_id: &str, episode: &EpisodeEntry) -> String {
+    let mut nfo = String::from("<episodedetails>\n");
+    let mut element = |name: &str, value: &str| {
+        nfo.push_str(&format!("  <{name}>{}</{name}>\n", escape(value)));
+    };
+    element("title", &episode.name);
+    element("showtitle", show_name);
+    element("season", &episode.season_number.to_string());
+    element("episode", &episode.episode_number.to_string());
+    if let Some(aired) = &episode.aired {
+        element("aired", aired);
+    }
+    if let Some(overview) = &episode.overview {
+        element("plot", overview);
+    }
+    if let Some(id) = episode.id {
+        // TVmaze series are cached as `tvmaze:<id>`, their episode IDs are TVmaze's too
+        let provider = if series_id.starts_with("tvmaze:") {
+            "tvmaze"
+        } else {
+            "tvdb"
+        };
+        nfo.push_str(&format!(
+            "  <uniqueid type=\"{provider}\" default=\"true\">{id}</uniqueid>\n"
+        ));
+    }
+    nfo.push_str("</episodedetails>\n");
+    nfo
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_episode_details() {
+        let episode = EpisodeEntry {
+            production_code: Some("1X03".to_string()),
+            season_number: 1,
+            episode_number: 3,
+            name: "Squeeze".to_string(),
+            overview: Some("Mulder & Scully hunt a <mutant>.".to_string()),
+            aired: Some("1993-09-24".to_string()),
+            runtime: None,
+            id: Some(184601),
+            absolute_number: None,
+        };
+
+        assert_eq!(
+            episode_details("The X-Files", "77398", &episode),
+            r#"<episodedetails>
+  <title>Squeeze</title>
+  <showtitle>The X-Files</showtitle>
+  <season>1</season>
+  <episode>3</episode>
+  <aired>1993-09-24</aired>
+  <plot>Mulder &amp; Scully hunt a &lt;mutant&gt;.</plot>
+  <uniqueid type="tvdb" default="true">184601</uniqueid>
+</episodedetails>
+"#
+        );
+
+        let unknown = EpisodeEntry {
+            overview: None,
+            aired: None,
+            ..episode
+        };
+        let nfo = episode_details("The X-Files", "tvmaze:430", &unknown);
+        assert!(nfo.contains(r#"<uniqueid type="tvmaze" default="true">184601</uniqueid>"#));
+        assert!(!nfo.contains("<plot>") && !nfo.contains("<aired>"));
+    }
+}
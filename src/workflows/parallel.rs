@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// Runs `work` for every item on `jobs` worker threads and passes the results to `consume`
+/// on the calling thread in the order of the items. Consuming starts as soon as the first
+/// result is ready, so slow interactive steps in `consume` overlap with the remaining work.
+/// Breaking out of `consume` stops the workers from starting on further items.
+pub fn for_each_ordered<T, R>(
+    items: &[T],
+    jobs: usize,
+    work: impl Fn(&T) -> R + Sync,
+    mut consume: impl FnMut(&T, R) -> ControlFlow<()>,
+) where
+    T: Sync,
+    R: Send,
+{
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel();
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, items.len().max(1)) {
+            let sender = sender.clone();
+            let (next, stop, work) = (&next, &stop, &work);
+            scope.spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    if sender.send((index, work(item))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Results arrive in completion order, hold back the ones that are ahead
+        let mut ready = HashMap::new();
+        for (index, item) in items.iter().enumerate() {
+            let result = loop {
+                if let Some(result) = ready.remove(&index) {
+                    break result;
+                }
+                let Ok((done, result)) = receiver.recv() else {
+                    return;
+                };
+                ready.insert(done, result);
+            };
+
+            if consume(item, result).is_break() {
+                stop.store(true, Ordering::Relaxed);
+                break;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_for_each_ordered() {
+        let items: Vec<u64> = (0..20).collect();
+        let mut consumed = Vec::new();
+        for_each_ordered(
+            &items,
+            4,
+            |item| {
+                // Finish out of order
+                thread::sleep(Duration::from_millis((20 - item) % 7));
+                item * 2
+            },
+            |item, result| {
+                consumed.push((*item, result));
+                ControlFlow::Continue(())
+            },
+        );
+        assert_eq!(
+            consumed,
+            items
+                .iter()
+                .map(|item| (*item, item * 2))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_for_each_ordered_stops_early() {
+        let items: Vec<u64> = (0..100).collect();
+        let worked = AtomicUsize::new(0);
+        let mut consumed = 0;
+        for_each_ordered(
+            &items,
+            2,
+            |_| {
+                worked.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(1));
+            },
+            |item, ()| {
+                consumed += 1;
+                if *item == 4 {
+                    ControlFlow::Break(())
+                } else {
+                    ControlFlow::Continue(())
+                }
+            },
+        );
+        assert_eq!(consumed, 5);
+        assert!(worked.load(Ordering::Relaxed) < items.len());
+    }
+
+    #[test]
+    fn test_for_each_ordered_empty() {
+        let items: Vec<u64> = Vec::new();
+        for_each_ordered(&items, 4, |_| (), |_, ()| panic!("nothing to consume"));
+    }
+}
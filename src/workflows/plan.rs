@@ -0,0 +1,168 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A rename a dry run would have made.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PlannedRename {
+    pub old_path: PathBuf,
+    pub new_path: PathBuf,
+    pub series_id: String,
+    pub season_number: u64,
+    pub episode_number: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_episode_number: Option<u64>, // of a multi-episode file
+    pub episode_name: String,
+    pub source: String, // how the episode was identified
+}
+
+pub fn save(path: &Path, plan: &[PlannedRename]) -> Result<()> {
+    let content = serde_json::to_string_pretty(plan)?;
+    fs::write(path, content)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Vec<PlannedRename>> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read rename plan {path:?}"))?;
+    serde_json::from_str(&content).with_context(|| format!("Invalid rename plan {path:?}"))
+}
+
+/// Checks that a planned rename can still be made: the file must still exist and the new
+/// name must be free unless `overwrite` is set, since either may have changed while the
+/// plan was being reviewed.
+pub fn validate(entry: &PlannedRename, overwrite: bool) -> Result<()> {
+    if !entry.old_path.is_file() {
+        bail!("{:?} no longer exists", entry.old_path);
+    }
+    if !overwrite && entry.new_path.exists() && entry.new_path != entry.old_path {
+        bail!("{:?} is already taken", entry.new_path);
+    }
+    if let Some(parent) = entry.new_path.parent() {
+        // Missing directories (e.g. season folders of --organize) are created
+        if parent.exists() && !parent.is_dir() {
+            bail!("{parent:?} is not a directory");
+        }
+    }
+    Ok(())
+}
+
+/// Formats the plan as a table with one row per file.
+pub fn format_table(plan: &[PlannedRename]) -> String {
+    let header = ["File", "New name", "Episode", "Source"];
+    let rows: Vec<[String; 4]> = plan
+        .iter()
+        .map(|entry| {
+            [
+                file_name(&entry.old_path),
+                file_name(&entry.new_path),
+                format!(
+                    "S{:02}E{:02}{} - {}",
+                    entry.season_number,
+                    entry.episode_number,
+                    entry
+                        .last_episode_number
+                        .map(|last| format!("-E{last:02}"))
+                        .unwrap_or_default(),
+                    entry.episode_name
+                ),
+                entry.source.clone(),
+            ]
+        })
+        .collect();
+
+    format_columns(header, &rows)
+}
+
+/// Formats rows as columns padded to their widest cell, under a header and a separator.
+pub fn format_columns<const N: usize>(header: [&str; N], rows: &[[String; N]]) -> String {
+    let mut widths = header.map(|title| title.chars().count());
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let format_row = |cells: [&str; N]| {
+        let line = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        format!("{}\n", line.trim_end())
+    };
+
+    let separator = widths.map(|width| "-".repeat(width));
+    let mut table = format_row(header);
+    table.push_str(&format_row(separator.each_ref().map(String::as_str)));
+    for row in rows {
+        table.push_str(&format_row(row.each_ref().map(String::as_str)));
+    }
+    table
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn planned(old: &str, new: &str) -> PlannedRename {
+        PlannedRename {
+            old_path: PathBuf::from(old),
+            new_path: PathBuf::from(new),
+            series_id: "77398".to_string(),
+            season_number: 1,
+            episode_number: 3,
+            last_episode_number: None,
+            episode_name: "Squeeze".to_string(),
+            source: "production code".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_format_table() {
+        let table = format_table(&[planned(
+            "/tv/title_t02.mkv",
+            "/tv/The X-Files - S01E03 - Squeeze.mkv",
+        )]);
+        assert_eq!(
+            table,
+            "File           New name                            Episode           Source\n\
+             -------------  ----------------------------------  ----------------  ---------------\n\
+             title_t02.mkv  The X-Files - S01E03 - Squeeze.mkv  S01E03 - Squeeze  production code\n"
+        );
+    }
+
+    #[test]
+    fn test_save_and_load() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("plan.json");
+        let plan = vec![planned("/tv/a.mkv", "/tv/b.mkv")];
+        save(&path, &plan).unwrap();
+        assert_eq!(load(&path).unwrap(), plan);
+    }
+
+    #[test]
+    fn test_validate() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::write(dir.join("a.mkv"), "").unwrap();
+        fs::write(dir.join("taken.mkv"), "").unwrap();
+        let path = |name: &str| dir.join(name).to_string_lossy().into_owned();
+
+        assert!(validate(&planned(&path("a.mkv"), &path("b.mkv")), false).is_ok());
+        assert!(validate(&planned(&path("a.mkv"), &path("a.mkv")), false).is_ok());
+        assert!(validate(&planned(&path("gone.mkv"), &path("b.mkv")), false).is_err());
+        assert!(validate(&planned(&path("a.mkv"), &path("taken.mkv")), false).is_err());
+        assert!(validate(&planned(&path("a.mkv"), &path("taken.mkv")), true).is_ok());
+        assert!(validate(&planned(&path("a.mkv"), &path("missing/b.mkv")), false).is_ok());
+        assert!(validate(&planned(&path("a.mkv"), &path("taken.mkv/b.mkv")), true).is_err());
+    }
+}
@@ -0,0 +1,108 @@
+use rustyline::DefaultEditor;
+
+/// Answer to a question asked once per file of a batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchAnswer {
+    Yes,
+    No,
+    /// Yes to this and every remaining file
+    All,
+    /// Stop processing the batch
+    Quit,
+}
+
+/// Asks a yes/no question on the terminal, an empty answer counts as no.
+pub fn confirm(question: &str) -> bool {
+    println!("{question} [y/N] ");
+
+    let mut rl = DefaultEditor::new().unwrap();
+    loop {
+        let input = rl.readline("").unwrap_or_default();
+        let input = input.trim().to_lowercase();
+
+        if input == "y" || input == "yes" {
+            return true;
+        } else if input == "n" || input == "no" || input.is_empty() {
+            return false;
+        } else {
+            println!("Please enter 'y' or 'n'.");
+        }
+    }
+}
+
+/// Asks to pick one of the choices by number, `None` for an empty answer.
+pub fn choose(question: &str, choices: &[String]) -> Option<usize> {
+    println!("{question}");
+    for (number, choice) in choices.iter().enumerate() {
+        println!("  {}) {choice}", number + 1);
+    }
+    println!("Enter a number, or nothing to skip:");
+
+    let mut rl = DefaultEditor::new().unwrap();
+    loop {
+        let input = rl.readline(">> ").unwrap_or_default();
+        match parse_choice(&input, choices.len()) {
+            Ok(choice) => return choice,
+            Err(()) => println!("Please enter a number from 1 to {}.", choices.len()),
+        }
+    }
+}
+
+fn parse_choice(input: &str, count: usize) -> Result<Option<usize>, ()> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Ok(None);
+    }
+    match input.parse::<usize>() {
+        Ok(number) if (1..=count).contains(&number) => Ok(Some(number - 1)),
+        _ => Err(()),
+    }
+}
+
+/// Asks a question about one file of a batch, an empty answer counts as no.
+pub fn ask_batch(question: &str) -> BatchAnswer {
+    println!("{question} [y/N/a/q] ");
+
+    let mut rl = DefaultEditor::new().unwrap();
+    loop {
+        let input = rl.readline("").unwrap_or_default();
+        match parse_batch_answer(&input) {
+            Some(answer) => return answer,
+            None => println!("Please enter 'y' (yes), 'n' (no), 'a' (yes to all) or 'q' (quit)."),
+        }
+    }
+}
+
+fn parse_batch_answer(input: &str) -> Option<BatchAnswer> {
+    match input.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(BatchAnswer::Yes),
+        "n" | "no" | "" => Some(BatchAnswer::No),
+        "a" | "all" => Some(BatchAnswer::All),
+        "q" | "quit" => Some(BatchAnswer::Quit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_batch_answer() {
+        assert_eq!(parse_batch_answer("y"), Some(BatchAnswer::Yes));
+        assert_eq!(parse_batch_answer(" YES\n"), Some(BatchAnswer::Yes));
+        assert_eq!(parse_batch_answer(""), Some(BatchAnswer::No));
+        assert_eq!(parse_batch_answer("a"), Some(BatchAnswer::All));
+        assert_eq!(parse_batch_answer("q"), Some(BatchAnswer::Quit));
+        assert_eq!(parse_batch_answer("maybe"), None);
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        assert_eq!(parse_choice("2\n", 3), Ok(Some(1)));
+        assert_eq!(parse_choice("", 3), Ok(None));
+        assert_eq!(parse_choice("4", 3), Err(()));
+        assert_eq!(parse_choice("0", 3), Err(()));
+        assert_eq!(parse_choice("two", 3), Err(()));
+    }
+}
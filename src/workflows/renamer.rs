@@ -1,41 +1,535 @@
-use rustyline::DefaultEditor;
-use std::fs;
+use std::fs::{self, File};
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+use std::thread;
+use std::time::Duration;
 
-use anyhow::Result;
-
-pub fn generate_filename(
-    show_name: &str,
-    season: u64,
-    episode: u64,
-    episode_title: &str,
-) -> String {
-    format!(
-        "{} - S{:02}E{:02} - {}.mkv",
-        sanitize_filename(show_name),
-        season,
-        episode,
-        sanitize_filename(episode_title)
-    )
-}
-
-fn sanitize_filename(name: &str) -> String {
-    // Remove or replace invalid filename characters
-    name.chars()
-        .map(|c| match c {
-            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
-            c => c,
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+
+use super::prompt::{self, BatchAnswer};
+use crate::cli::LinkMode;
+use crate::config::{ConflictPolicy, FilenamePolicy};
+use crate::domain::models::{EpisodeEntry, MediaInfo};
+
+/// How often a rename that fails with a transient error (e.g. EBUSY on an SMB share
+/// while a media server has the file open) is retried, and how long to wait in between.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub initial_backoff: Duration, // doubled after every attempt
+    pub verify_checksum: bool,     // compare copies with the original, retrying if they differ
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RenameOutcome {
+    /// With the checksum of the copy if the content was copied and verified
+    Renamed {
+        checksum: Option<String>,
+    },
+    Unchanged,
+    Skipped,
+    /// Still failing with a transient error after all retries, worth trying again later
+    Deferred,
+    /// The user asked to stop the batch
+    Quit,
+}
+
+/// Built-in naming templates, selectable by name with `--name-template`.
+const PRESETS: &[(&str, &str)] = &[
+    ("default", "{show} - S{season:02}E{episode:02} - {title}"),
+    // Plex's form without the series year, which `{year}` (of the episode's airing) isn't
+    ("plex", "{show} - S{season:02}E{episode:02} - {title}"),
+    ("jellyfin", "{show} S{season:02}E{episode:02} - {title}"),
+    // Keeps the production code, which identifies episodes across orderings
+    (
+        "archive",
+        "{show} - S{season:02}E{episode:02} [{code}] - {title}",
+    ),
+];
+
+// Names Windows reserves for devices, also with an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const EXTENSION: &str = ".mkv";
+
+/// Room a shortened name leaves for the ` [copy N]` suffix of [`unique_filename`], enough
+/// for up to 99 copies.
+const COPY_SUFFIX_ROOM: usize = " [copy 99]".len();
+
+// Videos that share a stem with another video are other episodes, not its sidecars
+pub const VIDEO_EXTENSIONS: &[&str] = &["mkv", "mp4", "m4v", "avi", "ts"];
+
+/// A file naming template like `{show} - S{season:02}E{episode:02} - {title}`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameTemplate {
+    parts: Vec<TemplatePart>,
+    specials: Option<Vec<TemplatePart>>, // for season 0, if it's named differently
+    filenames: FilenamePolicy,           // how values are sanitized and names shortened
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TemplatePart {
+    Literal(String),
+    Field {
+        field: Field,
+        width: usize,             // zero-padded to `width` digits
+        filters: Vec<TextFilter>, // in the order they're applied
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Show,
+    Season,
+    Episode,
+    Title,
+    Year, // of the first airing
+    Code, // production code
+    // Of the file rather than the episodes, see `MediaInfo`
+    Resolution,
+    VideoCodec,
+    Channels,
+    Hdr,
+}
+
+/// A modifier of a text placeholder, e.g. `{title|ascii}`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TextFilter {
+    Ascii,      // transliterated, `Pokémon` becomes `Pokemon`
+    NoBrackets, // without `(...)` and `[...]`
+}
+
+impl TextFilter {
+    fn apply(self, text: &str) -> String {
+        match self {
+            // Characters with no ASCII equivalent are dropped rather than turned into `[?]`
+            Self::Ascii => deunicode::deunicode_with_tofu(text, ""),
+            Self::NoBrackets => strip_brackets(text),
+        }
+    }
+}
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self::parse("default").expect("built-in template is valid")
+    }
+}
+
+impl NameTemplate {
+    /// Parses a template or the name of a preset (`default`, `plex`, `jellyfin`, `archive`).
+    pub fn parse(template: &str) -> Result<Self> {
+        let template = PRESETS
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(template))
+            .map_or(template, |(_, preset)| preset);
+        if template.contains(['/', '\\']) {
+            bail!("Name template '{template}' must not contain path separators");
+        }
+
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            if rest[start..].starts_with('}') {
+                bail!("Unmatched '}}' in name template '{template}'");
+            }
+            if start > 0 {
+                parts.push(TemplatePart::Literal(rest[..start].to_string()));
+            }
+            let Some(end) = rest[start..].find('}') else {
+                bail!("Unmatched '{{' in name template '{template}'");
+            };
+            parts.push(parse_placeholder(&rest[start + 1..start + end], template)?);
+            rest = &rest[start + end + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(TemplatePart::Literal(rest.to_string()));
+        }
+        Ok(Self {
+            parts,
+            specials: None,
+            filenames: FilenamePolicy::default(),
         })
-        .collect::<String>()
-        .trim()
+    }
+
+    /// Names the specials of season 0 by another template, e.g. `{show} - Special - {title}`.
+    pub fn with_specials(mut self, specials: NameTemplate) -> Self {
+        self.specials = Some(specials.parts);
+        self
+    }
+
+    pub fn with_filenames(mut self, filenames: FilenamePolicy) -> Self {
+        self.filenames = filenames;
+        self
+    }
+
+    /// Whether the template has placeholders like `{resolution}` that need the file probed.
+    pub fn uses_media(&self) -> bool {
+        self.parts
+            .iter()
+            .chain(self.specials.iter().flatten())
+            .any(|part| {
+                matches!(
+                    part,
+                    TemplatePart::Field {
+                        field: Field::Resolution | Field::VideoCodec | Field::Channels | Field::Hdr,
+                        ..
+                    }
+                )
+            })
+    }
+
+    /// Renders the file name of the episodes of a file in `directory`, `S01E01-E02` style
+    /// for a multi-episode file. Fields without a value (e.g. a missing production code, or
+    /// the `{resolution}` of a file that wasn't probed) are left empty, and so are the
+    /// brackets around them. The name is shortened to fit the filesystem's limits, see
+    /// [`FilenamePolicy`].
+    pub fn render(
+        &self,
+        directory: &Path,
+        show_name: &str,
+        episodes: &[EpisodeEntry],
+        media: Option<&MediaInfo>,
+    ) -> String {
+        let mut max_length = self.filenames.max_name_length;
+        if let Some(max_path_length) = self.filenames.max_path_length {
+            // The directory and the separator before the name
+            let available = max_path_length.saturating_sub(directory.as_os_str().len() + 1);
+            max_length = max_length.min(available);
+        }
+        self.render_within(show_name, episodes, media, max_length)
+    }
+
+    /// The `<root>/<Show>/Season XX` directory of a season in a media library.
+    pub fn season_dir(&self, root: &Path, show_name: &str, season: u64) -> PathBuf {
+        root.join(avoid_reserved(&sanitize_filename(
+            show_name,
+            &self.filenames,
+        )))
+        .join(format!("Season {season:02}"))
+    }
+
+    /// Renders a name of at most `max_length` bytes, minus the room of a ` [copy N]` suffix
+    /// so a conflicting name still fits. A name that's too long loses the end of its title,
+    /// at a word boundary if possible, and only the end of the whole name if the title alone
+    /// can't make it fit.
+    fn render_within(
+        &self,
+        show_name: &str,
+        episodes: &[EpisodeEntry],
+        media: Option<&MediaInfo>,
+        max_length: usize,
+    ) -> String {
+        let titles: Vec<String> = episodes
+            .iter()
+            .map(|episode| self.normalize_title(&episode.name))
+            .collect();
+        let title = sanitize_filename(&titles.join(" & "), &self.filenames);
+        let max_stem = max_length.saturating_sub(EXTENSION.len() + COPY_SUFFIX_ROOM);
+
+        let mut name = self.render_stem(show_name, episodes, media, &title);
+        let excess = name.len().saturating_sub(max_stem);
+        if excess > 0 && excess < title.len() {
+            let title = shorten(&title, title.len() - excess);
+            name = self.render_stem(show_name, episodes, media, title);
+        }
+        if name.len() > max_stem {
+            name = shorten(&name, max_stem).to_string();
+        }
+        format!("{}{EXTENSION}", avoid_reserved(&name))
+    }
+
+    /// The title with the normalization of the `[filenames]` section.
+    fn normalize_title(&self, title: &str) -> String {
+        let mut title = title.to_string();
+        if self.filenames.strip_brackets {
+            title = TextFilter::NoBrackets.apply(&title);
+        }
+        if self.filenames.ascii {
+            title = TextFilter::Ascii.apply(&title);
+        }
+        title
+    }
+
+    fn render_stem(
+        &self,
+        show_name: &str,
+        episodes: &[EpisodeEntry],
+        media: Option<&MediaInfo>,
+        title: &str,
+    ) -> String {
+        let Some(first) = episodes.first() else {
+            return String::new();
+        };
+        let parts = match &self.specials {
+            Some(specials) if first.season_number == 0 => specials,
+            _ => &self.parts,
+        };
+        let mut name = String::new();
+        // The bracket opening in front of fields left empty so far, with the space before
+        // it, and the bracket closing it. Put back if a later field in the brackets has a
+        // value, like the `x265` of `[{resolution} {vcodec}]`.
+        let mut unclosed: Option<(String, char)> = None;
+        let mut after_empty = false; // the last field was left empty
+        for part in parts {
+            match part {
+                TemplatePart::Literal(text) => {
+                    let mut text = text.as_str();
+                    if let Some((reopen, close)) = unclosed.take() {
+                        if let Some(rest) = text.strip_prefix(close) {
+                            text = rest;
+                        } else if text.trim().is_empty() {
+                            unclosed = Some((reopen, close)); // between fields in the brackets
+                            continue;
+                        } else {
+                            name.push_str(&reopen);
+                        }
+                    } else if after_empty && text.starts_with([']', ')']) {
+                        name.truncate(name.trim_end().len()); // `[1080p]` rather than `[1080p ]`
+                    }
+                    after_empty = false;
+                    name.push_str(text);
+                }
+                TemplatePart::Field {
+                    field,
+                    width,
+                    filters,
+                } => {
+                    let mut value = match field {
+                        Field::Show => sanitize_filename(show_name, &self.filenames),
+                        Field::Title => title.to_string(),
+                        Field::Season => format!("{:0width$}", first.season_number),
+                        Field::Episode => {
+                            // Repeat the letter in front of the number, e.g. `E01-E02`.
+                            // Plex and Sonarr read that as a range, so episodes that aren't
+                            // consecutive go without the dash, e.g. `E03E07`
+                            let consecutive = episodes
+                                .windows(2)
+                                .all(|pair| pair[1].episode_number == pair[0].episode_number + 1);
+                            let separator = match name.chars().last() {
+                                Some(c) if c.is_alphabetic() && consecutive => format!("-{c}"),
+                                Some(c) if c.is_alphabetic() => c.to_string(),
+                                _ => "-".to_string(),
+                            };
+                            episodes
+                                .iter()
+                                .map(|episode| format!("{:0width$}", episode.episode_number))
+                                .collect::<Vec<_>>()
+                                .join(&separator)
+                        }
+                        Field::Year => first
+                            .aired
+                            .as_deref()
+                            .and_then(|aired| aired.get(..4))
+                            .unwrap_or_default()
+                            .to_string(),
+                        Field::Code => {
+                            let codes: Vec<&str> = episodes
+                                .iter()
+                                .filter_map(|episode| episode.production_code.as_deref())
+                                .collect();
+                            sanitize_filename(&codes.join("-"), &self.filenames)
+                        }
+                        Field::Resolution => {
+                            media.and_then(MediaInfo::resolution).unwrap_or_default()
+                        }
+                        Field::VideoCodec => media
+                            .and_then(MediaInfo::codec_label)
+                            .map(|codec| sanitize_filename(&codec, &self.filenames))
+                            .unwrap_or_default(),
+                        Field::Channels => media
+                            .and_then(MediaInfo::channel_layout)
+                            .unwrap_or_default(),
+                        Field::Hdr => match media {
+                            Some(media) if media.hdr => "HDR".to_string(),
+                            _ => String::new(),
+                        },
+                    };
+                    if !filters.is_empty() {
+                        let filtered = filters
+                            .iter()
+                            .fold(value, |value, filter| filter.apply(&value));
+                        value = sanitize_filename(&filtered, &self.filenames);
+                    }
+                    after_empty = value.is_empty();
+                    if let Some((reopen, close)) = unclosed.take() {
+                        if value.is_empty() {
+                            unclosed = Some((reopen, close));
+                        } else {
+                            name.push_str(&reopen);
+                        }
+                    } else if value.is_empty() {
+                        let close = match name.chars().last() {
+                            Some('[') => Some(']'),
+                            Some('(') => Some(')'),
+                            _ => None,
+                        };
+                        if let Some(close) = close {
+                            let kept = name[..name.len() - 1].trim_end().len();
+                            unclosed = Some((name.split_off(kept), close));
+                        }
+                    }
+                    name.push_str(&value);
+                }
+            }
+        }
+        name.trim().to_string()
+    }
+}
+
+fn parse_placeholder(placeholder: &str, template: &str) -> Result<TemplatePart> {
+    let mut modifiers = placeholder.split('|');
+    let placeholder = modifiers.next().unwrap_or_default();
+    let (name, spec) = match placeholder.split_once(':') {
+        Some((name, spec)) => (name, Some(spec)),
+        None => (placeholder, None),
+    };
+    let field = match name {
+        "show" => Field::Show,
+        "season" => Field::Season,
+        "episode" => Field::Episode,
+        "title" => Field::Title,
+        "year" => Field::Year,
+        "code" => Field::Code,
+        "resolution" => Field::Resolution,
+        "vcodec" => Field::VideoCodec,
+        "channels" => Field::Channels,
+        "hdr" => Field::Hdr,
+        _ => bail!("Unknown placeholder '{{{name}}}' in name template '{template}'"),
+    };
+    let width = match spec {
+        None => 0,
+        Some(spec) if matches!(field, Field::Season | Field::Episode) => spec
+            .strip_prefix('0')
+            .and_then(|width| width.parse().ok())
+            .with_context(|| {
+                format!("Invalid format '{spec}' in name template '{template}', expected e.g. {{{name}:02}}")
+            })?,
+        Some(_) => bail!("Only {{season}} and {{episode}} take a format in name template '{template}'"),
+    };
+    let filters = modifiers
+        .map(|modifier| match modifier {
+            "ascii" => Ok(TextFilter::Ascii),
+            "nobrackets" => Ok(TextFilter::NoBrackets),
+            _ => bail!("Unknown modifier '|{modifier}' in name template '{template}', expected ascii or nobrackets"),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    if !filters.is_empty() && !matches!(field, Field::Show | Field::Title) {
+        bail!("Only {{show}} and {{title}} take modifiers in name template '{template}'");
+    }
+    Ok(TemplatePart::Field {
+        field,
+        width,
+        filters,
+    })
+}
+
+/// A parenthesized or bracketed part of a title and the whitespace before it.
+static BRACKETED: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\s*(?:\([^()]*\)|\[[^\[\]]*\])").unwrap());
+
+/// Drops the parenthesized and bracketed parts of a title, like `(a.k.a. "The One")`,
+/// unless nothing else is left.
+fn strip_brackets(text: &str) -> String {
+    let stripped = BRACKETED.replace_all(text, "");
+    match stripped.trim() {
+        "" => text.to_string(),
+        stripped => stripped.to_string(),
+    }
+}
+
+/// Replaces the characters Windows doesn't allow in file names (which includes the path
+/// separators) and control characters, and drops the trailing dots and spaces Windows
+/// doesn't allow either.
+fn sanitize_filename(name: &str, filenames: &FilenamePolicy) -> String {
+    let mut sanitized = String::new();
+    for c in name.chars() {
+        match filenames.replace.get(&c) {
+            Some(replacement) => sanitized.push_str(replacement),
+            None if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|')
+                || c.is_control() =>
+            {
+                sanitized.push_str(&filenames.replacement)
+            }
+            None => sanitized.push(c),
+        }
+    }
+    if filenames.collapse_whitespace {
+        sanitized = sanitized.split_whitespace().collect::<Vec<_>>().join(" ");
+    }
+    sanitized
+        .trim_start()
+        .trim_end_matches(['.', ' '])
         .to_string()
 }
 
+/// Appends `_` to a name Windows reserves for a device, like `Aux` or `Con.mkv`.
+fn avoid_reserved(name: &str) -> String {
+    let base = name.split('.').next().unwrap_or(name).trim_end();
+    if RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(base))
+    {
+        format!("{base}_{}", &name[base.len()..])
+    } else {
+        name.to_string()
+    }
+}
+
+/// Cuts the text to at most `max_length` bytes, at the last word boundary if that keeps
+/// most of it, without leaving separators or dots at the end.
+fn shorten(text: &str, max_length: usize) -> &str {
+    let mut end = max_length.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let mut shortened = &text[..end];
+    if !text[end..].is_empty() && !text[end..].starts_with(' ') {
+        if let Some(space) = shortened.rfind(' ').filter(|&space| space >= end / 2) {
+            shortened = &shortened[..space];
+        }
+    }
+    shortened.trim_end_matches([' ', '.', ',', '-', '&'])
+}
+
+/// Creates `<link_dir>/<filename>` as a symlink to the file, so the proposed library can be
+/// scanned by a media server without touching the real files.
+pub fn link_preview(file_path: &Path, link_dir: &Path, filename: &str) -> Result<PathBuf> {
+    fs::create_dir_all(link_dir)?;
+
+    let link_path = link_dir.join(filename);
+    if link_path.symlink_metadata().is_ok() {
+        fs::remove_file(&link_path)?;
+    }
+
+    let target = fs::canonicalize(file_path)?;
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(&target, &link_path)?;
+    #[cfg(windows)]
+    std::os::windows::fs::symlink_file(&target, &link_path)?;
+
+    Ok(link_path)
+}
+
 pub fn find_unique_filename(old_path: &Path, directory: &Path, base_filename: &str) -> PathBuf {
+    unique_filename(old_path, directory, base_filename, &[])
+}
+
+/// Like [`find_unique_filename`], also passing over the new names of the `planned` renames.
+pub fn unique_filename(
+    old_path: &Path,
+    directory: &Path,
+    base_filename: &str,
+    planned: &[PathBuf],
+) -> PathBuf {
     let mut path = directory.join(base_filename);
     let mut counter = 1;
 
-    while path.exists() && path.to_string_lossy() != old_path.to_string_lossy() {
+    while (path.exists() && !is_same_file(old_path, &path)) || planned.contains(&path) {
         let stem = Path::new(base_filename)
             .file_stem()
             .and_then(|s| s.to_str())
@@ -53,49 +547,411 @@ pub fn find_unique_filename(old_path: &Path, directory: &Path, base_filename: &s
     path
 }
 
-pub fn confirm_rename(old_path: &Path, new_path: &Path) -> bool {
-    println!(
-        "Rename \"{}\" -> \"{}\"? [y/N] ",
-        old_path.file_name().unwrap().to_string_lossy(),
+/// Whether both paths are the file, also when one is a hardlink or symlink of the other,
+/// which a run that kept the original already linked to its new name.
+pub fn is_same_file(old_path: &Path, new_path: &Path) -> bool {
+    if old_path.to_string_lossy() == new_path.to_string_lossy() {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (fs::metadata(old_path), fs::metadata(new_path)) {
+            (Ok(old), Ok(new)) => old.dev() == new.dev() && old.ino() == new.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        matches!(
+            (fs::canonicalize(old_path), fs::canonicalize(new_path)),
+            (Ok(old), Ok(new)) if old == new
+        )
+    }
+}
+
+fn verb(link_mode: LinkMode) -> &'static str {
+    match link_mode {
+        LinkMode::Rename => "Rename",
+        LinkMode::Hardlink => "Hardlink",
+        LinkMode::Copy => "Copy",
+        LinkMode::Symlink => "Symlink",
+    }
+}
+
+/// The new path of a file named `filename` in `directory` when that name may be taken by
+/// another file, `None` if the file should keep its old name. The new names of the
+/// `planned` renames of a dry run count as taken, their files aren't there yet.
+pub fn resolve_conflict(
+    file_path: &Path,
+    directory: &Path,
+    filename: &str,
+    on_conflict: ConflictPolicy,
+    planned: &[PathBuf],
+) -> Option<PathBuf> {
+    let target = directory.join(filename);
+    let taken = if planned.contains(&target) {
+        "is the new name of another file"
+    } else if target.exists() && !is_same_file(file_path, &target) {
+        "already exists"
+    } else {
+        return Some(target);
+    };
+    match on_conflict {
+        ConflictPolicy::Suffix => Some(unique_filename(file_path, directory, filename, planned)),
+        ConflictPolicy::Skip => {
+            eprintln!("{target:?} {taken}, leaving the file alone.");
+            None
+        }
+        ConflictPolicy::Overwrite => Some(target),
+        ConflictPolicy::Prompt => {
+            if prompt::confirm(&format!("{target:?} {taken}. Overwrite it?")) {
+                Some(target)
+            } else {
+                Some(unique_filename(file_path, directory, filename, planned))
+            }
+        }
+    }
+}
+
+pub fn confirm_rename(old_path: &Path, new_path: &Path, link_mode: LinkMode) -> BatchAnswer {
+    // Show the whole destination when the file also moves to another directory
+    let destination = if old_path.parent() == new_path.parent() {
         new_path.file_name().unwrap().to_string_lossy()
-    );
+    } else {
+        new_path.to_string_lossy()
+    };
+    prompt::ask_batch(&format!(
+        "{} \"{}\" -> \"{destination}\"?",
+        verb(link_mode),
+        old_path.file_name().unwrap().to_string_lossy(),
+    ))
+}
+
+/// Renames the file, or links or copies it to the new name, asking first unless
+/// `skip_confirm` is set. Answering "yes to all" sets `skip_confirm` for the rest of the
+/// batch.
+pub fn rename_file(
+    old_path: &Path,
+    new_path: &Path,
+    skip_confirm: &mut bool,
+    link_mode: LinkMode,
+    retry: &RetryPolicy,
+) -> Result<RenameOutcome> {
+    if is_same_file(old_path, new_path) {
+        println!("File is already named correctly.");
+        return Ok(RenameOutcome::Unchanged);
+    }
+    if !*skip_confirm {
+        match confirm_rename(old_path, new_path, link_mode) {
+            BatchAnswer::Yes => {}
+            BatchAnswer::All => *skip_confirm = true,
+            BatchAnswer::No => {
+                println!("Skipped.");
+                return Ok(RenameOutcome::Skipped);
+            }
+            BatchAnswer::Quit => {
+                println!("Stopping.");
+                return Ok(RenameOutcome::Quit);
+            }
+        }
+    }
 
-    let mut rl = DefaultEditor::new().unwrap();
+    match transfer_with_retry(old_path, new_path, link_mode, retry) {
+        Ok(checksum) => {
+            let done = match link_mode {
+                LinkMode::Rename => "Renamed",
+                LinkMode::Hardlink => "Hardlinked",
+                LinkMode::Copy => "Copied",
+                LinkMode::Symlink => "Symlinked",
+            };
+            println!("{done} successfully.");
+            Ok(RenameOutcome::Renamed { checksum })
+        }
+        Err(e) if is_transient(&e) => {
+            println!("Rename failed ({e}), deferring it to the end of the batch.");
+            Ok(RenameOutcome::Deferred)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn rename_with_retry(old_path: &Path, new_path: &Path, retry: &RetryPolicy) -> io::Result<()> {
+    transfer_with_retry(old_path, new_path, LinkMode::Rename, retry).map(|_| ())
+}
+
+/// Gives the file its new name the way `link_mode` says, retrying transient errors. Returns
+/// the checksum (XXH3 in hex) of the copy if the content was copied and verified.
+pub fn transfer_with_retry(
+    old_path: &Path,
+    new_path: &Path,
+    link_mode: LinkMode,
+    retry: &RetryPolicy,
+) -> io::Result<Option<String>> {
+    let mut backoff = retry.initial_backoff;
+    let mut attempt = 0;
     loop {
-        let input = rl.readline("").unwrap_or_default();
-        let input = input.trim().to_lowercase();
+        match transfer(old_path, new_path, link_mode, retry.verify_checksum) {
+            Err(e) if is_transient(&e) && attempt < retry.retries => {
+                attempt += 1;
+                eprintln!(
+                    "Warning: Rename failed ({e}), retrying in {}ms ({attempt}/{})",
+                    backoff.as_millis(),
+                    retry.retries
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+            }
+            result => return result,
+        }
+    }
+}
 
-        if input == "y" || input == "yes" {
-            return true;
-        } else if input == "n" || input == "no" || input.is_empty() {
-            return false;
-        } else {
-            println!("Please enter 'y' or 'n'.");
+/// Creates the directories of the new path and renames, links or copies the file to it.
+/// Moves to another filesystem copy the file and delete the original, hardlinks can't
+/// cross filesystems either and become copies.
+fn transfer(
+    old_path: &Path,
+    new_path: &Path,
+    link_mode: LinkMode,
+    verify_checksum: bool,
+) -> io::Result<Option<String>> {
+    if let Some(parent) = new_path.parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    match link_mode {
+        LinkMode::Rename => match fs::rename(old_path, new_path) {
+            Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                let checksum = copy_file(old_path, new_path, verify_checksum)?;
+                fs::remove_file(old_path)?;
+                Ok(checksum)
+            }
+            result => result.map(|()| None),
+        },
+        LinkMode::Hardlink => {
+            match replace_with(new_path, |partial| fs::hard_link(old_path, partial)) {
+                Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+                    eprintln!("Warning: {new_path:?} is on another filesystem than the file, copying it instead of hardlinking");
+                    copy_file(old_path, new_path, verify_checksum)
+                }
+                result => result.map(|()| None),
+            }
+        }
+        LinkMode::Copy => copy_file(old_path, new_path, verify_checksum),
+        LinkMode::Symlink => {
+            let target = fs::canonicalize(old_path)?;
+            #[cfg(unix)]
+            let linked = replace_with(new_path, |partial| {
+                std::os::unix::fs::symlink(&target, partial)
+            });
+            #[cfg(windows)]
+            let linked = replace_with(new_path, |partial| {
+                std::os::windows::fs::symlink_file(&target, partial)
+            });
+            linked.map(|()| None)
         }
     }
 }
 
-pub fn rename_file(old_path: &Path, new_path: &Path, skip_confirm: bool) -> Result<()> {
-    if old_path.to_string_lossy() == new_path.to_string_lossy() {
-        println!("File is already named correctly.");
-        return Ok(());
+/// Copies to a temporary name first so that an interrupted copy isn't mistaken for the
+/// episode. With `verify_checksum`, a copy whose content differs from the original's, e.g.
+/// one silently truncated on a network share, fails with a [`ChecksumMismatch`] and is
+/// removed. Returns the verified checksum in hex.
+fn copy_file(
+    old_path: &Path,
+    new_path: &Path,
+    verify_checksum: bool,
+) -> io::Result<Option<String>> {
+    copy_file_with(old_path, new_path, verify_checksum, |from, to| {
+        fs::copy(from, to).map(|_| ())
+    })
+}
+
+/// [`copy_file`] with the function that copies the content.
+fn copy_file_with(
+    old_path: &Path,
+    new_path: &Path,
+    verify_checksum: bool,
+    copy: impl FnOnce(&Path, &Path) -> io::Result<()>,
+) -> io::Result<Option<String>> {
+    let mut verified = None;
+    replace_with(new_path, |partial| {
+        let before = verify_checksum.then(|| checksum(old_path)).transpose()?;
+        copy(old_path, partial)?;
+        match before {
+            Some(before) if checksum(partial)? != before => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                ChecksumMismatch(old_path.to_path_buf()),
+            )),
+            before => {
+                verified = before.map(|before| format!("{before:032x}"));
+                Ok(())
+            }
+        }
+    })?;
+    Ok(verified)
+}
+
+/// The copy of a file doesn't have the original's content.
+#[derive(Debug)]
+struct ChecksumMismatch(PathBuf);
+
+impl std::fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "The copy of {:?} doesn't match the original", self.0)
     }
-    if !skip_confirm && !confirm_rename(old_path, new_path) {
-        println!("Skipped.");
-        return Ok(());
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+/// The XXH3 hash of a file's content, fast enough to not slow down copies much.
+pub fn checksum(path: &Path) -> io::Result<u128> {
+    let mut file = File::open(path)?;
+    let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+    let mut buffer = vec![0; 1 << 20];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => return Ok(hasher.digest128()),
+            read => hasher.update(&buffer[..read]),
+        }
     }
+}
 
-    fs::rename(old_path, new_path)?;
-    println!("Renamed successfully.");
+/// Creates the file at a temporary name and renames it to `new_path`, which replaces a
+/// file of that name in one step (links can't be created over an existing file).
+fn replace_with(new_path: &Path, create: impl FnOnce(&Path) -> io::Result<()>) -> io::Result<()> {
+    let mut partial = new_path.as_os_str().to_owned();
+    partial.push(".part");
+    let partial = PathBuf::from(partial);
+    if let Err(e) = create(&partial).and_then(|()| fs::rename(&partial, new_path)) {
+        let _ = fs::remove_file(&partial);
+        return Err(e);
+    }
     Ok(())
 }
 
+/// The files next to a video that belong to it, like `<stem>.en.srt`, `<stem>.nfo` or
+/// `<stem>-thumb.jpg`, with what follows the stem in their names.
+pub fn sidecar_files(video_path: &Path) -> Vec<(PathBuf, String)> {
+    let (Some(video_name), Some(stem)) = (
+        video_path.file_name(),
+        video_path.file_stem().and_then(|stem| stem.to_str()),
+    ) else {
+        return Vec::new();
+    };
+    let directory = match video_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = fs::read_dir(directory) else {
+        return Vec::new();
+    };
+
+    let mut sidecars = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name() == video_name || !path.is_file() {
+            continue;
+        }
+        let suffix = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.strip_prefix(stem))
+            .filter(|suffix| is_sidecar_suffix(suffix))
+            .map(str::to_string);
+        if let Some(suffix) = suffix {
+            sidecars.push((path, suffix));
+        }
+    }
+    sidecars.sort();
+    sidecars
+}
+
+/// `.nfo`, `.en.srt` or `-thumb.jpg`, but not the `-E02.srt` of the next episode or the
+/// `.part2.mkv` of another video.
+fn is_sidecar_suffix(suffix: &str) -> bool {
+    let extension = suffix.rsplit('.').next().unwrap_or_default();
+    if VIDEO_EXTENSIONS
+        .iter()
+        .any(|video| video.eq_ignore_ascii_case(extension))
+    {
+        return false;
+    }
+    match suffix.strip_prefix('-') {
+        Some(artwork) => artwork.split_once('.').is_some_and(|(kind, _)| {
+            !kind.is_empty() && kind.chars().all(|c| c.is_ascii_alphabetic())
+        }),
+        None => suffix.len() > 1 && suffix.starts_with('.'),
+    }
+}
+
+/// Gives the sidecar files of a video that got its new name the same stem, the way
+/// `link_mode` says. Sidecars whose new name is taken, or that fail, are left alone.
+/// Returns the old and new paths of those that were moved.
+pub fn move_sidecars(
+    old_video: &Path,
+    new_video: &Path,
+    link_mode: LinkMode,
+    retry: &RetryPolicy,
+) -> Vec<(PathBuf, PathBuf)> {
+    let Some(new_stem) = new_video.file_stem().and_then(|stem| stem.to_str()) else {
+        return Vec::new();
+    };
+    let mut moved = Vec::new();
+    for (old_path, suffix) in sidecar_files(old_video) {
+        let new_path = new_video.with_file_name(format!("{new_stem}{suffix}"));
+        if new_path.exists() {
+            eprintln!("Warning: Not moving {old_path:?}, {new_path:?} already exists");
+            continue;
+        }
+        match transfer_with_retry(&old_path, &new_path, link_mode, retry) {
+            Ok(_) => {
+                println!("Moved sidecar {old_path:?} -> {new_path:?}");
+                moved.push((old_path, new_path));
+            }
+            Err(e) => eprintln!("Warning: Failed to move sidecar {old_path:?}: {e}"),
+        }
+    }
+    moved
+}
+
+/// Deletes the new name of a file that was linked or copied there, undoing
+/// [`transfer`]. Refuses if the original is gone, the new file may be the only copy left.
+pub fn remove_link(original: &Path, linked: &Path) -> Result<()> {
+    if !original.is_file() {
+        bail!("the original file has been moved or deleted since, keeping the copy");
+    }
+    fs::remove_file(linked).with_context(|| format!("Failed to delete {linked:?}"))
+}
+
+/// Errors network filesystems return while another client holds the file, and copies
+/// that don't match the original.
+fn is_transient(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ResourceBusy
+            | io::ErrorKind::StaleNetworkFileHandle
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+    ) || error
+        .get_ref()
+        .is_some_and(|inner| inner.is::<ChecksumMismatch>())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::collections::HashMap;
     use std::fs::File;
     use tempfile::TempDir;
 
+    fn sanitize_filename(name: &str) -> String {
+        super::sanitize_filename(name, &FilenamePolicy::default())
+    }
+
     #[test]
     fn test_sanitize_filename() {
         assert_eq!(sanitize_filename("Normal Name"), "Normal Name");
@@ -115,20 +971,359 @@ mod tests {
         assert_eq!(sanitize_filename("Name>With>Greater"), "Name-With-Greater");
         assert_eq!(sanitize_filename("Name|With|Pipes"), "Name-With-Pipes");
         assert_eq!(sanitize_filename("  Trim Me  "), "Trim Me");
+        assert_eq!(
+            sanitize_filename("Agents of S.H.I.E.L.D. "),
+            "Agents of S.H.I.E.L.D"
+        );
+        assert_eq!(sanitize_filename("Tab\tNewline"), "Tab-Newline");
+
+        let filenames = FilenamePolicy {
+            replacement: String::new(),
+            replace: HashMap::from([(':', " -".to_string())]),
+            ..FilenamePolicy::default()
+        };
+        assert_eq!(
+            super::sanitize_filename("Star Trek: What?", &filenames),
+            "Star Trek - What"
+        );
+        assert_eq!(sanitize_filename("Two  Spaces"), "Two  Spaces");
+        let filenames = FilenamePolicy {
+            collapse_whitespace: true,
+            ..FilenamePolicy::default()
+        };
+        assert_eq!(
+            super::sanitize_filename("Two  Spaces", &filenames),
+            "Two Spaces"
+        );
     }
 
     #[test]
-    fn test_generate_filename() {
+    fn test_windows_safe_names() {
+        assert_eq!(avoid_reserved("Con"), "Con_");
+        assert_eq!(avoid_reserved("aux.mkv"), "aux_.mkv");
+        assert_eq!(avoid_reserved("Console.mkv"), "Console.mkv");
+
+        let template = NameTemplate::parse("{title}").unwrap();
+        let nul = EpisodeEntry {
+            name: "Nul".to_string(),
+            ..episode()
+        };
         assert_eq!(
-            generate_filename("Show Name", 1, 1, "Episode Name"),
-            "Show Name - S01E01 - Episode Name.mkv"
+            template.render(Path::new("."), "Show", &[nul], None),
+            "Nul_.mkv"
         );
         assert_eq!(
-            generate_filename("Show: Name", 2, 15, "Ep/isode?"),
-            "Show- Name - S02E15 - Ep-isode-.mkv"
+            template.season_dir(Path::new("/tv"), "PRN", 1),
+            Path::new("/tv/PRN_/Season 01")
         );
     }
 
+    #[test]
+    fn test_shortened_names() {
+        let long = EpisodeEntry {
+            name: "The Very Long Title of an Episode That Keeps Going".to_string(),
+            ..episode()
+        };
+        let template = NameTemplate::default().with_filenames(FilenamePolicy {
+            max_name_length: 40,
+            ..FilenamePolicy::default()
+        });
+        let name = template.render(
+            Path::new("."),
+            "The X-Files",
+            std::slice::from_ref(&long),
+            None,
+        );
+        assert_eq!(name, "The X-Files - S01E03 - The.mkv");
+        // A conflicting name still fits with its suffix
+        let copy = unique_filename(
+            Path::new("a.mkv"),
+            Path::new("."),
+            &name,
+            &[name.clone().into()],
+        );
+        assert!(copy.file_name().unwrap().len() <= 40);
+
+        // The directory counts against the path length
+        let template = NameTemplate::default().with_filenames(FilenamePolicy {
+            max_path_length: Some(50),
+            ..FilenamePolicy::default()
+        });
+        let name = template.render(Path::new("/tv/X"), "The X-Files", &[long], None);
+        assert_eq!(name, "The X-Files - S01E03 - The.mkv");
+        assert!(Path::new("/tv/X").join(&name).as_os_str().len() <= 50);
+
+        // Without a title to shorten, the name itself is cut
+        let template = NameTemplate::parse("{show} {season}x{episode:02}")
+            .unwrap()
+            .with_filenames(FilenamePolicy {
+                max_name_length: 22,
+                ..FilenamePolicy::default()
+            });
+        assert_eq!(
+            template.render(Path::new("."), "The X-Files", &[episode()], None),
+            "The X-Fi.mkv"
+        );
+        assert_eq!(shorten("Grüße", 3), "Gr");
+    }
+
+    fn episode() -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: Some("1X03".to_string()),
+            season_number: 1,
+            episode_number: 3,
+            name: "Squeeze".to_string(),
+            overview: None,
+            aired: Some("1993-09-24".to_string()),
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_name_template() {
+        let episode = episode();
+        let render = |template: &str| {
+            NameTemplate::parse(template).unwrap().render(
+                Path::new("."),
+                "The X-Files",
+                std::slice::from_ref(&episode),
+                None,
+            )
+        };
+
+        assert_eq!(
+            NameTemplate::default().render(
+                Path::new("."),
+                "The X-Files",
+                std::slice::from_ref(&episode),
+                None
+            ),
+            "The X-Files - S01E03 - Squeeze.mkv"
+        );
+        assert_eq!(render("plex"), "The X-Files - S01E03 - Squeeze.mkv");
+        assert_eq!(render("Jellyfin"), "The X-Files S01E03 - Squeeze.mkv");
+        assert_eq!(
+            render("{show} ({year}) - {season}x{episode:03} - {title} [{code}]"),
+            "The X-Files (1993) - 1x003 - Squeeze [1X03].mkv"
+        );
+
+        // Values are sanitized, missing ones are left empty
+        let unknown = EpisodeEntry {
+            production_code: None,
+            aired: None,
+            name: "Ep/isode?".to_string(),
+            ..episode.clone()
+        };
+        assert_eq!(
+            NameTemplate::default().render(
+                Path::new("."),
+                "Show: Name",
+                std::slice::from_ref(&unknown),
+                None
+            ),
+            "Show- Name - S01E03 - Ep-isode-.mkv"
+        );
+        assert_eq!(
+            NameTemplate::parse("{title} {code}").unwrap().render(
+                Path::new("."),
+                "Show",
+                std::slice::from_ref(&unknown),
+                None
+            ),
+            "Ep-isode-.mkv"
+        );
+        assert_eq!(
+            render("archive"),
+            "The X-Files - S01E03 [1X03] - Squeeze.mkv"
+        );
+        assert_eq!(
+            NameTemplate::parse("archive").unwrap().render(
+                Path::new("."),
+                "Show",
+                std::slice::from_ref(&unknown),
+                None
+            ),
+            "Show - S01E03 - Ep-isode-.mkv"
+        );
+        assert_eq!(
+            NameTemplate::parse("{show} ({year}) {title}")
+                .unwrap()
+                .render(Path::new("."), "Show", std::slice::from_ref(&unknown), None),
+            "Show Ep-isode-.mkv"
+        );
+
+        // Multi-episode files
+        let next = EpisodeEntry {
+            production_code: Some("1X04".to_string()),
+            episode_number: 4,
+            name: "Conduit".to_string(),
+            ..episode.clone()
+        };
+        let both = [episode.clone(), next];
+        assert_eq!(
+            NameTemplate::default().render(Path::new("."), "The X-Files", &both, None),
+            "The X-Files - S01E03-E04 - Squeeze & Conduit.mkv"
+        );
+        assert_eq!(
+            NameTemplate::parse("plex")
+                .unwrap()
+                .render(Path::new("."), "The X-Files", &both, None),
+            "The X-Files - S01E03-E04 - Squeeze & Conduit.mkv"
+        );
+        assert_eq!(
+            NameTemplate::parse("{season}x{episode:02} [{code}]")
+                .unwrap()
+                .render(Path::new("."), "The X-Files", &both, None),
+            "1x03-x04 [1X03-1X04].mkv"
+        );
+        let apart = EpisodeEntry {
+            episode_number: 7,
+            ..both[1].clone()
+        };
+        assert_eq!(
+            NameTemplate::default().render(
+                Path::new("."),
+                "The X-Files",
+                &[episode.clone(), apart],
+                None
+            ),
+            "The X-Files - S01E03E07 - Squeeze & Conduit.mkv"
+        );
+
+        // Specials may be named by a template of their own
+        let special = EpisodeEntry {
+            season_number: 0,
+            episode_number: 5,
+            name: "The Truth Is Out There".to_string(),
+            ..episode.clone()
+        };
+        let template = NameTemplate::default()
+            .with_specials(NameTemplate::parse("{show} - Special - {title}").unwrap());
+        assert_eq!(
+            template.render(
+                Path::new("."),
+                "The X-Files",
+                std::slice::from_ref(&special),
+                None
+            ),
+            "The X-Files - Special - The Truth Is Out There.mkv"
+        );
+        assert_eq!(
+            template.render(
+                Path::new("."),
+                "The X-Files",
+                std::slice::from_ref(&episode),
+                None
+            ),
+            "The X-Files - S01E03 - Squeeze.mkv"
+        );
+
+        // Titles are normalized by modifiers or for every placeholder by the config
+        let aka = EpisodeEntry {
+            name: "Pokémon,  Part 1 (a.k.a. \"The One\")".to_string(),
+            ..episode.clone()
+        };
+        let render_aka = |template: NameTemplate| {
+            template.render(Path::new("."), "Show", std::slice::from_ref(&aka), None)
+        };
+        assert_eq!(
+            render_aka(NameTemplate::parse("{title|ascii|nobrackets}").unwrap()),
+            "Pokemon,  Part 1.mkv"
+        );
+        assert_eq!(
+            render_aka(NameTemplate::parse("{title}").unwrap()),
+            "Pokémon,  Part 1 (a.k.a. -The One-).mkv"
+        );
+        let normalized = NameTemplate::parse("{title}")
+            .unwrap()
+            .with_filenames(FilenamePolicy {
+                collapse_whitespace: true,
+                strip_brackets: true,
+                ascii: true,
+                ..FilenamePolicy::default()
+            });
+        assert_eq!(render_aka(normalized), "Pokemon, Part 1.mkv");
+        assert_eq!(strip_brackets("(Untitled)"), "(Untitled)");
+
+        assert!(NameTemplate::parse("{title|upper}").is_err());
+        assert!(NameTemplate::parse("{season|ascii}").is_err());
+        assert!(NameTemplate::parse("{show} - {name}").is_err());
+        assert!(NameTemplate::parse("{show} - {title:02}").is_err());
+        assert!(NameTemplate::parse("{season:2}").is_err());
+        assert!(NameTemplate::parse("{show").is_err());
+        assert!(NameTemplate::parse("show}").is_err());
+        assert!(NameTemplate::parse("{show}/Season {season}/{title}").is_err());
+    }
+
+    #[test]
+    fn test_media_placeholders() {
+        let template = NameTemplate::parse(
+            "{show} - S{season:02}E{episode:02} - {title} [{resolution} {vcodec}]",
+        )
+        .unwrap();
+        assert!(template.uses_media());
+        assert!(!NameTemplate::default().uses_media());
+        let render =
+            |media: &MediaInfo| template.render(Path::new("."), "Show", &[episode()], Some(media));
+
+        let media = MediaInfo {
+            width: Some(1920),
+            height: Some(800),
+            video_codec: Some("hevc".to_string()),
+            audio_channels: Some(6),
+            hdr: false,
+        };
+        assert_eq!(render(&media), "Show - S01E03 - Squeeze [1080p x265].mkv");
+        let anamorphic = MediaInfo {
+            width: Some(1440),
+            height: Some(1080),
+            ..media.clone()
+        };
+        assert_eq!(
+            render(&anamorphic),
+            "Show - S01E03 - Squeeze [1080p x265].mkv"
+        );
+        let letterboxed = MediaInfo {
+            width: Some(1280),
+            height: Some(544),
+            ..media.clone()
+        };
+        assert_eq!(
+            render(&letterboxed),
+            "Show - S01E03 - Squeeze [720p x265].mkv"
+        );
+        let no_codec = MediaInfo {
+            video_codec: None,
+            ..media.clone()
+        };
+        assert_eq!(render(&no_codec), "Show - S01E03 - Squeeze [1080p].mkv");
+        let no_resolution = MediaInfo {
+            width: None,
+            ..media.clone()
+        };
+        assert_eq!(render(&no_resolution), "Show - S01E03 - Squeeze [x265].mkv");
+        assert_eq!(
+            template.render(Path::new("."), "Show", &[episode()], None),
+            "Show - S01E03 - Squeeze.mkv"
+        );
+
+        let hdr = MediaInfo {
+            width: Some(3840),
+            height: Some(2160),
+            hdr: true,
+            ..media
+        };
+        assert_eq!(
+            NameTemplate::parse("{title} ({resolution} {hdr} {channels})")
+                .unwrap()
+                .render(Path::new("."), "Show", &[episode()], Some(&hdr)),
+            "Squeeze (2160p HDR 5.1).mkv"
+        );
+        assert!(NameTemplate::parse("{vcodec:02}").is_err());
+    }
+
     #[test]
     fn test_find_unique_filename_no_conflict() {
         let temp_dir = TempDir::new().unwrap();
@@ -165,6 +1360,265 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_rename_into_new_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let old_path = temp_dir.path().join("title_t00.mkv");
+        File::create(&old_path).unwrap();
+
+        let new_path = NameTemplate::default()
+            .season_dir(&temp_dir.path().join("library"), "Show: Name", 2)
+            .join("Episode.mkv");
+        let retry = RetryPolicy {
+            retries: 0,
+            initial_backoff: Duration::ZERO,
+            verify_checksum: false,
+        };
+        rename_with_retry(&old_path, &new_path, &retry).unwrap();
+        assert!(new_path.is_file() && !old_path.exists());
+        assert!(new_path.ends_with("library/Show- Name/Season 02/Episode.mkv"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_transfer_keeping_original() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        let old_path = dir_path.join("title_t00.mkv");
+        fs::write(&old_path, "video").unwrap();
+        let retry = RetryPolicy {
+            retries: 0,
+            initial_backoff: Duration::ZERO,
+            verify_checksum: false,
+        };
+
+        for (link_mode, filename) in [
+            (LinkMode::Hardlink, "Hardlinked.mkv"),
+            (LinkMode::Copy, "Copied.mkv"),
+            (LinkMode::Symlink, "Symlinked.mkv"),
+        ] {
+            let new_path = dir_path.join("library").join(filename);
+            transfer_with_retry(&old_path, &new_path, link_mode, &retry).unwrap();
+            assert!(old_path.is_file());
+            assert_eq!(fs::read_to_string(&new_path).unwrap(), "video");
+        }
+
+        let copy = dir_path.join("Verified.mkv");
+        let verified = copy_file(&old_path, &copy, true).unwrap();
+        assert_eq!(checksum(&copy).unwrap(), checksum(&old_path).unwrap());
+        assert_eq!(
+            verified,
+            Some(format!("{:032x}", checksum(&old_path).unwrap()))
+        );
+        assert_eq!(copy_file(&old_path, &copy, false).unwrap(), None);
+        fs::write(&copy, "vid").unwrap();
+        assert_ne!(checksum(&copy).unwrap(), checksum(&old_path).unwrap());
+
+        // A link of the file doesn't make its name taken, copies do
+        let library = dir_path.join("library");
+        assert_eq!(
+            find_unique_filename(&old_path, &library, "Hardlinked.mkv"),
+            library.join("Hardlinked.mkv")
+        );
+        assert_eq!(
+            find_unique_filename(&old_path, &library, "Copied.mkv"),
+            library.join("Copied [copy 1].mkv")
+        );
+
+        remove_link(&old_path, &library.join("Copied.mkv")).unwrap();
+        assert!(!library.join("Copied.mkv").exists() && old_path.is_file());
+        fs::remove_file(&old_path).unwrap();
+        assert!(remove_link(&old_path, &library.join("Hardlinked.mkv")).is_err());
+    }
+
+    #[test]
+    fn test_move_sidecars() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        for name in [
+            "title_t00.mkv",
+            "title_t00.en.srt",
+            "title_t00.forced.ger.srt",
+            "title_t00.nfo",
+            "title_t00-thumb.jpg",
+            "title_t00-E02.srt",
+            "title_t00.part2.mkv",
+            "title_t001.srt",
+        ] {
+            File::create(dir_path.join(name)).unwrap();
+        }
+        let old_video = dir_path.join("title_t00.mkv");
+        let suffixes: Vec<String> = sidecar_files(&old_video)
+            .into_iter()
+            .map(|(_, suffix)| suffix)
+            .collect();
+        assert_eq!(
+            suffixes,
+            ["-thumb.jpg", ".en.srt", ".forced.ger.srt", ".nfo"]
+        );
+
+        let new_video = dir_path.join("Show - S01E01 - Pilot.mkv");
+        fs::rename(&old_video, &new_video).unwrap();
+        File::create(dir_path.join("Show - S01E01 - Pilot.nfo")).unwrap();
+        let retry = RetryPolicy {
+            retries: 0,
+            initial_backoff: Duration::ZERO,
+            verify_checksum: false,
+        };
+        let moved = move_sidecars(&old_video, &new_video, LinkMode::Rename, &retry);
+        assert_eq!(moved.len(), 3);
+        assert!(dir_path.join("Show - S01E01 - Pilot.en.srt").is_file());
+        assert!(dir_path.join("Show - S01E01 - Pilot-thumb.jpg").is_file());
+        assert!(!dir_path.join("title_t00.en.srt").exists());
+        // A sidecar whose new name is taken stays
+        assert!(dir_path.join("title_t00.nfo").is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_link_preview() {
+        let temp_dir = TempDir::new().unwrap();
+        let file_path = temp_dir.path().join("title_t00.mkv");
+        File::create(&file_path).unwrap();
+        let preview_root = temp_dir.path().join("preview");
+
+        let filename = "Show- Name - S02E15 - Episode.mkv";
+        let link_dir = NameTemplate::default().season_dir(&preview_root, "Show: Name", 2);
+        let link_path = link_preview(&file_path, &link_dir, filename).unwrap();
+        assert_eq!(
+            link_path,
+            preview_root
+                .join("Show- Name")
+                .join("Season 02")
+                .join("Show- Name - S02E15 - Episode.mkv")
+        );
+        assert_eq!(
+            fs::read_link(&link_path).unwrap(),
+            fs::canonicalize(&file_path).unwrap()
+        );
+
+        // Previewing again replaces the stale link
+        link_preview(&file_path, &link_dir, filename).unwrap();
+        assert!(file_path.exists());
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&io::Error::from(io::ErrorKind::ResourceBusy)));
+        assert!(is_transient(&io::Error::from(
+            io::ErrorKind::StaleNetworkFileHandle
+        )));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::NotFound)));
+        assert!(!is_transient(&io::Error::from(
+            io::ErrorKind::PermissionDenied
+        )));
+        assert!(!is_transient(&io::Error::from(io::ErrorKind::InvalidData)));
+    }
+
+    #[test]
+    fn test_copy_file_mismatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let old_path = temp_dir.path().join("original.mkv");
+        let new_path = temp_dir.path().join("Show - S01E01 - Episode.mkv");
+        fs::write(&old_path, b"the whole episode").unwrap();
+
+        // A copy cut short fails, is retried and leaves nothing behind
+        let truncated = |from: &Path, to: &Path| fs::write(to, &fs::read(from)?[..3]);
+        let error = copy_file_with(&old_path, &new_path, true, truncated).unwrap_err();
+        assert!(is_transient(&error));
+        assert!(!new_path.exists());
+
+        // Unless it isn't verified
+        assert_eq!(
+            copy_file_with(&old_path, &new_path, false, truncated).unwrap(),
+            None
+        );
+        assert_eq!(fs::read(&new_path).unwrap(), b"the");
+
+        let checksum = copy_file(&old_path, &new_path, true).unwrap().unwrap();
+        assert_eq!(checksum.len(), 32);
+        assert_eq!(fs::read(&new_path).unwrap(), b"the whole episode");
+    }
+
+    #[test]
+    fn test_rename_with_retry() {
+        let temp_dir = TempDir::new().unwrap();
+        let old_path = temp_dir.path().join("original.mkv");
+        let new_path = temp_dir.path().join("Show - S01E01 - Episode.mkv");
+        let retry = RetryPolicy {
+            retries: 3,
+            initial_backoff: Duration::from_secs(60),
+            verify_checksum: false,
+        };
+
+        // Permanent errors are returned right away instead of being retried
+        let err = rename_with_retry(&old_path, &new_path, &retry).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        File::create(&old_path).unwrap();
+        rename_with_retry(&old_path, &new_path, &retry).unwrap();
+        assert!(!old_path.exists());
+        assert!(new_path.exists());
+    }
+
+    #[test]
+    fn test_resolve_conflict() {
+        let temp_dir = TempDir::new().unwrap();
+        let dir_path = temp_dir.path();
+        let old_path = dir_path.join("title_t00.mkv");
+        let filename = "Show - S01E01 - Episode.mkv";
+        let resolve =
+            |on_conflict| resolve_conflict(&old_path, dir_path, filename, on_conflict, &[]);
+
+        // A free name is used whatever the policy
+        assert_eq!(resolve(ConflictPolicy::Skip), Some(dir_path.join(filename)));
+
+        File::create(dir_path.join(filename)).unwrap();
+        assert_eq!(
+            resolve(ConflictPolicy::Suffix),
+            Some(dir_path.join("Show - S01E01 - Episode [copy 1].mkv"))
+        );
+        assert_eq!(resolve(ConflictPolicy::Skip), None);
+        assert_eq!(
+            resolve(ConflictPolicy::Overwrite),
+            Some(dir_path.join(filename))
+        );
+
+        // Names planned for other files of a dry run are taken as well
+        let other = "Show - S01E02 - Episode.mkv";
+        let planned = [
+            dir_path.join(other),
+            dir_path.join("Show - S01E02 - Episode [copy 1].mkv"),
+        ];
+        let resolve =
+            |on_conflict| resolve_conflict(&old_path, dir_path, other, on_conflict, &planned);
+        assert_eq!(
+            resolve(ConflictPolicy::Suffix),
+            Some(dir_path.join("Show - S01E02 - Episode [copy 2].mkv"))
+        );
+        assert_eq!(resolve(ConflictPolicy::Skip), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_overwrite_with_link() {
+        let temp_dir = TempDir::new().unwrap();
+        let old_path = temp_dir.path().join("title_t00.mkv");
+        let new_path = temp_dir.path().join("Show - S01E01 - Episode.mkv");
+        fs::write(&old_path, "new").unwrap();
+        fs::write(&new_path, "old").unwrap();
+        let retry = RetryPolicy {
+            retries: 0,
+            initial_backoff: Duration::ZERO,
+            verify_checksum: false,
+        };
+
+        transfer_with_retry(&old_path, &new_path, LinkMode::Symlink, &retry).unwrap();
+        assert_eq!(fs::read_to_string(&new_path).unwrap(), "new");
+        transfer_with_retry(&old_path, &new_path, LinkMode::Hardlink, &retry).unwrap();
+        assert!(is_same_file(&old_path, &new_path));
+    }
+
     #[test]
     fn test_find_unique_filename_same_file() {
         let temp_dir = TempDir::new().unwrap();
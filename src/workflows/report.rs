@@ -0,0 +1,371 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::domain::models::{EpisodeEntry, MediaInfo, OcrCandidate};
+
+/// What happened to a file, see [`FileReport`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Action {
+    Renamed,
+    Unchanged, // already named correctly
+    Planned,   // --dry-run
+    Linked,    // --preview-dir
+    Deferred,  // the file was busy, retried at the end of the run
+    Skipped,
+    #[default]
+    Unmatched,
+    Flagged, // left for manual handling, e.g. a possible multi-part file
+    Failed,
+}
+
+impl Action {
+    // In the order the summary counts them
+    const ALL: [Action; 9] = [
+        Action::Renamed,
+        Action::Unchanged,
+        Action::Planned,
+        Action::Linked,
+        Action::Deferred,
+        Action::Skipped,
+        Action::Unmatched,
+        Action::Flagged,
+        Action::Failed,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::Renamed => "renamed",
+            Action::Unchanged => "unchanged",
+            Action::Planned => "planned",
+            Action::Linked => "linked",
+            Action::Deferred => "deferred",
+            Action::Skipped => "skipped",
+            Action::Unmatched => "unmatched",
+            Action::Flagged => "flagged",
+            Action::Failed => "failed",
+        }
+    }
+}
+
+// Candidates the summary lists for an unmatched file
+const SUMMARY_CANDIDATES: usize = 3;
+
+// Exit codes of a run whose setup succeeded, setup errors exit with 1
+pub const EXIT_UNMATCHED: i32 = 2; // some files weren't matched, all others were renamed
+pub const EXIT_FAILED: i32 = 3; // some files failed
+
+/// Result of one file for `--output json`.
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    pub episodes: Vec<EpisodeEntry>, // several for a multi-episode file
+    pub source: Option<String>,      // how the episodes were identified
+    pub candidates: Vec<OcrCandidate>,
+    pub action: Action,
+    pub new_path: Option<PathBuf>,
+    pub error: Option<String>,
+    pub flagged: Option<String>,  // why the file needs manual handling
+    pub media: Option<MediaInfo>, // what ffprobe reported, if the run probed the file
+}
+
+impl FileReport {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            episodes: Vec::new(),
+            source: None,
+            candidates: Vec::new(),
+            action: Action::default(),
+            new_path: None,
+            error: None,
+            flagged: None,
+            media: None,
+        }
+    }
+}
+
+/// What happened to the files of a run, printed at its end so that the files that need
+/// attention don't scroll away among the output of the others.
+#[derive(Debug, Default)]
+pub struct RunSummary {
+    files: Vec<(PathBuf, Action, Option<String>)>, // with the error or best candidates
+}
+
+impl RunSummary {
+    pub fn record(&mut self, report: &FileReport) {
+        let detail = match (&report.error, report.action) {
+            (Some(error), _) => Some(error.clone()),
+            (None, Action::Unmatched) => Some(best_candidates(&report.candidates)),
+            (None, Action::Flagged) => report.flagged.clone(),
+            (None, _) => None,
+        };
+        self.files
+            .push((report.path.clone(), report.action, detail));
+    }
+
+    /// Settles the deferred renames once they've been retried, `failed` holding the files
+    /// that still failed with their error.
+    pub fn settle_deferred(&mut self, failed: &[(PathBuf, String)]) {
+        for (path, action, detail) in &mut self.files {
+            if *action != Action::Deferred {
+                continue;
+            }
+            match failed.iter().find(|(failed, _)| failed == path) {
+                Some((_, error)) => {
+                    *action = Action::Failed;
+                    *detail = Some(format!("Still busy at the end of the run: {error}"));
+                }
+                None => *action = Action::Renamed,
+            }
+        }
+    }
+
+    /// Counts the files that failed before they were processed, e.g. because their series
+    /// couldn't be fetched, unless they're already recorded.
+    pub fn record_unprocessed(&mut self, paths: &[PathBuf], error: &str) {
+        for path in paths {
+            if !self.files.iter().any(|(recorded, _, _)| recorded == path) {
+                let detail = Some(error.to_string());
+                self.files.push((path.clone(), Action::Failed, detail));
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// [`EXIT_FAILED`] if any file failed, [`EXIT_UNMATCHED`] if any wasn't matched, else 0.
+    pub fn exit_code(&self) -> i32 {
+        let any = |action| self.files.iter().any(|(_, a, _)| *a == action);
+        if any(Action::Failed) {
+            EXIT_FAILED
+        } else if any(Action::Unmatched) || any(Action::Flagged) {
+            EXIT_UNMATCHED
+        } else {
+            0
+        }
+    }
+
+    /// The number of files per action, then the files that weren't renamed and why.
+    pub fn format(&self) -> String {
+        let counts: Vec<String> = Action::ALL
+            .iter()
+            .filter_map(|action| {
+                let count = self.files.iter().filter(|(_, a, _)| a == action).count();
+                (count > 0).then(|| format!("{count} {}", action.label()))
+            })
+            .collect();
+        let mut summary = format!("Summary: {}\n", counts.join(", "));
+        for action in [
+            Action::Unmatched,
+            Action::Flagged,
+            Action::Failed,
+            Action::Skipped,
+            Action::Deferred,
+        ] {
+            let files: Vec<_> = self.files.iter().filter(|(_, a, _)| *a == action).collect();
+            if files.is_empty() {
+                continue;
+            }
+            let _ = writeln!(summary, "{} ({}):", action.label(), files.len());
+            for (path, _, detail) in files {
+                match detail {
+                    Some(detail) => {
+                        let _ = writeln!(summary, "  {path:?}: {detail}");
+                    }
+                    None => {
+                        let _ = writeln!(summary, "  {path:?}");
+                    }
+                }
+            }
+        }
+        summary
+    }
+}
+
+/// The most confident distinct codes read from an unmatched file.
+fn best_candidates(candidates: &[OcrCandidate]) -> String {
+    let mut sorted: Vec<&OcrCandidate> = candidates.iter().collect();
+    sorted.sort_by_key(|candidate| std::cmp::Reverse(candidate.confidence));
+    let mut seen = HashSet::new();
+    let best: Vec<String> = sorted
+        .into_iter()
+        .filter(|candidate| seen.insert(candidate.code.as_str()))
+        .take(SUMMARY_CANDIDATES)
+        .map(|candidate| format!("{} ({}%)", candidate.code, candidate.confidence))
+        .collect();
+    if best.is_empty() {
+        "no candidates".to_string()
+    } else {
+        format!("best candidates {}", best.join(", "))
+    }
+}
+
+/// Writes one JSON object per line for every processed file.
+pub struct JsonReporter {
+    out: Box<dyn Write>,
+}
+
+impl JsonReporter {
+    /// Reports to `path`, or to stdout if it's `None`. All other output is moved to stderr
+    /// then, so that stdout holds nothing but the reports.
+    pub fn new(path: Option<&Path>) -> Result<Self> {
+        let out: Box<dyn Write> = match path {
+            Some(path) => Box::new(BufWriter::new(
+                File::create(path).with_context(|| format!("Failed to create {path:?}"))?,
+            )),
+            None => Box::new(stdout_to_stderr()?),
+        };
+        Ok(Self { out })
+    }
+
+    pub fn emit(&mut self, report: &FileReport) -> Result<()> {
+        serde_json::to_writer(&mut self.out, report)?;
+        writeln!(self.out)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// Stdout for output that scripts parse, with everything else that's printed moved to
+/// stderr where that's supported.
+pub fn parseable_stdout() -> Result<Box<dyn Write>> {
+    #[cfg(unix)]
+    return Ok(Box::new(stdout_to_stderr()?));
+    #[cfg(not(unix))]
+    Ok(Box::new(std::io::stdout()))
+}
+
+/// Points the process' stdout (including the `println!`s everywhere) at stderr and returns
+/// a handle to the original stdout.
+#[cfg(unix)]
+fn stdout_to_stderr() -> Result<File> {
+    use std::os::fd::AsFd;
+
+    std::io::stdout().flush()?;
+    let original = std::io::stdout().as_fd().try_clone_to_owned()?;
+    redirect_fd(std::io::stderr().as_fd(), 1)?;
+    Ok(File::from(original))
+}
+
+/// Points the descriptor `target` (e.g. 1 for stdout) at the file `fd` refers to.
+#[cfg(unix)]
+pub fn redirect_fd(fd: std::os::fd::BorrowedFd, target: std::ffi::c_int) -> std::io::Result<()> {
+    use std::os::fd::AsRawFd;
+
+    // SAFETY: `fd` is open for the duration of the call, `target` is replaced atomically
+    if unsafe { libc::dup2(fd.as_raw_fd(), target) } == -1 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn stdout_to_stderr() -> Result<File> {
+    anyhow::bail!("--output json needs --output-file on this platform")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_emit() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("report.jsonl");
+        let mut reporter = JsonReporter::new(Some(&path)).unwrap();
+
+        let mut renamed = FileReport::new(Path::new("/tv/title_t00.mkv"));
+        renamed.action = Action::Renamed;
+        renamed.new_path = Some(PathBuf::from("/tv/The X-Files - S01E03 - Squeeze.mkv"));
+        reporter.emit(&renamed).unwrap();
+        reporter
+            .emit(&FileReport::new(Path::new("/tv/title_t01.mkv")))
+            .unwrap();
+        drop(reporter);
+
+        let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["action"], "renamed");
+        assert_eq!(
+            lines[0]["new_path"],
+            "/tv/The X-Files - S01E03 - Squeeze.mkv"
+        );
+        assert_eq!(lines[1]["action"], "unmatched");
+        assert!(lines[1]["error"].is_null());
+    }
+
+    #[test]
+    fn test_run_summary() {
+        let mut summary = RunSummary::default();
+        let mut report = FileReport::new(Path::new("/tv/title_t00.mkv"));
+        report.action = Action::Renamed;
+        summary.record(&report);
+
+        let mut report = FileReport::new(Path::new("/tv/title_t01.mkv"));
+        for (code, confidence) in [("3X22", 87), ("3X22", 60), ("1X79", 40)] {
+            report.candidates.push(OcrCandidate {
+                code: code.to_string(),
+                frame: "end_0001".to_string(),
+                confidence,
+            });
+        }
+        summary.record(&report);
+
+        let mut report = FileReport::new(Path::new("/tv/title_t02.mkv"));
+        report.action = Action::Deferred;
+        summary.record(&report);
+        let mut report = FileReport::new(Path::new("/tv/title_t03.mkv"));
+        report.action = Action::Deferred;
+        summary.record(&report);
+        summary.settle_deferred(&[(
+            PathBuf::from("/tv/title_t03.mkv"),
+            "Resource busy".to_string(),
+        )]);
+
+        assert_eq!(
+            summary.format(),
+            "Summary: 2 renamed, 1 unmatched, 1 failed\n\
+             unmatched (1):\n  \"/tv/title_t01.mkv\": best candidates 3X22 (87%), 1X79 (40%)\n\
+             failed (1):\n  \"/tv/title_t03.mkv\": Still busy at the end of the run: Resource busy\n"
+        );
+        assert_eq!(summary.exit_code(), EXIT_FAILED);
+
+        let mut unmatched = RunSummary::default();
+        unmatched.record(&FileReport::new(Path::new("/tv/title_t01.mkv")));
+        assert_eq!(unmatched.exit_code(), EXIT_UNMATCHED);
+
+        let mut flagged = RunSummary::default();
+        let mut report = FileReport::new(Path::new("/tv/title_t04.mkv"));
+        report.action = Action::Flagged;
+        report.flagged = Some("codes of S01E03 and S01E07, possibly a multi-part file".into());
+        flagged.record(&report);
+        assert_eq!(
+            flagged.format(),
+            "Summary: 1 flagged\n\
+             flagged (1):\n  \"/tv/title_t04.mkv\": codes of S01E03 and S01E07, possibly a multi-part file\n"
+        );
+        assert_eq!(flagged.exit_code(), EXIT_UNMATCHED);
+        unmatched.record_unprocessed(
+            &[
+                PathBuf::from("/tv/title_t01.mkv"),
+                PathBuf::from("/tv/title_t02.mkv"),
+            ],
+            "TVDB is down",
+        );
+        assert_eq!(unmatched.files.len(), 2);
+        assert_eq!(unmatched.exit_code(), EXIT_FAILED);
+        assert_eq!(RunSummary::default().exit_code(), 0);
+    }
+}
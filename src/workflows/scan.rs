@@ -0,0 +1,364 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::LazyLock;
+
+use crate::domain::models::{size_and_modified, CatalogEntry, EpisodeEntry};
+use crate::infra::cache::Cache;
+use crate::infra::catalog_db::CatalogDb;
+use crate::workflows::{renamer, verify};
+
+/// What `scan` did to the catalog.
+#[derive(Debug, Default, PartialEq)]
+pub struct ScanSummary {
+    pub files: usize,       // video files found under the library
+    pub checksummed: usize, // new or changed since the last scan
+    pub unnamed: usize,     // without an episode number in their name
+    pub unreadable: usize,  // skipped, their entries are left as they were
+    pub removed: usize,     // cataloged before but gone now
+}
+
+/// Catalogs the video files under `root`. Files whose size and modification time haven't
+/// changed since the last scan keep their checksum, and files no longer there are dropped.
+/// Files that can't be read are skipped with a warning. The catalog is updated at once
+/// when the scan is done.
+pub fn scan(root: &Path, db: &CatalogDb, cache: &Cache) -> Result<ScanSummary> {
+    let root = fs::canonicalize(root).with_context(|| format!("Failed to read {root:?}"))?;
+    let mut files = Vec::new();
+    let entries = fs::read_dir(&root).with_context(|| format!("Failed to read {root:?}"))?;
+    collect_videos(entries, &mut files);
+    files.sort();
+
+    let transaction = db.transaction()?;
+    let mut summary = ScanSummary {
+        files: files.len(),
+        ..ScanSummary::default()
+    };
+    for path in &files {
+        let Some((size, modified)) = size_and_modified(path) else {
+            eprintln!("Warning: Skipping {path:?}, it can't be read");
+            summary.unreadable += 1;
+            continue;
+        };
+        let known = db.get(path)?.filter(|entry| entry.is_current());
+        let checksum = match known {
+            Some(entry) => entry.checksum,
+            None => match renamer::checksum(path) {
+                Ok(checksum) => {
+                    summary.checksummed += 1;
+                    format!("{checksum:032x}")
+                }
+                Err(e) => {
+                    eprintln!("Warning: Skipping {path:?}, it can't be read: {e}");
+                    summary.unreadable += 1;
+                    continue;
+                }
+            },
+        };
+        let entry = catalog_entry(path, size, modified, checksum, cache);
+        if entry.episodes.is_empty() {
+            summary.unnamed += 1;
+        }
+        db.set(&entry)?;
+    }
+
+    let found: HashSet<&PathBuf> = files.iter().collect();
+    for entry in db.entries()? {
+        if entry.path.starts_with(&root) && !found.contains(&entry.path) {
+            db.remove(&entry.path)?;
+            summary.removed += 1;
+        }
+    }
+    transaction.commit()?;
+    Ok(summary)
+}
+
+/// Adds the video files of the directory and its subdirectories. Symlinks to directories
+/// aren't followed, which could loop, and directories that can't be read are skipped.
+fn collect_videos(entries: fs::ReadDir, files: &mut Vec<PathBuf>) {
+    for entry in entries.flatten() {
+        let path = entry.path();
+        let Ok(file_type) = entry.file_type() else {
+            continue;
+        };
+        if file_type.is_dir() {
+            match fs::read_dir(&path) {
+                Ok(entries) => collect_videos(entries, files),
+                Err(e) => eprintln!("Warning: Skipping {path:?}, it can't be read: {e}"),
+            }
+        } else if is_video(&path) && (file_type.is_file() || path.is_file()) {
+            files.push(path);
+        }
+    }
+}
+
+fn is_video(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            renamer::VIDEO_EXTENSIONS
+                .iter()
+                .any(|video| video.eq_ignore_ascii_case(extension))
+        })
+}
+
+/// The entry of a file, with the episodes an earlier run matched it to if it hasn't changed
+/// since, or else the ones its name gives.
+fn catalog_entry(
+    path: &Path,
+    size: u64,
+    modified: u64,
+    checksum: String,
+    cache: &Cache,
+) -> CatalogEntry {
+    let mut entry = CatalogEntry {
+        path: path.to_path_buf(),
+        show: show_name(path),
+        series_id: None,
+        season: None,
+        episodes: Vec::new(),
+        size,
+        modified,
+        checksum,
+    };
+    let matched = cache
+        .get_processed_file(path)
+        .filter(|processed| processed.is_current(path));
+    if let Some(processed) = matched {
+        let episodes = processed.episodes();
+        entry.series_id = Some(processed.series_id.clone());
+        entry.season = episodes.first().map(|episode| episode.season_number);
+        entry.episodes = episodes
+            .iter()
+            .map(|episode| episode.episode_number)
+            .collect();
+    } else if let Some((season, episodes)) = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .and_then(verify::named_episodes)
+    {
+        entry.season = Some(season);
+        entry.episodes = episodes;
+    }
+    entry
+}
+
+static SEASON_DIR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)^(season\s*\d+|specials)$").unwrap());
+static EPISODE_CODE: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)\bS\d{1,2}E\d{1,3}").unwrap());
+static TRAILING_YEAR: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\s*\(\d{4}\)\s*$").unwrap());
+
+/// The show of a library file: the directory holding its `Season XX` or `Specials`
+/// directory, or else the part of its name before `S01E03`.
+fn show_name(path: &Path) -> Option<String> {
+    let parent = path.parent()?;
+    if parent
+        .file_name()
+        .and_then(|name| name.to_str())
+        .is_some_and(|name| SEASON_DIR.is_match(name))
+    {
+        return Some(parent.parent()?.file_name()?.to_str()?.to_string());
+    }
+    let stem = path.file_stem()?.to_str()?;
+    let code = EPISODE_CODE.find(stem)?;
+    let show = stem[..code.start()]
+        .replace(['.', '_'], " ")
+        .trim_matches(|c: char| c.is_whitespace() || c == '-')
+        .to_string();
+    (!show.is_empty()).then_some(show)
+}
+
+/// Lowercase letters and digits only, without a trailing `(2005)`, so that names of a show
+/// from different sources compare equal.
+fn normalize_show(name: &str) -> String {
+    TRAILING_YEAR
+        .replace(name, "")
+        .chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The cataloged files of one series, for `--catalog`.
+#[derive(Debug, Clone, Default)]
+pub struct ShowCatalog {
+    entries: Vec<CatalogEntry>,
+}
+
+impl ShowCatalog {
+    /// The entries of the series: the files a run matched to it, and the files of a show of
+    /// the same name that weren't matched to another series.
+    pub fn load(db: &CatalogDb, series_id: &str, show_name: &str) -> Result<Self> {
+        let show = normalize_show(show_name);
+        let entries = db
+            .entries()?
+            .into_iter()
+            .filter(|entry| match &entry.series_id {
+                Some(id) => id == series_id,
+                None => entry
+                    .show
+                    .as_deref()
+                    .is_some_and(|name| normalize_show(name) == show),
+            })
+            .collect();
+        Ok(Self { entries })
+    }
+
+    /// The cataloged files still there that hold one of the episodes, other than
+    /// `file_path`.
+    pub fn copies(&self, episodes: &[EpisodeEntry], file_path: &Path) -> Vec<PathBuf> {
+        self.entries
+            .iter()
+            .filter(|entry| {
+                episodes.iter().any(|episode| {
+                    entry.season == Some(episode.season_number)
+                        && entry.episodes.contains(&episode.episode_number)
+                })
+            })
+            .filter(|entry| entry.path.exists() && !renamer::is_same_file(file_path, &entry.path))
+            .map(|entry| entry.path.clone())
+            .collect()
+    }
+
+    /// The season and episode numbers there's a cataloged file of.
+    pub fn held(&self) -> HashSet<(u64, u64)> {
+        self.entries
+            .iter()
+            .filter_map(|entry| Some((entry.season?, &entry.episodes)))
+            .flat_map(|(season, episodes)| episodes.iter().map(move |&episode| (season, episode)))
+            .collect()
+    }
+}
+
+/// The `YYYY-MM-DD` date (UTC) of a Unix time.
+pub fn iso_date(unix_secs: u64) -> String {
+    // Howard Hinnant's civil_from_days
+    let days = (unix_secs / 86_400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(season: u64, number: u64) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: season,
+            episode_number: number,
+            name: format!("Episode {number}"),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    fn entry(path: &Path, show: &str, season: u64, episodes: Vec<u64>) -> CatalogEntry {
+        CatalogEntry {
+            path: path.to_path_buf(),
+            show: Some(show.to_string()),
+            series_id: None,
+            season: Some(season),
+            episodes,
+            size: 0,
+            modified: 0,
+            checksum: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_show_name() {
+        assert_eq!(
+            show_name(Path::new("/tv/The Office (2005)/Season 02/x.mkv")).as_deref(),
+            Some("The Office (2005)")
+        );
+        assert_eq!(
+            show_name(Path::new("/downloads/The.Office.S02E03.720p.mkv")).as_deref(),
+            Some("The Office")
+        );
+        assert_eq!(show_name(Path::new("/downloads/S02E03.mkv")), None);
+        assert_eq!(
+            normalize_show("The Office (2005)"),
+            normalize_show("the office")
+        );
+    }
+
+    #[test]
+    fn test_scan_and_catalog() {
+        let temp = tempfile::tempdir().unwrap();
+        let dir = temp.path().to_path_buf();
+        let season = dir.join("Show").join("Season 01");
+        fs::create_dir_all(&season).unwrap();
+        fs::write(season.join("Show - S01E01 - Pilot.mkv"), b"one").unwrap();
+        fs::write(season.join("Show - S01E03-E04 - Two Parter.mkv"), b"three").unwrap();
+        fs::write(season.join("Show - S01E03 - Two Parter.nfo"), b"<xml/>").unwrap();
+        fs::write(dir.join("Show").join("Extra.mp4"), b"extra").unwrap();
+
+        let db = CatalogDb::open_in_memory().unwrap();
+        let cache = Cache::default();
+        let summary = scan(&dir, &db, &cache).unwrap();
+        assert_eq!(
+            summary,
+            ScanSummary {
+                files: 3,
+                checksummed: 3,
+                unnamed: 1,
+                unreadable: 0,
+                removed: 0
+            }
+        );
+
+        // Unchanged files keep their checksum, and deleted ones are dropped
+        fs::remove_file(dir.join("Show").join("Extra.mp4")).unwrap();
+        let summary = scan(&dir, &db, &cache).unwrap();
+        assert_eq!((summary.checksummed, summary.removed), (0, 1));
+
+        // A symlink back up the tree isn't followed
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&dir, season.join("Loop")).unwrap();
+            assert_eq!(scan(&dir, &db, &cache).unwrap().files, 2);
+        }
+
+        let catalog = ShowCatalog::load(&db, "123", "Show").unwrap();
+        let copies = catalog.copies(&[episode(1, 4)], Path::new("/downloads/x.mkv"));
+        assert_eq!(copies.len(), 1);
+        assert!(copies[0].ends_with("Show - S01E03-E04 - Two Parter.mkv"));
+        assert!(ShowCatalog::load(&db, "123", "Other Show")
+            .unwrap()
+            .copies(&[episode(1, 4)], Path::new("/downloads/x.mkv"))
+            .is_empty());
+    }
+
+    #[test]
+    fn test_held() {
+        let catalog = ShowCatalog {
+            entries: vec![
+                entry(Path::new("/tv/a.mkv"), "Show", 1, vec![1]),
+                entry(Path::new("/tv/b.mkv"), "Show", 1, vec![3, 4]),
+            ],
+        };
+        assert_eq!(catalog.held(), HashSet::from([(1, 1), (1, 3), (1, 4)]));
+    }
+
+    #[test]
+    fn test_iso_date() {
+        assert_eq!(iso_date(0), "1970-01-01");
+        assert_eq!(iso_date(951_782_400), "2000-02-29");
+        assert_eq!(iso_date(1_700_000_000), "2023-11-14");
+    }
+}
@@ -0,0 +1,304 @@
+use anyhow::{bail, Result};
+use rustyline::DefaultEditor;
+
+use super::plan::format_columns;
+use crate::domain::models::EpisodeEntry;
+use crate::infra::provider::SeriesMatch;
+
+// Characters of a show's overview listed when choosing between shows
+const OVERVIEW_SNIPPET_CHARS: usize = 100;
+
+/// Formats the series a search found as a table of their IDs, names and details.
+pub fn format_series(results: &[SeriesMatch]) -> String {
+    let rows: Vec<[String; 6]> = results
+        .iter()
+        .map(|series| {
+            [
+                series.id.clone(),
+                series.name.clone(),
+                series.year.clone().unwrap_or_default(),
+                series.network.clone().unwrap_or_default(),
+                series.country.clone().unwrap_or_default(),
+                series.status.clone().unwrap_or_default(),
+            ]
+        })
+        .collect();
+    format_columns(
+        ["ID", "Name", "Year", "Network", "Country", "Status"],
+        &rows,
+    )
+}
+
+/// The name of a show followed by the details the provider has, e.g.
+/// `The X-Files (1993, FOX, US, Ended) [ID: 430]`.
+pub fn describe_series(series: &SeriesMatch) -> String {
+    let details: Vec<&str> = [
+        &series.year,
+        &series.network,
+        &series.country,
+        &series.status,
+    ]
+    .into_iter()
+    .flatten()
+    .map(String::as_str)
+    .collect();
+    if details.is_empty() {
+        format!("{} [ID: {}]", series.name, series.id)
+    } else {
+        format!(
+            "{} ({}) [ID: {}]",
+            series.name,
+            details.join(", "),
+            series.id
+        )
+    }
+}
+
+/// Splits a year suffix like in `Doctor Who (2005)` off a searched show name.
+pub fn split_year(query: &str) -> (&str, Option<u16>) {
+    let query = query.trim();
+    let year = query
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once('('))
+        .and_then(|(name, year)| Some((name.trim_end(), year.trim().parse::<u16>().ok()?)));
+    match year {
+        Some((name, year)) if !name.is_empty() && (1900..=2999).contains(&year) => {
+            (name, Some(year))
+        }
+        _ => (query, None),
+    }
+}
+
+/// The shows of the year, telling apart a reboot and the original by their year or a year
+/// in their name. All of them if none is from that year, e.g. because the provider knows
+/// no years.
+pub fn narrow_by_year(results: Vec<SeriesMatch>, year: u16) -> Vec<SeriesMatch> {
+    let (of_year, others): (Vec<_>, Vec<_>) = results.into_iter().partition(|series| {
+        series.year.as_deref() == Some(year.to_string().as_str())
+            || split_year(&series.name).1 == Some(year)
+    });
+    if of_year.is_empty() {
+        println!("No show found from {year}, choosing from all years");
+        return others;
+    }
+    of_year
+}
+
+/// The start of a show's overview on one line, cut at a word.
+fn overview_snippet(overview: &str) -> String {
+    let overview = overview.split_whitespace().collect::<Vec<_>>().join(" ");
+    if overview.chars().count() <= OVERVIEW_SNIPPET_CHARS {
+        return overview;
+    }
+    let cut: String = overview.chars().take(OVERVIEW_SNIPPET_CHARS).collect();
+    let cut = cut
+        .rsplit_once(' ')
+        .map_or(cut.as_str(), |(words, _)| words);
+    format!(
+        "{}...",
+        cut.trim_end_matches(|c: char| !c.is_alphanumeric())
+    )
+}
+
+/// The shows matching every word of the filter, in their original order. A word matches
+/// if it's part of the show's details, or if its letters appear in order in the name, so
+/// `xfl` finds `The X-Files`.
+pub fn filter_series<'a>(results: &'a [SeriesMatch], filter: &str) -> Vec<&'a SeriesMatch> {
+    let filter = filter.to_lowercase();
+    results
+        .iter()
+        .filter(|series| {
+            let details = describe_series(series).to_lowercase();
+            let name = series.name.to_lowercase();
+            filter
+                .split_whitespace()
+                .all(|word| details.contains(word) || is_subsequence(word, &name))
+        })
+        .collect()
+}
+
+fn is_subsequence(needle: &str, haystack: &str) -> bool {
+    let mut haystack = haystack.chars();
+    needle.chars().all(|c| haystack.any(|h| h == c))
+}
+
+/// Asks to pick one of several shows found by a search. Typing anything but a listed
+/// number narrows the list down to the shows matching it, and an empty line lists them
+/// all again.
+pub fn select_series(results: &[SeriesMatch]) -> Result<&SeriesMatch> {
+    let mut shown: Vec<&SeriesMatch> = results.iter().collect();
+    println!("Multiple shows found. Please select one:");
+    print_series(&shown);
+
+    let mut rl = DefaultEditor::new()?;
+    loop {
+        println!(
+            "Enter a number (1-{}), or text to filter the list:",
+            shown.len()
+        );
+        let Ok(input) = rl.readline(">> ") else {
+            bail!("No show selected");
+        };
+        let input = input.trim();
+        match input.parse::<usize>() {
+            Ok(number) if (1..=shown.len()).contains(&number) => return Ok(shown[number - 1]),
+            _ => {}
+        }
+        let filtered = if input.is_empty() {
+            results.iter().collect()
+        } else {
+            filter_series(results, input)
+        };
+        if filtered.is_empty() {
+            println!("No shows match '{input}'.");
+            continue;
+        }
+        shown = filtered;
+        print_series(&shown);
+    }
+}
+
+fn print_series(shown: &[&SeriesMatch]) {
+    for (number, series) in shown.iter().enumerate() {
+        println!("  {}: {}", number + 1, describe_series(series));
+        if let Some(overview) = series.overview.as_deref().filter(|o| !o.trim().is_empty()) {
+            println!("     {}", overview_snippet(overview));
+        }
+    }
+}
+
+/// Formats the episodes of a series as a table, in season and episode order.
+pub fn format_episodes(episodes: &[&EpisodeEntry]) -> String {
+    let mut episodes = episodes.to_vec();
+    episodes.sort_by_key(|episode| (episode.season_number, episode.episode_number));
+    let rows: Vec<[String; 4]> = episodes
+        .iter()
+        .map(|episode| {
+            [
+                format!(
+                    "S{:02}E{:02}",
+                    episode.season_number, episode.episode_number
+                ),
+                episode.production_code.clone().unwrap_or_default(),
+                episode.aired.clone().unwrap_or_default(),
+                episode.name.clone(),
+            ]
+        })
+        .collect();
+    format_columns(["Episode", "Code", "Aired", "Title"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(
+        season_number: u64,
+        episode_number: u64,
+        code: Option<&str>,
+        name: &str,
+    ) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: code.map(str::to_string),
+            season_number,
+            episode_number,
+            name: name.to_string(),
+            overview: None,
+            aired: Some("1993-09-24".to_string()),
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    fn series(id: &str, name: &str, year: Option<&str>, network: Option<&str>) -> SeriesMatch {
+        SeriesMatch {
+            id: id.to_string(),
+            name: name.to_string(),
+            year: year.map(str::to_string),
+            network: network.map(str::to_string),
+            ..SeriesMatch::default()
+        }
+    }
+
+    #[test]
+    fn test_describe_series() {
+        let mut x_files = series("430", "The X-Files", Some("1993"), Some("FOX"));
+        x_files.status = Some("Ended".to_string());
+        assert_eq!(
+            describe_series(&x_files),
+            "The X-Files (1993, FOX, Ended) [ID: 430]"
+        );
+        assert_eq!(
+            describe_series(&series("9", "Lost", None, None)),
+            "Lost [ID: 9]"
+        );
+
+        assert_eq!(overview_snippet("Two FBI\n agents."), "Two FBI agents.");
+        let snippet = overview_snippet(&"Trust no one. ".repeat(10));
+        assert!(snippet.ends_with("no one..."));
+        assert!(snippet.len() <= OVERVIEW_SNIPPET_CHARS + 3);
+    }
+
+    #[test]
+    fn test_split_year() {
+        assert_eq!(split_year("Doctor Who (2005)"), ("Doctor Who", Some(2005)));
+        assert_eq!(split_year(" Doctor Who(1963) "), ("Doctor Who", Some(1963)));
+        assert_eq!(split_year("Doctor Who"), ("Doctor Who", None));
+        assert_eq!(split_year("Cosmos (TV)"), ("Cosmos (TV)", None));
+        assert_eq!(split_year("(2005)"), ("(2005)", None));
+    }
+
+    #[test]
+    fn test_narrow_by_year() {
+        let results = vec![
+            series("76107", "Doctor Who", Some("1963"), None),
+            series("78804", "Doctor Who", Some("2005"), None),
+            series("2024", "Doctor Who (2023)", None, None),
+        ];
+        let ids = |results: Vec<SeriesMatch>| -> Vec<String> {
+            results.into_iter().map(|series| series.id).collect()
+        };
+        assert_eq!(ids(narrow_by_year(results.clone(), 2005)), ["78804"]);
+        assert_eq!(ids(narrow_by_year(results.clone(), 2023)), ["2024"]);
+        assert_eq!(
+            ids(narrow_by_year(results, 1999)),
+            ["76107", "78804", "2024"]
+        );
+    }
+
+    #[test]
+    fn test_filter_series() {
+        let results = [
+            series("430", "The X-Files", Some("1993"), Some("FOX")),
+            series("6061", "The X-Files", Some("2016"), Some("FOX")),
+            series("9", "X Files Reopened", None, Some("YouTube")),
+        ];
+        let ids = |filter| -> Vec<&str> {
+            filter_series(&results, filter)
+                .iter()
+                .map(|series| series.id.as_str())
+                .collect()
+        };
+        assert_eq!(ids("xfl"), ["430", "6061", "9"]);
+        assert_eq!(ids("2016"), ["6061"]);
+        assert_eq!(ids("fox files 93"), ["430"]);
+        assert_eq!(ids("youtube"), ["9"]);
+        assert!(ids("lost").is_empty());
+    }
+
+    #[test]
+    fn test_format_episodes() {
+        let squeeze = episode(1, 3, Some("1X02"), "Squeeze");
+        let pilot = episode(1, 1, Some("1X79"), "Pilot");
+        let special = episode(0, 1, None, "The Truth Is Out There");
+        assert_eq!(
+            format_episodes(&[&squeeze, &pilot, &special]),
+            "Episode  Code  Aired       Title\n\
+             -------  ----  ----------  ----------------------\n\
+             S00E01         1993-09-24  The Truth Is Out There\n\
+             S01E01   1X79  1993-09-24  Pilot\n\
+             S01E03   1X02  1993-09-24  Squeeze\n"
+        );
+    }
+}
@@ -0,0 +1,236 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::domain::models::EpisodeEntry;
+
+/// What a Sonarr custom script was run for, read from its `sonarr_*` environment variables.
+#[derive(Debug, PartialEq)]
+pub enum SonarrEvent {
+    /// The "Test" button of the connection settings
+    Test,
+    /// An episode file was imported
+    Download(ImportedFile),
+    /// Any other event, which has no episode file to check
+    Other(String),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ImportedFile {
+    pub path: PathBuf,
+    pub tvdb_id: String,
+    pub season_number: u64,
+    pub episode_numbers: Vec<u64>, // several for multi-episode files
+}
+
+/// Outcome of checking Sonarr's identification, also the exit code of the run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Verdict {
+    Confirmed,  // the file shows one of the expected episodes, e.g. those Sonarr imported it as
+    Mismatch,   // the file shows a different episode
+    Unverified, // the episode couldn't be identified
+}
+
+impl Verdict {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Verdict::Confirmed => 0,
+            Verdict::Mismatch => 2,
+            Verdict::Unverified => 3,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FoundEpisode {
+    pub season_number: u64,
+    pub episode_number: u64,
+    pub name: String,
+    pub source: String, // how the episode was identified
+}
+
+/// JSON report of a check, for Sonarr's log or a `--report` file.
+#[derive(Debug, Serialize)]
+pub struct Report {
+    pub file: PathBuf,
+    pub tvdb_id: String,
+    pub expected: Vec<String>, // SxxExx of every episode Sonarr imported the file as
+    pub found: Vec<FoundEpisode>, // several for a multi-episode file
+    pub verdict: Verdict,
+}
+
+/// Reads the event from Sonarr's environment variables.
+pub fn event_from_env() -> Result<SonarrEvent> {
+    parse_event(|name| std::env::var(name).ok())
+}
+
+fn parse_event(var: impl Fn(&str) -> Option<String>) -> Result<SonarrEvent> {
+    let event_type = var("sonarr_eventtype")
+        .context("sonarr_eventtype is not set, --sonarr must run as a Sonarr custom script")?;
+    match event_type.as_str() {
+        "Test" => return Ok(SonarrEvent::Test),
+        "Download" => {}
+        _ => return Ok(SonarrEvent::Other(event_type)),
+    }
+
+    let required = |name: &str| var(name).with_context(|| format!("{name} is not set"));
+    let season_number = required("sonarr_episodefile_seasonnumber")?;
+    let episode_numbers = required("sonarr_episodefile_episodenumbers")?;
+    let episode_numbers = episode_numbers
+        .split(',')
+        .map(|number| number.trim().parse())
+        .collect::<Result<Vec<u64>, _>>()
+        .with_context(|| {
+            format!("Invalid sonarr_episodefile_episodenumbers {episode_numbers:?}")
+        })?;
+
+    Ok(SonarrEvent::Download(ImportedFile {
+        path: PathBuf::from(required("sonarr_episodefile_path")?),
+        tvdb_id: required("sonarr_series_tvdbid")?,
+        season_number: season_number.trim().parse().with_context(|| {
+            format!("Invalid sonarr_episodefile_seasonnumber {season_number:?}")
+        })?,
+        episode_numbers,
+    }))
+}
+
+/// Compares the episodes the file was identified as, by `source`, with the ones Sonarr
+/// imported it as.
+pub fn check(file: &ImportedFile, found: &[EpisodeEntry], source: &str) -> Report {
+    let verdict = verdict(file.season_number, &file.episode_numbers, found);
+    Report {
+        file: file.path.clone(),
+        tvdb_id: file.tvdb_id.clone(),
+        expected: file
+            .episode_numbers
+            .iter()
+            .map(|episode| format!("S{:02}E{episode:02}", file.season_number))
+            .collect(),
+        found: found
+            .iter()
+            .map(|episode| FoundEpisode {
+                season_number: episode.season_number,
+                episode_number: episode.episode_number,
+                name: episode.name.clone(),
+                source: source.to_string(),
+            })
+            .collect(),
+        verdict,
+    }
+}
+
+/// Confirmed if every episode found is one of the expected ones of the season.
+pub fn verdict(season_number: u64, episode_numbers: &[u64], found: &[EpisodeEntry]) -> Verdict {
+    if found.is_empty() {
+        Verdict::Unverified
+    } else if found.iter().all(|episode| {
+        episode.season_number == season_number && episode_numbers.contains(&episode.episode_number)
+    }) {
+        Verdict::Confirmed
+    } else {
+        Verdict::Mismatch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn event(vars: &[(&str, &str)]) -> Result<SonarrEvent> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        parse_event(|name| vars.get(name).cloned())
+    }
+
+    fn imported(episode_numbers: Vec<u64>) -> ImportedFile {
+        ImportedFile {
+            path: PathBuf::from("/tv/The X-Files/Season 01/The X-Files - S01E03.mkv"),
+            tvdb_id: "77398".to_string(),
+            season_number: 1,
+            episode_numbers,
+        }
+    }
+
+    #[test]
+    fn test_parse_event() {
+        let download = event(&[
+            ("sonarr_eventtype", "Download"),
+            (
+                "sonarr_episodefile_path",
+                "/tv/The X-Files/Season 01/The X-Files - S01E03.mkv",
+            ),
+            ("sonarr_series_tvdbid", "77398"),
+            ("sonarr_episodefile_seasonnumber", "1"),
+            ("sonarr_episodefile_episodenumbers", "3"),
+        ]);
+        assert_eq!(download.unwrap(), SonarrEvent::Download(imported(vec![3])));
+
+        let multi = event(&[
+            ("sonarr_eventtype", "Download"),
+            (
+                "sonarr_episodefile_path",
+                "/tv/The X-Files/Season 01/The X-Files - S01E03.mkv",
+            ),
+            ("sonarr_series_tvdbid", "77398"),
+            ("sonarr_episodefile_seasonnumber", "1"),
+            ("sonarr_episodefile_episodenumbers", "3,4"),
+        ]);
+        assert_eq!(multi.unwrap(), SonarrEvent::Download(imported(vec![3, 4])));
+
+        assert_eq!(
+            event(&[("sonarr_eventtype", "Test")]).unwrap(),
+            SonarrEvent::Test
+        );
+        assert_eq!(
+            event(&[("sonarr_eventtype", "Grab")]).unwrap(),
+            SonarrEvent::Other("Grab".to_string())
+        );
+        assert!(event(&[("sonarr_eventtype", "Download")]).is_err());
+        assert!(event(&[]).is_err());
+    }
+
+    #[test]
+    fn test_check() {
+        let file = imported(vec![3, 4]);
+        let episode = |episode_number| EpisodeEntry {
+            production_code: Some(format!("1X0{episode_number}")),
+            season_number: 1,
+            episode_number,
+            name: "Squeeze".to_string(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        };
+
+        let report = check(&file, &[episode(4)], "production code");
+        assert_eq!(report.verdict, Verdict::Confirmed);
+        assert_eq!(report.expected, ["S01E03", "S01E04"]);
+
+        let report = check(&file, &[episode(5)], "production code");
+        assert_eq!(report.verdict, Verdict::Mismatch);
+        assert_eq!(report.found[0].episode_number, 5);
+
+        let both = [episode(3), episode(4)];
+        assert_eq!(
+            check(&file, &both, "production code").verdict,
+            Verdict::Confirmed
+        );
+        let overlapping = [episode(4), episode(5)];
+        assert_eq!(
+            check(&file, &overlapping, "production code").verdict,
+            Verdict::Mismatch
+        );
+
+        assert_eq!(
+            check(&file, &[], "production code").verdict,
+            Verdict::Unverified
+        );
+        assert_eq!(Verdict::Mismatch.exit_code(), 2);
+    }
+}
@@ -0,0 +1,215 @@
+use clap::ValueEnum;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::cli::LinkMode;
+
+/// What to do when a destination lacks the space for the copies of a batch, also
+/// `--low-space`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LowSpacePolicy {
+    /// Stop before any file is touched
+    Abort,
+    /// Give only the files that fit their new name, in order, and leave the others alone
+    Fit,
+}
+
+/// A filesystem that can't take all the copies of a batch.
+#[derive(Debug, PartialEq)]
+pub struct Shortfall {
+    pub destination: PathBuf, // the first destination directory on the filesystem
+    pub needed: u64,          // bytes
+    pub available: u64,
+}
+
+impl Shortfall {
+    pub fn describe(&self) -> String {
+        format!(
+            "{:?} has {} free, the batch copies {} to its filesystem",
+            self.destination,
+            format_size(self.available),
+            format_size(self.needed)
+        )
+    }
+}
+
+/// Which files of a batch there's room for.
+#[derive(Debug, Default, PartialEq)]
+pub struct Preflight {
+    pub left_out: Vec<PathBuf>, // the sources past the free space of their destination
+    pub shortfalls: Vec<Shortfall>,
+}
+
+/// Whether giving the file at `source` a name in `destination_dir` writes its content again:
+/// copies always do, moves and hardlinks only to another filesystem, symlinks never.
+pub fn needs_space(source: &Path, destination_dir: &Path, link_mode: LinkMode) -> bool {
+    match link_mode {
+        LinkMode::Symlink => false,
+        LinkMode::Copy => true,
+        LinkMode::Rename | LinkMode::Hardlink => filesystem(source) != filesystem(destination_dir),
+    }
+}
+
+/// Sums the sizes of the files (source and destination directory) each filesystem has to
+/// take and compares them with its free space. Files are counted in the batch's order, the
+/// ones that don't fit anymore are left out.
+pub fn check(transfers: &[(PathBuf, PathBuf)], link_mode: LinkMode) -> Preflight {
+    check_with(transfers, link_mode, |path| fs4::available_space(path).ok())
+}
+
+fn check_with(
+    transfers: &[(PathBuf, PathBuf)],
+    link_mode: LinkMode,
+    available_space: impl Fn(&Path) -> Option<u64>,
+) -> Preflight {
+    struct Volume {
+        destination: PathBuf,
+        available: u64,
+        needed: u64, // by the whole batch
+        taken: u64,  // by the files that fit
+    }
+    let mut volumes: HashMap<String, Volume> = HashMap::new();
+    let mut preflight = Preflight::default();
+    for (source, destination_dir) in transfers {
+        if !needs_space(source, destination_dir, link_mode) {
+            continue;
+        }
+        let (Ok(metadata), Some(id)) = (source.metadata(), filesystem(destination_dir)) else {
+            continue; // the transfer itself reports what's wrong
+        };
+        let volume = match volumes.entry(id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let Some(available) = available_space(&existing_ancestor(destination_dir)) else {
+                    continue;
+                };
+                entry.insert(Volume {
+                    destination: destination_dir.clone(),
+                    available,
+                    needed: 0,
+                    taken: 0,
+                })
+            }
+        };
+        volume.needed += metadata.len();
+        if volume.taken + metadata.len() <= volume.available {
+            volume.taken += metadata.len();
+        } else {
+            preflight.left_out.push(source.clone());
+        }
+    }
+
+    preflight.shortfalls = volumes
+        .into_values()
+        .filter(|volume| volume.needed > volume.available)
+        .map(|volume| Shortfall {
+            destination: volume.destination,
+            needed: volume.needed,
+            available: volume.available,
+        })
+        .collect();
+    preflight
+        .shortfalls
+        .sort_by(|a, b| a.destination.cmp(&b.destination));
+    preflight
+}
+
+/// The directory itself or its closest parent that exists, since a season directory of the
+/// library is only created by the first file moved there.
+fn existing_ancestor(path: &Path) -> PathBuf {
+    path.ancestors()
+        .find(|ancestor| !ancestor.as_os_str().is_empty() && ancestor.exists())
+        .unwrap_or(Path::new("."))
+        .to_path_buf()
+}
+
+/// Identifies the filesystem the path is on: its device ID on Unix, its drive or share
+/// elsewhere.
+#[cfg(unix)]
+fn filesystem(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    Some(existing_ancestor(path).metadata().ok()?.dev().to_string())
+}
+
+#[cfg(not(unix))]
+fn filesystem(path: &Path) -> Option<String> {
+    let path = std::fs::canonicalize(existing_ancestor(path)).ok()?;
+    let root = path.components().next()?;
+    Some(root.as_os_str().to_string_lossy().to_lowercase())
+}
+
+/// Bytes in the largest unit that keeps a number of at least 1, e.g. `4.2 GB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["KB", "MB", "GB", "TB"];
+    if bytes < 1000 {
+        return format!("{bytes} B");
+    }
+    let mut size = bytes as f64 / 1000.0;
+    let mut unit = 0;
+    while size >= 1000.0 && unit + 1 < UNITS.len() {
+        size /= 1000.0;
+        unit += 1;
+    }
+    format!("{size:.1} {}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_check() {
+        let dir = std::env::temp_dir().join(format!("space_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files: Vec<PathBuf> = [600, 300, 200]
+            .iter()
+            .enumerate()
+            .map(|(i, &size)| {
+                let path = dir.join(format!("{i}.mkv"));
+                fs::write(&path, vec![0; size]).unwrap();
+                path
+            })
+            .collect();
+        // Into a season directory that doesn't exist yet
+        let library = dir.join("Show").join("Season 01");
+        let transfers: Vec<(PathBuf, PathBuf)> = files
+            .iter()
+            .map(|file| (file.clone(), library.clone()))
+            .collect();
+
+        let preflight = check_with(&transfers, LinkMode::Copy, |_| Some(1000));
+        assert_eq!(preflight.left_out, vec![files[2].clone()]);
+        assert_eq!(
+            preflight.shortfalls,
+            vec![Shortfall {
+                destination: library.clone(),
+                needed: 1100,
+                available: 1000
+            }]
+        );
+        assert_eq!(
+            check_with(&transfers, LinkMode::Copy, |_| Some(1100)),
+            Preflight::default()
+        );
+        // Moves and links on the same filesystem take no space
+        assert_eq!(
+            check_with(&transfers, LinkMode::Rename, |_| Some(0)),
+            Preflight::default()
+        );
+        assert_eq!(
+            check_with(&transfers, LinkMode::Hardlink, |_| Some(0)),
+            Preflight::default()
+        );
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(4_200_000_000), "4.2 GB");
+        assert_eq!(format_size(52_000_000_000_000_000), "52000.0 TB");
+    }
+}
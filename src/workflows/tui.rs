@@ -0,0 +1,520 @@
+use anyhow::{bail, Result};
+use ratatui::backend::CrosstermBackend;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::crossterm::execute;
+use ratatui::crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::layout::{Constraint, Direction, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Borders, List, ListItem, ListState, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+use regex::Regex;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::time::Duration;
+
+use super::plan::PlannedRename;
+use super::renamer::{self, NameTemplate};
+use super::report::{self, FileReport};
+use crate::domain::models::EpisodeEntry;
+
+/// A file matched in the background: what was found and the rename a dry run would make.
+pub struct Matched {
+    pub report: FileReport,
+    pub plan: Option<PlannedRename>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Decision {
+    Pending,
+    Approved,
+    Skipped,
+}
+
+struct Item {
+    path: PathBuf,
+    matched: Option<Matched>, // None while the file is still being matched
+    decision: Decision,
+}
+
+/// How edited files are named, the same way the run names matched files.
+pub struct Naming<'a> {
+    pub series_id: &'a str,
+    pub show_name: &'a str,
+    pub template: &'a NameTemplate,
+    pub library_root: Option<&'a Path>, // --organize
+    pub episodes: Vec<EpisodeEntry>,    // of the series, for files identified by hand
+}
+
+/// The review queue without the terminal, the files in the order they're matched in.
+struct Queue<'a> {
+    items: Vec<Item>,
+    received: usize,
+    naming: Naming<'a>,
+}
+
+impl<'a> Queue<'a> {
+    fn new(files: &[PathBuf], naming: Naming<'a>) -> Self {
+        let items = files
+            .iter()
+            .map(|path| Item {
+                path: path.clone(),
+                matched: None,
+                decision: Decision::Pending,
+            })
+            .collect();
+        Self {
+            items,
+            received: 0,
+            naming,
+        }
+    }
+
+    fn receive(&mut self, matched: Matched) {
+        if let Some(item) = self.items.get_mut(self.received) {
+            item.matched = Some(matched);
+            self.received += 1;
+        }
+    }
+
+    fn plan(&self, index: usize) -> Option<&PlannedRename> {
+        self.items[index].matched.as_ref()?.plan.as_ref()
+    }
+
+    fn decide(&mut self, index: usize, decision: Decision) -> Result<()> {
+        if decision == Decision::Approved && self.plan(index).is_none() {
+            bail!("No episode to rename to, press e to enter one");
+        }
+        self.items[index].decision = decision;
+        Ok(())
+    }
+
+    fn approve_all(&mut self) {
+        for index in 0..self.items.len() {
+            if self.items[index].decision == Decision::Pending && self.plan(index).is_some() {
+                self.items[index].decision = Decision::Approved;
+            }
+        }
+    }
+
+    /// Sets the file's new name, or identifies it as another episode if `input` is SXXEXX.
+    fn edit(&mut self, index: usize, input: &str) -> Result<()> {
+        let input = input.trim();
+        let sxxexx = Regex::new(r"(?i)^s(\d{1,2})e(\d{1,3})$").unwrap();
+        let plan = match sxxexx.captures(input) {
+            Some(caps) => {
+                let (season, number): (u64, u64) = (caps[1].parse()?, caps[2].parse()?);
+                let Some(episode) = self.naming.episodes.iter().find(|episode| {
+                    episode.season_number == season && episode.episode_number == number
+                }) else {
+                    bail!("S{season:02}E{number:02} is not in the cache");
+                };
+                self.episode_plan(index, episode)
+            }
+            None => {
+                if input.is_empty() || input.contains(['/', '\\']) {
+                    bail!("Enter a file name or SXXEXX");
+                }
+                let Some(plan) = self.plan(index) else {
+                    bail!("Enter SXXEXX to identify the file first");
+                };
+                let mut file_name = input.to_string();
+                if Path::new(input).extension().is_none() {
+                    file_name.push_str(".mkv");
+                }
+                PlannedRename {
+                    new_path: plan.new_path.with_file_name(file_name),
+                    ..plan.clone()
+                }
+            }
+        };
+
+        let item = &mut self.items[index];
+        if let Some(matched) = &mut item.matched {
+            matched.plan = Some(plan);
+        }
+        item.decision = Decision::Approved;
+        Ok(())
+    }
+
+    fn episode_plan(&self, index: usize, episode: &EpisodeEntry) -> PlannedRename {
+        let file_path = &self.items[index].path;
+        let media = self.items[index]
+            .matched
+            .as_ref()
+            .and_then(|matched| matched.report.media.as_ref());
+        let naming = &self.naming;
+        let directory = match naming.library_root {
+            Some(root) => naming
+                .template
+                .season_dir(root, naming.show_name, episode.season_number),
+            None => file_path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        };
+        let new_filename = naming.template.render(
+            &directory,
+            naming.show_name,
+            std::slice::from_ref(episode),
+            media,
+        );
+        PlannedRename {
+            old_path: file_path.clone(),
+            new_path: renamer::find_unique_filename(file_path, &directory, &new_filename),
+            series_id: naming.series_id.to_string(),
+            season_number: episode.season_number,
+            episode_number: episode.episode_number,
+            last_episode_number: None,
+            episode_name: episode.name.clone(),
+            source: "review".to_string(),
+        }
+    }
+
+    /// The approved renames that change anything.
+    fn approved(&self) -> Vec<PlannedRename> {
+        (0..self.items.len())
+            .filter(|&index| self.items[index].decision == Decision::Approved)
+            .filter_map(|index| self.plan(index))
+            .filter(|plan| plan.new_path != plan.old_path)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Lists `files` for review while their matches arrive from `matched`, in the order of
+/// `files`. Returns the approved renames once the user finishes, none if they abort.
+pub fn review(
+    terminal: File,
+    files: &[PathBuf],
+    matched: &Receiver<Matched>,
+    naming: Naming,
+) -> Result<Vec<PlannedRename>> {
+    let mut queue = Queue::new(files, naming);
+    let mut terminal = Terminal::new(CrosstermBackend::new(terminal))?;
+    enable_raw_mode()?;
+    execute!(terminal.backend_mut(), EnterAlternateScreen)?;
+
+    let result = event_loop(&mut terminal, &mut queue, matched);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    result
+}
+
+fn event_loop<W: Write>(
+    terminal: &mut Terminal<CrosstermBackend<W>>,
+    queue: &mut Queue,
+    matched: &Receiver<Matched>,
+) -> Result<Vec<PlannedRename>> {
+    let mut selected = ListState::default().with_selected(Some(0));
+    let mut input: Option<String> = None; // being edited
+    let mut message = String::new();
+
+    loop {
+        while let Ok(file) = matched.try_recv() {
+            queue.receive(file);
+        }
+        terminal.draw(|frame| draw(frame, queue, &mut selected, input.as_deref(), &message))?;
+
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let Event::Key(key) = event::read()? else {
+            continue;
+        };
+        if key.kind != KeyEventKind::Press {
+            continue;
+        }
+        let index = selected.selected().unwrap_or(0);
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            return Ok(Vec::new());
+        }
+
+        if let Some(text) = &mut input {
+            match key.code {
+                KeyCode::Enter => {
+                    message = match queue.edit(index, text) {
+                        Ok(()) => String::new(),
+                        Err(e) => e.to_string(),
+                    };
+                    input = None;
+                }
+                KeyCode::Esc => input = None,
+                KeyCode::Backspace => {
+                    text.pop();
+                }
+                KeyCode::Char(c) => text.push(c),
+                _ => {}
+            }
+            continue;
+        }
+
+        message.clear();
+        let result = match key.code {
+            KeyCode::Char('q') => return Ok(queue.approved()),
+            KeyCode::Down | KeyCode::Char('j') => {
+                selected.select(Some((index + 1).min(queue.items.len() - 1)));
+                Ok(())
+            }
+            KeyCode::Up | KeyCode::Char('k') => {
+                selected.select(Some(index.saturating_sub(1)));
+                Ok(())
+            }
+            KeyCode::Char('a') | KeyCode::Enter => queue.decide(index, Decision::Approved),
+            KeyCode::Char('s') => queue.decide(index, Decision::Skipped),
+            KeyCode::Char('u') => queue.decide(index, Decision::Pending),
+            KeyCode::Char('A') => {
+                queue.approve_all();
+                Ok(())
+            }
+            KeyCode::Char('e') if queue.items[index].matched.is_some() => {
+                let current = queue.plan(index).and_then(|plan| plan.new_path.file_name());
+                input = Some(current.map_or(String::new(), |name| name.to_string_lossy().into()));
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            message = e.to_string();
+        }
+    }
+}
+
+fn draw(
+    frame: &mut Frame,
+    queue: &Queue,
+    selected: &mut ListState,
+    input: Option<&str>,
+    message: &str,
+) {
+    let [main, status] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints([Constraint::Min(3), Constraint::Length(3)])
+        .areas(frame.area());
+    let [files, details] = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(40), Constraint::Percentage(60)])
+        .areas(main);
+
+    let items: Vec<ListItem> = queue
+        .items
+        .iter()
+        .map(|item| {
+            let name = file_name(&item.path);
+            let (mark, color) = match (&item.matched, item.decision) {
+                (None, _) => ("…", Color::DarkGray),
+                (_, Decision::Approved) => ("✓", Color::Green),
+                (_, Decision::Skipped) => ("✗", Color::DarkGray),
+                (Some(matched), Decision::Pending) if matched.plan.is_none() => ("?", Color::Red),
+                (Some(_), Decision::Pending) => (" ", Color::Reset),
+            };
+            ListItem::new(format!("{mark} {name}")).style(Style::default().fg(color))
+        })
+        .collect();
+    let title = format!(" Files ({}/{} matched) ", queue.received, queue.items.len());
+    let list = List::new(items)
+        .block(Block::default().borders(Borders::ALL).title(title))
+        .highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, files, selected);
+
+    let item = &queue.items[selected.selected().unwrap_or(0)];
+    let details_text = Paragraph::new(details_lines(item))
+        .block(Block::default().borders(Borders::ALL).title(" Details "))
+        .wrap(Wrap { trim: false });
+    frame.render_widget(details_text, details);
+
+    let status_text = match input {
+        Some(text) => format!("New name or SXXEXX: {text}▏  (Enter to save, Esc to cancel)"),
+        None if !message.is_empty() => message.to_string(),
+        None => "↑/↓ select  a approve  s skip  u undecide  e edit  A approve all  q rename approved and quit  Ctrl-C abort".to_string(),
+    };
+    let status_style = if message.is_empty() || input.is_some() {
+        Style::default()
+    } else {
+        Style::default().fg(Color::Red)
+    };
+    frame.render_widget(
+        Paragraph::new(status_text)
+            .style(status_style)
+            .block(Block::default().borders(Borders::ALL)),
+        status,
+    );
+}
+
+fn details_lines(item: &Item) -> Vec<Line<'static>> {
+    let mut lines = vec![Line::from(format!("File: {}", item.path.display()))];
+    let Some(matched) = &item.matched else {
+        lines.push(Line::from("Matching…"));
+        return lines;
+    };
+    let report = &matched.report;
+
+    if report.episodes.is_empty() {
+        lines.push(Line::from("No episode found"));
+    }
+    for episode in &report.episodes {
+        lines.push(Line::from(format!(
+            "Episode: S{:02}E{:02} - {}",
+            episode.season_number, episode.episode_number, episode.name
+        )));
+    }
+    if let Some(source) = &report.source {
+        lines.push(Line::from(format!("Source: {source}")));
+    }
+    if let Some(plan) = &matched.plan {
+        lines.push(Line::from(format!(
+            "New name: {}",
+            file_name(&plan.new_path)
+        )));
+        if plan.new_path.parent() != item.path.parent() {
+            lines.push(Line::from(format!("Moved to: {}", plan.new_path.display())));
+        }
+    }
+    if let Some(error) = &report.error {
+        lines.push(Line::styled(
+            format!("Error: {error}"),
+            Style::default().fg(Color::Red),
+        ));
+    }
+
+    if !report.candidates.is_empty() {
+        lines.push(Line::from(""));
+        lines.push(Line::from("OCR candidates:"));
+        for candidate in &report.candidates {
+            lines.push(Line::from(format!(
+                "  {:<10} confidence {:>3}  {}",
+                candidate.code, candidate.confidence, candidate.frame
+            )));
+        }
+    }
+    lines
+}
+
+/// Sends the process' stdout and stderr to a log file while the review runs, so the
+/// output of the matching in the background doesn't draw over the UI. Restored on drop.
+pub struct OutputRedirect {
+    stdout: File, // the original ones
+    stderr: File,
+}
+
+impl OutputRedirect {
+    #[cfg(unix)]
+    pub fn to_file(log_path: &Path) -> Result<Self> {
+        use std::os::fd::AsFd;
+
+        let log = File::create(log_path)?;
+        std::io::stdout().flush()?;
+        let stdout = std::io::stdout().as_fd().try_clone_to_owned()?;
+        let stderr = std::io::stderr().as_fd().try_clone_to_owned()?;
+        report::redirect_fd(log.as_fd(), 1)?;
+        report::redirect_fd(log.as_fd(), 2)?;
+        Ok(Self {
+            stdout: stdout.into(),
+            stderr: stderr.into(),
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn to_file(_log_path: &Path) -> Result<Self> {
+        bail!("--tui is only supported on Unix")
+    }
+
+    /// The original stdout, for drawing the UI.
+    pub fn terminal(&self) -> Result<File> {
+        Ok(self.stdout.try_clone()?)
+    }
+}
+
+impl Drop for OutputRedirect {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        {
+            use std::os::fd::AsFd;
+
+            let _ = std::io::stdout().flush();
+            let _ = report::redirect_fd(self.stdout.as_fd(), 1);
+            let _ = report::redirect_fd(self.stderr.as_fd(), 2);
+        }
+    }
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map_or_else(
+        || path.display().to_string(),
+        |name| name.to_string_lossy().into(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn episode(episode_number: u64, name: &str) -> EpisodeEntry {
+        EpisodeEntry {
+            production_code: None,
+            season_number: 1,
+            episode_number,
+            name: name.to_string(),
+            overview: None,
+            aired: None,
+            runtime: None,
+            id: None,
+            absolute_number: None,
+        }
+    }
+
+    #[test]
+    fn test_queue() {
+        let template = NameTemplate::parse("default").unwrap();
+        let files = vec![
+            PathBuf::from("/tv/title_t00.mkv"),
+            PathBuf::from("/tv/title_t01.mkv"),
+        ];
+        let mut queue = Queue::new(
+            &files,
+            Naming {
+                series_id: "77398",
+                show_name: "The X-Files",
+                template: &template,
+                library_root: None,
+                episodes: vec![episode(1, "Pilot"), episode(3, "Squeeze")],
+            },
+        );
+
+        let plan = queue.episode_plan(0, &episode(1, "Pilot"));
+        queue.receive(Matched {
+            report: FileReport::new(&files[0]),
+            plan: Some(plan),
+        });
+        queue.receive(Matched {
+            report: FileReport::new(&files[1]),
+            plan: None,
+        });
+
+        // Unmatched files can't be approved until they're identified
+        assert!(queue.decide(1, Decision::Approved).is_err());
+        assert!(queue.edit(1, "Squeeze.mkv").is_err());
+        queue.edit(1, "s01e03").unwrap();
+        assert_eq!(
+            queue.plan(1).unwrap().new_path,
+            PathBuf::from("/tv/The X-Files - S01E03 - Squeeze.mkv")
+        );
+        assert!(queue.edit(1, "S01E09").is_err());
+
+        queue.edit(0, "Pilot (Director's Cut)").unwrap();
+        queue.decide(0, Decision::Skipped).unwrap();
+        let approved = queue.approved();
+        assert_eq!(approved.len(), 1);
+        assert_eq!(approved[0].episode_number, 3);
+
+        queue.decide(0, Decision::Pending).unwrap();
+        queue.approve_all();
+        assert_eq!(
+            queue.approved()[0].new_path,
+            PathBuf::from("/tv/Pilot (Director's Cut).mkv")
+        );
+    }
+}
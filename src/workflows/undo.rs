@@ -0,0 +1,204 @@
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+
+use super::renamer::{self, RetryPolicy};
+use crate::domain::models::{unix_now, JournalEntry};
+use crate::infra::journal;
+
+/// A run recorded in the rename journal.
+#[derive(Debug, PartialEq)]
+pub struct JournalRun<'a> {
+    pub run_id: u64,
+    pub entries: Vec<&'a JournalEntry>,
+}
+
+impl JournalRun<'_> {
+    /// Whether the run was recorded by `import` rather than made by this tool.
+    pub fn is_imported(&self) -> bool {
+        self.entries.iter().all(|entry| entry.imported)
+    }
+}
+
+/// Groups journal entries by run, oldest run first.
+pub fn group_runs(entries: &[JournalEntry]) -> Vec<JournalRun<'_>> {
+    let mut runs: Vec<JournalRun> = Vec::new();
+    for entry in entries {
+        match runs.iter_mut().find(|run| run.run_id == entry.run_id) {
+            Some(run) => run.entries.push(entry),
+            None => runs.push(JournalRun {
+                run_id: entry.run_id,
+                entries: vec![entry],
+            }),
+        }
+    }
+    runs
+}
+
+/// Renames the files of a run back, newest rename first. Files that were moved or whose
+/// old name has been taken since are skipped. The reverting renames are journaled as a
+/// run of their own. Links and copies of files that were kept in place are deleted, which
+/// can't be undone in turn, and so are the files the run wrote, like NFOs. Returns the
+/// number of reverted and skipped renames.
+pub fn revert(run: &JournalRun, retry: &RetryPolicy) -> Result<(usize, usize)> {
+    let run_id = journal::new_run_id();
+    let mut reverted = 0;
+    let mut skipped = 0;
+
+    for entry in run.entries.iter().rev() {
+        if entry.created {
+            let deleted = match entry.new_path.is_file() {
+                true => fs::remove_file(&entry.new_path).map_err(|e| e.to_string()),
+                false => Err("the file has been moved or deleted since".to_string()),
+            };
+            match deleted {
+                Ok(()) => {
+                    println!("Deleted {:?}", entry.new_path);
+                    reverted += 1;
+                }
+                Err(reason) => {
+                    eprintln!("Skipping {:?}: {reason}", entry.new_path);
+                    skipped += 1;
+                }
+            }
+            continue;
+        }
+        if entry.kept_original {
+            match renamer::remove_link(&entry.old_path, &entry.new_path) {
+                Ok(()) => {
+                    println!("Deleted {:?}", entry.new_path);
+                    reverted += 1;
+                }
+                Err(e) => {
+                    eprintln!("Skipping {:?}: {e:#}", entry.new_path);
+                    skipped += 1;
+                }
+            }
+            continue;
+        }
+        if let Err(reason) = check_revertible(&entry.new_path, &entry.old_path) {
+            eprintln!("Skipping {:?}: {reason}", entry.new_path);
+            skipped += 1;
+            continue;
+        }
+
+        match renamer::rename_with_retry(&entry.new_path, &entry.old_path, retry) {
+            Ok(()) => {
+                println!("Renamed {:?} -> {:?}", entry.new_path, entry.old_path);
+                journal::append(&[JournalEntry {
+                    timestamp: unix_now(),
+                    run_id,
+                    old_path: entry.new_path.clone(),
+                    new_path: entry.old_path.clone(),
+                    series_id: entry.series_id.clone(),
+                    season_number: entry.season_number,
+                    episode_number: entry.episode_number,
+                    last_episode_number: entry.last_episode_number,
+                    kept_original: false,
+                    checksum: None,
+                    imported: false,
+                    created: false,
+                }])?;
+                reverted += 1;
+            }
+            Err(e) => {
+                eprintln!("Skipping {:?}: {e}", entry.new_path);
+                skipped += 1;
+            }
+        }
+    }
+
+    Ok((reverted, skipped))
+}
+
+fn check_revertible(current: &Path, original: &Path) -> Result<(), &'static str> {
+    if !current.is_file() {
+        return Err("the file has been moved or deleted since");
+    }
+    if original.exists() && original != current {
+        return Err("its old name is taken by another file");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn entry(run_id: u64, old_path: &Path, new_path: &Path) -> JournalEntry {
+        JournalEntry {
+            timestamp: run_id,
+            run_id,
+            old_path: old_path.to_path_buf(),
+            new_path: new_path.to_path_buf(),
+            series_id: "1".to_string(),
+            season_number: 1,
+            episode_number: 1,
+            last_episode_number: None,
+            kept_original: false,
+            checksum: None,
+            imported: false,
+            created: false,
+        }
+    }
+
+    #[test]
+    fn test_group_runs() {
+        let (a, b) = (PathBuf::from("a"), PathBuf::from("b"));
+        let entries = vec![entry(1, &a, &b), entry(2, &b, &a), entry(1, &b, &a)];
+        let runs = group_runs(&entries);
+
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].run_id, 1);
+        assert_eq!(runs[0].entries, vec![&entries[0], &entries[2]]);
+        assert_eq!(runs[1].entries, vec![&entries[1]]);
+        assert!(!runs[0].is_imported());
+
+        let imported = JournalEntry {
+            imported: true,
+            ..entry(3, &a, &b)
+        };
+        assert!(group_runs(&[imported])[0].is_imported());
+    }
+
+    #[test]
+    fn test_check_revertible() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let renamed = temp_dir.path().join("Show - S01E01 - Pilot.mkv");
+        let original = temp_dir.path().join("title_t00.mkv");
+        let other = temp_dir.path().join("title_t01.mkv");
+        fs::write(&renamed, "").unwrap();
+        fs::write(&other, "").unwrap();
+
+        assert!(check_revertible(&renamed, &original).is_ok());
+        assert!(check_revertible(&original, &renamed).is_err());
+        assert!(check_revertible(&renamed, &other).is_err());
+    }
+
+    #[test]
+    fn test_revert_created() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let nfo = temp_dir.path().join("Show - S01E01 - Pilot.nfo");
+        fs::write(&nfo, "<episodedetails/>").unwrap();
+        let written = JournalEntry {
+            created: true,
+            ..entry(1, &nfo, &nfo)
+        };
+        let run = JournalRun {
+            run_id: 1,
+            entries: vec![&written],
+        };
+        let retry = RetryPolicy {
+            retries: 0,
+            initial_backoff: std::time::Duration::ZERO,
+            verify_checksum: false,
+        };
+
+        assert_eq!(revert(&run, &retry).unwrap(), (1, 0));
+        assert!(!nfo.exists());
+        // Already gone
+        assert_eq!(revert(&run, &retry).unwrap(), (0, 1));
+    }
+}
@@ -0,0 +1,46 @@
+use regex::Regex;
+
+/// The season and episodes a renamed file's name holds, several for the `S01E03-E04`
+/// names of multi-episode files.
+pub fn named_episodes(file_name: &str) -> Option<(u64, Vec<u64>)> {
+    let re = Regex::new(r"(?i)\bS(\d{1,2})E(\d{1,3})((?:-?E\d{1,3})*)").unwrap();
+    let caps = re.captures(file_name)?;
+    let season = caps[1].parse().ok()?;
+    let mut episodes = vec![caps[2].parse().ok()?];
+    for number in caps[3].split(['E', 'e']).skip(1) {
+        episodes.push(number.trim_end_matches('-').parse().ok()?);
+    }
+    Some((season, episodes))
+}
+
+/// `S01E03` or `S01E03-E04`.
+pub fn format_episodes(season: u64, episodes: &[u64]) -> String {
+    let numbers: Vec<String> = episodes
+        .iter()
+        .map(|episode| format!("E{episode:02}"))
+        .collect();
+    format!("S{season:02}{}", numbers.join("-"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named_episodes() {
+        assert_eq!(
+            named_episodes("The X-Files - S01E03 - Squeeze.mkv"),
+            Some((1, vec![3]))
+        );
+        assert_eq!(
+            named_episodes("The X-Files - S01E03-E04 - Squeeze & Conduit.mkv"),
+            Some((1, vec![3, 4]))
+        );
+        assert_eq!(
+            named_episodes("the.x-files.s02e10e11.mkv"),
+            Some((2, vec![10, 11]))
+        );
+        assert_eq!(named_episodes("The X-Files - Squeeze.mkv"), None);
+        assert_eq!(format_episodes(1, &[3, 4]), "S01E03-E04");
+    }
+}